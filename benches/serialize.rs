@@ -0,0 +1,77 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use inliner::html::{Dom, SerializeMode};
+use std::io;
+
+/// document is a page of a few megabytes, made of `sections` copies of a
+/// section with the mix of markup, attributes and text of a real page.
+fn document(sections: usize) -> Dom {
+    let section = r#"
+        <section class="card">
+            <header id="title">A heading</header>
+            <img src="img/photo.jpg" alt="photo"/>
+            <p>Some text about the photo, long enough to be a paragraph of the
+            sort a page is mostly made of, with <a href="more.html">a link</a>
+            and <em>emphasis</em> &amp; an entity in it.</p>
+            <!-- a comment -->
+            <ul><li>one</li><li>two</li><li>three</li></ul>
+        </section>"#;
+    format!("<html><body>{}</body></html>", section.repeat(sections))
+        .parse()
+        .expect("parsing document")
+}
+
+/// nested is a document of `depth` elements, each inside the one before.
+fn nested(depth: usize) -> Dom {
+    format!(
+        "{}text{}",
+        r#"<div class="level">"#.repeat(depth),
+        "</div>".repeat(depth)
+    )
+    .parse()
+    .expect("parsing document")
+}
+
+fn serialize(c: &mut Criterion) {
+    let dom = document(10_000);
+    let size = dom.to_string().len() as u64;
+    let mut group = c.benchmark_group("serialize");
+    group.throughput(Throughput::Bytes(size));
+    group.sample_size(10);
+    group.bench_function("display", |b| b.iter(|| dom.to_string()));
+    for mode in [
+        SerializeMode::Html5,
+        SerializeMode::Xhtml,
+        SerializeMode::Preserve,
+    ] {
+        group.bench_function(format!("{:?}", mode), |b| {
+            b.iter(|| dom.to_string_with(mode))
+        });
+    }
+    group.bench_function("to_writer", |b| b.iter(|| dom.to_writer(&mut io::sink())));
+    group.finish();
+}
+
+/// Serializing writes each node once, straight into the output, so
+/// throughput should hold steady as documents grow wider or deeper.
+fn scaling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize scaling");
+    group.sample_size(10);
+    for sections in [2_500, 5_000, 10_000] {
+        let dom = document(sections);
+        group.throughput(Throughput::Bytes(dom.to_string().len() as u64));
+        group.bench_with_input(BenchmarkId::new("wide", sections), &dom, |b, dom| {
+            b.iter(|| dom.to_string())
+        });
+    }
+    for depth in [250, 500, 1_000] {
+        let dom = nested(depth);
+        group.throughput(Throughput::Bytes(dom.to_string().len() as u64));
+        group.bench_with_input(BenchmarkId::new("deep", depth), &dom, |b, dom| {
+            b.iter(|| dom.to_string())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, serialize, scaling);
+criterion_main!(benches);
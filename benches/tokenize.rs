@@ -0,0 +1,84 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use inliner::html::token::Token;
+use inliner::html::{Parser, StrTokenizer, Tokenizer};
+
+/// document is a page of a few megabytes, made of a section repeated over and
+/// over, with the mix of markup, attributes and text of a real page.
+fn document() -> String {
+    let section = r#"
+        <section class="card">
+            <header id="title">A heading</header>
+            <img src="img/photo.jpg" alt="photo"/>
+            <p>Some text about the photo, long enough to be a paragraph of the
+            sort a page is mostly made of, with <a href="more.html">a link</a>
+            and <em>emphasis</em> in it.</p>
+            <!-- a comment -->
+            <ul><li>one</li><li>two</li><li>three</li></ul>
+        </section>"#;
+    format!("<html><body>{}</body></html>", section.repeat(10_000))
+}
+
+fn tokenize(c: &mut Criterion) {
+    let input = document();
+    assert!(Parser::new(StrTokenizer::new(&input).merged())
+        .parse()
+        .is_ok());
+    let mut group = c.benchmark_group("tokenize");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.sample_size(10);
+    group.bench_function("owned", |b| {
+        b.iter(|| {
+            Tokenizer::new(input.chars())
+                .merged()
+                .collect::<Vec<Token<String, String>>>()
+        })
+    });
+    group.bench_function("borrowed", |b| {
+        b.iter(|| {
+            StrTokenizer::new(&input)
+                .merged()
+                .collect::<Vec<Token<&str, &str>>>()
+        })
+    });
+    group.bench_function("parse owned", |b| {
+        b.iter(|| Parser::new(Tokenizer::new(input.chars()).merged()).parse())
+    });
+    group.bench_function("parse borrowed", |b| {
+        b.iter(|| Parser::new(StrTokenizer::new(&input).merged()).parse())
+    });
+    group.finish();
+}
+
+/// text_heavy is a page of about `mb` megabytes that is mostly a large inline
+/// script and long paragraphs, with little markup between them.
+fn text_heavy(mb: usize) -> String {
+    let script = "if (a < b && b > c) { console.log('<p>' + a + '</p>'); }\n";
+    let paragraph = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ";
+    let half = mb * 1024 * 1024 / 2;
+    format!(
+        "<html><head><script>{}</script></head><body><p>{}</p></body></html>",
+        script.repeat(half / script.len()),
+        paragraph.repeat(half / paragraph.len()),
+    )
+}
+
+/// Tokenizing is O(n), so throughput should hold steady as the text between
+/// tags grows.
+fn scaling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("text heavy");
+    group.sample_size(10);
+    for mb in [1, 2, 4] {
+        let input = text_heavy(mb);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::new("owned", mb), &input, |b, input| {
+            b.iter(|| Tokenizer::new(input.chars()).merged().count())
+        });
+        group.bench_with_input(BenchmarkId::new("borrowed", mb), &input, |b, input| {
+            b.iter(|| StrTokenizer::new(input).merged().count())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tokenize, scaling);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+//! Parses arbitrary input, in both modes and with both tokenizers, and
+//! writes back whatever parses, none of which may panic.
+//!
+//!     cargo +nightly fuzz run parse fuzz/corpus/parse
+
+#![no_main]
+
+use inliner::html::{ParseMode, Parser, ReadChars, SerializeMode, StrTokenizer, Tokenizer};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let input = match std::str::from_utf8(data) {
+        Ok(input) => input,
+        Err(_) => return,
+    };
+    for mode in [ParseMode::Lenient, ParseMode::Strict] {
+        if let Ok(dom) = Parser::new(StrTokenizer::new(input).merged())
+            .mode(mode)
+            .parse()
+        {
+            for format in [SerializeMode::Preserve, SerializeMode::Html5, SerializeMode::Xhtml] {
+                let _ = dom.to_string_with(format);
+            }
+        }
+        let chars = ReadChars::new(input.as_bytes());
+        let _ = Parser::new(Tokenizer::new(chars).merged()).mode(mode).parse();
+    }
+});
@@ -0,0 +1,149 @@
+use crate::inline::{inline_with_resources, InlineError, Options, Report};
+use futures_util::stream::{self, StreamExt};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Resources read so far, or why they couldn't be read.
+type Loaded = HashMap<PathBuf, Result<Vec<u8>, String>>;
+
+/// Inline html resources into a single html buffer, as `inline` does, without
+/// blocking: resources are read with `tokio::fs`.
+pub async fn inline_async(input: String, base: &Path) -> Result<String, InlineError> {
+    inline_async_with(input, base, &Options::default())
+        .await
+        .map(|(output, _)| output)
+}
+
+/// Inline html resources into a single html buffer, as `inline_with` does,
+/// without blocking: resources are read with `tokio::fs`, at most
+/// `options.concurrency` at a time.
+///
+/// Which resources a document needs is only known once the resources linking
+/// to them have been read, so inlining happens in rounds. Each round inlines
+/// the document with the resources read so far and notes the ones it is
+/// missing, which are then read concurrently. The first round that misses
+/// nothing produces the output. Parsing happens synchronously within a round
+/// and the DOM never lives across an `.await`, so the future is `Send`.
+pub async fn inline_async_with(
+    input: String,
+    base: &Path,
+    options: &Options,
+) -> Result<(String, Report), InlineError> {
+    let mut loaded = Loaded::new();
+    loop {
+        let missing = match attempt(&input, base, options, &loaded) {
+            Attempt::Done(output) => return output,
+            Attempt::Missing(missing) => missing,
+        };
+        let limit = options.concurrency.unwrap_or(missing.len()).max(1);
+        let mut reads = stream::iter(missing)
+            .map(|path| async move {
+                let content = tokio::fs::read(&path)
+                    .await
+                    .map_err(|e| format!("{}: {:?}", &path.to_string_lossy(), e));
+                (path, content)
+            })
+            .buffer_unordered(limit);
+        while let Some((path, content)) = reads.next().await {
+            loaded.insert(path, content);
+        }
+    }
+}
+
+/// Attempt is the outcome of one round of inlining.
+enum Attempt {
+    Done(Result<(String, Report), InlineError>),
+    Missing(BTreeSet<PathBuf>),
+}
+
+/// attempt inlines `input` with the resources in `loaded`. Resources that
+/// haven't been read yet are empty, so that the round carries on to find the
+/// rest of the missing resources.
+fn attempt(input: &str, base: &Path, options: &Options, loaded: &Loaded) -> Attempt {
+    let missing = RefCell::new(BTreeSet::new());
+    let resources = |path: &Path| -> Result<Vec<u8>, Box<dyn Error>> {
+        match loaded.get(path) {
+            Some(Ok(content)) => Ok(content.clone()),
+            Some(Err(err)) => Err(err.clone().into()),
+            None => {
+                missing.borrow_mut().insert(path.to_owned());
+                Ok(vec![])
+            }
+        }
+    };
+    let output = inline_with_resources(input.to_owned(), base, options, &resources);
+    let missing = missing.into_inner();
+    if missing.is_empty() {
+        Attempt::Done(output.map_err(InlineError::from))
+    } else {
+        Attempt::Missing(missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inline::inline_with;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// site writes a page whose resources link to further resources.
+    fn site() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let files = vec![
+            ("css/site.css", "@import 'theme.css';\nbody{margin:0}"),
+            ("css/theme.css", "body{background:url(img/dot.png)}"),
+            ("css/img/dot.png", "dot"),
+            ("app.js", "console.log(1)"),
+            ("logo.png", "logo"),
+        ];
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    const PAGE: &str = r#"<head><link rel="stylesheet" href="css/site.css"/><script src="app.js"></script></head><body><img src="logo.png"/></body>"#;
+
+    #[tokio::test]
+    async fn matches_blocking_inline() {
+        let dir = site();
+        let want = inline_with(PAGE.into(), dir.path(), &Options::default()).unwrap();
+        for concurrency in [None, Some(1), Some(2)] {
+            let options = Options {
+                concurrency,
+                ..Options::default()
+            };
+            let got = inline_async_with(PAGE.into(), dir.path(), &options)
+                .await
+                .unwrap();
+            assert_eq!(want, got, "concurrency {:?}", concurrency);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spawned() {
+        let dir = site();
+        let base = dir.path().to_owned();
+        let got = tokio::spawn(async move { inline_async(PAGE.into(), &base).await })
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(got.contains("data:image/png;base64,"), "{}", got);
+        assert!(!got.contains("site.css"), "{}", got);
+    }
+
+    #[tokio::test]
+    async fn missing_resources() {
+        let dir = site();
+        let err = inline_async(r#"<img src="nope.png"/>"#.into(), dir.path())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("nope.png"), "{}", err);
+    }
+}
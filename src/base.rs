@@ -0,0 +1,519 @@
+use crate::inline::is_local;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+/// Base is what the links in a document or stylesheet resolve against.
+/// A base whose root is an `http` or `https` url resolves links to urls,
+/// kept as paths so that they're read through `Resources` like files are.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Base {
+    /// The directory the document was inlined from, which root-relative
+    /// links such as `/img/logo.png` resolve against. For a url, it's the
+    /// origin, such as `https://example.com`.
+    root: PathBuf,
+    /// The directory relative links resolve against.
+    dir: PathBuf,
+    /// Whether links must resolve to somewhere inside `root`.
+    contained: bool,
+    /// The local links resolved against this base, and the bases of the
+    /// resources it's the base of, with the paths they resolved to.
+    resolved: Rc<RefCell<Vec<(String, PathBuf)>>>,
+    /// The links deliberately left as links, as they're written, which the
+    /// bases derived from this one share.
+    skipped: Rc<RefCell<Vec<String>>>,
+}
+
+impl Base {
+    /// new returns the base of a document inlined from `root`. Links that
+    /// resolve outside of `root` are refused, unless `root` is a url, where
+    /// links resolve anywhere on the web.
+    pub fn new(root: &Path) -> Self {
+        if let Some(url) = root.to_str().filter(|root| is_url(root)) {
+            let dir = url.trim_end_matches('/');
+            return Base {
+                root: origin(dir).into(),
+                dir: dir.into(),
+                contained: false,
+                resolved: Rc::default(),
+                skipped: Rc::default(),
+            };
+        }
+        Base {
+            root: root.to_owned(),
+            dir: root.to_owned(),
+            contained: true,
+            resolved: Rc::default(),
+            skipped: Rc::default(),
+        }
+    }
+
+    /// allow_outside lets links resolve outside of the root, such as to the
+    /// sibling packages of a monorepo.
+    pub fn allow_outside(self) -> Self {
+        Base {
+            contained: false,
+            ..self
+        }
+    }
+
+    /// with_href returns the base a `<base href>` element sets, where `href`
+    /// is relative to this base. A trailing file name in `href` is not part
+    /// of the base, just as it isn't for a browser.
+    pub fn with_href(&self, href: &str) -> Result<Self, Box<dyn Error>> {
+        let dir = match href.rfind('/') {
+            Some(ii) => &href[..=ii],
+            None => "",
+        };
+        if self.is_url() {
+            return Ok(Base {
+                skipped: self.skipped.clone(),
+                ..Base::new(&self.resolve_path(dir)?)
+            });
+        }
+        Ok(Base {
+            dir: self.resolve_path(dir)?,
+            ..self.clone()
+        })
+    }
+
+    /// of returns the base of links inside the resource at `path`, which is
+    /// its directory.
+    pub fn of(&self, path: &Path) -> Self {
+        if let Some(url) = path.to_str().filter(|path| is_url(path)) {
+            return Base {
+                skipped: self.skipped.clone(),
+                ..Base::new(Path::new(url_dir(url)))
+            };
+        }
+        Base {
+            dir: path.parent().unwrap_or_else(|| Path::new("")).to_owned(),
+            ..self.clone()
+        }
+    }
+
+    /// resolve returns the path of the local resource `link` refers to, less
+    /// any query or fragment, once its percent-escapes are decoded.
+    /// Unless the base allows it, a link that resolves outside of the root,
+    /// by way of `..` or a symlink, is an error.
+    pub fn resolve(&self, link: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let path = self.resolve_path(link)?;
+        if !self.is_url() {
+            self.resolved
+                .borrow_mut()
+                .push((link.to_owned(), path.clone()));
+        }
+        Ok(path)
+    }
+
+    /// locate returns the path of the resource `link` refers to, as `resolve`
+    /// does, without recording it and wherever it is, for links that are
+    /// followed rather than inlined.
+    pub fn locate(&self, link: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let base = Base {
+            contained: false,
+            ..self.clone()
+        };
+        base.resolve_path(link)
+    }
+
+    /// skip records that `link` is deliberately left as a link, such as one
+    /// to a resource too large to embed, so that it isn't taken for one that
+    /// failed to be.
+    pub fn skip(&self, link: &str) {
+        self.skipped.borrow_mut().push(link.to_owned());
+    }
+
+    /// skipped returns the links `skip` recorded, once each, in the order
+    /// they were first recorded.
+    pub fn skipped(&self) -> Vec<String> {
+        let mut skipped: Vec<String> = vec![];
+        for link in self.skipped.borrow().iter() {
+            if !skipped.contains(link) {
+                skipped.push(link.clone());
+            }
+        }
+        skipped
+    }
+
+    /// resolved returns the local links resolved so far, as `resolve` was
+    /// given them, with the paths they resolved to, in the order they were
+    /// resolved.
+    pub fn resolved(&self) -> Vec<(String, PathBuf)> {
+        self.resolved.borrow().clone()
+    }
+
+    /// resolve_path resolves `link` as `resolve` does, without recording it.
+    fn resolve_path(&self, link: &str) -> Result<PathBuf, Box<dyn Error>> {
+        if self.is_url() {
+            return Ok(self.resolve_url(link).into());
+        }
+        // A query or fragment means nothing to a file, so it's left off.
+        let link_path = link.split(['?', '#']).next().unwrap_or(link);
+        let decoded = percent_decode(link_path);
+        let path = match decoded.strip_prefix('/') {
+            Some(link) => self.root.join(link.trim_start_matches('/')),
+            None => self.dir.join(&*decoded),
+        };
+        if self.contained && !self.is_inside(&path) {
+            return Err(format!(
+                "{}: resolves outside of the base directory {}",
+                link,
+                self.root.display()
+            )
+            .into());
+        }
+        Ok(path)
+    }
+
+    /// resolves reports whether `link` refers to a resource the base resolves,
+    /// rather than one that's left linked: a local resource, or for a url
+    /// base any other url on the web.
+    pub fn resolves(&self, link: &str) -> bool {
+        is_local(link) || (self.is_url() && (is_url(link) || link.starts_with("//")))
+    }
+
+    fn is_url(&self) -> bool {
+        self.root.to_str().is_some_and(is_url)
+    }
+
+    /// resolve_url returns the url `link` refers to, less its fragment.
+    /// Root-relative links resolve against the origin and protocol-relative
+    /// links against the scheme.
+    fn resolve_url(&self, link: &str) -> String {
+        let link = link.split('#').next().unwrap_or_default();
+        let root = self.root.to_string_lossy();
+        let url = if is_url(link) {
+            link.to_owned()
+        } else if let Some(rest) = link.strip_prefix("//") {
+            let scheme = root.split("//").next().unwrap_or("https:");
+            format!("{}//{}", scheme, rest)
+        } else if link.starts_with('/') {
+            format!("{}{}", root, link)
+        } else {
+            format!("{}/{}", self.dir.to_string_lossy(), link)
+        };
+        normalize(&url)
+    }
+
+    /// is_inside reports whether `path` is inside the root.
+    fn is_inside(&self, path: &Path) -> bool {
+        // Checked lexically first, which covers resources that aren't on the
+        // filesystem.
+        let rest = match path.strip_prefix(&self.root) {
+            Ok(rest) => rest,
+            Err(_) => return false,
+        };
+        let mut depth = 0;
+        for component in rest.components() {
+            match component {
+                Component::Normal(_) => depth += 1,
+                Component::CurDir => {}
+                Component::ParentDir if depth > 0 => depth -= 1,
+                _ => return false,
+            }
+        }
+        // Symlinks can lead anywhere, so where the path really is matters
+        // when it's on the filesystem.
+        match (fs::canonicalize(path), fs::canonicalize(&self.root)) {
+            (Ok(path), Ok(root)) => path.starts_with(root),
+            _ => true,
+        }
+    }
+}
+
+/// is_url reports whether `link` is an absolute `http` or `https` url.
+fn is_url(link: &str) -> bool {
+    let lower = link.get(..8).unwrap_or(link).to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// url_dir returns the directory of the resource at `url`, which the links
+/// inside it are relative to.
+pub(crate) fn url_dir(url: &str) -> &str {
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+    match url.rfind('/') {
+        Some(ii) if ii >= origin(url).len() => &url[..ii],
+        _ => origin(url),
+    }
+}
+
+/// origin returns the scheme and host of `url`, such as
+/// `https://example.com`.
+pub(crate) fn origin(url: &str) -> &str {
+    let host = url.find("://").map(|ii| ii + 3).unwrap_or(0);
+    match url[host..].find(['/', '?']) {
+        Some(ii) => &url[..host + ii],
+        None => url,
+    }
+}
+
+/// normalize removes the `.` and `..` segments of the path of `url`.
+fn normalize(url: &str) -> String {
+    let origin = origin(url);
+    let (path, query) = match url[origin.len()..].split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (&url[origin.len()..], None),
+    };
+    let mut segments: Vec<&str> = vec![];
+    let parts: Vec<&str> = path.split('/').skip(1).collect();
+    for (ii, segment) in parts.iter().enumerate() {
+        let last = ii + 1 == parts.len();
+        match *segment {
+            "." | ".." if last => {
+                if *segment == ".." {
+                    segments.pop();
+                }
+                segments.push("");
+            }
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let mut normalized = format!("{}/{}", origin, segments.join("/"));
+    if let Some(query) = query {
+        normalized.push('?');
+        normalized.push_str(query);
+    }
+    normalized
+}
+
+/// percent_decode decodes the percent-escapes in `link`. Unlike in a query
+/// string, `+` is a plus in a path, not a space. A link with an invalid
+/// escape, or that doesn't decode to UTF-8, is returned as it is.
+pub(crate) fn percent_decode(link: &str) -> Cow<'_, str> {
+    if !link.contains('%') {
+        return Cow::Borrowed(link);
+    }
+    match percent_decode_bytes(link).map(String::from_utf8) {
+        Some(Ok(decoded)) => Cow::Owned(decoded),
+        _ => Cow::Borrowed(link),
+    }
+}
+
+/// percent_decode_bytes decodes the percent-escapes in `text` into the bytes
+/// they stand for, which needn't be UTF-8, or returns `None` if an escape is
+/// invalid.
+pub(crate) fn percent_decode_bytes(text: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            decoded.push(b);
+            continue;
+        }
+        let hex = |b: Option<u8>| b.and_then(|b| (b as char).to_digit(16));
+        decoded.push((hex(bytes.next())? * 16 + hex(bytes.next())?) as u8);
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn resolve() {
+        let base = Base::new(Path::new("site"));
+        let nested = base.of(Path::new("site/css/main.css"));
+        let tests = vec![
+            ("relative", base.clone(), "img/a.png", "site/img/a.png"),
+            (
+                "root relative",
+                base.clone(),
+                "/img/a.png",
+                "site/img/a.png",
+            ),
+            ("nested relative", nested.clone(), "a.png", "site/css/a.png"),
+            (
+                "nested root relative",
+                nested.clone(),
+                "/img/a.png",
+                "site/img/a.png",
+            ),
+            (
+                "base href",
+                base.with_href("/static/").unwrap(),
+                "a.png",
+                "site/static/a.png",
+            ),
+            (
+                "relative base href",
+                nested.with_href("fonts/").unwrap(),
+                "a.woff",
+                "site/css/fonts/a.woff",
+            ),
+            (
+                "base href with a file name",
+                base.with_href("/static/index.html").unwrap(),
+                "a.png",
+                "site/static/a.png",
+            ),
+            (
+                "root relative under a base href",
+                base.with_href("/static/").unwrap(),
+                "/a.png",
+                "site/a.png",
+            ),
+        ];
+        for (desc, base, link, want) in tests {
+            assert_eq!(PathBuf::from(want), base.resolve(link).unwrap(), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn urls() {
+        let base = Base::new(Path::new("https://example.com/blog/"));
+        let nested = base.of(Path::new("https://cdn.example.com/css/main.css?v=2"));
+        let tests = vec![
+            (
+                "relative",
+                base.clone(),
+                "img/a.png",
+                "https://example.com/blog/img/a.png",
+            ),
+            (
+                "parent",
+                base.clone(),
+                "../img/./a.png",
+                "https://example.com/img/a.png",
+            ),
+            (
+                "root relative",
+                base.clone(),
+                "/img/a.png",
+                "https://example.com/img/a.png",
+            ),
+            (
+                "protocol relative",
+                base.clone(),
+                "//cdn.example.com/a.png",
+                "https://cdn.example.com/a.png",
+            ),
+            (
+                "absolute",
+                base.clone(),
+                "http://other.example/a.png?x=1#top",
+                "http://other.example/a.png?x=1",
+            ),
+            (
+                "nested relative",
+                nested.clone(),
+                "../fonts/a.woff",
+                "https://cdn.example.com/fonts/a.woff",
+            ),
+            (
+                "nested root relative",
+                nested.clone(),
+                "/a.png",
+                "https://cdn.example.com/a.png",
+            ),
+            (
+                "base href",
+                base.with_href("/static/").unwrap(),
+                "a.png",
+                "https://example.com/static/a.png",
+            ),
+            (
+                "absolute base href",
+                base.with_href("https://cdn.example.com/v1/index.html")
+                    .unwrap(),
+                "/a.png",
+                "https://cdn.example.com/a.png",
+            ),
+        ];
+        for (desc, base, link, want) in tests {
+            assert_eq!(PathBuf::from(want), base.resolve(link).unwrap(), "{}", desc);
+        }
+        let local = Base::new(Path::new("site"));
+        for (link, resolves) in [
+            ("a.png", true),
+            ("https://example.com/a.png", false),
+            ("//example.com/a.png", false),
+            ("data:,", false),
+        ] {
+            assert_eq!(resolves, local.resolves(link), "{} locally", link);
+            assert!(base.resolves(link) || link.starts_with("data:"), "{}", link);
+        }
+    }
+
+    #[test]
+    fn containment() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("site");
+        fs::create_dir_all(root.join("css")).unwrap();
+        fs::write(root.join("css/main.css"), "").unwrap();
+        fs::write(dir.path().join("secret"), "").unwrap();
+        let base = Base::new(&root);
+        let nested = base.of(&root.join("css/main.css"));
+        let tests = vec![
+            ("inside", &base, "css/main.css", true),
+            ("parent inside", &nested, "../css/main.css", true),
+            ("traversal", &base, "../secret", false),
+            ("deep traversal", &base, "../../../../etc/passwd", false),
+            ("nested traversal", &nested, "../../secret", false),
+            (
+                "traversal back in",
+                &base,
+                "css/../../site/css/main.css",
+                false,
+            ),
+            ("absolute is root relative", &base, "/etc/passwd", true),
+            (
+                "missing files are checked lexically",
+                &base,
+                "img/x.png",
+                true,
+            ),
+        ];
+        for (desc, base, link, inside) in tests {
+            assert_eq!(inside, base.resolve(link).is_ok(), "{}", desc);
+            let uncontained = base.clone().allow_outside();
+            assert!(uncontained.resolve(link).is_ok(), "{}: allowed", desc);
+        }
+        let err = base.resolve("../secret").unwrap_err().to_string();
+        assert!(err.starts_with("../secret: resolves outside"), "{}", err);
+        assert!(base.with_href("../").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_escape() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("site");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(dir.path().join("secret"), "").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("secret"), root.join("link")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), root.join("up")).unwrap();
+        let base = Base::new(&root);
+        assert!(base.resolve("link").is_err());
+        assert!(base.resolve("up/secret").is_err());
+        assert!(base.clone().allow_outside().resolve("link").is_ok());
+    }
+
+    #[test]
+    fn percent_decoding() {
+        let tests = vec![
+            ("plain", "a.css", "a.css"),
+            ("space", "my%20styles.css", "my styles.css"),
+            ("utf-8", "caf%C3%A9.png", "caf\u{e9}.png"),
+            ("lower case hex", "caf%c3%a9.png", "caf\u{e9}.png"),
+            ("plus", "a+b.css", "a+b.css"),
+            ("encoded plus", "a%2Bb.css", "a+b.css"),
+            ("percent", "100%25.png", "100%.png"),
+            ("invalid escape", "100%.png", "100%.png"),
+            ("truncated escape", "a%2", "a%2"),
+            ("not utf-8", "caf%E9.png", "caf%E9.png"),
+        ];
+        for (desc, link, want) in tests {
+            assert_eq!(want, percent_decode(link), "{}", desc);
+        }
+    }
+}
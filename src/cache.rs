@@ -0,0 +1,290 @@
+//! An on-disk cache of the responses fetched over HTTP, so that runs after
+//! the first only revalidate what they fetched before, or fetch nothing at
+//! all while it's fresh.
+
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Cache keeps responses in a directory, a file for each url.
+#[derive(Debug, Clone)]
+pub(crate) struct Cache {
+    dir: PathBuf,
+}
+
+/// Entry is a cached response.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Entry {
+    /// The url the response came from, once redirects were followed.
+    pub url: String,
+    pub media_type: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// When the response was fetched or last revalidated, in seconds since
+    /// the Unix epoch.
+    pub fetched: u64,
+    /// How many seconds after `fetched` the response is fresh for.
+    pub max_age: u64,
+    pub content: Vec<u8>,
+}
+
+impl Cache {
+    pub fn new(dir: &Path) -> Self {
+        Cache {
+            dir: dir.to_owned(),
+        }
+    }
+
+    /// path returns the file the response for `url` is kept in.
+    fn path(&self, url: &str) -> PathBuf {
+        let digest = Sha256::digest(url.as_bytes());
+        let name: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(name)
+    }
+
+    /// get returns the cached response for `url`, or `None` if there isn't
+    /// one or it can't be read.
+    pub fn get(&self, url: &str) -> Option<Entry> {
+        Entry::decode(&fs::read(self.path(url)).ok()?)
+    }
+
+    /// put caches `entry` as the response for `url`. It's written to a file
+    /// of its own first and then renamed into place, so that runs sharing
+    /// the cache never read a half written entry.
+    pub fn put(&self, url: &str, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        let path = self.path(url);
+        fs::create_dir_all(&self.dir).map_err(|e| format!("{}: {}", self.dir.display(), e))?;
+        let temporary = path.with_extension(format!("{}-{}.tmp", process::id(), now_nanos()));
+        fs::write(&temporary, entry.encode())
+            .and_then(|_| fs::rename(&temporary, &path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&temporary);
+                format!("{}: {}", path.display(), e).into()
+            })
+    }
+}
+
+impl Entry {
+    /// is_fresh reports whether the response can be used without asking the
+    /// server at the time `now`.
+    pub fn is_fresh(&self, now: u64) -> bool {
+        now < self.fetched.saturating_add(self.max_age)
+    }
+
+    /// encode writes the entry as header lines, a blank line and the content.
+    fn encode(&self) -> Vec<u8> {
+        let mut header = format!(
+            "url: {}\ncontent-type: {}\nfetched: {}\nmax-age: {}\n",
+            self.url, self.media_type, self.fetched, self.max_age
+        );
+        if let Some(etag) = &self.etag {
+            header.push_str(&format!("etag: {}\n", etag));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            header.push_str(&format!("last-modified: {}\n", last_modified));
+        }
+        header.push('\n');
+        let mut encoded = header.into_bytes();
+        encoded.extend_from_slice(&self.content);
+        encoded
+    }
+
+    fn decode(encoded: &[u8]) -> Option<Entry> {
+        let end = encoded.windows(2).position(|w| w == b"\n\n")?;
+        let header = std::str::from_utf8(&encoded[..end]).ok()?;
+        let mut entry = Entry {
+            url: String::new(),
+            media_type: String::new(),
+            etag: None,
+            last_modified: None,
+            fetched: 0,
+            max_age: 0,
+            content: encoded[end + 2..].to_vec(),
+        };
+        for line in header.lines() {
+            let (name, value) = line.split_once(": ")?;
+            match name {
+                "url" => entry.url = value.to_owned(),
+                "content-type" => entry.media_type = value.to_owned(),
+                "fetched" => entry.fetched = value.parse().ok()?,
+                "max-age" => entry.max_age = value.parse().ok()?,
+                "etag" => entry.etag = Some(value.to_owned()),
+                "last-modified" => entry.last_modified = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+        Some(entry)
+    }
+}
+
+/// max_age returns how many seconds a response is fresh for, from its
+/// `Cache-Control` and `Last-Modified` headers, or `None` if it mustn't be
+/// stored. Without a `max-age`, a response is fresh for a tenth of the time
+/// since it last changed, as browsers guess.
+pub(crate) fn max_age(
+    cache_control: Option<&str>,
+    last_modified: Option<&str>,
+    now: u64,
+) -> Option<u64> {
+    let directives: Vec<String> = cache_control
+        .unwrap_or_default()
+        .split(',')
+        .map(|d| d.trim().to_ascii_lowercase())
+        .collect();
+    if directives.iter().any(|d| d == "no-store") {
+        return None;
+    }
+    if directives.iter().any(|d| d == "no-cache") {
+        return Some(0);
+    }
+    if let Some(age) = directives
+        .iter()
+        .find_map(|d| d.strip_prefix("max-age="))
+        .and_then(|age| age.trim_matches('"').parse().ok())
+    {
+        return Some(age);
+    }
+    let modified = last_modified.and_then(parse_http_date).unwrap_or(now);
+    Some(now.saturating_sub(modified) / 10)
+}
+
+/// now returns the time in seconds since the Unix epoch.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos())
+}
+
+/// parse_http_date parses a date such as `Sun, 06 Nov 1994 08:49:37 GMT`
+/// into seconds since the Unix epoch.
+fn parse_http_date(date: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let fields: Vec<&str> = date.split_whitespace().collect();
+    let (day, month, year, time) = match fields[..] {
+        [_, day, month, year, time, "GMT"] => (day, month, year, time),
+        _ => return None,
+    };
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as i64 + 1;
+    let year: i64 = year.parse().ok()?;
+    let mut time = time.split(':').map(|t| t.parse::<i64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+    // Days since the epoch of the civil date, counting years from March so
+    // that the leap day comes last.
+    let (y, m) = match month <= 2 {
+        true => (year - 1, month + 9),
+        false => (year, month - 3),
+    };
+    // The fields come from the server, so an absurd one gives no date
+    // rather than overflowing.
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = ((153 * m + 2) / 5).checked_add(day)? - 1;
+    let day_of_era =
+        (year_of_era * 365 + year_of_era / 4 - year_of_era / 100).checked_add(day_of_year)?;
+    let days = era
+        .checked_mul(146097)?
+        .checked_add(day_of_era)?
+        .checked_sub(719468)?;
+    let seconds = days
+        .checked_mul(86400)?
+        .checked_add(hours.checked_mul(3600)?)?
+        .checked_add(minutes.checked_mul(60)?)?
+        .checked_add(seconds)?;
+    u64::try_from(seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(&dir.path().join("cache"));
+        let entry = Entry {
+            url: "https://example.com/a.png".into(),
+            media_type: "image/png".into(),
+            etag: Some("\"v1\"".into()),
+            last_modified: None,
+            fetched: 100,
+            max_age: 60,
+            content: b"\x89PNG\n\n\x00".to_vec(),
+        };
+        assert_eq!(None, cache.get("https://example.com/a.png"));
+        cache.put("https://example.com/a.png", &entry).unwrap();
+        assert_eq!(Some(entry.clone()), cache.get("https://example.com/a.png"));
+        assert_eq!(None, cache.get("https://example.com/b.png"));
+        let files = fs::read_dir(dir.path().join("cache")).unwrap().count();
+        assert_eq!(1, files, "no temporary files are left");
+        assert!(entry.is_fresh(159));
+        assert!(!entry.is_fresh(160));
+        let forever = Entry {
+            max_age: u64::MAX,
+            ..entry
+        };
+        assert!(forever.is_fresh(u64::MAX - 1), "max-age doesn't overflow");
+    }
+
+    #[test]
+    fn freshness() {
+        let now = 784111777 + 1000;
+        let tests = vec![
+            ("max-age", Some("public, max-age=3600"), None, Some(3600)),
+            ("no-store", Some("no-store"), None, None),
+            ("no-cache", Some("no-cache, max-age=60"), None, Some(0)),
+            (
+                "last modified",
+                None,
+                Some("Sun, 06 Nov 1994 08:49:37 GMT"),
+                Some(100),
+            ),
+            ("nothing to go on", None, None, Some(0)),
+            (
+                "absurd year",
+                None,
+                Some("Sun, 06 Nov 99999999999999 08:49:37 GMT"),
+                Some(0),
+            ),
+            (
+                "absurd hour",
+                None,
+                Some("Sun, 06 Nov 1994 9999999999999999:49:37 GMT"),
+                Some(0),
+            ),
+        ];
+        for (desc, cache_control, last_modified, want) in tests {
+            assert_eq!(want, max_age(cache_control, last_modified, now), "{}", desc);
+        }
+        assert_eq!(
+            Some(784111777),
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT")
+        );
+        assert_eq!(
+            Some(951782400),
+            parse_http_date("Tue, 29 Feb 2000 00:00:00 GMT")
+        );
+        assert_eq!(None, parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"));
+        for date in [
+            "Sun, 06 Nov 99999999999999 08:49:37 GMT",
+            "Sun, 9223372036854775807 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 9999999999999999:49:37 GMT",
+            "Sun, 06 Nov 1994 08:49:9223372036854775807 GMT",
+        ] {
+            assert_eq!(None, parse_http_date(date), "{}", date);
+        }
+    }
+}
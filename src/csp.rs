@@ -0,0 +1,615 @@
+//! Adjustment of the content security policies of `<meta>` tags, which can
+//! block the inline scripts, inline styles and data urls that inlining
+//! produces.
+
+use crate::css;
+use crate::html::{Dom, Node, NodeRef};
+use crate::inline::Warning;
+use crate::srcset::SrcSet;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// CspMode selects what is done about a content security policy that blocks
+/// the inlined document.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CspMode {
+    /// Leave the policy as it is, warning about what it blocks.
+    #[default]
+    Warn,
+    /// Change the policy as little as allows the inlined document: add
+    /// `data:` to the directives that block data urls, and allow inline
+    /// scripts and styles by the policy's nonce, by hash, or with
+    /// `'unsafe-inline'`, whichever it already uses.
+    Rewrite,
+    /// Remove the `<meta>` tag of the policy.
+    Remove,
+}
+
+impl FromStr for CspMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "warn" => Ok(CspMode::Warn),
+            "rewrite" => Ok(CspMode::Rewrite),
+            "remove" => Ok(CspMode::Remove),
+            _ => Err(format!("unknown csp mode: {}", s)),
+        }
+    }
+}
+
+/// InlineHash is the hash of an inline script or style, as a source of a
+/// content security policy that allows it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlineHash {
+    pub element: String,
+    /// The hash source, such as `'sha256-...'`, quotes included.
+    pub hash: String,
+}
+
+impl fmt::Display for InlineHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{}> {}", self.element, self.hash)
+    }
+}
+
+/// Policy is a parsed content security policy: its directives in the order
+/// they're written. Only the first directive of a name takes effect.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Policy(Vec<Directive>);
+
+/// Directive is a directive of a policy: a name, in lower case, and sources,
+/// which keep their quotes.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Directive {
+    pub name: String,
+    pub sources: Vec<String>,
+}
+
+impl Policy {
+    /// parse parses a serialized policy, skipping empty directives.
+    pub fn parse(policy: &str) -> Self {
+        let directives = policy
+            .split(';')
+            .filter_map(|directive| {
+                let mut tokens = directive.split_ascii_whitespace();
+                Some(Directive {
+                    name: tokens.next()?.to_ascii_lowercase(),
+                    sources: tokens.map(str::to_owned).collect(),
+                })
+            })
+            .collect();
+        Policy(directives)
+    }
+
+    /// effective returns the index of the directive that governs what
+    /// `names` name, the first of which to be present taking effect, or
+    /// `None` if nothing restricts it.
+    fn effective(&self, names: &[&str]) -> Option<usize> {
+        names
+            .iter()
+            .find_map(|name| self.0.iter().position(|d| d.name == *name))
+    }
+
+    /// sources returns the sources of the directive that governs what
+    /// `names` name.
+    fn sources(&self, names: &[&str]) -> Option<&[String]> {
+        self.effective(names).map(|i| self.0[i].sources.as_slice())
+    }
+
+    /// allow adds `source` to the directive that governs what `names` name.
+    /// A fallback directive, the last of `names`, is left as it is for the
+    /// rest of what it governs: the directive before it is added instead,
+    /// with its sources.
+    fn allow(&mut self, names: &[&str], source: &str) {
+        let i = match self.effective(names) {
+            Some(i) if self.0[i].name == names[names.len() - 1] => {
+                let sources = self.0[i].sources.clone();
+                self.0.push(Directive {
+                    name: names[names.len() - 2].to_owned(),
+                    sources,
+                });
+                self.0.len() - 1
+            }
+            Some(i) => i,
+            None => return,
+        };
+        let sources = &mut self.0[i].sources;
+        sources.retain(|s| !s.eq_ignore_ascii_case("'none'"));
+        if !sources.iter().any(|s| s.eq_ignore_ascii_case(source)) {
+            sources.push(source.to_owned());
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, directive) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            f.write_str(&directive.name)?;
+            for source in &directive.sources {
+                write!(f, " {}", source)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Inline is the kind of an inline script or style element.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Inline {
+    Script,
+    Style,
+}
+
+impl Inline {
+    /// directives are the directives governing the element, in the order
+    /// they take effect.
+    fn directives(self) -> &'static [&'static str] {
+        match self {
+            Inline::Script => &["script-src-elem", "script-src", "default-src"],
+            Inline::Style => &["style-src-elem", "style-src", "default-src"],
+        }
+    }
+
+    fn new(node: &Node) -> Option<Self> {
+        let kind = match node.tag_name()?.to_ascii_lowercase().as_str() {
+            "script" if !node.has_attr("src") => Inline::Script,
+            "style" => Inline::Style,
+            _ => return None,
+        };
+        match node.child_text().trim().is_empty() {
+            true => None,
+            false => Some(kind),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Inline::Script => "inline scripts",
+            Inline::Style => "inline styles",
+        }
+    }
+}
+
+/// DATA_DIRECTIVES are the directives governing data urls, with what they
+/// govern.
+const DATA_DIRECTIVES: &[(&str, &str)] = &[
+    ("img-src", "images"),
+    ("font-src", "fonts"),
+    ("media-src", "media"),
+];
+
+/// adjust handles the content security policies of the `<meta>` tags of `dom`
+/// that block any of its inline scripts, inline styles or data urls as `mode`
+/// says.
+pub(crate) fn adjust(dom: &mut Dom, mode: CspMode, warnings: &mut Vec<Warning>) {
+    let metas: Vec<NodeRef> = dom
+        .find_by_tag("meta")
+        .into_iter()
+        .filter(|n| {
+            n.borrow()
+                .attr("http-equiv")
+                .is_some_and(|h| h.trim().eq_ignore_ascii_case("content-security-policy"))
+        })
+        .collect();
+    if metas.is_empty() {
+        return;
+    }
+    let inline: Vec<(Inline, NodeRef)> = dom
+        .find_by_tag("script")
+        .into_iter()
+        .chain(dom.find_by_tag("style"))
+        .filter_map(|n| Some((Inline::new(&n.borrow())?, n.clone())))
+        .collect();
+    let data = data_directives(dom);
+    for meta in metas {
+        let mut policy = Policy::parse(meta.borrow().attr("content").unwrap_or_default());
+        let blocked_inline: Vec<&(Inline, NodeRef)> = inline
+            .iter()
+            .filter(|(kind, n)| !allows_inline(&policy, *kind, &n.borrow()))
+            .collect();
+        let blocked_data: Vec<&(&str, &str)> = DATA_DIRECTIVES
+            .iter()
+            .filter(|(directive, _)| data.contains(directive))
+            .filter(|(directive, _)| !allows_data(&policy, directive))
+            .collect();
+        if blocked_inline.is_empty() && blocked_data.is_empty() {
+            continue;
+        }
+        match mode {
+            CspMode::Warn => {
+                let mut kinds: Vec<Inline> = vec![];
+                for (kind, _) in blocked_inline {
+                    if !kinds.contains(kind) {
+                        kinds.push(*kind);
+                    }
+                }
+                let mut warn = |directive: &str, what: &str| {
+                    warnings.push(Warning {
+                        element: "meta".into(),
+                        link: directive.into(),
+                        reason: format!("the content security policy blocks {}", what),
+                    })
+                };
+                for kind in kinds {
+                    let directive = policy.effective(kind.directives()).unwrap();
+                    warn(&policy.0[directive].name, kind.name());
+                }
+                for (directive, what) in blocked_data {
+                    let effective = policy.effective(&[directive, "default-src"]).unwrap();
+                    warn(&policy.0[effective].name, &format!("data: {}", what));
+                }
+            }
+            CspMode::Rewrite => {
+                for (kind, n) in blocked_inline {
+                    allow_inline(&mut policy, *kind, &mut n.borrow_mut());
+                }
+                for (directive, _) in blocked_data {
+                    policy.allow(&[directive, "default-src"], "data:");
+                }
+                meta.borrow_mut().set_attr("content", policy.to_string());
+            }
+            CspMode::Remove => dom.remove(&meta),
+        }
+    }
+}
+
+/// allows_inline reports whether `policy` allows the inline script or style
+/// element `node`: by its nonce, by the hash of its text, or by
+/// `'unsafe-inline'`, which nonces, hashes and `'strict-dynamic'` disable.
+fn allows_inline(policy: &Policy, kind: Inline, node: &Node) -> bool {
+    let sources = match policy.sources(kind.directives()) {
+        Some(sources) => sources,
+        None => return true,
+    };
+    let nonce = node.attr("nonce").map(|nonce| format!("'nonce-{}'", nonce));
+    let hashes = hashes(&node.child_text());
+    let matches = |source: &String| {
+        nonce.as_ref() == Some(source) || hashes.iter().any(|h| h.eq_ignore_ascii_case(source))
+    };
+    if sources.iter().any(matches) {
+        return true;
+    }
+    let keyword = |keyword: &str| sources.iter().any(|s| s.eq_ignore_ascii_case(keyword));
+    keyword("'unsafe-inline'")
+        && !sources.iter().any(|s| is_nonce(s) || is_hash(s))
+        && !(kind == Inline::Script && keyword("'strict-dynamic'"))
+}
+
+/// allow_inline changes `policy`, or `node`, so that the policy allows the
+/// inline element `node`: with the policy's nonce if it has one, otherwise
+/// with the hash of its text if the policy allows by hash, and otherwise with
+/// `'unsafe-inline'`.
+fn allow_inline(policy: &mut Policy, kind: Inline, node: &mut Node) {
+    let sources = policy.sources(kind.directives()).unwrap_or_default();
+    if let Some(nonce) = sources.iter().find(|s| is_nonce(s)) {
+        let nonce = &nonce["'nonce-".len()..nonce.len() - 1];
+        node.set_attr("nonce", nonce.to_owned());
+    } else if sources.iter().any(|s| is_hash(s)) {
+        let hash = hashes(&node.child_text()).swap_remove(0);
+        policy.allow(kind.directives(), &hash);
+    } else {
+        policy.allow(kind.directives(), "'unsafe-inline'");
+    }
+}
+
+/// allows_data reports whether `policy` allows data urls for what
+/// `directive` governs.
+fn allows_data(policy: &Policy, directive: &str) -> bool {
+    match policy.sources(&[directive, "default-src"]) {
+        Some(sources) => sources.iter().any(|s| s.eq_ignore_ascii_case("data:")),
+        None => true,
+    }
+}
+
+/// data_directives returns the directives governing the data urls of `dom`.
+fn data_directives(dom: &Dom) -> BTreeSet<&'static str> {
+    let mut urls = vec![];
+    for n in dom.find_by_attr("style", None) {
+        let _ = css::rewrite_urls(n.borrow().attr("style").unwrap(), |url| {
+            urls.push(url.to_owned());
+            Ok(None)
+        });
+    }
+    for n in dom.find_by_tag("style") {
+        let _ = css::rewrite_urls(&n.borrow().child_text(), |url| {
+            urls.push(url.to_owned());
+            Ok(None)
+        });
+    }
+    for key in ["src", "href", "poster", "data"] {
+        for n in dom.find_by_attr(key, None) {
+            urls.push(n.borrow().attr(key).unwrap().to_owned());
+        }
+    }
+    for n in dom.find_by_attr("srcset", None) {
+        let srcset = SrcSet::parse(n.borrow().attr("srcset").unwrap());
+        urls.extend(srcset.0.into_iter().map(|candidate| candidate.url));
+    }
+    urls.iter().filter_map(|url| data_directive(url)).collect()
+}
+
+/// data_directive returns the directive governing the data url `url` by its
+/// media type, or `None` if it isn't a data url of an image, font or media.
+fn data_directive(url: &str) -> Option<&'static str> {
+    let url = url.trim();
+    if !url.get(..5)?.eq_ignore_ascii_case("data:") {
+        return None;
+    }
+    let media_type = url[5..]
+        .split([';', ','])
+        .next()?
+        .trim()
+        .to_ascii_lowercase();
+    let (kind, subtype) = media_type.split_once('/')?;
+    match kind {
+        "image" => Some("img-src"),
+        "font" => Some("font-src"),
+        "application" if subtype.starts_with("font-") || subtype.starts_with("x-font-") => {
+            Some("font-src")
+        }
+        "audio" | "video" => Some("media-src"),
+        "text" if subtype == "vtt" => Some("media-src"),
+        _ => None,
+    }
+}
+
+/// inline_hashes returns the SHA-256 hashes of the inline scripts and styles
+/// of `dom`, in document order. Browsers hash the text of an element as it's
+/// written, encoded as UTF-8.
+pub(crate) fn inline_hashes(dom: &Dom) -> Vec<InlineHash> {
+    let found = RefCell::new(vec![]);
+    dom.depth_first(&|n: NodeRef| {
+        let node = n.borrow();
+        let element = match node.tag_name() {
+            Some(name) if name.eq_ignore_ascii_case("script") && !node.has_attr("src") => name,
+            Some(name) if name.eq_ignore_ascii_case("style") => name,
+            _ => return Ok(()),
+        };
+        found.borrow_mut().push(InlineHash {
+            element: element.to_owned(),
+            hash: hash(&node.child_text()),
+        });
+        Ok(())
+    })
+    .expect("hashing never fails");
+    found.into_inner()
+}
+
+/// hash returns the SHA-256 hash source of `text`.
+fn hash(text: &str) -> String {
+    format!("'sha256-{}'", base64::encode(&Sha256::digest(text)))
+}
+
+/// hashes returns the hash sources of `text` for each algorithm a policy can
+/// use, SHA-256 first.
+fn hashes(text: &str) -> Vec<String> {
+    vec![
+        hash(text),
+        format!("'sha384-{}'", base64::encode(&Sha384::digest(text))),
+        format!("'sha512-{}'", base64::encode(&Sha512::digest(text))),
+    ]
+}
+
+fn is_nonce(source: &str) -> bool {
+    let source = source.to_ascii_lowercase();
+    source.starts_with("'nonce-") && source.ends_with('\'') && source.len() > "'nonce-'".len()
+}
+
+fn is_hash(source: &str) -> bool {
+    let source = source.to_ascii_lowercase();
+    ["'sha256-", "'sha384-", "'sha512-"]
+        .iter()
+        .any(|prefix| source.starts_with(prefix))
+        && source.ends_with('\'')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    #[test]
+    fn policies() {
+        let tests = vec![
+            ("empty", "", ""),
+            ("one directive", "img-src 'self'", "img-src 'self'"),
+            (
+                "several sources",
+                "script-src  'self'   https://cdn.example.com 'nonce-abc'",
+                "script-src 'self' https://cdn.example.com 'nonce-abc'",
+            ),
+            (
+                "names are case insensitive, sources keep their case",
+                "IMG-SRC 'Self' Data:",
+                "img-src 'Self' Data:",
+            ),
+            (
+                "empty directives are dropped",
+                ";default-src 'none';; report-uri /csp ;",
+                "default-src 'none'; report-uri /csp",
+            ),
+            (
+                "directive without sources",
+                "upgrade-insecure-requests",
+                "upgrade-insecure-requests",
+            ),
+        ];
+        for (desc, input, want) in tests {
+            assert_eq!(want, Policy::parse(input).to_string(), "{}", desc);
+        }
+        let policy = Policy::parse("img-src 'self'; IMG-SRC data:; default-src *");
+        assert_eq!(
+            Some(&["'self'".to_owned()][..]),
+            policy.sources(&["img-src", "default-src"]),
+            "only the first directive of a name takes effect"
+        );
+        assert_eq!(
+            Some(&["*".to_owned()][..]),
+            policy.sources(&["font-src", "default-src"]),
+            "default-src is the fallback"
+        );
+        assert_eq!(None, policy.sources(&["script-src"]));
+    }
+
+    #[test]
+    fn allow() {
+        let tests = vec![
+            (
+                "added to the directive",
+                "img-src 'self'; report-uri /csp",
+                "img-src 'self' data:; report-uri /csp",
+            ),
+            ("not added twice", "img-src DATA:", "img-src DATA:"),
+            ("replaces 'none'", "img-src 'none'", "img-src data:"),
+            (
+                "the fallback is copied, not widened",
+                "default-src 'self'",
+                "default-src 'self'; img-src 'self' data:",
+            ),
+            ("nothing to allow", "script-src 'self'", "script-src 'self'"),
+        ];
+        for (desc, input, want) in tests {
+            let mut policy = Policy::parse(input);
+            policy.allow(&["img-src", "default-src"], "data:");
+            assert_eq!(want, policy.to_string(), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn inline_elements() {
+        let script = parse("<script>go()</script>").nodes[0].clone();
+        let script = script.borrow();
+        let by_hash = format!("script-src {}", hashes("go()")[0]);
+        let tests = vec![
+            ("no policy", "img-src 'self'", true),
+            ("unsafe-inline", "script-src 'self' 'unsafe-inline'", true),
+            (
+                "unsafe-inline, quoted keywords only",
+                "script-src unsafe-inline",
+                false,
+            ),
+            ("fallback", "default-src 'UNSAFE-INLINE'", true),
+            ("blocked", "script-src 'self'", false),
+            (
+                "disabled by a nonce",
+                "script-src 'unsafe-inline' 'nonce-abc'",
+                false,
+            ),
+            (
+                "disabled by strict-dynamic",
+                "script-src 'unsafe-inline' 'strict-dynamic'",
+                false,
+            ),
+            ("by hash", &by_hash, true),
+            (
+                "elem takes precedence",
+                "script-src-elem 'self'; script-src 'unsafe-inline'",
+                false,
+            ),
+        ];
+        for (desc, policy, want) in tests {
+            let got = allows_inline(&Policy::parse(policy), Inline::Script, &script);
+            assert_eq!(want, got, "{}", desc);
+        }
+        let nonced = parse(r#"<script nonce="abc">go()</script>"#).nodes[0].clone();
+        let policy = Policy::parse("script-src 'nonce-abc'");
+        assert!(allows_inline(&policy, Inline::Script, &nonced.borrow()));
+        assert!(!allows_inline(&policy, Inline::Script, &script));
+    }
+
+    #[test]
+    fn inline_hashes() {
+        let dom = parse(
+            r#"<head><style>p{}</style><script src="app.js"></script></head><script>alert('Hello, world.');</script><script>é</script>"#,
+        );
+        let got: Vec<String> = super::inline_hashes(&dom)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            vec![
+                "<style> 'sha256-gG2yISYereRMiG2lMXrbiUgi0Ubw9p7QCeWcroOvy9Y='",
+                "<script> 'sha256-qznLcsROx4GACP2dm0UCKCzCG+HiZ1guq6ZZDob/Tng='",
+                "<script> 'sha256-SplVfkAzw1Od4utlRyAXytX5VX96BiWgnxw/biumnEw='",
+            ],
+            got
+        );
+    }
+
+    #[test]
+    fn data_urls() {
+        let tests = vec![
+            ("image", "data:image/png;base64,AA", Some("img-src")),
+            ("font", "DATA:font/woff2;base64,AA", Some("font-src")),
+            (
+                "legacy font",
+                "data:application/x-font-ttf;base64,AA",
+                Some("font-src"),
+            ),
+            ("video", "data:video/mp4;base64,AA", Some("media-src")),
+            ("captions", "data:text/vtt,WEBVTT", Some("media-src")),
+            ("other", "data:application/pdf;base64,AA", None),
+            ("not a data url", "logo.png", None),
+        ];
+        for (desc, url, want) in tests {
+            assert_eq!(want, data_directive(url), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn modes() {
+        let input = r#"<head><meta http-equiv="Content-Security-Policy" content="default-src 'self'; script-src 'self'; report-uri /csp"><style>body{background:url(data:image/png;base64,AA)}</style></head><body><script>go()</script><img src="data:image/gif;base64,AA"></body>"#;
+        let tests = vec![
+            (CspMode::Warn, input.to_owned(), 3),
+            (
+                CspMode::Rewrite,
+                input.replace(
+                    "script-src 'self'; report-uri /csp",
+                    "script-src 'self' 'unsafe-inline'; report-uri /csp; style-src 'self' 'unsafe-inline'; img-src 'self' data:",
+                ),
+                0,
+            ),
+            (
+                CspMode::Remove,
+                input.replace(
+                    r#"<meta http-equiv="Content-Security-Policy" content="default-src 'self'; script-src 'self'; report-uri /csp">"#,
+                    "",
+                ),
+                0,
+            ),
+        ];
+        for (mode, want, warned) in tests {
+            let mut dom = parse(input);
+            let mut warnings = vec![];
+            adjust(&mut dom, mode, &mut warnings);
+            assert_eq!(parse(&want), dom, "{:?}", mode);
+            assert_eq!(warned, warnings.len(), "{:?}: {:?}", mode, warnings);
+        }
+    }
+
+    #[test]
+    fn nonces_and_hashes() {
+        let input = r#"<meta http-equiv="content-security-policy" content="script-src 'nonce-r4nd'; style-src 'sha256-x'"><script>go()</script><style>p{}</style>"#;
+        let mut dom = parse(input);
+        adjust(&mut dom, CspMode::Rewrite, &mut vec![]);
+        let want = format!(
+            r#"<meta http-equiv="content-security-policy" content="script-src 'nonce-r4nd'; style-src 'sha256-x' {}"><script nonce="r4nd">go()</script><style>p{{}}</style>"#,
+            hashes("p{}")[0]
+        );
+        assert_eq!(parse(&want), dom);
+        let mut warnings = vec![];
+        adjust(&mut dom, CspMode::Warn, &mut warnings);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+}
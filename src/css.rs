@@ -0,0 +1,552 @@
+use crate::base::Base;
+use crate::encoding;
+use crate::inline::{embed, is_empty_resource, Options, Warning};
+use crate::resource::Resources;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Inline a stylesheet whose links resolve against `base`.
+/// Local `@import`s are replaced by the imported stylesheet, inlined relative
+/// to its own directory, and `url()` resources are embedded as data urls.
+pub(crate) fn inline(
+    css: &str,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+    warnings: &mut Vec<Warning>,
+) -> Result<String, Box<dyn Error>> {
+    inline_stylesheet(css, base, options, resources, &mut vec![], warnings)
+}
+
+/// Inline the stylesheet file at `path`, as `inline` does, where `base` is
+/// the base of the document linking to it.
+pub(crate) fn inline_file(
+    path: &Path,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+    warnings: &mut Vec<Warning>,
+) -> Result<String, Box<dyn Error>> {
+    inline_import(path, base, options, resources, &mut vec![], warnings)
+}
+
+/// inline_import reads and inlines the stylesheet at `path`, relative to its
+/// own directory.
+fn inline_import(
+    path: &Path,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+    ancestors: &mut Vec<PathBuf>,
+    warnings: &mut Vec<Warning>,
+) -> Result<String, Box<dyn Error>> {
+    let content = encoding::read_text(path, None, options, resources)?;
+    ancestors.push(path.to_owned());
+    let inlined = inline_stylesheet(
+        strip_charset(&content),
+        &base.of(path),
+        options,
+        resources,
+        ancestors,
+        warnings,
+    );
+    ancestors.pop();
+    inlined
+}
+
+/// inline_stylesheet inlines `css`, where `ancestors` are the stylesheets
+/// currently being imported. An import of an ancestor would never end, so it
+/// is left as it is. References deeper than `options.max_depth` are left as
+/// they are too, with a warning.
+fn inline_stylesheet(
+    css: &str,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+    ancestors: &mut Vec<PathBuf>,
+    warnings: &mut Vec<Warning>,
+) -> Result<String, Box<dyn Error>> {
+    // The references in this stylesheet are one deeper than the stylesheet.
+    let too_deep = ancestors.len() >= options.max_depth;
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    // Imports are only valid before any other rules, so only the prelude of
+    // the stylesheet needs to be scanned for them.
+    loop {
+        let trimmed = skip_comments(rest);
+        out.push_str(&rest[..rest.len() - trimmed.len()]);
+        rest = trimmed;
+        let lower = rest.get(..8).unwrap_or(rest).to_ascii_lowercase();
+        if lower.starts_with("@charset") {
+            let end = rest.find(';').map(|ii| ii + 1).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if lower.starts_with("@import") {
+            let end = rule_end(rest);
+            let rule = &rest[..end];
+            rest = &rest[end..];
+            match Import::parse(rule) {
+                Some(import) if base.resolves(&import.url) && import.is_media_only() => {
+                    let path = base.resolve(&import.url)?;
+                    if ancestors.contains(&path) {
+                        out.push_str(rule);
+                        continue;
+                    }
+                    if too_deep {
+                        base.skip(&import.url);
+                        warnings.push(depth_warning(ancestors, &import.url, options));
+                        out.push_str(rule);
+                        continue;
+                    }
+                    let imported =
+                        inline_import(&path, base, options, resources, ancestors, warnings)?;
+                    if import.condition.is_empty() {
+                        out.push_str(&imported);
+                    } else {
+                        out.push_str(&format!("@media {} {{\n{}\n}}", import.condition, imported));
+                    }
+                }
+                _ => out.push_str(rule),
+            }
+        } else {
+            break;
+        }
+    }
+    out.push_str(&rewrite_urls(rest, |url| {
+        if too_deep && !url.is_empty() && base.resolves(url) {
+            base.skip(url);
+            warnings.push(depth_warning(ancestors, url, options));
+            return Ok(None);
+        }
+        embed_or_warn("style", url, base, options, resources, warnings)
+    })?);
+    Ok(out)
+}
+
+/// embed_or_warn embeds the resource at `url`, as `embed` does, except that
+/// an empty file is left linked with a warning for `element`.
+pub(crate) fn embed_or_warn(
+    element: &str,
+    url: &str,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+    warnings: &mut Vec<Warning>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    match embed(url, None, base, options, resources) {
+        Err(err) if is_empty_resource(&*err) => {
+            warnings.push(Warning {
+                element: element.into(),
+                link: url.into(),
+                reason: err.to_string(),
+            });
+            Ok(None)
+        }
+        embedded => embedded,
+    }
+}
+
+/// depth_warning reports that `url`, referenced by the last of `ancestors`,
+/// is too deep to inline. The reason names the chain of stylesheets leading
+/// to it.
+fn depth_warning(ancestors: &[PathBuf], url: &str, options: &Options) -> Warning {
+    let mut chain: Vec<String> = ancestors.iter().map(|p| p.display().to_string()).collect();
+    chain.push(url.to_owned());
+    Warning {
+        element: "style".into(),
+        link: url.into(),
+        reason: format!(
+            "exceeds the maximum depth of {}: {}",
+            options.max_depth,
+            chain.join(" -> ")
+        ),
+    }
+}
+
+/// Import is a parsed `@import` rule.
+#[derive(Debug, PartialEq)]
+struct Import {
+    url: String,
+    /// Everything after the url: media queries, `layer` or `supports()`.
+    condition: String,
+}
+
+impl Import {
+    /// parse an `@import` rule, including the terminating semicolon if any.
+    fn parse(rule: &str) -> Option<Self> {
+        let rest = rule.get("@import".len()..)?.trim_start();
+        let (url, rest) = if rest.to_ascii_lowercase().starts_with("url(") {
+            let arg = &rest["url(".len()..];
+            let (start, end) = url_argument(arg)?;
+            let close = arg[end..].find(')')? + end;
+            (&arg[start..end], &arg[close + 1..])
+        } else {
+            let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let end = rest[1..].find(quote)? + 1;
+            (&rest[1..end], &rest[end + 1..])
+        };
+        Some(Import {
+            url: url.to_owned(),
+            condition: rest.trim().trim_end_matches(';').trim().to_owned(),
+        })
+    }
+
+    /// is_media_only reports whether the import is conditional only on media
+    /// queries, which can be expressed by wrapping the content in `@media`.
+    fn is_media_only(&self) -> bool {
+        let condition = self.condition.to_ascii_lowercase();
+        !condition.starts_with("layer") && !condition.starts_with("supports(")
+    }
+}
+
+/// rule_end returns the byte offset just past the semicolon ending the at-rule
+/// that `css` starts with, ignoring semicolons inside quotes.
+fn rule_end(css: &str) -> usize {
+    let mut quote = None;
+    for (ii, c) in css.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, ';') => return ii + 1,
+            _ => {}
+        }
+    }
+    css.len()
+}
+
+/// skip_comments returns `css` without leading whitespace and comments.
+fn skip_comments(css: &str) -> &str {
+    let mut rest = css.trim_start();
+    while rest.starts_with("/*") {
+        rest = match rest.find("*/") {
+            Some(end) => rest[end + 2..].trim_start(),
+            None => "",
+        };
+    }
+    rest
+}
+
+/// strip_charset removes the `@charset` rule an imported stylesheet may start
+/// with, since it is only valid at the very start of a stylesheet.
+fn strip_charset(css: &str) -> &str {
+    let css = css.trim_start_matches('\u{feff}');
+    if css.starts_with("@charset") {
+        css.find(';').map(|ii| &css[ii + 1..]).unwrap_or("")
+    } else {
+        css
+    }
+}
+
+/// Rewrite the url of every `url()` function in `css` with the result of `f`.
+/// `f` returns `None` to leave a url as it is. Everything other than the urls,
+/// including their quotes, is copied through untouched, as is `url(` inside
+/// strings and comments, where it isn't a function.
+pub(crate) fn rewrite_urls<F>(css: &str, mut f: F) -> Result<String, Box<dyn Error>>
+where
+    F: FnMut(&str) -> Result<Option<String>, Box<dyn Error>>,
+{
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(open) = find_url_function(rest) {
+        // Copy up to and including the "url(".
+        let (before, after) = rest.split_at(open + "url(".len());
+        out.push_str(before);
+        rest = after;
+        if let Some((start, end)) = url_argument(rest) {
+            out.push_str(&rest[..start]);
+            let url = &rest[start..end];
+            match f(url)? {
+                Some(replacement) => out.push_str(&replacement),
+                None => out.push_str(url),
+            }
+            // The closing quote of a quoted url is copied too, so that it
+            // isn't taken to open a string.
+            let is_quoted = rest[..start].ends_with(['"', '\'']);
+            let (close, after) = rest[end..].split_at(if is_quoted { 1 } else { 0 });
+            out.push_str(close);
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// find_url_function returns the byte offset of the first `url(` in `css`
+/// that isn't the tail of a longer identifier, or inside a string or a
+/// comment. Matching is case insensitive.
+fn find_url_function(css: &str) -> Option<usize> {
+    // An escaped character is part of an identifier too.
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '-' || c == '_' || c == '\\';
+    let mut previous = None;
+    let mut ii = 0;
+    while let Some(c) = css[ii..].chars().next() {
+        let rest = &css[ii..];
+        let is_comment = rest.starts_with("/*");
+        let next = if is_comment {
+            rest[2..].find("*/").map_or(css.len(), |end| ii + end + 4)
+        } else if c == '"' || c == '\'' {
+            string_end(rest).map_or(css.len(), |end| ii + end)
+        } else if c == '\\' {
+            ii + 1 + rest[1..].chars().next().map_or(0, char::len_utf8)
+        } else if !previous.is_some_and(is_identifier)
+            && rest
+                .get(.."url(".len())
+                .is_some_and(|s| s.eq_ignore_ascii_case("url("))
+        {
+            return Some(ii);
+        } else {
+            ii + c.len_utf8()
+        };
+        // A comment separates tokens as whitespace does.
+        previous = Some(if is_comment { ' ' } else { c });
+        ii = next;
+    }
+    None
+}
+
+/// string_end returns the byte offset just past the end of the string `css`
+/// starts with, or `None` if it's never closed.
+fn string_end(css: &str) -> Option<usize> {
+    let quote = css.chars().next()?;
+    let mut escaped = false;
+    for (ii, c) in css.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == quote => return Some(ii + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// url_argument returns the byte range of the url inside the argument of a
+/// `url(` function, which `css` starts just after. Quotes are not part of the
+/// range. Returns `None` if the argument is unterminated.
+fn url_argument(css: &str) -> Option<(usize, usize)> {
+    let start = css.len() - css.trim_start().len();
+    let body = &css[start..];
+    match body.chars().next()? {
+        '"' | '\'' => string_end(body).map(|end| (start + 1, start + end - 1)),
+        _ => {
+            let end = body.find(|c: char| c == ')' || c.is_whitespace())?;
+            Some((start, start + end))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::Filesystem;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn rewrite() {
+        let tests = vec![
+            ("unquoted", "a:url(x.png)", "a:url(X.PNG)"),
+            ("single quoted", "a:url('x.png')", "a:url('X.PNG')"),
+            ("double quoted", r#"a:url("x.png")"#, r#"a:url("X.PNG")"#),
+            (
+                "all forms in one declaration block",
+                r#"background: url('a.png'), url("b.png") no-repeat; mask-image: url(c.svg);"#,
+                r#"background: url('A.PNG'), url("B.PNG") no-repeat; mask-image: url(C.SVG);"#,
+            ),
+            (
+                "whitespace inside parens",
+                "a: url(  x.png  )",
+                "a: url(  X.PNG  )",
+            ),
+            ("case insensitive function", "a:URL(x.png)", "a:URL(X.PNG)"),
+            (
+                "quoted url with parens",
+                "a:url('x(1).png')",
+                "a:url('X(1).PNG')",
+            ),
+            (
+                "escaped quote",
+                r"a:url('it\'s.png')",
+                r"a:url('IT\'S.PNG')",
+            ),
+            ("not a url function", "a:myurl(x.png)", "a:myurl(x.png)"),
+            (
+                "cursor with fallbacks",
+                "a{cursor: url(grab.png) 4 4, url('grab.cur'), grab;}",
+                "a{cursor: url(GRAB.PNG) 4 4, url('GRAB.CUR'), grab;}",
+            ),
+            (
+                "list style image",
+                "ul{list-style-image: url(\"bullet.svg\")}",
+                "ul{list-style-image: url(\"BULLET.SVG\")}",
+            ),
+            (
+                "border image source",
+                "a{border-image-source: url(frame.png); border-image: url(frame.png) 30 round}",
+                "a{border-image-source: url(FRAME.PNG); border-image: url(FRAME.PNG) 30 round}",
+            ),
+            (
+                "mask image",
+                "a{-webkit-mask-image: url(mask.svg#m), linear-gradient(red, blue)}",
+                "a{-webkit-mask-image: url(MASK.SVG#M), linear-gradient(red, blue)}",
+            ),
+            (
+                "background shorthand",
+                "a{background: #fff url(\"bg.png\") no-repeat center / cover, url(top.png) repeat-x}",
+                "a{background: #fff url(\"BG.PNG\") no-repeat center / cover, url(TOP.PNG) repeat-x}",
+            ),
+            (
+                "generated content",
+                "a::before{content: url(icon.png) \" url(label)\"}",
+                "a::before{content: url(ICON.PNG) \" url(label)\"}",
+            ),
+            (
+                "url in a string",
+                r#"a::after{content: "see url(x.png)"; b: 'it\'s url(y.png)'}"#,
+                r#"a::after{content: "see url(x.png)"; b: 'it\'s url(y.png)'}"#,
+            ),
+            (
+                "commented out",
+                "a{/* background: url(old.png); */ background: url(new.png)}",
+                "a{/* background: url(old.png); */ background: url(NEW.PNG)}",
+            ),
+            (
+                "url after a comment",
+                "a{b:/**/url(x.png)}",
+                "a{b:/**/url(X.PNG)}",
+            ),
+            ("unterminated comment", "a{} /* url(x.png)", "a{} /* url(x.png)"),
+            ("escaped identifier", r"a{b:my\url(x.png)}", r"a{b:my\url(x.png)}"),
+            ("unterminated", "a:url('x.png", "a:url('x.png"),
+            ("no urls", "color: red", "color: red"),
+        ];
+        for (desc, input, want) in tests {
+            let got = rewrite_urls(input, |url| Ok(Some(url.to_uppercase()))).unwrap();
+            assert_eq!(want, got, "{}", desc);
+        }
+    }
+
+    #[test]
+    fn rewrite_skipped() {
+        let input = "a:url(data:image/png;base64,AAAA), url(x.png)";
+        let got = rewrite_urls(input, |url| {
+            Ok(if url.starts_with("data:") {
+                None
+            } else {
+                Some("y.png".into())
+            })
+        })
+        .unwrap();
+        assert_eq!("a:url(data:image/png;base64,AAAA), url(y.png)", got);
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join("style")
+            .join(name)
+    }
+
+    #[test]
+    fn parse_import() {
+        let tests = vec![
+            ("quoted", r#"@import "a.css";"#, Some(("a.css", ""))),
+            ("single quoted", "@import 'a.css';", Some(("a.css", ""))),
+            ("url function", "@import url(a.css);", Some(("a.css", ""))),
+            (
+                "quoted url function with media",
+                r#"@import url("a.css") screen and (min-width: 800px);"#,
+                Some(("a.css", "screen and (min-width: 800px)")),
+            ),
+            (
+                "layer",
+                r#"@import "a.css" layer(base);"#,
+                Some(("a.css", "layer(base)")),
+            ),
+            ("no url", "@import ;", None),
+        ];
+        for (desc, input, want) in tests {
+            let want = want.map(|(url, condition)| Import {
+                url: url.into(),
+                condition: condition.into(),
+            });
+            assert_eq!(want, Import::parse(input), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn imports() {
+        let print = "@import 'print.css' print;\n.a{}";
+        let got = inline(
+            print,
+            &Base::new(&fixture("css")),
+            &Options::default(),
+            &Filesystem,
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!("@media print {\nbody{color:black}\n}\n.a{}", got);
+        let remote = "@import url(https://example.com/a.css);\n@import 'print.css' layer(x);";
+        let got = inline(
+            remote,
+            &Base::new(&fixture("css")),
+            &Options::default(),
+            &Filesystem,
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(remote, got, "remote and layered imports are left alone");
+    }
+
+    #[test]
+    fn import_cycle() {
+        let got = inline_file(
+            &fixture("css/a.css"),
+            &Base::new(&fixture("")),
+            &Options::default(),
+            &Filesystem,
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!("@import \"a.css\";\n.b{color:blue}\n.a{color:red}", got);
+    }
+
+    #[test]
+    fn max_depth() {
+        let options = |max_depth| Options {
+            max_depth,
+            ..Options::default()
+        };
+        let mut warnings = vec![];
+        let got = inline_file(
+            &fixture("depth/1.css"),
+            &Base::new(&fixture("")),
+            &options(3),
+            &Filesystem,
+            &mut warnings,
+        )
+        .unwrap();
+        assert_eq!(".c{}\n.b{}\n.a{}", got, "at the limit");
+        assert!(warnings.is_empty());
+
+        let got = inline_file(
+            &fixture("depth/1.css"),
+            &Base::new(&fixture("")),
+            &options(2),
+            &Filesystem,
+            &mut warnings,
+        )
+        .unwrap();
+        assert_eq!("@import '3.css';\n.b{}\n.a{}", got, "one past the limit");
+        assert_eq!(1, warnings.len());
+        assert_eq!("3.css", warnings[0].link);
+        let reason = &warnings[0].reason;
+        assert!(reason.starts_with("exceeds the maximum depth of 2: "));
+        assert!(
+            reason.contains("1.css -> ") && reason.ends_with("2.css -> 3.css"),
+            "{}",
+            reason
+        );
+    }
+}
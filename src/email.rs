@@ -0,0 +1,382 @@
+//! Inlining of stylesheet rules into the `style` attributes of the elements
+//! they match, for email clients that ignore `<style>` elements.
+//!
+//! Only rules whose selectors `html::Selector` supports are inlined. At-rules,
+//! such as media queries, and rules with pseudo-classes or other unsupported
+//! selectors are left in the `<style>` element they came from. Declarations
+//! are merged property by property, so a shorthand doesn't override the
+//! longhands it covers, nor a longhand the shorthand.
+
+use crate::html::{Dom, Selector, Tree};
+use crate::inline::set_text;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Rule is a style rule the selectors of which are all supported.
+struct Rule {
+    selectors: Vec<Selector>,
+    declarations: Vec<Declaration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Declaration {
+    property: String,
+    value: String,
+    important: bool,
+}
+
+/// Precedence orders the declarations that apply to an element: important
+/// declarations win, then those of the `style` attribute, then those of the
+/// more specific selector, then those that come later.
+type Precedence = (bool, bool, (usize, usize, usize), usize);
+
+/// inline_styles moves the rules of the document's `<style>` elements into
+/// the `style` attributes of the elements they match, removing the `<style>`
+/// elements that are left empty. Styles for media other than screens are
+/// left as they are.
+pub(crate) fn inline_styles(dom: &mut Dom) {
+    let mut rules = vec![];
+    let mut empty = vec![];
+    for style in dom.find_by_tag("style") {
+        if !is_screen(style.borrow().attr("media")) {
+            continue;
+        }
+        let (inlined, residual) = split_rules(&style.borrow().child_text());
+        rules.extend(inlined);
+        match residual.trim() {
+            "" => empty.push(style),
+            residual => set_text(&mut style.borrow_mut(), residual.to_owned()),
+        }
+    }
+    let tree = Tree::new(dom);
+    let mut matched: HashMap<*const _, Vec<(Precedence, Declaration)>> = HashMap::new();
+    let mut order = 0;
+    for rule in &rules {
+        for selector in &rule.selectors {
+            for n in tree.elements.iter().filter(|n| selector.matches(n, &tree)) {
+                let applied = matched.entry(Rc::as_ptr(n)).or_default();
+                for declaration in &rule.declarations {
+                    order += 1;
+                    let precedence = (declaration.important, false, selector.specificity(), order);
+                    applied.push((precedence, declaration.clone()));
+                }
+            }
+        }
+    }
+    for n in &tree.elements {
+        let applied = match matched.get_mut(&Rc::as_ptr(n)) {
+            Some(applied) => applied,
+            None => continue,
+        };
+        let mut node = n.borrow_mut();
+        for declaration in declarations(node.attr("style").unwrap_or_default()) {
+            order += 1;
+            applied.push(((declaration.important, true, (0, 0, 0), order), declaration));
+        }
+        applied.sort_by_key(|(precedence, _)| *precedence);
+        node.set_attr("style", cascade(applied));
+    }
+    for style in empty {
+        dom.remove(&style);
+    }
+}
+
+/// cascade returns the declaration that wins for each property, of those
+/// `applied` in order of precedence, as a `style` attribute.
+fn cascade(applied: &[(Precedence, Declaration)]) -> String {
+    // The winner of a property is written after the declarations it beat,
+    // so that a shorthand it overrides can't reset it, as `margin` would a
+    // `margin-top` written before it.
+    let mut properties: Vec<&Declaration> = vec![];
+    for (_, declaration) in applied {
+        properties.retain(|d| d.property != declaration.property);
+        properties.push(declaration);
+    }
+    properties
+        .iter()
+        .map(|d| match d.important {
+            true => format!("{}: {} !important", d.property, d.value),
+            false => format!("{}: {}", d.property, d.value),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// is_screen reports whether a `<style>` element with the `media` attribute
+/// `media` applies to screens regardless of their size.
+fn is_screen(media: Option<&str>) -> bool {
+    media.is_none_or(|media| {
+        media.split(',').any(|m| {
+            matches!(
+                m.trim().to_ascii_lowercase().as_str(),
+                "" | "all" | "screen"
+            )
+        })
+    })
+}
+
+/// split_rules returns the style rules of `css` that can be inlined, and the
+/// rest of `css` as it was, less its comments.
+fn split_rules(css: &str) -> (Vec<Rule>, String) {
+    let mut rules = vec![];
+    let mut residual = vec![];
+    let css = strip_comments(css);
+    let mut rest = css.trim_start();
+    while !rest.is_empty() {
+        let end = match rest.starts_with('@') {
+            true => statement_end(rest),
+            false => block_end(rest),
+        };
+        let (statement, after) = rest.split_at(end);
+        rest = after.trim_start();
+        if !statement.starts_with('@') {
+            if let Some(rule) = rule(statement) {
+                rules.push(rule);
+                continue;
+            }
+        }
+        residual.push(statement.trim());
+    }
+    (rules, residual.join("\n"))
+}
+
+/// rule parses the style rule `statement`, or returns `None` if any of its
+/// selectors isn't supported.
+fn rule(statement: &str) -> Option<Rule> {
+    let open = statement.find('{')?;
+    let body = statement[open + 1..].trim_end().strip_suffix('}')?;
+    Some(Rule {
+        selectors: Selector::parse_list(&statement[..open]).ok()?,
+        declarations: declarations(body),
+    })
+}
+
+/// declarations parses the declarations of a rule or `style` attribute,
+/// skipping those that aren't well formed.
+fn declarations(body: &str) -> Vec<Declaration> {
+    split_outside(body, ';')
+        .into_iter()
+        .filter_map(|declaration| {
+            let (property, value) = declaration.split_once(':')?;
+            let property = property.trim().to_ascii_lowercase();
+            let mut value = value.trim();
+            let mut important = false;
+            if let Some(bang) = value.rfind('!') {
+                if value[bang + 1..].trim().eq_ignore_ascii_case("important") {
+                    value = value[..bang].trim_end();
+                    important = true;
+                }
+            }
+            match property.is_empty() || value.is_empty() {
+                true => None,
+                false => Some(Declaration {
+                    property,
+                    value: value.to_owned(),
+                    important,
+                }),
+            }
+        })
+        .collect()
+}
+
+/// split_outside splits `css` at `separator`, except where it's inside
+/// quotes or brackets.
+fn split_outside(css: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    scan(css, |ii, c, depth| {
+        if c == separator && depth == 0 {
+            parts.push(&css[start..ii]);
+            start = ii + c.len_utf8();
+        }
+        false
+    });
+    parts.push(&css[start..]);
+    parts
+}
+
+/// statement_end returns the byte offset just past the at-rule that `css`
+/// starts with, which ends with either a semicolon or a block.
+fn statement_end(css: &str) -> usize {
+    scan(css, |_, c, depth| (c == ';' || c == '}') && depth == 0)
+}
+
+/// block_end returns the byte offset just past the block of the rule that
+/// `css` starts with.
+fn block_end(css: &str) -> usize {
+    scan(css, |_, c, depth| c == '}' && depth == 0)
+}
+
+/// scan calls `f` with each character of `css` outside of quotes, and how
+/// deeply nested in brackets it is, until `f` returns true. It returns the
+/// byte offset just past that character, or the length of `css`.
+fn scan<F>(css: &str, mut f: F) -> usize
+where
+    F: FnMut(usize, char, usize) -> bool,
+{
+    let mut quote = None;
+    let mut depth = 0usize;
+    for (ii, c) in css.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => continue,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '{' | '(' | '[') => depth += 1,
+            (None, '}' | ')' | ']') => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        if f(ii, c, depth) {
+            return ii + c.len_utf8();
+        }
+    }
+    css.len()
+}
+
+/// strip_comments removes the comments from `css`, except those inside
+/// quotes.
+fn strip_comments(css: &str) -> String {
+    let mut stripped = String::with_capacity(css.len());
+    let mut quote = None;
+    let mut rest = css;
+    while let Some(c) = rest.chars().next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if rest.starts_with("/*") => {
+                rest = rest.find("*/").map(|end| &rest[end + 2..]).unwrap_or("");
+                continue;
+            }
+            _ => {}
+        }
+        stripped.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// styles returns the id and `style` attribute of every element in `dom`
+    /// that has a `style` attribute.
+    fn styles(dom: &Dom) -> Vec<(String, String)> {
+        dom.find_by_attr("style", None)
+            .iter()
+            .map(|n| {
+                let node = n.borrow();
+                (
+                    node.attr("id").unwrap_or_default().to_owned(),
+                    node.attr("style").unwrap_or_default().to_owned(),
+                )
+            })
+            .collect()
+    }
+
+    fn inline(input: &str) -> Dom {
+        let mut dom: Dom = input.parse().unwrap();
+        inline_styles(&mut dom);
+        dom
+    }
+
+    #[test]
+    fn specificity() {
+        let tests = vec![
+            (
+                "type",
+                "<style>p { color: red }</style><p id=a>a</p>",
+                vec![("a", "color: red")],
+            ),
+            (
+                "more specific wins",
+                "<style>#a { color: blue } p.x { color: green } p { color: red }</style><p id=a class=x>a</p>",
+                vec![("a", "color: blue")],
+            ),
+            (
+                "later wins at the same specificity",
+                "<style>.x { color: red } .y { color: green }</style><p id=a class='y x'>a</p>",
+                vec![("a", "color: green")],
+            ),
+            (
+                "inline style wins over the sheet",
+                "<style>#a { color: red; margin: 0 }</style><p id=a style='color:blue'>a</p>",
+                vec![("a", "margin: 0; color: blue")],
+            ),
+            (
+                "important wins over inline style",
+                "<style>p { color: red !important }</style><p id=a style='color: blue'>a</p>",
+                vec![("a", "color: red !important")],
+            ),
+            (
+                "inline important wins over important",
+                "<style>#a { color: red !important }</style><p id=a style='color: blue !IMPORTANT'>a</p>",
+                vec![("a", "color: blue !important")],
+            ),
+            (
+                "selector lists",
+                "<style>h1, #b { font-weight: bold }</style><h1 id=a>a</h1><p id=b>b</p><p id=c style='x: y'>c</p>",
+                vec![("a", "font-weight: bold"), ("b", "font-weight: bold"), ("c", "x: y")],
+            ),
+            (
+                "combinators",
+                "<style>div > p + p { color: red } div p { margin: 0 }</style><div><p id=a>a</p><p id=b>b</p></div>",
+                vec![("a", "margin: 0"), ("b", "margin: 0; color: red")],
+            ),
+            (
+                "shorthand then longhand",
+                "<style>p { margin-top: 2px } p.x { margin: 1px } #a { margin-top: 5px }</style><p id=a class=x>a</p>",
+                vec![("a", "margin: 1px; margin-top: 5px")],
+            ),
+            (
+                "quoted semicolons",
+                r#"<style>p { font-family: "a;b", serif; background: url("x;y") }</style><p id=a>a</p>"#,
+                vec![("a", r#"font-family: "a;b", serif; background: url("x;y")"#)],
+            ),
+        ];
+        for (desc, input, want) in tests {
+            let want: Vec<(String, String)> = want
+                .into_iter()
+                .map(|(id, style)| (id.to_owned(), style.to_owned()))
+                .collect();
+            let dom = inline(input);
+            assert_eq!(want, styles(&dom), "{}", desc);
+            assert!(dom.find_by_tag("style").is_empty(), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn residual() {
+        let tests = vec![
+            (
+                "media query left behind",
+                "<style>p { color: red } @media (max-width: 600px) { p { color: blue } }</style><p id=a>a</p>",
+                "@media (max-width: 600px) { p { color: blue } }",
+            ),
+            (
+                "pseudo-class left behind",
+                "<style>a { color: red } /* hover */ a:hover { color: blue }</style><a id=a>a</a>",
+                "a:hover { color: blue }",
+            ),
+            (
+                "at-rule statements",
+                "<style>@import url(x.css); @font-face { font-family: x } p { color: red }</style><p id=a>a</p>",
+                "@import url(x.css);\n@font-face { font-family: x }",
+            ),
+        ];
+        for (desc, input, want) in tests {
+            let dom = inline(input);
+            let sheets = dom.find_by_tag("style");
+            assert_eq!(1, sheets.len(), "{}", desc);
+            assert_eq!(want, sheets[0].borrow().child_text(), "{}", desc);
+            assert_eq!(1, styles(&dom).len(), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn print_styles() {
+        let dom = inline("<style media=print>p { color: red }</style><p>a</p>");
+        assert_eq!(1, dom.find_by_tag("style").len());
+        assert!(styles(&dom).is_empty());
+    }
+}
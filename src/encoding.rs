@@ -0,0 +1,167 @@
+use crate::inline::Options;
+use crate::resource::Resources;
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use std::error::Error;
+use std::path::Path;
+
+/// How far into a document to look for a `<meta>` charset declaration.
+pub(crate) const PRESCAN_LIMIT: usize = 1024;
+
+/// sniff determines the encoding of an html document from its byte order
+/// mark or, failing that, a `<meta>` charset declaration within the first
+/// 1024 bytes. Documents declaring neither are taken to be UTF-8.
+pub(crate) fn sniff(document: &[u8]) -> &'static Encoding {
+    Encoding::for_bom(document)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| prescan(document))
+        .unwrap_or(UTF_8)
+}
+
+/// read_text reads the text resource at `path`. Its encoding is, in order of
+/// precedence, given by a byte order mark, a stylesheet's `@charset` rule,
+/// the `declared` charset label from the referring element, or the
+/// document's encoding from `options`.
+pub(crate) fn read_text(
+    path: &Path,
+    declared: Option<&str>,
+    options: &Options,
+    resources: &dyn Resources,
+) -> Result<String, Box<dyn Error>> {
+    let bytes = resources.read(path)?;
+    let fallback = charset_rule(&bytes)
+        .or_else(|| declared.and_then(|label| Encoding::for_label(label.trim().as_bytes())))
+        .or(options.encoding)
+        .unwrap_or(UTF_8);
+    // `decode` lets a byte order mark override the fallback, and removes it.
+    let (text, _, _) = fallback.decode(&bytes);
+    Ok(text.into_owned())
+}
+
+/// prescan looks for a `charset` in the `<meta>` elements at the start of
+/// `document`, which covers both `<meta charset="...">` and the
+/// `<meta http-equiv="Content-Type" content="...; charset=...">` form.
+fn prescan(document: &[u8]) -> Option<&'static Encoding> {
+    let head = document[..document.len().min(PRESCAN_LIMIT)].to_ascii_lowercase();
+    let mut rest = &head[..];
+    while let Some(start) = find(rest, b"<meta") {
+        let tag = &rest[start + b"<meta".len()..];
+        let end = tag.iter().position(|b| *b == b'>').unwrap_or(tag.len());
+        if let Some(encoding) = charset_parameter(&tag[..end]) {
+            // A document claiming to be UTF-16 would have had a byte order
+            // mark, since the declaration itself was readable as ASCII.
+            return Some(match encoding.name() {
+                "UTF-16LE" | "UTF-16BE" => UTF_8,
+                "x-user-defined" => WINDOWS_1252,
+                _ => encoding,
+            });
+        }
+        rest = &tag[end..];
+    }
+    None
+}
+
+/// charset_parameter returns the encoding named by the first `charset=` in
+/// the attributes of a tag.
+fn charset_parameter(attributes: &[u8]) -> Option<&'static Encoding> {
+    let mut rest = attributes;
+    while let Some(start) = find(rest, b"charset") {
+        rest = trim_start(&rest[start + b"charset".len()..]);
+        if let Some(value) = rest.strip_prefix(b"=") {
+            let value = trim_start(value);
+            let label = match value.first() {
+                Some(quote @ b'"') | Some(quote @ b'\'') => {
+                    let value = &value[1..];
+                    &value[..value.iter().position(|b| b == quote).unwrap_or(value.len())]
+                }
+                _ => {
+                    let end = value
+                        .iter()
+                        .position(|b| b.is_ascii_whitespace() || b";\"'/".contains(b))
+                        .unwrap_or(value.len());
+                    &value[..end]
+                }
+            };
+            return Encoding::for_label(label);
+        }
+    }
+    None
+}
+
+/// charset_rule returns the encoding named by the `@charset` rule a
+/// stylesheet starts with, if any.
+fn charset_rule(css: &[u8]) -> Option<&'static Encoding> {
+    let label = css.strip_prefix(b"@charset \"")?;
+    let end = find(label, b"\";")?;
+    // A stylesheet readable as ASCII can't actually be UTF-16.
+    Encoding::for_label(&label[..end]).map(|encoding| match encoding.name() {
+        "UTF-16LE" | "UTF-16BE" => UTF_8,
+        _ => encoding,
+    })
+}
+
+/// find returns the offset of the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn trim_start(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, UTF_16LE};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn sniff_documents() {
+        let late = format!("<html>{}<meta charset=\"shift_jis\"/>", " ".repeat(1024));
+        let tests: Vec<(&str, &[u8], &'static Encoding)> = vec![
+            ("no declaration", b"<html></html>", UTF_8),
+            (
+                "meta charset",
+                b"<meta charset=\"windows-1252\"/>",
+                WINDOWS_1252,
+            ),
+            ("unquoted", b"<META CHARSET=Shift_JIS>", SHIFT_JIS),
+            (
+                "http-equiv",
+                b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=shift_jis\">",
+                SHIFT_JIS,
+            ),
+            ("unknown label", b"<meta charset=\"klingon\"/>", UTF_8),
+            ("utf-16 label", b"<meta charset=\"utf-16\"/>", UTF_8),
+            (
+                "byte order mark wins",
+                b"\xff\xfe<\x00m\x00e\x00t\x00a\x00",
+                UTF_16LE,
+            ),
+            ("beyond the prescan", late.as_bytes(), UTF_8),
+        ];
+        for (desc, input, want) in tests {
+            assert_eq!(want, sniff(input), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn stylesheet_charset_rule() {
+        let tests: Vec<(&str, &[u8], Option<&'static Encoding>)> = vec![
+            ("rule", b"@charset \"windows-1252\";a{}", Some(WINDOWS_1252)),
+            ("no rule", b"a{}", None),
+            (
+                "single quotes are not a rule",
+                b"@charset 'shift_jis';",
+                None,
+            ),
+            ("utf-16", b"@charset \"utf-16\";", Some(UTF_8)),
+        ];
+        for (desc, input, want) in tests {
+            assert_eq!(want, charset_rule(input), "{}", desc);
+        }
+    }
+}
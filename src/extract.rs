@@ -0,0 +1,292 @@
+//! Extraction, the reverse of inlining: the data urls and the inline scripts
+//! and styles of a bundled document are taken out into files of their own,
+//! which the document then links to.
+
+use crate::base;
+use crate::css;
+use crate::html::{Dom, Node, NodeRef, SerializeMode};
+use crate::mhtml;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Asset is a resource taken out of a document, to be written alongside it
+/// under `name`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Asset {
+    pub name: String,
+    pub media_type: String,
+    pub content: Vec<u8>,
+}
+
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}, {} bytes",
+            self.name,
+            self.media_type,
+            self.content.len()
+        )
+    }
+}
+
+/// Assets collects the resources of a document, once each however often
+/// they turn up.
+#[derive(Default)]
+struct Assets {
+    assets: Vec<Asset>,
+    by_content: HashMap<(String, Vec<u8>), usize>,
+}
+
+impl Assets {
+    /// add returns the name of the asset holding `content`, adding it if
+    /// there isn't one yet.
+    fn add(&mut self, media_type: &str, content: Vec<u8>) -> String {
+        let key = (media_type.to_owned(), content);
+        if let Some(&index) = self.by_content.get(&key) {
+            return self.assets[index].name.clone();
+        }
+        let prefix = prefix(media_type);
+        let n = self
+            .assets
+            .iter()
+            .filter(|a| a.name.starts_with(&format!("{}-", prefix)))
+            .count();
+        let name = format!("{}-{}.{}", prefix, n + 1, extension(media_type));
+        self.assets.push(Asset {
+            name: name.clone(),
+            media_type: key.0.clone(),
+            content: key.1.clone(),
+        });
+        self.by_content.insert(key, self.assets.len() - 1);
+        name
+    }
+
+    /// add_url returns the name of the asset holding the data of `url`, or
+    /// `None` if it isn't a data url. The data urls in a stylesheet are
+    /// taken out of it too.
+    fn add_url(&mut self, url: &str) -> Option<String> {
+        let (header, data) = url.strip_prefix("data:")?.split_once(',')?;
+        let (media_type, content) = match header.strip_suffix(";base64") {
+            Some(media_type) => (media_type, base64::decode(data).ok()?),
+            // The escapes may stand for bytes of any encoding, or none.
+            None => (
+                header,
+                base::percent_decode_bytes(data).unwrap_or_else(|| data.as_bytes().to_vec()),
+            ),
+        };
+        let media_type = match media_type.split(';').next().unwrap_or_default().trim() {
+            "" => "text/plain".to_owned(),
+            media_type => media_type.to_ascii_lowercase(),
+        };
+        let content = match media_type.as_str() {
+            "text/css" => {
+                let text = String::from_utf8(content).ok()?;
+                css::rewrite_urls(&text, |url| Ok(self.add_url(url)))
+                    .ok()?
+                    .into_bytes()
+            }
+            _ => content,
+        };
+        Some(self.add(&media_type, content))
+    }
+}
+
+/// extract takes the data urls and the inline scripts and styles out of the
+/// document `input`. It returns the document, linking to them by name
+/// instead, and the assets to write alongside it. The same content is only
+/// taken out once.
+pub fn extract(input: &str) -> Result<(String, Vec<Asset>), Box<dyn Error>> {
+    let dom: Dom = input.parse()?;
+    let assets = RefCell::new(Assets::default());
+    dom.depth_first(&|n: NodeRef| {
+        mhtml::replace_urls(&mut n.borrow_mut(), &mut |url| {
+            assets.borrow_mut().add_url(url)
+        })
+    })?;
+    let mut assets = assets.into_inner();
+    // Scripts and styles in SVG documents are left as they are, since an
+    // SVG `<link>` doesn't load a stylesheet.
+    let mut foreign = HashSet::new();
+    for svg in dom.find_by_tag("svg") {
+        let svg = svg.borrow();
+        for n in svg
+            .find_by_tag("style")
+            .iter()
+            .chain(&svg.find_by_tag("script"))
+        {
+            foreign.insert(Rc::as_ptr(n));
+        }
+    }
+    for n in dom.find_by_tag("style") {
+        let text = n.borrow().child_text();
+        if foreign.contains(&Rc::as_ptr(&n)) || text.trim().is_empty() {
+            continue;
+        }
+        let name = assets.add("text/css", text.into_bytes());
+        let mut node = n.borrow_mut();
+        if let Node::Tag { attributes, .. } = &*node {
+            let mut attributes = attributes.clone();
+            attributes.insert("rel".into(), "stylesheet".into());
+            attributes.insert("href".into(), name);
+            *node = Node::Tag {
+                name: "link".into(),
+                attributes,
+                children: vec![],
+            };
+        }
+    }
+    for n in dom.find_by_tag("script") {
+        let text = n.borrow().child_text();
+        let mut node = n.borrow_mut();
+        if foreign.contains(&Rc::as_ptr(&n))
+            || !is_javascript(node.attr("type"))
+            || node.has_attr("src")
+            || text.trim().is_empty()
+        {
+            continue;
+        }
+        let name = assets.add("text/javascript", text.into_bytes());
+        node.set_attr("src", name);
+        if let Some(children) = node.children_mut() {
+            children.clear();
+        }
+    }
+    Ok((dom.to_string_with(SerializeMode::Preserve), assets.assets))
+}
+
+/// extract_to extracts the assets of `input` as `extract` does, writing them
+/// to the directory `dir`, which is created if it doesn't exist. It returns
+/// the document and the assets written.
+pub fn extract_to(input: &str, dir: &Path) -> Result<(String, Vec<Asset>), Box<dyn Error>> {
+    let (output, assets) = extract(input)?;
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    for asset in &assets {
+        let path = dir.join(&asset.name);
+        fs::write(&path, &asset.content).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok((output, assets))
+}
+
+/// is_javascript reports whether a `<script>` of the `type` given holds
+/// JavaScript, rather than data such as JSON or an import map.
+pub(crate) fn is_javascript(kind: Option<&str>) -> bool {
+    matches!(
+        kind.map(|kind| kind.trim().to_ascii_lowercase()).as_deref(),
+        None | Some("" | "module" | "text/javascript" | "application/javascript")
+    )
+}
+
+/// prefix returns what an asset of `media_type` is named after.
+fn prefix(media_type: &str) -> &'static str {
+    let (kind, subtype) = media_type.split_once('/').unwrap_or((media_type, ""));
+    match (kind, subtype) {
+        ("text", "css") => "style",
+        ("text" | "application", "javascript") => "script",
+        ("image", _) => "image",
+        ("font", _) | ("application", "font-woff") => "font",
+        ("audio" | "video", _) => "media",
+        _ => "file",
+    }
+}
+
+/// extension returns the extension of an asset of `media_type`, one that
+/// inlining guesses the same media type from.
+fn extension(media_type: &str) -> &'static str {
+    match media_type {
+        "text/css" => "css",
+        "text/javascript" | "application/javascript" => "js",
+        "text/plain" => "txt",
+        "text/html" => "html",
+        "image/jpeg" => "jpg",
+        "image/svg+xml" => "svg",
+        _ => mime_guess::get_mime_extensions_str(media_type)
+            .and_then(|extensions| {
+                extensions
+                    .iter()
+                    .copied()
+                    .find(|ext| mime_guess::from_ext(ext).first_raw() == Some(media_type))
+            })
+            .unwrap_or("bin"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inline::{inline_with, inline_with_resources, Options};
+    use crate::resource::Memory;
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    #[test]
+    fn assets() {
+        let png = base64::encode(b"png");
+        let input = format!(
+            r#"<style media="print">body {{ background: url("data:image/png;base64,{png}") }}</style><img src="data:image/png;base64,{png}" srcset="data:image/jpeg;base64,anBn 2x"><a href="data:text/plain,a%20b">a</a><a href="data:image/gif,GIF%FF%00">b</a><script>go()</script><script type="application/json">{{}}</script><script type="module">go()</script><svg><style>rect {{}}</style></svg>"#,
+            png = png
+        );
+        let (got, assets) = extract(&input).unwrap();
+        let want = r#"<link rel="stylesheet" href="style-1.css" media="print"><img src="image-1.png" srcset="image-2.jpg 2x"><a href="file-1.txt">a</a><a href="image-3.gif">b</a><script src="script-1.js"></script><script type="application/json">{}</script><script type="module" src="script-1.js"></script><svg><style>rect {}</style></svg>"#;
+        assert_eq!(parse(want), parse(&got));
+        let assets: Vec<(&str, &str, &[u8])> = assets
+            .iter()
+            .map(|a| (a.name.as_str(), a.media_type.as_str(), a.content.as_slice()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("image-1.png", "image/png", &b"png"[..]),
+                ("image-2.jpg", "image/jpeg", b"jpg"),
+                ("file-1.txt", "text/plain", b"a b"),
+                ("image-3.gif", "image/gif", b"GIF\xff\x00"),
+                (
+                    "style-1.css",
+                    "text/css",
+                    b"body { background: url(\"image-1.png\") }"
+                ),
+                ("script-1.js", "text/javascript", b"go()"),
+            ],
+            assets
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let base = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join("extract");
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        let (inlined, _) = inline_with(input, &base, &Options::default()).unwrap();
+        let (extracted, assets) = extract(&inlined).unwrap();
+        let names: Vec<&str> = assets.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(
+            vec![
+                "image-1.png",
+                "image-2.jpg",
+                "image-3.jpg",
+                "style-1.css",
+                "script-1.js",
+                "script-2.js",
+            ],
+            names,
+            "the logo is written once"
+        );
+        let resources = assets.into_iter().fold(Memory::new(), |memory, asset| {
+            memory.with(PathBuf::from("out").join(asset.name), asset.content)
+        });
+        let (again, _) =
+            inline_with_resources(extracted, Path::new("out"), &Options::default(), &resources)
+                .unwrap();
+        assert_eq!(parse(&inlined), parse(&again));
+    }
+}
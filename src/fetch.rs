@@ -0,0 +1,375 @@
+//! What is sent with the requests for pages and resources on the web: the
+//! headers, cookies and user agent, and which origins get the credentials.
+
+use crate::base::origin;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// FetchOptions control the requests made for pages and resources on the
+/// web, with the `remote` feature.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FetchOptions {
+    /// Headers sent with requests, such as `Authorization` or `Cookie`.
+    pub headers: Vec<(String, String)>,
+    /// User-Agent sent with every request, in place of the HTTP client's.
+    pub user_agent: Option<String>,
+    /// Cookies sent with requests to the hosts and paths they're for.
+    pub cookies: Vec<Cookie>,
+    /// Origin, such as `https://example.com`, that the headers and cookies
+    /// are only sent to, so that credentials don't leak to the other hosts a
+    /// page links or redirects to. `inline_url` keeps them to the origin of
+    /// the page's url when it's `None`; elsewhere `None` sends them nowhere.
+    pub credentials_origin: Option<String>,
+    /// Send the headers and cookies with every request instead, as far as a
+    /// cookie's domain allows, whatever `credentials_origin` says.
+    pub credentials_everywhere: bool,
+    /// Directory responses are cached in between runs, and revalidated from
+    /// once they're stale. `None` caches nothing.
+    pub cache_dir: Option<PathBuf>,
+    /// Whether responses only come from `cache_dir`, however stale, and it's
+    /// an error for one not to be cached.
+    pub offline: bool,
+}
+
+/// Cookie is a cookie as a Netscape cookie file, which browser extensions
+/// and curl write, holds it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    /// The host the cookie is for, without a leading dot.
+    pub domain: String,
+    /// Whether the cookie is sent to the subdomains of `domain` too.
+    pub include_subdomains: bool,
+    pub path: String,
+    /// Whether the cookie is only sent over https.
+    pub secure: bool,
+    /// When the cookie expires, in seconds since the Unix epoch, or 0 if it
+    /// lasts for the session.
+    pub expires: u64,
+    pub name: String,
+    pub value: String,
+}
+
+impl Cookie {
+    /// parse_file parses the cookies of a Netscape cookie file, one per line
+    /// as seven tab separated fields. Comments and blank lines are skipped,
+    /// but cookies marked `#HttpOnly_` are kept.
+    pub fn parse_file(text: &str) -> Result<Vec<Cookie>, String> {
+        let mut cookies = vec![];
+        for (ii, line) in text.lines().enumerate() {
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+            let (domain, subdomains, path, secure, expires, name, value) = match fields[..] {
+                [domain, subdomains, path, secure, expires, name, value] => {
+                    (domain, subdomains, path, secure, expires, name, value)
+                }
+                _ => return Err(format!("line {}: expected 7 tab separated fields", ii + 1)),
+            };
+            cookies.push(Cookie {
+                domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+                include_subdomains: subdomains.eq_ignore_ascii_case("TRUE"),
+                path: path.to_owned(),
+                secure: secure.eq_ignore_ascii_case("TRUE"),
+                expires: expires
+                    .parse()
+                    .map_err(|e| format!("line {}: parsing expiry: {}", ii + 1, e))?,
+                name: name.to_owned(),
+                value: value.to_owned(),
+            });
+        }
+        Ok(cookies)
+    }
+
+    /// matches reports whether the cookie is sent with a request for `url`
+    /// at the time `now`, in seconds since the Unix epoch.
+    fn matches(&self, url: &str, now: u64) -> bool {
+        let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+        let authority = rest.split(['/', '?']).next().unwrap_or_default();
+        let host = authority
+            .rsplit_once(':')
+            .map_or(authority, |(host, _)| host)
+            .to_ascii_lowercase();
+        let path = match rest.find('/') {
+            Some(ii) => rest[ii..].split('?').next().unwrap_or("/"),
+            None => "/",
+        };
+        let domain = host == self.domain
+            || (self.include_subdomains && host.ends_with(&format!(".{}", self.domain)));
+        domain
+            && self.path_matches(path)
+            && (!self.secure || scheme.eq_ignore_ascii_case("https"))
+            && (self.expires == 0 || self.expires > now)
+    }
+
+    /// path_matches reports whether the cookie's path covers the request
+    /// `path`, as RFC 6265 matches them: the paths are the same, or the
+    /// cookie's is a prefix ending at a `/`, so that a cookie for `/app`
+    /// goes to `/app/x` but not to `/application`.
+    fn path_matches(&self, path: &str) -> bool {
+        match path.strip_prefix(self.path.as_str()) {
+            Some(rest) => rest.is_empty() || self.path.ends_with('/') || rest.starts_with('/'),
+            None => false,
+        }
+    }
+}
+
+/// normalized_origin returns the origin of `url` lowercased, without the
+/// port when it's the default for the scheme, so that `https://example.com`
+/// and `https://EXAMPLE.com:443` are the same origin.
+fn normalized_origin(url: &str) -> String {
+    let origin = origin(url.trim_end_matches('/')).to_ascii_lowercase();
+    for (scheme, port) in [("http://", ":80"), ("https://", ":443")] {
+        if origin.starts_with(scheme) {
+            if let Some(origin) = origin.strip_suffix(port) {
+                return origin.to_owned();
+            }
+        }
+    }
+    origin
+}
+
+impl FetchOptions {
+    /// headers returns the headers to send with a request for `url`, for
+    /// `Resources` that fetch it.
+    pub fn headers(&self, url: &str) -> Vec<(String, String)> {
+        let mut headers = vec![];
+        if let Some(user_agent) = &self.user_agent {
+            headers.push(("User-Agent".to_owned(), user_agent.clone()));
+        }
+        let credentialed = self.credentials_everywhere
+            || self
+                .credentials_origin
+                .as_ref()
+                .is_some_and(|allowed| normalized_origin(allowed) == normalized_origin(url));
+        if !credentialed {
+            return headers;
+        }
+        headers.extend(self.headers.iter().cloned());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let cookies: Vec<String> = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url, now))
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        if cookies.is_empty() {
+            return headers;
+        }
+        let cookies = cookies.join("; ");
+        match headers
+            .iter_mut()
+            .find(|(name, _)| name.eq_ignore_ascii_case("cookie"))
+        {
+            Some((_, value)) => *value = format!("{}; {}", value, cookies),
+            None => headers.push(("Cookie".to_owned(), cookies)),
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn cookie(domain: &str, path: &str, secure: bool, expires: u64) -> Cookie {
+        Cookie {
+            domain: domain.into(),
+            include_subdomains: false,
+            path: path.into(),
+            secure,
+            expires,
+            name: "session".into(),
+            value: "s3cr3t".into(),
+        }
+    }
+
+    #[test]
+    fn cookie_file() {
+        let file = "# Netscape HTTP Cookie File\n\n.example.com\tTRUE\t/\tTRUE\t0\tsession\ts3cr3t\n#HttpOnly_app.example.com\tFALSE\t/admin\tFALSE\t4102444800\tid\t42\n";
+        let want = vec![
+            Cookie {
+                include_subdomains: true,
+                ..cookie("example.com", "/", true, 0)
+            },
+            Cookie {
+                name: "id".into(),
+                value: "42".into(),
+                ..cookie("app.example.com", "/admin", false, 4102444800)
+            },
+        ];
+        assert_eq!(Ok(want), Cookie::parse_file(file));
+        assert_eq!(
+            Err("line 1: expected 7 tab separated fields".to_owned()),
+            Cookie::parse_file("example.com TRUE / FALSE 0 a b")
+        );
+    }
+
+    #[test]
+    fn cookies() {
+        let subdomains = Cookie {
+            include_subdomains: true,
+            ..cookie("example.com", "/", false, 0)
+        };
+        let tests = vec![
+            (
+                "host",
+                cookie("example.com", "/", false, 0),
+                "http://example.com/a",
+                true,
+            ),
+            (
+                "port",
+                cookie("example.com", "/", false, 0),
+                "http://example.com:8080/",
+                true,
+            ),
+            (
+                "other host",
+                cookie("example.com", "/", false, 0),
+                "http://example.org/",
+                false,
+            ),
+            (
+                "subdomain",
+                cookie("example.com", "/", false, 0),
+                "http://www.example.com/",
+                false,
+            ),
+            (
+                "subdomains",
+                subdomains.clone(),
+                "http://www.example.com/",
+                true,
+            ),
+            ("suffix", subdomains, "http://badexample.com/", false),
+            (
+                "path",
+                cookie("example.com", "/app", false, 0),
+                "http://example.com/app/x",
+                true,
+            ),
+            (
+                "same path",
+                cookie("example.com", "/app", false, 0),
+                "http://example.com/app?x=1",
+                true,
+            ),
+            (
+                "other path",
+                cookie("example.com", "/app", false, 0),
+                "http://example.com/",
+                false,
+            ),
+            (
+                "longer path",
+                cookie("example.com", "/app", false, 0),
+                "http://example.com/application",
+                false,
+            ),
+            (
+                "longer path, hyphenated",
+                cookie("example.com", "/app", false, 0),
+                "http://example.com/app-admin/",
+                false,
+            ),
+            (
+                "directory path",
+                cookie("example.com", "/app/", false, 0),
+                "http://example.com/app/x",
+                true,
+            ),
+            (
+                "secure",
+                cookie("example.com", "/", true, 0),
+                "http://example.com/",
+                false,
+            ),
+            (
+                "secure https",
+                cookie("example.com", "/", true, 0),
+                "https://example.com/",
+                true,
+            ),
+            (
+                "expired",
+                cookie("example.com", "/", false, 10),
+                "http://example.com/",
+                false,
+            ),
+            (
+                "unexpired",
+                cookie("example.com", "/", false, 30),
+                "http://example.com/",
+                true,
+            ),
+        ];
+        for (desc, cookie, url, want) in tests {
+            assert_eq!(want, cookie.matches(url, 20), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn headers() {
+        let options = FetchOptions {
+            headers: vec![
+                ("Authorization".into(), "Bearer t0ken".into()),
+                ("Cookie".into(), "theme=dark".into()),
+            ],
+            user_agent: Some("snapshot/1.0".into()),
+            cookies: vec![cookie("example.com", "/", false, 0)],
+            credentials_origin: Some("https://example.com/".into()),
+            ..FetchOptions::default()
+        };
+        let all = vec![
+            ("User-Agent".to_owned(), "snapshot/1.0".to_owned()),
+            ("Authorization".to_owned(), "Bearer t0ken".to_owned()),
+            ("Cookie".to_owned(), "theme=dark; session=s3cr3t".to_owned()),
+        ];
+        let tests = vec![
+            (
+                "credentials origin",
+                "https://example.com/page",
+                all.clone(),
+            ),
+            (
+                "other origin",
+                "https://cdn.example.net/a.png",
+                all[..1].to_vec(),
+            ),
+            ("other scheme", "http://example.com/page", all[..1].to_vec()),
+            ("default port", "https://example.com:443/page", all.clone()),
+            ("host case", "https://EXAMPLE.com/page", all.clone()),
+            (
+                "other port",
+                "https://example.com:8443/page",
+                all[..1].to_vec(),
+            ),
+        ];
+        for (desc, url, want) in tests {
+            assert_eq!(want, options.headers(url), "{}", desc);
+        }
+        let nowhere = FetchOptions {
+            credentials_origin: None,
+            ..options.clone()
+        };
+        assert_eq!(
+            all[..1].to_vec(),
+            nowhere.headers("https://example.com/page"),
+            "without an origin, credentials go nowhere"
+        );
+        let everywhere = FetchOptions {
+            credentials_everywhere: true,
+            ..nowhere
+        };
+        assert_eq!(
+            all[..2].to_vec(),
+            everywhere.headers("https://cdn.example.net/a.png")[..2].to_vec(),
+            "everywhere, headers go to other origins too"
+        );
+    }
+}
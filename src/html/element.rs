@@ -0,0 +1,259 @@
+use super::parse::{Dom, Node, NodeRef};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+impl Node {
+    /// tag_name returns the name of the element, or `None` if the node isn't
+    /// an element.
+    pub fn tag_name(&self) -> Option<&str> {
+        match self {
+            Node::Tag { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    /// is_element reports whether the node is an element named `name`,
+    /// ignoring ASCII case.
+    pub fn is_element(&self, name: &str) -> bool {
+        self.tag_name()
+            .is_some_and(|tag| tag.eq_ignore_ascii_case(name))
+    }
+
+    /// attr returns the value of the attribute `name`, ignoring ASCII case,
+    /// or `None` if the node isn't an element or hasn't got the attribute.
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        match self {
+            Node::Tag { attributes, .. } => attributes
+                .get(name)
+                .or_else(|| {
+                    attributes
+                        .iter()
+                        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                        .map(|(_, value)| value)
+                })
+                .map(String::as_str),
+            _ => None,
+        }
+    }
+
+    /// has_attr reports whether the node is an element with the attribute
+    /// `name`, ignoring ASCII case.
+    pub fn has_attr(&self, name: &str) -> bool {
+        self.attr(name).is_some()
+    }
+
+    /// set_attr sets the attribute `name` to `value`, replacing the value of
+    /// an attribute of the same name in any case. It does nothing if the node
+    /// isn't an element.
+    pub fn set_attr(&mut self, name: &str, value: impl Into<String>) {
+        if let Node::Tag { attributes, .. } = self {
+            let key = key(attributes.keys(), name).unwrap_or(name).to_owned();
+            attributes.insert(key, value.into());
+        }
+    }
+
+    /// remove_attr removes the attribute `name`, ignoring ASCII case, and
+    /// returns its value if it had one.
+    pub fn remove_attr(&mut self, name: &str) -> Option<String> {
+        match self {
+            Node::Tag { attributes, .. } => {
+                let key = key(attributes.keys(), name)?.to_owned();
+                attributes.remove(&key)
+            }
+            _ => None,
+        }
+    }
+
+    /// children returns the children of the element, which a node that isn't
+    /// an element hasn't got.
+    pub fn children(&self) -> &[NodeRef] {
+        match self {
+            Node::Tag { children, .. } => children,
+            _ => &[],
+        }
+    }
+
+    /// children_mut returns the children of the element, or `None` if the
+    /// node isn't an element.
+    pub fn children_mut(&mut self) -> Option<&mut Vec<NodeRef>> {
+        match self {
+            Node::Tag { children, .. } => Some(children),
+            _ => None,
+        }
+    }
+
+    /// child_text returns the text of the element's children, as it's
+    /// written, such as the content of a `<script>` or `<style>`. Only its
+    /// text children count, not the text of their descendants.
+    pub(crate) fn child_text(&self) -> String {
+        self.children()
+            .iter()
+            .filter_map(|c| match &*c.borrow() {
+                Node::Text(text) => Some(text.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// template_content returns the content of a `<template>` element, which
+    /// is parsed into its children but stays inert until a script puts a copy
+    /// of it in the document, or `None` if the node isn't a template.
+    pub fn template_content(&self) -> Option<&[NodeRef]> {
+        match self.is_element("template") {
+            true => Some(self.children()),
+            false => None,
+        }
+    }
+}
+
+impl Dom {
+    /// remove removes `node` from the document, wherever it is in the tree.
+    pub(crate) fn remove(&mut self, node: &NodeRef) {
+        self.nodes.retain(|n| !Rc::ptr_eq(n, node));
+        let mut stack = self.nodes.clone();
+        while let Some(n) = stack.pop() {
+            if let Some(children) = n.borrow_mut().children_mut() {
+                children.retain(|c| !Rc::ptr_eq(c, node));
+                stack.extend(children.iter().cloned());
+            }
+        }
+    }
+
+    /// template_content returns the addresses of the nodes inside the
+    /// `<template>` elements of the document, so that walks over the whole
+    /// document can tell inert nodes from live ones.
+    pub(crate) fn template_content(&self) -> HashSet<*const RefCell<Node>> {
+        let mut content = HashSet::new();
+        let mut stack: Vec<(NodeRef, bool)> =
+            self.nodes.iter().map(|n| (n.clone(), false)).collect();
+        while let Some((n, inert)) = stack.pop() {
+            if inert {
+                content.insert(Rc::as_ptr(&n));
+            }
+            let node = n.borrow();
+            let inert = inert || node.is_element("template");
+            stack.extend(node.children().iter().map(|c| (c.clone(), inert)));
+        }
+        content
+    }
+}
+
+/// key returns the key in `keys` that is `name`, ignoring ASCII case,
+/// preferring an exact match.
+fn key<'a>(mut keys: impl Iterator<Item = &'a String> + Clone, name: &str) -> Option<&'a str> {
+    keys.clone()
+        .find(|key| *key == name)
+        .or_else(|| keys.find(|key| key.eq_ignore_ascii_case(name)))
+        .map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Parser, StrTokenizer};
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// element parses the first node of `input`, keeping the case of names,
+    /// as XML is, to check that the accessors ignore it.
+    fn element(input: &str) -> Node {
+        let dom = Parser::new(StrTokenizer::new(input).merged())
+            .normalize_case(false)
+            .parse()
+            .expect("parsing dom");
+        dom.nodes[0].replace(Node::Text(String::new()))
+    }
+
+    #[test]
+    fn accessors() {
+        let img = element(r#"<IMG SRC="a.png" alt="a"/>"#);
+        let tests = vec![
+            ("tag name", img.tag_name(), Some("IMG")),
+            ("attribute", img.attr("alt"), Some("a")),
+            ("attribute, ignoring case", img.attr("src"), Some("a.png")),
+            ("missing attribute", img.attr("srcset"), None),
+        ];
+        for (desc, got, want) in tests {
+            assert_eq!(want, got, "{}", desc);
+        }
+        assert!(img.is_element("img"));
+        assert!(img.is_element("IMG"));
+        assert!(!img.is_element("image"));
+        assert!(img.has_attr("Src"));
+        assert!(!img.has_attr("href"));
+    }
+
+    #[test]
+    fn mutators() {
+        let mut img = element(r#"<img SRC="a.png" alt="a"/>"#);
+        img.set_attr("src", "b.png");
+        assert_eq!(Some("b.png"), img.attr("src"));
+        img.set_attr("title", String::from("t"));
+        assert_eq!(Some("t"), img.attr("TITLE"));
+        assert_eq!(
+            element(r#"<img SRC="b.png" alt="a" title="t"/>"#),
+            img,
+            "set_attr keeps the case the attribute has"
+        );
+        assert_eq!(Some("b.png".to_owned()), img.remove_attr("Src"));
+        assert_eq!(None, img.remove_attr("src"));
+        assert_eq!(element(r#"<img alt="a" title="t"/>"#), img);
+    }
+
+    #[test]
+    fn child_text() {
+        assert_eq!("a{}", element("<style>a{}</style>").child_text());
+        let p = element("<p>a<!-- b --><b>bold</b>c</p>");
+        assert_eq!("ac", p.child_text());
+    }
+
+    #[test]
+    fn remove() {
+        let mut dom: Dom = "<p>a</p><div><p>b<span>c</span></p></div>"
+            .parse()
+            .expect("parsing dom");
+        let spans = dom.find_by_tag("span");
+        let ps = dom.find_by_tag("p");
+        dom.remove(&spans[0]);
+        dom.remove(&ps[0]);
+        assert_eq!("<div><p>b</p></div>\n", dom.to_string());
+    }
+
+    #[test]
+    fn template_content() {
+        let template =
+            element(r#"<template id="row"><tr><td>a<td><img src="x.svg"></tr></template>"#);
+        let content = template.template_content().expect("template content");
+        assert_eq!(1, content.len());
+        assert!(content[0].borrow().is_element("tr"));
+        assert_eq!(2, content[0].borrow().children().len());
+        let div = element("<div><p>text</p></div>");
+        assert_eq!(None, div.template_content());
+        let dom: Dom = "<div><template><p>a<b>b</b></p></template><p>c</p></div>"
+            .parse()
+            .expect("parsing dom");
+        let content = dom.template_content();
+        let inert: Vec<String> = ["template", "p", "b"]
+            .iter()
+            .flat_map(|tag| dom.find_by_tag(tag))
+            .filter(|n| content.contains(&Rc::as_ptr(n)))
+            .map(|n| n.borrow().tag_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(vec!["p", "b"], inert);
+    }
+
+    #[test]
+    fn text() {
+        let mut text = Node::Text("text".into());
+        assert_eq!(None, text.tag_name());
+        assert!(!text.is_element("text"));
+        assert_eq!(None, text.attr("src"));
+        assert!(!text.has_attr("src"));
+        text.set_attr("src", "a.png");
+        assert_eq!(None, text.remove_attr("src"));
+        assert!(text.children().is_empty());
+        assert!(text.children_mut().is_none());
+        assert!(text.template_content().is_none());
+        assert_eq!(Node::Text("text".into()), text);
+    }
+}
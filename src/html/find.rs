@@ -0,0 +1,172 @@
+use super::parse::{visit_nodes, Dom, Node, NodeRef};
+use std::cell::RefCell;
+
+impl Dom {
+    /// find_by_tag returns the elements named `tag`, ignoring case, in
+    /// document order.
+    pub fn find_by_tag(&self, tag: &str) -> Vec<NodeRef> {
+        find_all(&self.nodes, |node| has_tag(node, tag))
+    }
+
+    /// find_by_id returns the first element whose id is `id`.
+    pub fn find_by_id(&self, id: &str) -> Option<NodeRef> {
+        self.find_by_attr("id", Some(id)).into_iter().next()
+    }
+
+    /// find_by_attr returns the elements that have the attribute `name`, in
+    /// document order. With a `value`, the attribute must be exactly `value`.
+    pub fn find_by_attr(&self, name: &str, value: Option<&str>) -> Vec<NodeRef> {
+        find_all(&self.nodes, |node| has_attr(node, name, value))
+    }
+}
+
+impl Node {
+    /// find_by_tag returns the descendants named `tag`, ignoring case, in
+    /// document order.
+    pub fn find_by_tag(&self, tag: &str) -> Vec<NodeRef> {
+        find_all(self.children(), |node| has_tag(node, tag))
+    }
+
+    /// find_by_id returns the first descendant whose id is `id`.
+    pub fn find_by_id(&self, id: &str) -> Option<NodeRef> {
+        self.find_by_attr("id", Some(id)).into_iter().next()
+    }
+
+    /// find_by_attr returns the descendants that have the attribute `name`,
+    /// in document order. With a `value`, the attribute must be exactly
+    /// `value`.
+    pub fn find_by_attr(&self, name: &str, value: Option<&str>) -> Vec<NodeRef> {
+        find_all(self.children(), |node| has_attr(node, name, value))
+    }
+}
+
+/// find_all walks `nodes` depth first and returns those that match.
+fn find_all<P>(nodes: &[NodeRef], matches: P) -> Vec<NodeRef>
+where
+    P: Fn(&Node) -> bool,
+{
+    let found = RefCell::new(vec![]);
+    visit_nodes(nodes, &|n: NodeRef| {
+        if matches(&n.borrow()) {
+            found.borrow_mut().push(n.clone());
+        }
+        Ok(())
+    })
+    .expect("finding nodes never fails");
+    found.into_inner()
+}
+
+fn has_tag(node: &Node, tag: &str) -> bool {
+    node.is_element(tag)
+}
+
+/// has_attr reports whether `node` has the attribute `name`, ignoring case,
+/// with the value `value` if there is one.
+fn has_attr(node: &Node, name: &str, value: Option<&str>) -> bool {
+    node.attr(name)
+        .is_some_and(|v| value.is_none_or(|value| v == value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Parser, StrTokenizer};
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// parse parses `input` keeping the case of names, as XML is, to check
+    /// that finding ignores it.
+    fn parse(input: &str) -> Dom {
+        Parser::new(StrTokenizer::new(input).merged())
+            .normalize_case(false)
+            .parse()
+            .expect("parsing dom")
+    }
+
+    /// names returns a description of each node, to compare what was found.
+    fn names(nodes: &[NodeRef]) -> Vec<String> {
+        nodes
+            .iter()
+            .map(|n| match &*n.borrow() {
+                Node::Tag {
+                    name, attributes, ..
+                } => match attributes.get("id") {
+                    Some(id) => format!("{}#{}", name, id),
+                    None => name.clone(),
+                },
+                node => node.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn find() {
+        let dom = parse(
+            r#"
+            <div id="app">
+                <IMG id="logo" src="logo.png"/>
+                <nav data-role="nav" id="top">
+                    <img id="home" src="home.png"/>
+                    <a data-role="link" href="/">Home</a>
+                </nav>
+            </div>
+            <img id="footer" src="footer.png"/>
+            <nav DATA-ROLE="nav" id="bottom"></nav>
+            "#,
+        );
+        let tests = vec![
+            (
+                "tag, ignoring case",
+                dom.find_by_tag("img"),
+                vec!["IMG#logo", "img#home", "img#footer"],
+            ),
+            ("missing tag", dom.find_by_tag("video"), vec![]),
+            (
+                "attribute present",
+                dom.find_by_attr("data-role", None),
+                vec!["nav#top", "a", "nav#bottom"],
+            ),
+            (
+                "attribute value",
+                dom.find_by_attr("data-role", Some("nav")),
+                vec!["nav#top", "nav#bottom"],
+            ),
+            (
+                "values are exact",
+                dom.find_by_attr("data-role", Some("NAV")),
+                vec![],
+            ),
+            (
+                "id",
+                dom.find_by_id("home").into_iter().collect(),
+                vec!["img#home"],
+            ),
+            (
+                "missing id",
+                dom.find_by_id("none").into_iter().collect(),
+                vec![],
+            ),
+        ];
+        for (desc, got, want) in tests {
+            assert_eq!(want, names(&got), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn find_in_subtree() {
+        let dom = parse(
+            r#"<div id="app"><nav id="top"><img id="home"/></nav><img id="logo"/></div><img id="out"/>"#,
+        );
+        let app = dom.find_by_id("app").unwrap();
+        let app = app.borrow();
+        assert_eq!(vec!["img#home", "img#logo"], names(&app.find_by_tag("img")));
+        assert_eq!(vec!["nav#top"], names(&app.find_by_attr("id", Some("top"))));
+        assert_eq!(
+            None,
+            app.find_by_id("app"),
+            "the node itself isn't searched"
+        );
+        assert_eq!(None, app.find_by_id("out"));
+        let text = Node::Text("text".into());
+        assert!(text.find_by_tag("img").is_empty());
+    }
+}
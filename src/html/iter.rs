@@ -0,0 +1,141 @@
+use super::parse::{Dom, NodeRef};
+
+/// Iter yields the nodes of a DOM in document order, each before its
+/// children, as `Dom::depth_first` visits them. It keeps a stack of the nodes
+/// still to yield rather than recursing, so deep trees don't overflow.
+///
+/// The tree may be changed during iteration. The children of a node are read
+/// when the iterator moves past it, so children added to or removed from the
+/// node last yielded are taken into account, as `depth_first` does, while
+/// changes to the children of nodes further back aren't. Nodes waiting on the
+/// stack are still yielded if they're removed from the tree in the meantime.
+pub struct Iter {
+    /// The nodes still to yield, the next one last.
+    stack: Vec<NodeRef>,
+    /// The node last yielded, whose children are yet to be read.
+    last: Option<NodeRef>,
+}
+
+impl Iterator for Iter {
+    type Item = NodeRef;
+
+    fn next(&mut self) -> Option<NodeRef> {
+        if let Some(last) = self.last.take() {
+            let node = last.borrow();
+            self.stack.extend(node.children().iter().rev().cloned());
+        }
+        let next = self.stack.pop()?;
+        self.last = Some(next.clone());
+        Some(next)
+    }
+}
+
+impl Dom {
+    /// iter returns an iterator over every node of the DOM in document order,
+    /// the content of templates included.
+    ///
+    /// ```
+    /// let dom = inliner::html::parse(r#"<p><img src="a.png"/></p><img src="b.png"/>"#).unwrap();
+    /// assert_eq!(2, dom.iter().filter(|n| n.borrow().is_element("img")).count());
+    /// ```
+    pub fn iter(&self) -> Iter {
+        Iter {
+            stack: self.nodes.iter().rev().cloned().collect(),
+            last: None,
+        }
+    }
+
+    /// iter_elements returns an iterator over the elements of the DOM in
+    /// document order, skipping text, comments, processing instructions and
+    /// declarations such as the doctype.
+    pub fn iter_elements(&self) -> impl Iterator<Item = NodeRef> {
+        self.iter().filter(|n| {
+            n.borrow()
+                .tag_name()
+                .is_some_and(|name| !name.starts_with('!'))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Node;
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    /// name describes `n`, to compare the order nodes were yielded in.
+    fn name(n: &NodeRef) -> String {
+        match &*n.borrow() {
+            Node::Tag {
+                name, attributes, ..
+            } => match attributes.get("id") {
+                Some(id) => format!("{}#{}", name, id),
+                None => name.clone(),
+            },
+            node => node.to_string(),
+        }
+    }
+
+    #[test]
+    fn order() {
+        let dom = parse(
+            r#"<!DOCTYPE html><html><head><title>t</title></head><body><div id="a"><p id="b">one</p><!--c--><p id="c"><em>two</em></p></div><template><img id="d"/></template>after</body></html>"#,
+        );
+        let got: Vec<String> = dom.iter().map(|n| name(&n)).collect();
+        assert_eq!(
+            vec![
+                "!DOCTYPE", "html", "head", "title", "t", "body", "div#a", "p#b", "one",
+                "<!--c-->", "p#c", "em", "two", "template", "img#d", "after",
+            ],
+            got
+        );
+        let walked = std::cell::RefCell::new(vec![]);
+        dom.depth_first(&|n: NodeRef| {
+            walked.borrow_mut().push(name(&n));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(walked.into_inner(), got, "as depth_first visits them");
+        let elements: Vec<String> = dom.iter_elements().map(|n| name(&n)).collect();
+        assert_eq!(
+            vec![
+                "html", "head", "title", "body", "div#a", "p#b", "p#c", "em", "template", "img#d",
+            ],
+            elements
+        );
+        assert_eq!(0, Dom::from(vec![]).iter().count());
+    }
+
+    #[test]
+    fn mutation() {
+        let mut dom =
+            parse(r#"<div id="a"><p id="b"></p></div><div id="c"></div><div id="d"></div>"#);
+        let mut got = vec![];
+        for n in dom.iter() {
+            got.push(name(&n));
+            match name(&n).as_str() {
+                // Children added to the node just yielded are visited.
+                "div#a" => n
+                    .borrow_mut()
+                    .children_mut()
+                    .unwrap()
+                    .insert(0, Node::Text("added".into()).into()),
+                "div#c" => {
+                    // Children added to nodes further back aren't.
+                    let a = dom.find_by_id("a").unwrap();
+                    let late = Node::Text("late".into()).into();
+                    a.borrow_mut().children_mut().unwrap().push(late);
+                    // Nodes removed from the tree are still yielded.
+                    dom.nodes.retain(|n| n.borrow().attr("id") != Some("d"));
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(vec!["div#a", "added", "p#b", "div#c", "div#d"], got);
+        assert_eq!(2, dom.nodes.len());
+    }
+}
@@ -0,0 +1,186 @@
+//! Serde support for the DOM, for its JSON representation.
+//!
+//! A node is an object tagged with its `type`:
+//!
+//! ```json
+//! {"type": "element", "name": "a", "attributes": {"href": "/"}, "children": [
+//!     {"type": "text", "text": "Home"}
+//! ]}
+//! ```
+//!
+//! Comments and processing instructions are `comment` and
+//! `processing-instruction` nodes with `text`. Attributes are written in name
+//! order, since the DOM doesn't keep the order they appeared in. A `Dom` is
+//! the array of its root nodes.
+
+use super::parse::{Dom, Node, NodeRef};
+use serde::ser::{SerializeSeq, SerializeStruct};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+
+impl Serialize for Dom {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Nodes(&self.nodes).serialize(serializer)
+    }
+}
+
+impl Serialize for Node {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (kind, text) = match self {
+            Node::Tag {
+                name,
+                attributes,
+                children,
+            } => {
+                let mut node = serializer.serialize_struct("Node", 4)?;
+                node.serialize_field("type", "element")?;
+                node.serialize_field("name", name)?;
+                let attributes: BTreeMap<&String, &String> = attributes.iter().collect();
+                node.serialize_field("attributes", &attributes)?;
+                node.serialize_field("children", &Nodes(children))?;
+                return node.end();
+            }
+            Node::Text(text) => ("text", text),
+            Node::Comment(text) => ("comment", text),
+            Node::ProcessingInstruction(text) => ("processing-instruction", text),
+        };
+        let mut node = serializer.serialize_struct("Node", 2)?;
+        node.serialize_field("type", kind)?;
+        node.serialize_field("text", text)?;
+        node.end()
+    }
+}
+
+/// Nodes serializes a list of nodes in place, without copying the tree.
+struct Nodes<'a>(&'a [NodeRef]);
+
+impl Serialize for Nodes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for node in self.0 {
+            seq.serialize_element(&*node.borrow())?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Dom {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let nodes = Vec::<Owned>::deserialize(deserializer)?;
+        Ok(Dom::from(
+            nodes
+                .into_iter()
+                .map(|n| Node::from(n).into())
+                .collect::<Vec<NodeRef>>(),
+        ))
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Owned::deserialize(deserializer).map(Node::from)
+    }
+}
+
+/// Owned is the form a node is deserialized through, since a `NodeRef` can't
+/// be deserialized directly.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Owned {
+    Element {
+        name: String,
+        #[serde(default)]
+        attributes: HashMap<String, String>,
+        #[serde(default)]
+        children: Vec<Owned>,
+    },
+    Text {
+        text: String,
+    },
+    Comment {
+        text: String,
+    },
+    ProcessingInstruction {
+        text: String,
+    },
+}
+
+impl From<Owned> for Node {
+    fn from(node: Owned) -> Self {
+        match node {
+            Owned::Element {
+                name,
+                attributes,
+                children,
+            } => Node::Tag {
+                name,
+                attributes,
+                children: children.into_iter().map(|n| Node::from(n).into()).collect(),
+            },
+            Owned::Text { text } => Node::Text(text),
+            Owned::Comment { text } => Node::Comment(text),
+            Owned::ProcessingInstruction { text } => Node::ProcessingInstruction(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use std::path::Path;
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    #[test]
+    fn shape() {
+        let dom = parse(r#"<?xml version="1.0"?><a href="/">Home<!-- nav --></a>"#);
+        let want = serde_json::json!([
+            {"type": "processing-instruction", "text": r#"xml version="1.0""#},
+            {"type": "element", "name": "a", "attributes": {"href": "/"}, "children": [
+                {"type": "text", "text": "Home"},
+                {"type": "comment", "text": " nav "},
+            ]},
+        ]);
+        assert_eq!(want, serde_json::to_value(&dom).unwrap());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut inputs: Vec<String> = vec![
+            "<!DOCTYPE html><html><head><title>t</title></head><body></body></html>",
+            r#"<div class="card" hidden><img src="a.png"/><p>text <em>and</em> more</p></div>"#,
+            "<ul><li>one</li><li>two</li></ul><!-- trailing -->",
+            "<?php echo 1; ?><div>if (a < b) {}</div>",
+            "text at the root",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        for dir in fs::read_dir(fixtures).unwrap() {
+            for file in fs::read_dir(dir.unwrap().path()).unwrap() {
+                let path = file.unwrap().path();
+                if path.extension().is_some_and(|ext| ext == "html") {
+                    inputs.push(String::from_utf8_lossy(&fs::read(path).unwrap()).into_owned());
+                }
+            }
+        }
+        for input in inputs {
+            let dom = parse(&input);
+            let json = serde_json::to_string(&dom).unwrap();
+            let got: Dom = serde_json::from_str(&json).unwrap();
+            assert_eq!(dom, got, "{}", input);
+            // Attributes serialize in any order, so the html is compared parsed.
+            assert_eq!(
+                parse(&dom.to_string()),
+                parse(&got.to_string()),
+                "{}",
+                input
+            );
+        }
+    }
+}
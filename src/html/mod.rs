@@ -1,5 +1,59 @@
+mod element;
+mod find;
+mod iter;
+#[cfg(feature = "serde")]
+mod json;
 pub mod parse;
+mod select;
+pub mod serialize;
+mod sync;
+pub mod text;
 pub mod token;
+mod validate;
+mod whitespace;
 
-pub use parse::{Node, NodeRef, Parser};
-pub use token::Tokenizer;
+pub use iter::Iter;
+pub use parse::{
+    Dom, Node, NodeRef, ParseError, ParseMode, ParseWarning, Parser, ParserState, Position,
+};
+pub use select::Selector;
+pub(crate) use select::Tree;
+pub use serialize::SerializeMode;
+pub use sync::{NodeId, SyncDom, SyncNode};
+pub use text::TextOptions;
+pub use token::{ReadChars, StrTokenizer, TokenSink, Tokenizer};
+pub use validate::{Rule, Severity, ValidationIssue};
+
+use std::str::FromStr;
+
+/// parse parses `input` into a DOM tree, tokenizing it in place and merging
+/// adjacent text.
+///
+/// Any input whatsoever returns either a DOM or an error: parsing never
+/// panics, and elements nested deeper than `parse::MAX_DEPTH` are flattened
+/// rather than overflowing the stack. The fuzz target in `fuzz/` checks as
+/// much.
+///
+/// ```
+/// let dom = inliner::html::parse(r#"<p class="intro">Hello</p>"#).unwrap();
+/// assert_eq!("Hello", dom.text());
+/// ```
+pub fn parse(input: &str) -> Result<Dom, ParseError> {
+    Parser::new(StrTokenizer::new(input).merged()).parse()
+}
+
+impl FromStr for Dom {
+    type Err = ParseError;
+
+    /// from_str parses `input` as `parse` does.
+    ///
+    /// ```
+    /// use inliner::html::Dom;
+    ///
+    /// let dom: Dom = "<ul><li>one</li><li>two</li></ul>".parse().unwrap();
+    /// assert_eq!(2, dom.find_by_tag("li").len());
+    /// ```
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        parse(input)
+    }
+}
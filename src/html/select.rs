@@ -0,0 +1,521 @@
+use super::parse::{Dom, Node, NodeRef};
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::{Chars, FromStr};
+
+/// Selector is a CSS selector of the supported subset: type, universal, id,
+/// class and attribute selectors, joined by the descendant, child, next
+/// sibling and subsequent sibling combinators. Pseudo-classes, pseudo-elements,
+/// namespaces and escapes aren't supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    /// The compound selectors, from left to right.
+    compounds: Vec<Compound>,
+    /// The combinator between each compound selector and the next.
+    combinators: Vec<Combinator>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+    NextSibling,
+    SubsequentSibling,
+}
+
+/// Compound is a sequence of simple selectors an element matches all of.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Compound {
+    /// The type selected, or `None` for any type.
+    tag: Option<String>,
+    ids: Vec<String>,
+    classes: Vec<String>,
+    attributes: Vec<Attribute>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Attribute {
+    name: String,
+    /// The operator and value the attribute is compared with, or `None` if
+    /// it only has to be present.
+    value: Option<(Operator, String)>,
+    ignore_case: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    /// `=`
+    Equals,
+    /// `~=`, one of the whitespace separated words of the value.
+    Includes,
+    /// `|=`, the value or the value followed by a hyphen.
+    DashMatch,
+    /// `^=`
+    Prefix,
+    /// `$=`
+    Suffix,
+    /// `*=`
+    Substring,
+}
+
+impl Selector {
+    /// parse_list parses a comma separated list of selectors.
+    pub fn parse_list(selectors: &str) -> Result<Vec<Selector>, String> {
+        split_list(selectors).into_iter().map(str::parse).collect()
+    }
+
+    /// specificity returns the number of id selectors, of class and
+    /// attribute selectors, and of type selectors, which say which of the
+    /// selectors matching an element takes precedence.
+    pub fn specificity(&self) -> (usize, usize, usize) {
+        self.compounds
+            .iter()
+            .fold((0, 0, 0), |(a, b, c), compound| {
+                (
+                    a + compound.ids.len(),
+                    b + compound.classes.len() + compound.attributes.len(),
+                    c + compound.tag.iter().count(),
+                )
+            })
+    }
+
+    /// matches reports whether the element `n` matches the selector, where
+    /// `tree` is the document it is in.
+    pub(crate) fn matches(&self, n: &NodeRef, tree: &Tree) -> bool {
+        self.matches_at(self.compounds.len() - 1, n, tree)
+    }
+
+    /// matches_at reports whether `n` matches the selector up to and
+    /// including the compound `i`.
+    fn matches_at(&self, i: usize, n: &NodeRef, tree: &Tree) -> bool {
+        if !self.compounds[i].matches(&n.borrow()) {
+            return false;
+        }
+        if i == 0 {
+            return true;
+        }
+        let next = |n: &NodeRef| self.matches_at(i - 1, n, tree);
+        match self.combinators[i - 1] {
+            Combinator::Descendant => tree.ancestors(n).any(|p| next(&p)),
+            Combinator::Child => tree.parent(n).is_some_and(|p| next(&p)),
+            Combinator::NextSibling => tree.previous(n).is_some_and(|p| next(&p)),
+            Combinator::SubsequentSibling => tree.previous_all(n).any(|p| next(&p)),
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    /// from_str parses a single selector.
+    fn from_str(selector: &str) -> Result<Self, Self::Err> {
+        let unsupported = |reason: &str| format!("{} in selector {:?}", reason, selector);
+        let mut chars = selector.trim().chars().peekable();
+        let mut compounds = vec![];
+        let mut combinators = vec![];
+        loop {
+            compounds.push(compound(&mut chars).map_err(|r| unsupported(&r))?);
+            let spaced = skip_whitespace(&mut chars);
+            let combinator = match chars.peek() {
+                None => break,
+                Some('>') => Combinator::Child,
+                Some('+') => Combinator::NextSibling,
+                Some('~') => Combinator::SubsequentSibling,
+                Some(_) if spaced => {
+                    combinators.push(Combinator::Descendant);
+                    continue;
+                }
+                Some(c) => return Err(unsupported(&format!("unexpected {:?}", c))),
+            };
+            chars.next();
+            skip_whitespace(&mut chars);
+            combinators.push(combinator);
+        }
+        Ok(Selector {
+            compounds,
+            combinators,
+        })
+    }
+}
+
+/// compound parses a compound selector.
+fn compound(chars: &mut Peekable<Chars>) -> Result<Compound, String> {
+    let mut compound = Compound::default();
+    let mut empty = true;
+    if chars.peek() == Some(&'*') {
+        chars.next();
+        empty = false;
+    } else if chars.peek().is_some_and(|c| is_ident(*c)) {
+        compound.tag = Some(ident(chars)?);
+        empty = false;
+    }
+    loop {
+        match chars.peek() {
+            Some('#') => {
+                chars.next();
+                compound.ids.push(ident(chars)?);
+            }
+            Some('.') => {
+                chars.next();
+                compound.classes.push(ident(chars)?);
+            }
+            Some('[') => {
+                chars.next();
+                compound.attributes.push(attribute(chars)?);
+            }
+            Some(':') => return Err("pseudo-classes aren't supported".into()),
+            Some('|') => return Err("namespaces aren't supported".into()),
+            _ if empty => return Err("expected a selector".into()),
+            _ => return Ok(compound),
+        }
+        empty = false;
+    }
+}
+
+/// attribute parses an attribute selector, after its `[`.
+fn attribute(chars: &mut Peekable<Chars>) -> Result<Attribute, String> {
+    skip_whitespace(chars);
+    let name = ident(chars)?;
+    skip_whitespace(chars);
+    let operator = match chars.next() {
+        Some(']') => {
+            return Ok(Attribute {
+                name,
+                value: None,
+                ignore_case: false,
+            })
+        }
+        Some('=') => Operator::Equals,
+        Some(c) if chars.peek() == Some(&'=') => {
+            let operator = match c {
+                '~' => Operator::Includes,
+                '|' => Operator::DashMatch,
+                '^' => Operator::Prefix,
+                '$' => Operator::Suffix,
+                '*' => Operator::Substring,
+                _ => return Err(format!("unknown attribute operator {:?}", c)),
+            };
+            chars.next();
+            operator
+        }
+        _ => return Err("unterminated attribute selector".into()),
+    };
+    skip_whitespace(chars);
+    let value = match chars.peek() {
+        Some(&quote) if quote == '"' || quote == '\'' => {
+            chars.next();
+            let value: String = chars.by_ref().take_while(|c| *c != quote).collect();
+            if value.contains('\\') {
+                return Err("escapes aren't supported".into());
+            }
+            value
+        }
+        _ => ident(chars)?,
+    };
+    skip_whitespace(chars);
+    let ignore_case = chars.peek().is_some_and(|c| c.eq_ignore_ascii_case(&'i'));
+    if chars.peek().is_some_and(|c| "iIsS".contains(*c)) {
+        chars.next();
+        skip_whitespace(chars);
+    }
+    match chars.next() {
+        Some(']') => Ok(Attribute {
+            name,
+            value: Some((operator, value)),
+            ignore_case,
+        }),
+        _ => Err("unterminated attribute selector".into()),
+    }
+}
+
+/// ident parses a name, such as that of a type, class or attribute.
+fn ident(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut ident = String::new();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\\' => return Err("escapes aren't supported".into()),
+            c if is_ident(c) => ident.push(c),
+            _ => break,
+        }
+        chars.next();
+    }
+    match ident.is_empty() {
+        true => Err("expected a name".into()),
+        false => Ok(ident),
+    }
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_' || !c.is_ascii()
+}
+
+/// skip_whitespace skips whitespace, reporting whether there was any.
+fn skip_whitespace(chars: &mut Peekable<Chars>) -> bool {
+    let mut skipped = false;
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+        skipped = true;
+    }
+    skipped
+}
+
+/// split_list splits a selector list at its commas, except those inside
+/// attribute selectors.
+fn split_list(selectors: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut quote = None;
+    let mut brackets = 0;
+    for (i, c) in selectors.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[') => brackets += 1,
+            (None, ']') => brackets -= 1,
+            (None, ',') if brackets == 0 => {
+                parts.push(&selectors[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&selectors[start..]);
+    parts
+}
+
+impl Compound {
+    fn matches(&self, node: &Node) -> bool {
+        if node.tag_name().is_none() {
+            return false;
+        }
+        if let Some(tag) = &self.tag {
+            if !node.is_element(tag) {
+                return false;
+            }
+        }
+        let classes: Vec<&str> = node
+            .attr("class")
+            .map(|class| class.split_ascii_whitespace().collect())
+            .unwrap_or_default();
+        self.ids.iter().all(|id| node.attr("id") == Some(id))
+            && self.classes.iter().all(|c| classes.contains(&c.as_str()))
+            && self.attributes.iter().all(|a| a.matches(node))
+    }
+}
+
+impl Attribute {
+    fn matches(&self, node: &Node) -> bool {
+        let actual = match node.attr(&self.name) {
+            Some(actual) => actual,
+            None => return false,
+        };
+        let (operator, value) = match &self.value {
+            Some(value) => value,
+            None => return true,
+        };
+        let (actual, value) = match self.ignore_case {
+            true => (actual.to_lowercase(), value.to_lowercase()),
+            false => (actual.to_owned(), value.clone()),
+        };
+        match operator {
+            Operator::Equals => actual == value,
+            Operator::Includes => actual.split_ascii_whitespace().any(|w| w == value),
+            Operator::DashMatch => actual == value || actual.starts_with(&format!("{}-", value)),
+            _ if value.is_empty() => false,
+            Operator::Prefix => actual.starts_with(&value),
+            Operator::Suffix => actual.ends_with(&value),
+            Operator::Substring => actual.contains(&value),
+        }
+    }
+}
+
+/// Tree is a document indexed for matching selectors: the elements in
+/// document order, with the parent and previous sibling element of each.
+/// The content of `<template>` elements isn't part of the document.
+pub(crate) struct Tree {
+    pub elements: Vec<NodeRef>,
+    parents: HashMap<*const std::cell::RefCell<Node>, NodeRef>,
+    previous: HashMap<*const std::cell::RefCell<Node>, NodeRef>,
+}
+
+impl Tree {
+    pub fn new(dom: &Dom) -> Self {
+        let mut tree = Tree {
+            elements: vec![],
+            parents: HashMap::new(),
+            previous: HashMap::new(),
+        };
+        tree.index(&dom.nodes, None);
+        tree
+    }
+
+    fn index(&mut self, nodes: &[NodeRef], parent: Option<&NodeRef>) {
+        let mut previous: Option<&NodeRef> = None;
+        for n in nodes {
+            let node = n.borrow();
+            if node.tag_name().is_none() {
+                continue;
+            }
+            self.elements.push(n.clone());
+            if let Some(parent) = parent {
+                self.parents.insert(Rc::as_ptr(n), parent.clone());
+            }
+            if let Some(previous) = previous {
+                self.previous.insert(Rc::as_ptr(n), previous.clone());
+            }
+            previous = Some(n);
+            if !node.is_element("template") {
+                self.index(node.children(), Some(n));
+            }
+        }
+    }
+
+    fn parent(&self, n: &NodeRef) -> Option<NodeRef> {
+        self.parents.get(&Rc::as_ptr(n)).cloned()
+    }
+
+    fn previous(&self, n: &NodeRef) -> Option<NodeRef> {
+        self.previous.get(&Rc::as_ptr(n)).cloned()
+    }
+
+    fn ancestors<'a>(&'a self, n: &NodeRef) -> impl Iterator<Item = NodeRef> + 'a {
+        std::iter::successors(self.parent(n), move |p| self.parent(p))
+    }
+
+    fn previous_all<'a>(&'a self, n: &NodeRef) -> impl Iterator<Item = NodeRef> + 'a {
+        std::iter::successors(self.previous(n), move |p| self.previous(p))
+    }
+}
+
+impl Dom {
+    /// select returns the elements that match any of the comma separated
+    /// `selectors`, in document order, as `querySelectorAll` does for the
+    /// subset of selectors `Selector` supports.
+    pub fn select(&self, selectors: &str) -> Result<Vec<NodeRef>, String> {
+        let selectors = Selector::parse_list(selectors)?;
+        let tree = Tree::new(self);
+        Ok(tree
+            .elements
+            .iter()
+            .filter(|n| selectors.iter().any(|s| s.matches(n, &tree)))
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    #[test]
+    fn select() {
+        let dom = parse(
+            r#"<div id="main" class="box wide"><h1 lang="en-GB">Title</h1><p class="lead">one</p><p>two <a href="https://example.com/a.pdf" data-x="Y">a</a></p><template><p>inert</p></template></div><p id="after">three</p>"#,
+        );
+        let tests = vec![
+            ("type", "h1", vec!["Title"]),
+            ("type, ignoring case", "H1", vec!["Title"]),
+            (
+                "universal",
+                "#main > *",
+                vec!["Title", "one", "two a", "inert"],
+            ),
+            ("not in templates", "p", vec!["one", "two a", "three"]),
+            ("id", "#after", vec!["three"]),
+            ("class", ".lead", vec!["one"]),
+            ("classes", "div.box.wide > h1", vec!["Title"]),
+            ("missing class", ".box.narrow", vec![]),
+            ("descendant", "div a", vec!["a"]),
+            ("child", "div > a", vec![]),
+            ("next sibling", "h1 + p", vec!["one"]),
+            ("subsequent siblings", "h1 ~ p", vec!["one", "two a"]),
+            (
+                "list, in document order",
+                "#after, h1",
+                vec!["Title", "three"],
+            ),
+            ("attribute", "[href]", vec!["a"]),
+            ("equals", r#"a[data-x="Y"]"#, vec!["a"]),
+            ("equals, ignoring case", "a[data-x=y i]", vec!["a"]),
+            ("case sensitive", "a[data-x=y]", vec![]),
+            ("prefix", "[href^='https:']", vec!["a"]),
+            ("suffix", "[href$=\".pdf\"]", vec!["a"]),
+            ("substring", "[href*=example]", vec!["a"]),
+            ("includes", "[class~=wide]", vec!["Title one two a inert"]),
+            ("dash match", "[lang|=en]", vec!["Title"]),
+            (
+                "comma in an attribute",
+                "[data-x=\"Y,Z\"], h1",
+                vec!["Title"],
+            ),
+        ];
+        for (desc, selector, want) in tests {
+            let got: Vec<String> = dom
+                .select(selector)
+                .unwrap()
+                .iter()
+                .map(|n| n.borrow().text_content())
+                .collect();
+            assert_eq!(want, got, "{}", desc);
+        }
+    }
+
+    #[test]
+    fn unsupported() {
+        let tests = vec![
+            (
+                "a:hover",
+                "pseudo-classes aren't supported in selector \"a:hover\"",
+            ),
+            (
+                "p::before",
+                "pseudo-classes aren't supported in selector \"p::before\"",
+            ),
+            (
+                "svg|rect",
+                "namespaces aren't supported in selector \"svg|rect\"",
+            ),
+            (
+                ".a\\:b",
+                "escapes aren't supported in selector \".a\\\\:b\"",
+            ),
+            ("p >", "expected a selector in selector \"p >\""),
+            ("", "expected a selector in selector \"\""),
+            (
+                "[href",
+                "unterminated attribute selector in selector \"[href\"",
+            ),
+        ];
+        for (selector, want) in tests {
+            assert_eq!(
+                Err(want.to_owned()),
+                selector.parse::<Selector>(),
+                "{}",
+                selector
+            );
+        }
+    }
+
+    #[test]
+    fn specificity() {
+        let tests = vec![
+            ("*", (0, 0, 0)),
+            ("p", (0, 0, 1)),
+            ("div p", (0, 0, 2)),
+            ("p.lead", (0, 1, 1)),
+            ("a[href][title]", (0, 2, 1)),
+            ("#main .box > p", (1, 1, 1)),
+        ];
+        for (selector, want) in tests {
+            let selector: Selector = selector.parse().unwrap();
+            assert_eq!(want, selector.specificity());
+        }
+    }
+}
@@ -0,0 +1,883 @@
+use std::borrow::Cow;
+use std::cell::Ref;
+use std::fmt::{self, Write};
+use std::io;
+use std::str::FromStr;
+
+use super::parse::{Dom, Node, Raw, Source};
+use super::token::{classify, Kind};
+use super::whitespace::spacing;
+
+/// SerializeMode selects the syntax a DOM is written out with.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SerializeMode {
+    /// HTML5 for browsers: void elements are written `<br>`, empty elements
+    /// get an explicit close tag, boolean attributes are minimized and text is
+    /// escaped, except for script and style contents which must not be.
+    #[default]
+    Html5,
+    /// XHTML for XML pipelines such as EPUB: void and empty elements are
+    /// closed with ` />`, every attribute has a value and text, including
+    /// script and style contents, is escaped.
+    Xhtml,
+    /// HTML5, except that nodes unchanged since they were parsed are written
+    /// exactly as they were in the source, with the whitespace around them,
+    /// so only what changed differs from the source.
+    Preserve,
+}
+
+impl FromStr for SerializeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "html" | "html5" => Ok(SerializeMode::Html5),
+            "xhtml" | "xml" => Ok(SerializeMode::Xhtml),
+            "preserve" => Ok(SerializeMode::Preserve),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+/// Void elements never have content, so they never have a close tag.
+pub const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Foreign elements hold SVG and MathML, where tags self-close as in XML.
+const FOREIGN_ELEMENTS: &[&str] = &["svg", "math"];
+
+/// Raw text elements hold code rather than text, which character references
+/// would change the meaning of.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Characters replaced by character references in text.
+const TEXT_SPECIAL: &[char] = &['&', '<', '>'];
+/// Characters replaced by character references in double quoted HTML
+/// attribute values.
+const ATTRIBUTE_SPECIAL: &[char] = &['&', '"'];
+/// Characters replaced by character references in XML attribute values.
+const XML_ATTRIBUTE_SPECIAL: &[char] = &['&', '<', '>', '"'];
+
+/// is_void reports whether `name` is a void element. Names are ASCII case
+/// insensitive.
+pub fn is_void(name: &str) -> bool {
+    VOID_ELEMENTS.iter().any(|v| v.eq_ignore_ascii_case(name))
+}
+
+/// is_foreign reports whether `name` is an element holding SVG or MathML.
+pub(crate) fn is_foreign(name: &str) -> bool {
+    FOREIGN_ELEMENTS
+        .iter()
+        .any(|f| f.eq_ignore_ascii_case(name))
+}
+
+impl Dom {
+    /// to_string_with serializes the DOM using the syntax of `mode`.
+    pub fn to_string_with(&self, mode: SerializeMode) -> String {
+        let mut out = String::new();
+        // Writing to a String cannot fail.
+        let _ = write_dom(&mut out, self, mode);
+        out
+    }
+}
+
+impl Dom {
+    /// to_writer streams the DOM to `w` as `to_string` would serialize it,
+    /// without building the output in memory first.
+    pub fn to_writer<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.to_writer_with(w, SerializeMode::default())
+    }
+
+    /// to_writer_with streams the DOM to `w` using the syntax of `mode`.
+    pub fn to_writer_with<W: io::Write>(&self, w: &mut W, mode: SerializeMode) -> io::Result<()> {
+        write_io(w, |w| write_dom(w, self, mode))
+    }
+}
+
+impl Node {
+    /// to_string_with serializes the node and its descendants using the
+    /// syntax of `mode`.
+    pub fn to_string_with(&self, mode: SerializeMode) -> String {
+        let mut out = String::new();
+        let _ = write_node(&mut out, self, mode);
+        out
+    }
+
+    /// to_writer streams the node and its descendants to `w`.
+    pub fn to_writer<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        self.to_writer_with(w, SerializeMode::default())
+    }
+
+    /// to_writer_with streams the node and its descendants to `w` using the
+    /// syntax of `mode`.
+    pub fn to_writer_with<W: io::Write>(&self, w: &mut W, mode: SerializeMode) -> io::Result<()> {
+        write_io(w, |w| write_node(w, self, mode))
+    }
+}
+
+/// write_io runs `write` against `w`, buffered, through an adapter from
+/// `io::Write` to `fmt::Write` so that every form of output shares one
+/// serialization code path.
+fn write_io<W, F>(w: &mut W, write: F) -> io::Result<()>
+where
+    W: io::Write,
+    F: FnOnce(&mut IoWriter<io::BufWriter<&mut W>>) -> fmt::Result,
+{
+    let mut adapter = IoWriter {
+        inner: io::BufWriter::new(w),
+        error: None,
+    };
+    match (write(&mut adapter), adapter.error) {
+        (_, Some(err)) => Err(err),
+        (Err(_), None) => Err(io::Error::other("formatter error")),
+        (Ok(()), None) => io::Write::flush(&mut adapter.inner),
+    }
+}
+
+/// IoWriter adapts an `io::Write` to `fmt::Write`, keeping the `io::Error`
+/// that a `fmt::Error` can't carry.
+struct IoWriter<W: io::Write> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+}
+
+/// write_dom writes each root node on its own line, or as it was in the
+/// source when preserving it.
+pub(crate) fn write_dom<W: Write>(w: &mut W, dom: &Dom, mode: SerializeMode) -> fmt::Result {
+    let source = &dom.source;
+    for node in &dom.nodes {
+        let node = node.borrow();
+        match preserved(source, &node, mode) {
+            Some(raw) => {
+                w.write_str(&raw.leading)?;
+                write_tree(w, &node, mode, source, false)?;
+            }
+            None => {
+                write_tree(w, &node, mode, source, false)?;
+                w.write_char('\n')?;
+            }
+        }
+    }
+    match mode {
+        SerializeMode::Preserve => w.write_str(&source.tail),
+        _ => Ok(()),
+    }
+}
+
+/// write_node writes `node` and its descendants.
+pub(crate) fn write_node<W: Write>(w: &mut W, node: &Node, mode: SerializeMode) -> fmt::Result {
+    write_tree(w, node, mode, &Source::default(), false)
+}
+
+/// write_tree writes `node` and its descendants, writing those in `source`
+/// that haven't changed since they were parsed as they were written there
+/// when preserving it, and the whitespace between them as `spacing` says
+/// otherwise. `foreign` says whether the node is inside SVG or MathML.
+fn write_tree<W: Write>(
+    w: &mut W,
+    node: &Node,
+    mode: SerializeMode,
+    source: &Source,
+    foreign: bool,
+) -> fmt::Result {
+    let raw = preserved(source, node, mode);
+    match node {
+        Node::Text(text) if raw.is_some_and(|raw| is_text(raw, text)) => {
+            w.write_str(&raw.unwrap().open)
+        }
+        // Markup declarations such as the `<![if !IE]>` of a conditional
+        // comment are kept as text by the parser, and aren't text to escape.
+        Node::Text(text)
+            if (mode != SerializeMode::Xhtml && is_declaration(text)) || is_doctype(text) =>
+        {
+            w.write_str(text)
+        }
+        Node::Text(text) => escape(w, text, TEXT_SPECIAL),
+        Node::Comment(text) => write!(w, "<!--{}-->", text),
+        Node::ProcessingInstruction(text) => write!(w, "<?{}?>", text),
+        Node::Tag {
+            name,
+            attributes,
+            children,
+        } => {
+            // Declarations such as `<!DOCTYPE html>` are neither elements nor
+            // XML, so they look the same in every mode.
+            let is_declaration = name.starts_with('!');
+            let foreign = foreign || is_foreign(name);
+            let raw_open = raw.filter(|raw| is_open_tag_of(raw, node, foreign));
+            match raw_open {
+                Some(raw) if is_declaration => return w.write_str(&raw.open),
+                Some(raw) => w.write_str(&raw.open)?,
+                None => {
+                    write!(w, "<{}", name)?;
+                    // Attributes are written sorted by name, so that a tree
+                    // is always written the same, however it was parsed.
+                    let mut attributes: Vec<_> = attributes.iter().collect();
+                    attributes.sort();
+                    for (key, value) in attributes {
+                        match mode {
+                            _ if value.is_empty() && is_declaration => write!(w, " {}", key)?,
+                            SerializeMode::Xhtml => {
+                                write!(w, " {}=\"", key)?;
+                                escape(w, value, XML_ATTRIBUTE_SPECIAL)?;
+                                w.write_char('"')?;
+                            }
+                            _ if value.is_empty() => write!(w, " {}", key)?,
+                            _ => {
+                                write!(w, " {}=\"", key)?;
+                                escape(w, value, ATTRIBUTE_SPECIAL)?;
+                                w.write_char('"')?;
+                            }
+                        }
+                    }
+                    if is_declaration {
+                        return w.write_char('>');
+                    }
+                    if children.is_empty() {
+                        match mode {
+                            SerializeMode::Xhtml => return w.write_str(" />"),
+                            _ if is_void(name) => return w.write_char('>'),
+                            _ => return write!(w, "></{}>", name),
+                        }
+                    }
+                    w.write_char('>')?;
+                }
+            }
+            // XML has no raw text, so only HTML writes script and style
+            // contents as they are.
+            let is_raw = mode != SerializeMode::Xhtml
+                && RAW_TEXT_ELEMENTS
+                    .iter()
+                    .any(|r| r.eq_ignore_ascii_case(name));
+            let mut previous: Option<Ref<Node>> = None;
+            for child in children {
+                let child = child.borrow();
+                let child_raw = preserved(source, &child, mode);
+                match (mode, child_raw) {
+                    (SerializeMode::Preserve, Some(raw)) => w.write_str(&raw.leading)?,
+                    // A node that wasn't parsed has no whitespace around it.
+                    (SerializeMode::Preserve, None) => {}
+                    _ => {
+                        let whitespace = previous
+                            .as_ref()
+                            .map_or(String::new(), |p| whitespace_after(source, p))
+                            + &whitespace_before(source, &child);
+                        let parent = name.as_str();
+                        w.write_str(spacing(
+                            &whitespace,
+                            parent,
+                            previous.as_deref(),
+                            Some(&child),
+                        ))?;
+                    }
+                }
+                match &*child {
+                    Node::Text(text) if is_raw && !child_raw.is_some_and(|r| is_text(r, text)) => {
+                        w.write_str(text)?
+                    }
+                    child => write_tree(
+                        w,
+                        child,
+                        mode,
+                        source,
+                        foreign && !name.eq_ignore_ascii_case("foreignObject"),
+                    )?,
+                }
+                previous = Some(child);
+            }
+            if mode != SerializeMode::Preserve {
+                let close = source
+                    .get(node)
+                    .and_then(|raw| raw.close.as_deref())
+                    .unwrap_or_default();
+                let whitespace = previous
+                    .as_ref()
+                    .map_or(String::new(), |p| whitespace_after(source, p))
+                    + &close[..close.len() - close.trim_start().len()];
+                w.write_str(spacing(&whitespace, name, previous.as_deref(), None))?;
+            }
+            match raw.and_then(|raw| raw.close.as_deref()) {
+                // The close tag was left out, as it may be, so the element is
+                // still ended where it was.
+                Some("") if raw_open.is_some() => Ok(()),
+                Some(close) if is_close_tag_of(close, name) => w.write_str(close),
+                // A renamed element keeps the whitespace before its close tag.
+                Some(close) => {
+                    let trimmed = close.trim_start();
+                    w.write_str(&close[..close.len() - trimmed.len()])?;
+                    write!(w, "</{}>", name)
+                }
+                // The element was left unclosed in the source.
+                None if raw_open.is_some() && children.is_empty() => Ok(()),
+                None => write!(w, "</{}>", name),
+            }
+        }
+    }
+}
+
+/// preserved returns the source of `node` to write it as, which is only
+/// when preserving the source.
+fn preserved<'a>(source: &'a Source, node: &Node, mode: SerializeMode) -> Option<&'a Raw> {
+    match mode {
+        SerializeMode::Preserve => source.get(node),
+        _ => None,
+    }
+}
+
+/// whitespace_before returns the whitespace the source had before `node`,
+/// including that the parser trimmed from the start of text.
+fn whitespace_before(source: &Source, node: &Node) -> String {
+    match (source.get(node), node) {
+        (Some(raw), Node::Text(_)) => {
+            let trimmed = raw.open.trim_start();
+            raw.leading.clone() + &raw.open[..raw.open.len() - trimmed.len()]
+        }
+        (Some(raw), _) => raw.leading.clone(),
+        (None, _) => String::new(),
+    }
+}
+
+/// whitespace_after returns the whitespace the parser trimmed from the end
+/// of `node`, if it's text.
+fn whitespace_after(source: &Source, node: &Node) -> String {
+    match (source.get(node), node) {
+        (Some(raw), Node::Text(_)) => raw.open[raw.open.trim_end().len()..].to_owned(),
+        _ => String::new(),
+    }
+}
+
+/// is_open_tag_of reports whether the open tag in `raw` is still that of
+/// `node`, which it isn't once the node has been renamed or its attributes
+/// changed. Self-closed elements other than void elements are never written
+/// as they were, since browsers would take what follows to be inside them,
+/// except inside SVG and MathML, where they are closed as in XML.
+fn is_open_tag_of(raw: &Raw, node: &Node, foreign: bool) -> bool {
+    let (name, attributes) = match node {
+        Node::Tag {
+            name, attributes, ..
+        } => (name, attributes),
+        _ => return false,
+    };
+    if raw.open.ends_with("/>") && !is_void(name) && !foreign {
+        return false;
+    }
+    match classify(&raw.open).kind {
+        Kind::OpenTag {
+            name: raw_name,
+            attributes: raw_attributes,
+        } => {
+            is_name_of(raw_name, name)
+                && raw_attributes.len() == attributes.len()
+                && raw_attributes.iter().all(|(key, value)| {
+                    attributes
+                        .get(key)
+                        .or_else(|| attributes.get(&key.to_ascii_lowercase()))
+                        .is_some_and(|v| v == value)
+                })
+        }
+        _ => false,
+    }
+}
+
+/// is_text reports whether `raw` is still the source of `text`, which the
+/// parser trimmed.
+fn is_text(raw: &Raw, text: &str) -> bool {
+    raw.open.trim() == text
+}
+
+/// is_close_tag_of reports whether `close`, with the whitespace before it,
+/// is the close tag of an element named `name`.
+fn is_close_tag_of(close: &str, name: &str) -> bool {
+    close
+        .trim()
+        .strip_prefix("</")
+        .and_then(|close| close.strip_suffix('>'))
+        .is_some_and(|close| is_name_of(close.trim(), name))
+}
+
+/// is_name_of reports whether `raw`, a name as the source wrote it, is still
+/// `name`, as written or as the parser lowercased it.
+fn is_name_of(raw: &str, name: &str) -> bool {
+    raw == name || (raw.eq_ignore_ascii_case(name) && !name.bytes().any(|b| b.is_ascii_uppercase()))
+}
+
+/// is_declaration reports whether `text` is a markup declaration, such as
+/// `<![endif]>`, that the parser couldn't make an element of.
+fn is_declaration(text: &str) -> bool {
+    let text = text.trim();
+    text.starts_with("<![") && text.ends_with("]>") || is_doctype(text)
+}
+
+/// is_doctype reports whether `text` is a `<!DOCTYPE>` the parser kept as
+/// text, as it does those with public and system identifiers. Unlike other
+/// declarations it's XML too.
+fn is_doctype(text: &str) -> bool {
+    let text = text.trim();
+    text.get(.."<!doctype".len())
+        .is_some_and(|start| start.eq_ignore_ascii_case("<!doctype"))
+        && text.ends_with('>')
+}
+
+/// escape writes `text` with the `special` characters replaced by character
+/// references.
+/// Text from the parser is kept as written in the source, so an ampersand
+/// that already starts a character reference is left alone.
+fn escape<W: Write>(w: &mut W, text: &str, special: &[char]) -> fmt::Result {
+    let mut written = 0;
+    for (ii, c) in text.char_indices() {
+        if !special.contains(&c) {
+            continue;
+        }
+        let reference = match c {
+            '&' if is_reference(&text[ii..]) => continue,
+            '&' => "&amp;",
+            '<' => "&lt;",
+            '>' => "&gt;",
+            _ => "&quot;",
+        };
+        w.write_str(&text[written..ii])?;
+        w.write_str(reference)?;
+        written = ii + c.len_utf8();
+    }
+    w.write_str(&text[written..])
+}
+
+/// to_srcdoc writes `dom` as the value of a `srcdoc` attribute, with `mode`.
+/// A srcdoc holds a whole document, so its ampersands are escaped along with
+/// the quotes, for the document to read back as it was written.
+pub(crate) fn to_srcdoc(dom: &Dom, mode: SerializeMode) -> String {
+    dom.to_string_with(mode)
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+}
+
+/// unescape replaces the character references in `text`, as written in the
+/// source, by the characters they stand for. Numeric references are decoded,
+/// as are the named references the serializer writes and `&apos;` and
+/// `&nbsp;`; any other is left as it is.
+pub(crate) fn unescape(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest
+            .find(';')
+            .filter(|_| is_reference(rest))
+            .and_then(|end| Some((reference(&rest[1..end])?, end + 1)));
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+/// reference returns the character the body of a character reference, the
+/// part between `&` and `;`, stands for.
+fn reference(body: &str) -> Option<char> {
+    let code = if let Some(hex) = body.strip_prefix("#x").or_else(|| body.strip_prefix("#X")) {
+        u32::from_str_radix(hex, 16).ok()?
+    } else if let Some(decimal) = body.strip_prefix('#') {
+        decimal.parse().ok()?
+    } else {
+        return match body {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => None,
+        };
+    };
+    char::from_u32(code)
+}
+
+/// is_reference reports whether `text` starts with a character reference
+/// such as `&amp;`, `&#38;` or `&#x26;`.
+fn is_reference(text: &str) -> bool {
+    let body = match text.get(1..).and_then(|t| t.split(';').next()) {
+        Some(body) if body.len() < text.len() - 1 => body,
+        _ => return false,
+    };
+    if let Some(hex) = body.strip_prefix("#x").or_else(|| body.strip_prefix("#X")) {
+        !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit())
+    } else if let Some(decimal) = body.strip_prefix('#') {
+        !decimal.is_empty() && decimal.chars().all(|c| c.is_ascii_digit())
+    } else {
+        body.starts_with(|c: char| c.is_ascii_alphabetic())
+            && body.chars().all(|c| c.is_ascii_alphanumeric())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    #[test]
+    fn modes() {
+        let tests = vec![
+            (
+                "doctype",
+                "<!DOCTYPE html>",
+                "<!DOCTYPE html>",
+                "<!DOCTYPE html>",
+            ),
+            (
+                "doctype with identifiers",
+                r#"<!DOCTYPE svg PUBLIC "-//W3C//DTD SVG 1.1//EN" "svg11.dtd">"#,
+                r#"<!DOCTYPE svg PUBLIC "-//W3C//DTD SVG 1.1//EN" "svg11.dtd">"#,
+                r#"<!DOCTYPE svg PUBLIC "-//W3C//DTD SVG 1.1//EN" "svg11.dtd">"#,
+            ),
+            ("void element", "<br/>", "<br>", "<br />"),
+            ("empty element", "<div></div>", "<div></div>", "<div />"),
+            (
+                "self-closed element",
+                "<div/><p>after</p>",
+                "<div></div>\n<p>after</p>",
+                "<div />\n<p>after</p>",
+            ),
+            (
+                "empty script",
+                r#"<script src="app.js"/><p>after</p>"#,
+                "<script src=\"app.js\"></script>\n<p>after</p>",
+                "<script src=\"app.js\" />\n<p>after</p>",
+            ),
+            (
+                "empty textarea",
+                "<textarea></textarea><p>after</p>",
+                "<textarea></textarea>\n<p>after</p>",
+                "<textarea />\n<p>after</p>",
+            ),
+            (
+                "self-closed void element",
+                "<img/><BR/>",
+                "<img>\n<br>",
+                "<img />\n<br />",
+            ),
+            (
+                "boolean attribute",
+                "<input disabled/>",
+                "<input disabled>",
+                r#"<input disabled="" />"#,
+            ),
+            (
+                "attribute value",
+                r#"<a title="fish&chips">x</a>"#,
+                r#"<a title="fish&amp;chips">x</a>"#,
+                r#"<a title="fish&amp;chips">x</a>"#,
+            ),
+            (
+                "text",
+                "<p>fish & chips &amp; a > b</p>",
+                "<p>fish &amp; chips &amp; a &gt; b</p>",
+                "<p>fish &amp; chips &amp; a &gt; b</p>",
+            ),
+            (
+                "script",
+                "<script>if (a > b && c) {}</script>",
+                "<script>if (a > b && c) {}</script>",
+                "<script>if (a &gt; b &amp;&amp; c) {}</script>",
+            ),
+            (
+                "style",
+                "<style>a > b { content: '&' }</style>",
+                "<style>a > b { content: '&' }</style>",
+                "<style>a &gt; b { content: '&amp;' }</style>",
+            ),
+            (
+                "conditional comment markers",
+                "<![if !IE]><p>x</p><![endif]>",
+                "<![if !IE]>\n<p>x</p>\n<![endif]>",
+                "&lt;![if !IE]&gt;\n<p>x</p>\n&lt;![endif]&gt;",
+            ),
+            (
+                "comments and processing instructions are verbatim",
+                "<?xml version=\"1.0\"?><!-- a < b -->",
+                "<?xml version=\"1.0\"?>\n<!-- a < b -->",
+                "<?xml version=\"1.0\"?>\n<!-- a < b -->",
+            ),
+        ];
+        for (desc, input, html5, xhtml) in tests {
+            let dom = parse(input);
+            assert_eq!(
+                format!("{}\n", html5),
+                dom.to_string_with(SerializeMode::Html5),
+                "{}: html5",
+                desc
+            );
+            assert_eq!(
+                format!("{}\n", xhtml),
+                dom.to_string_with(SerializeMode::Xhtml),
+                "{}: xhtml",
+                desc
+            );
+        }
+    }
+
+    #[test]
+    fn injection() {
+        let script = "<script>alert(1)</script>";
+        let node = Node::Tag {
+            name: "div".into(),
+            attributes: vec![("title".to_string(), format!("\">{}", script))]
+                .into_iter()
+                .collect(),
+            children: vec![
+                Node::Text(format!("{} & more", script)).into(),
+                Node::Tag {
+                    name: "script".into(),
+                    attributes: HashMap::new(),
+                    children: vec![Node::Text("if (a < b && c) {}".into()).into()],
+                }
+                .into(),
+            ],
+        };
+        assert_eq!(
+            r#"<div title="&quot;><script>alert(1)</script>">&lt;script&gt;alert(1)&lt;/script&gt; &amp; more<script>if (a < b && c) {}</script></div>"#,
+            node.to_string_with(SerializeMode::Html5)
+        );
+        assert_eq!(
+            r#"<div title="&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;">&lt;script&gt;alert(1)&lt;/script&gt; &amp; more<script>if (a &lt; b &amp;&amp; c) {}</script></div>"#,
+            node.to_string_with(SerializeMode::Xhtml)
+        );
+    }
+
+    #[test]
+    fn unescaping() {
+        let tests = vec![
+            ("none", "plain text", "plain text"),
+            (
+                "named",
+                "&lt;p class=&quot;a&quot;&gt;fish &amp; chips&apos;",
+                "<p class=\"a\">fish & chips'",
+            ),
+            ("numeric", "&#38; &#x26; &#X3C;", "& & <"),
+            ("nested", "&amp;amp;", "&amp;"),
+            ("unknown name", "&copy; &nbsp;", "&copy; \u{a0}"),
+            ("not a reference", "a & b &c d &#xZZ;", "a & b &c d &#xZZ;"),
+            ("no such character", "&#xD800;", "&#xD800;"),
+            ("trailing", "&amp", "&amp"),
+        ];
+        for (desc, input, want) in tests {
+            assert_eq!(want, unescape(input), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let inputs = vec![
+            r#"<a title="fish&amp;chips" href="?a&b">fish &amp; chips</a>"#,
+            "<p>a > b & c &lt; d &#38; e</p>",
+            "<script>if (a > b && c) {}</script><style>a > b {}</style>",
+            r#"<div title="&quot;quoted&quot;">&lt;not a tag&gt;</div>"#,
+        ];
+        for input in inputs {
+            for mode in [SerializeMode::Html5, SerializeMode::Xhtml] {
+                // Escaping is idempotent, and attributes come out in the same
+                // order.
+                let once = parse(input).to_string_with(mode);
+                let twice = parse(&once).to_string_with(mode);
+                assert_eq!(once, twice, "{:?}: {}", mode, input);
+            }
+        }
+    }
+
+    #[test]
+    fn preserve() {
+        let mut inputs: Vec<String> = vec![
+            "",
+            "  \n",
+            "<!DOCTYPE html>\n<html lang=\"en\">\n  <body   class=\"a\"  id=\"b\" >\n\ttext  \n  </body>\n</html>\n",
+            "<p>unclosed<br/><img src=\"a.png\">",
+            "<ul>\n  <li>one\n  <li>two\n</ul>",
+            "<table>\n<tr><td>a<td>b\n<tr><td>c</td><td>d\n</table>\n<p>one\n<p>two",
+            "<script>if (a < b && c) {}</script><style> a > b {} </style>",
+            "<p>fish & chips</p><!-- a < b --><?xml version=\"1.0\"?>",
+            "<![if !IE]>\n<p>x</p>\n<![endif]>",
+            "<svg><linearGradient id=\"g\"><stop offset=\"0\"/></linearGradient><use href=\"#g\"/></svg>",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        for dir in std::fs::read_dir(fixtures).unwrap() {
+            for file in std::fs::read_dir(dir.unwrap().path()).unwrap() {
+                let path = file.unwrap().path();
+                // Documents in other encodings are skipped, since the
+                // source is compared as a string.
+                if path.extension().is_some_and(|ext| ext == "html") {
+                    inputs.extend(std::fs::read_to_string(path).ok());
+                }
+            }
+        }
+        for input in inputs {
+            let got = parse(&input).to_string_with(SerializeMode::Preserve);
+            assert_eq!(input, got);
+        }
+        // Only void elements are written self-closed, outside of SVG and
+        // MathML.
+        let got = parse("<div/>\n<br/>").to_string_with(SerializeMode::Preserve);
+        assert_eq!("<div></div>\n<br/>", got);
+        let got = parse("<svg><foreignObject><div/></foreignObject><path/></svg>")
+            .to_string_with(SerializeMode::Preserve);
+        assert_eq!(
+            "<svg><foreignObject><div></div></foreignObject><path/></svg>",
+            got
+        );
+    }
+
+    #[test]
+    fn preserve_changes() {
+        // Change changes the first child of the first root element.
+        type Change = fn(&mut Node);
+        let attribute = |node: &mut Node| {
+            if let Node::Tag { attributes, .. } = node {
+                attributes.insert("src".into(), "b.png".into());
+            }
+        };
+        let rename = |node: &mut Node| {
+            if let Node::Tag { name, .. } = node {
+                *name = "section".into();
+            }
+        };
+        let text = |node: &mut Node| {
+            if let Node::Tag { children, .. } = node {
+                if let Node::Text(text) = &mut *children[0].borrow_mut() {
+                    *text = "a < b".into();
+                }
+            }
+        };
+        let append = |node: &mut Node| {
+            if let Node::Tag { children, .. } = node {
+                children.push(Node::Text("new".into()).into());
+            }
+        };
+        let tests: Vec<(&str, &str, Change, &str)> = vec![
+            (
+                "attribute",
+                "<div>\n  <img  src=\"a.png\" >\n</div>\n",
+                attribute,
+                "<div>\n  <img src=\"b.png\">\n</div>\n",
+            ),
+            (
+                "renamed element",
+                "<body>\n  <div  id=\"x\">  <p>text</p>\n  </div>\n</body>",
+                rename,
+                "<body>\n  <section id=\"x\">  <p>text</p>\n  </section>\n</body>",
+            ),
+            (
+                "text",
+                "<p>\n  <span>\n  old  </span>\n</p>",
+                text,
+                "<p>\n  <span>a &lt; b</span>\n</p>",
+            ),
+            (
+                "new child of an unclosed element",
+                "<body>\n<span class=\"x\">\n</body>",
+                append,
+                "<body>\n<span class=\"x\">new</span>\n</body>",
+            ),
+            (
+                "new child of an element whose close tag was left out",
+                "<body>\n<p class=\"x\">\n</body>",
+                append,
+                "<body>\n<p class=\"x\">new\n</body>",
+            ),
+        ];
+        for (desc, input, change, want) in tests {
+            let dom = parse(input);
+            if let Node::Tag { children, .. } = &*dom.nodes[0].borrow() {
+                change(&mut children[0].borrow_mut());
+            }
+            assert_eq!(
+                want,
+                dom.to_string_with(SerializeMode::Preserve),
+                "{}",
+                desc
+            );
+        }
+    }
+
+    #[test]
+    fn references() {
+        let tests = vec![
+            ("&amp;", true),
+            ("&#38;", true),
+            ("&#x26;", true),
+            ("&nbsp; more", true),
+            ("& amp;", false),
+            ("&amp", false),
+            ("&#;", false),
+            ("&#xZ;", false),
+            ("&;", false),
+            ("&", false),
+        ];
+        for (text, want) in tests {
+            assert_eq!(want, is_reference(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn to_writer() {
+        let large = "x".repeat(4 * 1024 * 1024);
+        let input = format!(
+            "<html><body><p>{}</p><br/></body></html><!-- end -->",
+            large
+        );
+        let dom = parse(&input);
+        for mode in [SerializeMode::Html5, SerializeMode::Xhtml] {
+            let mut out = vec![];
+            dom.to_writer_with(&mut out, mode).unwrap();
+            assert!(out == dom.to_string_with(mode).into_bytes(), "{:?}", mode);
+            let node = dom.nodes[0].borrow();
+            let mut out = vec![];
+            node.to_writer_with(&mut out, mode).unwrap();
+            assert!(out == node.to_string_with(mode).into_bytes(), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn to_writer_errors() {
+        struct Full;
+        impl io::Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::WriteZero, "full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let dom = parse(&format!("<p>{}</p>", "x".repeat(64 * 1024)));
+        let err = dom.to_writer(&mut Full).unwrap_err();
+        assert_eq!(io::ErrorKind::WriteZero, err.kind());
+    }
+}
@@ -0,0 +1,272 @@
+use super::parse::{Dom, Node, NodeRef, Source};
+use std::collections::HashMap;
+
+/// SyncDom holds a DOM as an arena of nodes that refer to each other by
+/// `NodeId`, rather than by `Rc`, so that unlike a `Dom` it can be sent to
+/// another thread. Each node knows its parent, too. `Dom::into_sync` and
+/// `SyncDom::into_local` convert between the two, keeping the source of
+/// parsed nodes, so that the preserve mode still writes them as they were.
+///
+/// Ids are only those of the `SyncDom` they came from. Nodes detached from
+/// the tree keep their ids, but are dropped by `into_local`.
+#[derive(Debug, Clone, Default)]
+pub struct SyncDom {
+    entries: Vec<Entry>,
+    roots: Vec<NodeId>,
+    /// The whitespace after the last node, as `Source::tail`.
+    tail: String,
+}
+
+/// NodeId is the index of a node in a `SyncDom`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// SyncNode is a `Node` of a `SyncDom`, whose children the `SyncDom` keeps.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncNode {
+    Text(String),
+    Comment(String),
+    ProcessingInstruction(String),
+    Tag {
+        name: String,
+        attributes: HashMap<String, String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    node: SyncNode,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    /// The leading whitespace, open tag and close tag the node was parsed
+    /// from, as `Raw` holds them.
+    raw: Option<(String, String, Option<String>)>,
+}
+
+impl SyncDom {
+    /// roots returns the nodes at the top of the tree.
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    /// node returns the node `id`.
+    pub fn node(&self, id: NodeId) -> &SyncNode {
+        &self.entries[id.0].node
+    }
+
+    /// node_mut returns the node `id`, to change its text or attributes.
+    pub fn node_mut(&mut self, id: NodeId) -> &mut SyncNode {
+        &mut self.entries[id.0].node
+    }
+
+    /// children returns the children of `id`, which a node that isn't an
+    /// element hasn't got.
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.entries[id.0].children
+    }
+
+    /// parent returns the element `id` is a child of, or `None` if it's a
+    /// root or has been detached.
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.entries[id.0].parent
+    }
+
+    /// append adds `node` as the last child of the element `parent`, or as
+    /// the last root if there's no `parent`. It returns the id of the node, or
+    /// `None` if `parent` isn't an element.
+    pub fn append(&mut self, parent: Option<NodeId>, node: SyncNode) -> Option<NodeId> {
+        if let Some(parent) = parent {
+            if !matches!(self.node(parent), SyncNode::Tag { .. }) {
+                return None;
+            }
+        }
+        Some(self.push(parent, node, None))
+    }
+
+    /// detach takes `id` and its descendants out of the tree.
+    pub fn detach(&mut self, id: NodeId) {
+        let siblings = match self.entries[id.0].parent.take() {
+            Some(parent) => &mut self.entries[parent.0].children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|&sibling| sibling != id);
+    }
+
+    /// push adds an entry for `node` under `parent`.
+    fn push(
+        &mut self,
+        parent: Option<NodeId>,
+        node: SyncNode,
+        raw: Option<(String, String, Option<String>)>,
+    ) -> NodeId {
+        let id = NodeId(self.entries.len());
+        self.entries.push(Entry {
+            node,
+            parent,
+            children: vec![],
+            raw,
+        });
+        match parent {
+            Some(parent) => self.entries[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    /// into_local converts the tree back into a `Dom`, on whichever thread
+    /// it's been sent to.
+    pub fn into_local(self) -> Dom {
+        let mut source = Source::default();
+        source.tail = self.tail;
+        let mut nodes = vec![];
+        // The tree is walked with a stack rather than by recursion, as
+        // `deep_clone` walks it, so that however deeply it's nested it can be
+        // converted.
+        let mut stack: Vec<(NodeId, Option<NodeRef>)> =
+            self.roots.iter().rev().map(|&id| (id, None)).collect();
+        while let Some((id, parent)) = stack.pop() {
+            let entry = &self.entries[id.0];
+            let node: NodeRef = match &entry.node {
+                SyncNode::Text(text) => Node::Text(text.clone()),
+                SyncNode::Comment(text) => Node::Comment(text.clone()),
+                SyncNode::ProcessingInstruction(text) => Node::ProcessingInstruction(text.clone()),
+                SyncNode::Tag { name, attributes } => Node::Tag {
+                    name: name.clone(),
+                    attributes: attributes.clone(),
+                    children: vec![],
+                },
+            }
+            .into();
+            if let Some((leading, open, close)) = &entry.raw {
+                source.insert(&node, leading.clone(), open.clone(), close.clone(), None);
+            }
+            match parent.as_ref().map(|p| p.borrow_mut()) {
+                Some(mut parent) => {
+                    if let Some(children) = parent.children_mut() {
+                        children.push(node.clone());
+                    }
+                }
+                None => nodes.push(node.clone()),
+            }
+            for &child in entry.children.iter().rev() {
+                stack.push((child, Some(node.clone())));
+            }
+        }
+        Dom { nodes, source }
+    }
+}
+
+impl Dom {
+    /// into_sync converts the DOM into a `SyncDom`, which can be sent to
+    /// another thread.
+    ///
+    /// ```
+    /// use std::thread;
+    ///
+    /// let dom = inliner::html::parse("<p>Hello</p>").unwrap().into_sync();
+    /// let text = thread::spawn(move || dom.into_local().text()).join().unwrap();
+    /// assert_eq!("Hello", text);
+    /// ```
+    pub fn into_sync(self) -> SyncDom {
+        let mut sync = SyncDom {
+            tail: self.source.tail.clone(),
+            ..SyncDom::default()
+        };
+        let mut stack: Vec<(NodeRef, Option<NodeId>)> =
+            self.nodes.iter().rev().map(|n| (n.clone(), None)).collect();
+        while let Some((n, parent)) = stack.pop() {
+            let node = n.borrow();
+            let raw = self
+                .source
+                .get(&node)
+                .map(|raw| (raw.leading.clone(), raw.open.clone(), raw.close.clone()));
+            let copy = match &*node {
+                Node::Text(text) => SyncNode::Text(text.clone()),
+                Node::Comment(text) => SyncNode::Comment(text.clone()),
+                Node::ProcessingInstruction(text) => SyncNode::ProcessingInstruction(text.clone()),
+                Node::Tag {
+                    name, attributes, ..
+                } => SyncNode::Tag {
+                    name: name.clone(),
+                    attributes: attributes.clone(),
+                },
+            };
+            let id = sync.push(parent, copy, raw);
+            for child in node.children().iter().rev() {
+                stack.push((child.clone(), Some(id)));
+            }
+        }
+        sync
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::SerializeMode;
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::thread;
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    #[test]
+    fn threads() {
+        fn is_send_and_sync<T: Send + Sync>() {}
+        is_send_and_sync::<SyncDom>();
+        let input = "<!DOCTYPE html>\n<html>\n  <body   class=\"a\">\n    <img src=\"a.png\">\n    <p>text <!-- note --></p>\n  </body>\n</html>\n";
+        let docs: Vec<SyncDom> = (0..4).map(|_| parse(input).into_sync()).collect();
+        let outputs: Vec<String> = docs
+            .into_iter()
+            .map(|dom| {
+                thread::spawn(move || {
+                    let mut dom = dom;
+                    let body = dom.children(dom.roots()[1])[0];
+                    let img = dom.children(body)[0];
+                    if let SyncNode::Tag { attributes, .. } = dom.node_mut(img) {
+                        attributes.insert("src".into(), "b.png".into());
+                    }
+                    dom.into_local().to_string_with(SerializeMode::Preserve)
+                })
+            })
+            .map(|handle| handle.join().unwrap())
+            .collect();
+        for got in outputs {
+            assert_eq!(input.replace("a.png", "b.png"), got);
+        }
+    }
+
+    #[test]
+    fn tree() {
+        let mut dom = parse(r#"<div id="a"><p>one</p><p>two</p></div>text"#).into_sync();
+        let div = dom.roots()[0];
+        let (one, two) = (dom.children(div)[0], dom.children(div)[1]);
+        assert_eq!(Some(div), dom.parent(one));
+        assert_eq!(None, dom.parent(div));
+        assert_eq!(&SyncNode::Text("text".into()), dom.node(dom.roots()[1]));
+        dom.detach(one);
+        assert_eq!(None, dom.parent(one));
+        let three = dom
+            .append(
+                Some(div),
+                SyncNode::Tag {
+                    name: "p".into(),
+                    attributes: HashMap::new(),
+                },
+            )
+            .unwrap();
+        dom.append(Some(three), SyncNode::Text("three".into()));
+        assert_eq!(&[two, three], dom.children(div));
+        assert_eq!(
+            None,
+            dom.append(Some(dom.roots()[1]), SyncNode::Text("x".into())),
+            "text has no children"
+        );
+        dom.append(None, SyncNode::Comment(" end ".into()));
+        assert_eq!(
+            parse(r#"<div id="a"><p>two</p><p>three</p></div>text<!-- end -->"#),
+            dom.into_local()
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use super::parse::{Dom, Node};
+use super::serialize::unescape;
+
+/// TextOptions control how the text of a tree is extracted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextOptions {
+    /// Put this between the text of different block-level elements, such as
+    /// paragraphs or list items, rather than a space. `None` separates all
+    /// text with a space.
+    pub block_separator: Option<String>,
+    /// Include the contents of `<script>` and `<style>` elements, which
+    /// aren't text a reader sees.
+    pub include_scripts: bool,
+}
+
+/// Block-level elements, whose text is separated from the text around them.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "br",
+    "caption",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "legend",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "option",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+impl Node {
+    /// text_content returns the text of the node and its descendants, with
+    /// script and style contents left out and character references decoded,
+    /// as `serialize::unescape` decodes them. The parser trims the whitespace
+    /// around text, so pieces of text are joined with a single space.
+    pub fn text_content(&self) -> String {
+        self.text_content_with(&TextOptions::default())
+    }
+
+    /// text_content_with returns the text of the node and its descendants as
+    /// configured by `options`.
+    pub fn text_content_with(&self, options: &TextOptions) -> String {
+        let mut pieces = vec![];
+        collect(self, options, &mut pieces);
+        join(&pieces, options)
+    }
+}
+
+impl Dom {
+    /// text returns the text of the whole document, as
+    /// `Node::text_content` does.
+    pub fn text(&self) -> String {
+        self.text_with(&TextOptions::default())
+    }
+
+    /// text_with returns the text of the whole document as configured by
+    /// `options`.
+    pub fn text_with(&self, options: &TextOptions) -> String {
+        let mut pieces = vec![];
+        for node in &self.nodes {
+            collect(&node.borrow(), options, &mut pieces);
+        }
+        join(&pieces, options)
+    }
+}
+
+/// Piece is a piece of the text of a tree.
+enum Piece {
+    Text(String),
+    /// The start or end of a block-level element.
+    Break,
+}
+
+/// collect appends the pieces of text in `node` to `pieces`, in document
+/// order, with their character references decoded. The contents of scripts
+/// and styles are raw text, which has none, so they're taken as they are.
+fn collect(node: &Node, options: &TextOptions, pieces: &mut Vec<Piece>) {
+    match node {
+        Node::Text(text) => pieces.push(Piece::Text(unescape(text).into_owned())),
+        Node::Tag { name, children, .. } => {
+            let name = name.to_ascii_lowercase();
+            let is_raw = name == "script" || name == "style";
+            if is_raw && !options.include_scripts {
+                return;
+            }
+            let is_block = BLOCK_ELEMENTS.contains(&name.as_str());
+            if is_block {
+                pieces.push(Piece::Break);
+            }
+            for child in children {
+                match &*child.borrow() {
+                    Node::Text(text) if is_raw => pieces.push(Piece::Text(text.clone())),
+                    child => collect(child, options, pieces),
+                }
+            }
+            if is_block {
+                pieces.push(Piece::Break);
+            }
+        }
+        Node::Comment(_) | Node::ProcessingInstruction(_) => {}
+    }
+}
+
+/// join joins the text in `pieces` with a space, or with the block separator
+/// where a block-level element starts or ends between them. There is never a
+/// separator before the first or after the last piece of text.
+fn join(pieces: &[Piece], options: &TextOptions) -> String {
+    let mut out = String::new();
+    let mut broken = false;
+    for piece in pieces {
+        match piece {
+            Piece::Break => broken = true,
+            Piece::Text(text) => {
+                if !out.is_empty() {
+                    match &options.block_separator {
+                        Some(separator) if broken => out.push_str(separator),
+                        _ => out.push(' '),
+                    }
+                }
+                out.push_str(text);
+                broken = false;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    #[test]
+    fn text() {
+        let page = parse(
+            r#"
+            <html>
+                <head>
+                    <title>The page</title>
+                    <style>p { color: red }</style>
+                </head>
+                <body>
+                    <header>Heading</header>
+                    <p>Some <em>emphasised</em> text.</p>
+                    <!-- a comment -->
+                    <ul><li>one</li><li>two <b>bold</b></li></ul>
+                    <script>var x = 1;</script>
+                </body>
+            </html>
+            "#,
+        );
+        let separated = |separator: &str, include_scripts| TextOptions {
+            block_separator: Some(separator.into()),
+            include_scripts,
+        };
+        let tests = vec![
+            (
+                "default",
+                TextOptions::default(),
+                "The page Heading Some emphasised text. one two bold",
+            ),
+            (
+                "block separator",
+                separated("\n", false),
+                "The page\nHeading\nSome emphasised text.\none\ntwo bold",
+            ),
+            (
+                "scripts",
+                TextOptions {
+                    include_scripts: true,
+                    ..TextOptions::default()
+                },
+                "The page p { color: red } Heading Some emphasised text. one two bold var x = 1;",
+            ),
+            (
+                "scripts and block separator",
+                separated(" | ", true),
+                "The page | p { color: red } | Heading | Some emphasised text. | one | two bold | var x = 1;",
+            ),
+        ];
+        for (desc, options, want) in tests {
+            assert_eq!(want, page.text_with(&options), "{}", desc);
+        }
+        assert_eq!(
+            "The page Heading Some emphasised text. one two bold",
+            page.text()
+        );
+    }
+
+    #[test]
+    fn text_content() {
+        let dom = parse("<body><div><p>first</p><p>second <i>and</i> more</p></div></body>");
+        let div = dom.find_by_tag("div").remove(0);
+        let div = div.borrow();
+        assert_eq!("first second and more", div.text_content());
+        let options = TextOptions {
+            block_separator: Some("\n".into()),
+            include_scripts: false,
+        };
+        assert_eq!("first\nsecond and more", div.text_content_with(&options));
+        assert_eq!("", parse("<img src=\"a.png\"/>").text());
+    }
+
+    #[test]
+    fn references() {
+        let tests = vec![
+            ("named", "<p>Fish &amp; chips</p>", "Fish & chips"),
+            (
+                "numeric",
+                "<p>&#169; 2020 &#x2014; now</p>",
+                "\u{a9} 2020 \u{2014} now",
+            ),
+            ("unknown", "<p>&copy; &amp</p>", "&copy; &amp"),
+            (
+                "raw text",
+                "<p>&lt;p&gt;</p><script>a &amp;&amp; b</script>",
+                "<p> a &amp;&amp; b",
+            ),
+        ];
+        let options = TextOptions {
+            include_scripts: true,
+            ..TextOptions::default()
+        };
+        for (desc, input, want) in tests {
+            assert_eq!(want, parse(input).text_with(&options), "{}", desc);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead};
 use std::iter::Peekable;
 
 // Token is a significant grouping of characters.
@@ -14,6 +15,88 @@ where
     pub literal: L,
 }
 
+impl<K, L> From<&Token<K, L>> for Token<String, String>
+where
+    K: Borrow<str>,
+    L: Borrow<str>,
+{
+    /// from copies `token` out of whatever it borrows from.
+    ///
+    /// ```
+    /// use inliner::html::token::Token;
+    /// use inliner::html::StrTokenizer;
+    ///
+    /// let input = String::from("<p class=intro>");
+    /// let borrowed = StrTokenizer::new(&input).next().unwrap();
+    /// let owned = Token::from(&borrowed);
+    /// drop(input);
+    /// assert_eq!("<p class=intro>", owned.literal);
+    /// ```
+    fn from(token: &Token<K, L>) -> Self {
+        Token {
+            kind: match &token.kind {
+                Kind::OpenTag { name, attributes } => Kind::OpenTag {
+                    name: name.borrow().to_string(),
+                    attributes: attributes
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.borrow().to_string()))
+                        .collect(),
+                },
+                Kind::CloseTag { name } => Kind::CloseTag {
+                    name: name.borrow().to_string(),
+                },
+                Kind::Text(text) => Kind::Text(text.borrow().to_string()),
+                Kind::Comment(text) => Kind::Comment(text.borrow().to_string()),
+                Kind::ProcessingInstruction(text) => {
+                    Kind::ProcessingInstruction(text.borrow().to_string())
+                }
+            },
+            literal: token.literal.borrow().to_string(),
+        }
+    }
+}
+
+impl<K, L> Token<K, L>
+where
+    K: Borrow<str>,
+    L: Borrow<str>,
+{
+    /// to_owned copies the token out of whatever it borrows from.
+    #[deprecated(note = "use `Token::from(&token)`")]
+    pub fn to_owned(&self) -> Token<String, String> {
+        Token::from(self)
+    }
+
+    /// duplicate_attributes returns the attributes of an open tag that appear
+    /// more than once, which were ignored after their first occurrence.
+    pub fn duplicate_attributes(&self) -> Vec<&str> {
+        let mut seen = vec![];
+        let mut duplicates = vec![];
+        for name in self.attribute_names() {
+            if seen.contains(&name) {
+                if !duplicates.contains(&name) {
+                    duplicates.push(name);
+                }
+            } else {
+                seen.push(name);
+            }
+        }
+        duplicates
+    }
+
+    /// attribute_names returns the names of the attributes of an open tag in
+    /// the order they're written, duplicates included.
+    pub(crate) fn attribute_names(&self) -> Vec<&str> {
+        if !matches!(self.kind, Kind::OpenTag { .. }) {
+            return vec![];
+        }
+        match open_tag(self.literal.borrow()) {
+            Some((_, attributes)) => attributes.into_iter().map(|(name, _)| name).collect(),
+            None => vec![],
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Kind<K>
 where
@@ -27,6 +110,11 @@ where
         name: K,
     },
     Text(K),
+    /// Comment holds the text between `<!--` and `-->`.
+    Comment(K),
+    /// ProcessingInstruction holds the text between `<?` and `?>`, such as
+    /// an XML declaration or a PHP block.
+    ProcessingInstruction(K),
 }
 
 /// Tokenizer converts a char stream into a token stream.
@@ -35,7 +123,10 @@ where
     Src: Iterator<Item = char>,
 {
     source: Peekable<Src>,
-    buffer: Vec<Token<String, String>>,
+    /// Characters read ahead of the scan and put back, to be scanned again.
+    lookahead: VecDeque<char>,
+    /// Tokens scanned but not yet returned, in document order.
+    buffer: VecDeque<Token<String, String>>,
 }
 
 impl<Src> Tokenizer<Src>
@@ -45,9 +136,114 @@ where
     pub fn new(source: Src) -> Self {
         Tokenizer {
             source: source.peekable(),
-            buffer: vec![],
+            lookahead: VecDeque::new(),
+            buffer: VecDeque::new(),
+        }
+    }
+    /// next_char takes the next character, from those put back first.
+    fn next_char(&mut self) -> Option<char> {
+        self.lookahead.pop_front().or_else(|| self.source.next())
+    }
+    /// quoted_tag reads ahead through the open tag whose `<` was just
+    /// consumed, returning it if a quoted attribute value in it holds a `<`
+    /// or `>`. Otherwise what was read is put back.
+    fn quoted_tag(&mut self) -> Option<String> {
+        let mut ahead = String::from("<");
+        let mut scanner = TagScanner::default();
+        let mut end = None;
+        while end.is_none() {
+            match self.next_char() {
+                Some(c) => {
+                    ahead.push(c);
+                    end = scanner.push(c);
+                }
+                None => end = Some(scanner.end()),
+            }
+        }
+        if let Some(TagEnd::Spans(_)) = end {
+            if let Kind::OpenTag { .. } = classify(&ahead).kind {
+                return Some(ahead);
+            }
+        }
+        for c in ahead[1..].chars().rev() {
+            self.lookahead.push_front(c);
+        }
+        None
+    }
+    /// comment consumes the rest of a comment, up to and including `-->`.
+    /// The opening `<!--` must already have been consumed.
+    /// An unterminated comment runs to the end of input.
+    fn comment(&mut self) -> Token<String, String> {
+        let mut literal = String::from("<!--");
+        let mut closed = false;
+        while let Some(c) = self.next_char() {
+            literal.push(c);
+            if c == '>'
+                && (literal[4..].ends_with("-->") || literal == "<!-->" || literal == "<!--->")
+            {
+                closed = true;
+                break;
+            }
+        }
+        let text = if !closed {
+            &literal[4..]
+        } else if literal.len() >= 7 {
+            &literal[4..literal.len() - 3]
+        } else {
+            ""
+        };
+        Token {
+            kind: Kind::Comment(text.to_owned()),
+            literal,
         }
     }
+    /// processing_instruction consumes the rest of a processing instruction,
+    /// up to and including `?>`. The opening `<?` must already have been
+    /// consumed. A `?>` inside a quoted string does not end the instruction.
+    /// An unterminated instruction runs to the end of input.
+    fn processing_instruction(&mut self) -> Token<String, String> {
+        let mut literal = String::from("<?");
+        let mut quote: Option<char> = None;
+        let mut closed = false;
+        while let Some(c) = self.next_char() {
+            literal.push(c);
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"') | (None, '\'') => quote = Some(c),
+                (None, '>') if literal.len() > 3 && literal.ends_with("?>") => {
+                    closed = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let text = if closed {
+            &literal[2..literal.len() - 2]
+        } else {
+            &literal[2..]
+        };
+        Token {
+            kind: Kind::ProcessingInstruction(text.to_owned()),
+            literal,
+        }
+    }
+    /// emit queues `token`, preceded by any `text` that came before it, and
+    /// returns the first of them.
+    fn emit(
+        &mut self,
+        text: String,
+        token: Token<String, String>,
+    ) -> Option<Token<String, String>> {
+        if !text.is_empty() {
+            self.buffer.push_back(Token {
+                kind: Kind::Text(text.clone()),
+                literal: text,
+            });
+        }
+        self.buffer.push_back(token);
+        self.buffer.pop_front()
+    }
     /// merged adapts Tokenizer to an iterator that merges adjacent text tokens.
     pub fn merged(self) -> TextMerger<Tokenizer<Src>> {
         TextMerger {
@@ -64,121 +260,278 @@ where
 
     /// next returns the next xml token in the sequence.
     ///
-    /// Buffer chars until '>' is found. The buffer is processed to yield one
-    /// or more tokens.
+    /// Characters are scanned forward into a run that ends at the next '>',
+    /// or at the start of a comment or processing instruction, which are
+    /// scanned to their end by their own loops. An open tag is read ahead to
+    /// its end too, and taken whole if a quoted value in it holds a '<' or
+    /// '>', which would otherwise end the run. The characters read ahead of
+    /// any other tag are scanned again, at most once, so tokenizing is O(n)
+    /// in the length of the input however large the text between tags.
     fn next(&mut self) -> Option<Self::Item> {
         // Drain the buffer before processing more characters.
-        if !self.buffer.is_empty() {
-            return self.buffer.pop();
-        }
-        // Collect chars until we hit '>'.
-        let mut stack: Vec<char> = vec![];
-        while let Some(current) = self.source.next() {
-            stack.push(current);
-            // We begin to unwind the stack.
-            if current == '>' {
-                // Unwind the stack.
-                let mut buffer: Vec<char> = vec![];
-                while let Some(c) = stack.pop() {
-                    buffer.push(c);
-                    // If angle bracket, we might have tag.
-                    if c == '<' {
-                        // we have a buffer containing "<*.>"
-                        // try parse as open tag, close tag, else text
-                        let buffer: String = buffer.drain(..).rev().collect();
-                        if buffer.starts_with("</") {
-                            self.buffer.push(Token {
-                                kind: Kind::CloseTag {
-                                    name: buffer
-                                        .trim_start_matches("</")
-                                        .trim_end_matches(">")
-                                        .trim()
-                                        .to_owned(),
-                                },
-                                literal: buffer,
-                            });
-                        } else {
-                            let mut words = buffer
-                                .trim_start_matches('<')
-                                .trim_start_matches('/')
-                                .trim_end_matches('>')
-                                .trim_end_matches('/')
-                                .split_whitespace()
-                                .map(String::from)
-                                .collect::<Vec<String>>();
-                            // is_tag if there are words that do not contain "=\"", and
-                            // also contain non-alphabetic chars.
-                            // If the word contains "=\"" we have an attribute value
-                            // that can contain arbitrary chars, hence we can't simply
-                            // look for non-alphabetic chars.
-                            let is_tag = words.len() > 0
-                                && words.iter().fold(true, |is_tag, word| {
-                                    if !is_tag {
-                                        return false;
-                                    }
-                                    if !word.contains("=\"")
-                                        && word
-                                            // Ignore bang as a special provision for `<!DOCTYPE>`.
-                                            .trim_start_matches('!')
-                                            .contains(|c: char| !c.is_alphabetic() && c != '-')
-                                    {
-                                        false
-                                    } else {
-                                        true
-                                    }
-                                });
-                            if is_tag {
-                                let mut words = words.drain(..);
-                                let name = words.next().unwrap();
-                                let attributes: HashMap<String, String> = words
-                                    .map(|attr: String| {
-                                        let mut parts = attr.split("=");
-                                        let name = parts.next().unwrap();
-                                        let value = parts
-                                            .next()
-                                            .unwrap_or("")
-                                            .trim_start_matches('"')
-                                            .trim_end_matches('"');
-                                        (name.to_owned(), value.to_owned())
-                                    })
-                                    .collect();
-                                self.buffer.push(Token {
-                                    kind: Kind::OpenTag { name, attributes },
-                                    literal: buffer,
-                                });
-                            } else {
-                                self.buffer.push(Token {
-                                    kind: Kind::Text(buffer.clone()),
-                                    literal: buffer,
-                                });
-                            }
+        if let Some(token) = self.buffer.pop_front() {
+            return Some(token);
+        }
+        let mut run = String::new();
+        while let Some(current) = self.next_char() {
+            run.push(current);
+            match current {
+                '<' => {
+                    if let Some(tag) = self.quoted_tag() {
+                        run.pop();
+                        if !run.is_empty() {
+                            let mut tokens = VecDeque::new();
+                            split(&run, &mut tokens);
+                            self.buffer.extend(tokens.iter().map(Token::from));
                         }
+                        self.buffer.push_back(Token::from(&classify(&tag)));
+                        return self.buffer.pop_front();
                     }
                 }
-                if buffer.len() > 0 {
-                    let buffer: String = buffer.drain(..).rev().collect();
-                    self.buffer.push(Token {
-                        kind: Kind::Text(buffer.clone()),
-                        literal: buffer,
-                    });
+                // Comments run until "-->", regardless of the angle brackets
+                // they contain, so they are consumed in one go.
+                '-' if run.ends_with("<!--") => {
+                    run.truncate(run.len() - 4);
+                    let comment = self.comment();
+                    return self.emit(run, comment);
+                }
+                // Likewise processing instructions run until "?>".
+                '?' if run.ends_with("<?") => {
+                    run.truncate(run.len() - 2);
+                    let instruction = self.processing_instruction();
+                    return self.emit(run, instruction);
                 }
-                return self.buffer.pop();
+                '>' => {
+                    let mut tokens = VecDeque::new();
+                    split(&run, &mut tokens);
+                    self.buffer.extend(tokens.iter().map(Token::from));
+                    return self.buffer.pop_front();
+                }
+                _ => {}
             }
         }
-        // If we are here then we hit EOF without hitting '>'.
-        // Lets return any chars buffered as a text token.
-        if !stack.is_empty() {
-            let text: String = stack.drain(..).collect();
+        // Hit EOF without hitting '>', so the run is text.
+        if run.is_empty() {
+            None
+        } else {
             Some(Token {
-                kind: Kind::Text(text.clone()),
-                literal: text,
+                kind: Kind::Text(run.clone()),
+                literal: run,
             })
-        } else {
-            None
         }
     }
 }
 
+/// TagEnd is what scanning an open tag for its quoted values finds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TagEnd {
+    /// The tag ends this many bytes from its `<`, past a quoted value that
+    /// holds a `<` or `>`, where the value ends rather than the tag.
+    Spans(usize),
+    /// The tag, if it is one, ends at the first `>`.
+    Plain,
+    /// The input ended inside a quoted value, or past one holding a `<` or
+    /// `>` but before the end of the tag, so the tag ends at the first `>`
+    /// unless more input turns up.
+    Open,
+}
+
+/// TagScanner follows an open tag character by character, after its `<`, to
+/// find where it ends when a quoted value holds a `<` or `>`, as in the
+/// `srcdoc` of an iframe. A quote only opens a value after an `=`, and a value
+/// whose quote is never closed runs to the first `>`, as before.
+#[derive(Debug, Default)]
+struct TagScanner {
+    /// Bytes scanned, the `<` included.
+    len: usize,
+    after_equals: bool,
+    quote: Option<char>,
+    /// Whether a quoted value held a `<` or `>`.
+    spans: bool,
+}
+
+impl TagScanner {
+    /// push scans the next character, returning how the tag ends once that's
+    /// known.
+    fn push(&mut self, c: char) -> Option<TagEnd> {
+        let first = self.len == 0;
+        self.len += if first { 1 } else { 0 } + c.len_utf8();
+        match (self.quote, c) {
+            _ if first && !c.is_ascii_alphabetic() => return Some(TagEnd::Plain),
+            (Some(q), c) if c == q => self.quote = None,
+            (Some(_), '<' | '>') => self.spans = true,
+            (Some(_), _) => {}
+            (None, '>') if self.spans => return Some(TagEnd::Spans(self.len)),
+            (None, '<' | '>') => return Some(TagEnd::Plain),
+            (None, '"' | '\'') if self.after_equals => self.quote = Some(c),
+            (None, '=') => self.after_equals = true,
+            (None, c) if c.is_whitespace() => {}
+            _ => self.after_equals = false,
+        }
+        None
+    }
+
+    /// end returns how the tag ends when the input ends before it's known.
+    fn end(&self) -> TagEnd {
+        match self.quote.is_some() || self.spans {
+            true => TagEnd::Open,
+            false => TagEnd::Plain,
+        }
+    }
+}
+
+/// quoted_tag_end scans the open tag `input` starts with, whose `<` it
+/// starts at, as `TagScanner` does.
+fn quoted_tag_end(input: &str) -> TagEnd {
+    let mut scanner = TagScanner::default();
+    for c in input[1..].chars() {
+        if let Some(end) = scanner.push(c) {
+            return end;
+        }
+    }
+    scanner.end()
+}
+
+/// split tokenizes `run`, a run of characters ending in '>', onto the back
+/// of `tokens` in document order. Each '<' in the run starts a token, and
+/// anything before the first is text.
+fn split<'a>(run: &'a str, tokens: &mut VecDeque<Token<&'a str, &'a str>>) {
+    let starts: Vec<usize> = run.match_indices('<').map(|(lt, _)| lt).collect();
+    match starts.first() {
+        Some(&0) => {}
+        Some(&first) => tokens.push_back(Token {
+            kind: Kind::Text(&run[..first]),
+            literal: &run[..first],
+        }),
+        None => tokens.push_back(Token {
+            kind: Kind::Text(run),
+            literal: run,
+        }),
+    }
+    for (ii, &start) in starts.iter().enumerate() {
+        let end = starts.get(ii + 1).copied().unwrap_or(run.len());
+        tokens.push_back(classify(&run[start..end]));
+    }
+}
+
+/// classify tokenizes `segment`, which starts with `<` and runs up to the
+/// next `<` or up to and including the next `>`, as a close tag or an open
+/// tag, or as text when it isn't a tag at all.
+pub(super) fn classify(segment: &str) -> Token<&str, &str> {
+    if segment.starts_with("</") {
+        return Token {
+            kind: Kind::CloseTag {
+                name: segment
+                    .trim_start_matches("</")
+                    .trim_end_matches('>')
+                    .trim(),
+            },
+            literal: segment,
+        };
+    }
+    let (name, pairs) = match open_tag(segment) {
+        Some(tag) => tag,
+        None => {
+            return Token {
+                kind: Kind::Text(segment),
+                literal: segment,
+            }
+        }
+    };
+    // The first of duplicated attributes wins, and the rest are ignored.
+    let mut attributes = HashMap::new();
+    for (name, value) in pairs {
+        attributes.entry(name.to_owned()).or_insert(value);
+    }
+    Token {
+        kind: Kind::OpenTag { name, attributes },
+        literal: segment,
+    }
+}
+
+/// open_tag scans an open tag segment into its name and its attributes, in
+/// the order they appear, or returns `None` if the segment isn't a tag.
+///
+/// A value runs from the `=` after the attribute name to its closing quote,
+/// whatever it holds in between, or to the next whitespace if it isn't
+/// quoted. Attributes without a value have an empty one.
+fn open_tag(segment: &str) -> Option<(&str, Vec<(&str, &str)>)> {
+    let body = segment
+        .strip_prefix('<')?
+        .trim_end_matches('>')
+        .trim_end_matches('/');
+    let end = body.find(char::is_whitespace).unwrap_or(body.len());
+    let (name, mut rest) = body.split_at(end);
+    // Ignore bang as a special provision for `<!DOCTYPE>`.
+    if !is_tag_name(name.trim_start_matches('!')) {
+        return None;
+    }
+    let mut attributes = vec![];
+    loop {
+        // Browsers skip stray slashes between attributes, as in `<br / >`.
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            return Some((name, attributes));
+        }
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let (name, after) = rest.split_at(end);
+        if !is_name(name) {
+            return None;
+        }
+        let value = match after.trim_start().strip_prefix('=') {
+            Some(value) => value.trim_start(),
+            None => {
+                attributes.push((name, ""));
+                rest = after;
+                continue;
+            }
+        };
+        let (value, after) = match value.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                let value = &value[1..];
+                // An unterminated value runs to the end of the tag.
+                match value.find(quote) {
+                    Some(end) => (&value[..end], &value[end + 1..]),
+                    None => (value, ""),
+                }
+            }
+            _ => {
+                let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                let (value, after) = value.split_at(end);
+                if value.is_empty() || value.contains(['"', '\'', '=', '<', '`']) {
+                    return None;
+                }
+                (value, after)
+            }
+        };
+        attributes.push((name, value));
+        rest = after;
+    }
+}
+
+/// is_tag_name reports whether `word` can be the name of an element: an ASCII
+/// letter followed by ASCII letters, digits and hyphens, as in `h1` or a
+/// custom element like `my-widget`.
+fn is_tag_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// is_name reports whether `word` can be the name of an attribute, which is
+/// anything but whitespace, control characters, quotes, `>`, `/` and `=`, so
+/// that names like `data-id`, `xmlns:xlink`, `@click` and `(click)` are. A
+/// name must have a letter or digit in it, though, so that operators in text
+/// such as `a<b || c>d` aren't taken for attributes.
+fn is_name(word: &str) -> bool {
+    word.contains(|c: char| c.is_alphanumeric())
+        && !word.contains(|c: char| {
+            c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '<' | '>' | '/' | '=')
+        })
+}
+
 /// TextMerger merges adjacent Text Tokens into one Text Token.
 pub struct TextMerger<Src>
 where
@@ -209,8 +562,8 @@ where
                         literal: next_literal,
                     }) = self.source.next()
                     {
-                        text.extend(next_text.chars());
-                        literal.extend(next_literal.chars());
+                        text.push_str(&next_text);
+                        literal.push_str(&next_literal);
                     }
                 }
                 Some(Token {
@@ -224,6 +577,429 @@ where
     }
 }
 
+/// StrTokenizer tokenizes a string in place, as `Tokenizer` does, yielding
+/// tokens that borrow from the string rather than allocating. A `Parser`
+/// reading them allocates each string once, as it builds the node holding
+/// it, since a `Dom` owns its strings and outlives its input.
+pub struct StrTokenizer<'a> {
+    source: &'a str,
+    /// Byte offset of the first character not yet tokenized.
+    position: usize,
+    /// Byte offset up to which the tokens are final, however the input were
+    /// to go on: the end of the last tag, comment or processing instruction
+    /// that was closed.
+    complete: usize,
+    /// Whether an open tag was cut short by the end of input while in, or
+    /// just past, a quoted value, so that nothing after it is final.
+    provisional: bool,
+    /// Tokens scanned but not yet returned, in document order.
+    buffer: VecDeque<Token<&'a str, &'a str>>,
+}
+
+impl<'a> StrTokenizer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        StrTokenizer {
+            source,
+            position: 0,
+            complete: 0,
+            provisional: false,
+            buffer: VecDeque::new(),
+        }
+    }
+    /// comment tokenizes the comment whose `<!--` ends at `from`, up to and
+    /// including `-->`. An unterminated comment runs to the end of input.
+    fn comment(&mut self, from: usize) -> Token<&'a str, &'a str> {
+        let rest = &self.source[from..];
+        let end = rest
+            .match_indices('>')
+            .map(|(ii, _)| ii + 1)
+            .find(|&end| matches!(&rest[..end], ">" | "->") || rest[..end].ends_with("-->"));
+        let text = match end {
+            Some(end) if end >= 3 => &rest[..end - 3],
+            Some(_) => "",
+            None => rest,
+        };
+        self.position = from + end.unwrap_or(rest.len());
+        if end.is_some() {
+            self.complete();
+        }
+        Token {
+            kind: Kind::Comment(text),
+            literal: &self.source[from - 4..self.position],
+        }
+    }
+    /// processing_instruction tokenizes the processing instruction whose `<?`
+    /// ends at `from`, up to and including `?>`. A `?>` inside a quoted string
+    /// does not end the instruction. An unterminated instruction runs to the
+    /// end of input.
+    fn processing_instruction(&mut self, from: usize) -> Token<&'a str, &'a str> {
+        let rest = &self.source[from..];
+        let mut quote: Option<char> = None;
+        let mut end = None;
+        for (ii, c) in rest.char_indices() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"') | (None, '\'') => quote = Some(c),
+                (None, '>') if ii > 0 && rest[..ii].ends_with('?') => {
+                    end = Some(ii + 1);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let text = match end {
+            Some(end) => &rest[..end - 2],
+            None => rest,
+        };
+        self.position = from + end.unwrap_or(rest.len());
+        if end.is_some() {
+            self.complete();
+        }
+        Token {
+            kind: Kind::ProcessingInstruction(text),
+            literal: &self.source[from - 2..self.position],
+        }
+    }
+    /// complete marks the tokens up to the position as final, unless an
+    /// earlier tag may yet go on.
+    fn complete(&mut self) {
+        if !self.provisional {
+            self.complete = self.position;
+        }
+    }
+    /// emit queues `token`, preceded by any `text` that came before it, and
+    /// returns the first of them.
+    fn emit(
+        &mut self,
+        text: &'a str,
+        token: Token<&'a str, &'a str>,
+    ) -> Option<Token<&'a str, &'a str>> {
+        if !text.is_empty() {
+            self.buffer.push_back(Token {
+                kind: Kind::Text(text),
+                literal: text,
+            });
+        }
+        self.buffer.push_back(token);
+        self.buffer.pop_front()
+    }
+    /// merged adapts StrTokenizer to an iterator that merges adjacent text
+    /// tokens. Tokens are contiguous slices of the source, so merged text
+    /// still borrows from it.
+    pub fn merged(self) -> StrTextMerger<'a> {
+        StrTextMerger {
+            source: self.source,
+            tokens: self.peekable(),
+        }
+    }
+}
+
+impl<'a> Iterator for StrTokenizer<'a> {
+    type Item = Token<&'a str, &'a str>;
+
+    /// next returns the next token in the sequence.
+    ///
+    /// Characters are scanned up to the next '>', and everything from the
+    /// last token up to it yields one or more tokens. An open tag is scanned
+    /// to its end first, and taken whole if a quoted value in it holds a '<'
+    /// or '>'.
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(token) = self.buffer.pop_front() {
+            return Some(token);
+        }
+        let start = self.position;
+        let bytes = self.source.as_bytes();
+        for ii in start..bytes.len() {
+            match bytes[ii] {
+                b'-' if ii >= start + 3 && &bytes[ii - 3..ii] == b"<!-" => {
+                    let text = &self.source[start..ii - 3];
+                    let comment = self.comment(ii + 1);
+                    return self.emit(text, comment);
+                }
+                b'?' if ii > start && bytes[ii - 1] == b'<' => {
+                    let text = &self.source[start..ii - 1];
+                    let instruction = self.processing_instruction(ii + 1);
+                    return self.emit(text, instruction);
+                }
+                b'<' => match quoted_tag_end(&self.source[ii..]) {
+                    TagEnd::Spans(len) => {
+                        let tag = &self.source[ii..ii + len];
+                        if let token @ Token {
+                            kind: Kind::OpenTag { .. },
+                            ..
+                        } = classify(tag)
+                        {
+                            self.position = ii + len;
+                            self.complete();
+                            if ii > start {
+                                split(&self.source[start..ii], &mut self.buffer);
+                            }
+                            self.buffer.push_back(token);
+                            return self.buffer.pop_front();
+                        }
+                    }
+                    TagEnd::Open => self.provisional = true,
+                    TagEnd::Plain => {}
+                },
+                b'>' => {
+                    self.position = ii + 1;
+                    self.complete();
+                    split(&self.source[start..=ii], &mut self.buffer);
+                    return self.buffer.pop_front();
+                }
+                _ => {}
+            }
+        }
+        // Hit EOF without hitting '>', so the rest is text.
+        self.position = bytes.len();
+        let text = &self.source[start..];
+        if text.is_empty() {
+            None
+        } else {
+            Some(Token {
+                kind: Kind::Text(text),
+                literal: text,
+            })
+        }
+    }
+}
+
+/// StrTextMerger merges adjacent Text Tokens from a StrTokenizer into one
+/// Text Token, without allocating.
+pub struct StrTextMerger<'a> {
+    source: &'a str,
+    tokens: Peekable<StrTokenizer<'a>>,
+}
+
+impl<'a> Iterator for StrTextMerger<'a> {
+    type Item = Token<&'a str, &'a str>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.tokens.next()?;
+        let first = match token.kind {
+            Kind::Text(text) => text,
+            _ => return Some(token),
+        };
+        let mut last = first;
+        while let Some(Token {
+            kind: Kind::Text(text),
+            ..
+        }) = self.tokens.peek()
+        {
+            last = *text;
+            self.tokens.next();
+        }
+        let offset = |text: &str| text.as_ptr() as usize - self.source.as_ptr() as usize;
+        let text = &self.source[offset(first)..offset(last) + last.len()];
+        Some(Token {
+            kind: Kind::Text(text),
+            literal: text,
+        })
+    }
+}
+
+/// TokenSink tokenizes input pushed to it in chunks as it arrives, say from a
+/// socket, where `Tokenizer` pulls it from an iterator that blocks until it
+/// can. Each chunk returns the tokens it completed and `finish` returns the
+/// rest, which together are the tokens `StrTokenizer::merged` yields for the
+/// whole input, however it was split.
+///
+/// A token is complete once no more input could change it: a tag at its `>`,
+/// a comment or processing instruction at its end, and text at the next token
+/// that isn't text. Whatever follows the last of those, such as half a tag,
+/// attribute value or entity, waits for the next chunk.
+///
+/// ```
+/// use inliner::html::TokenSink;
+///
+/// let literals = |tokens: Vec<inliner::html::token::Token<String, String>>| {
+///     tokens.into_iter().map(|t| t.literal).collect::<Vec<_>>()
+/// };
+/// let mut sink = TokenSink::new();
+/// assert!(sink.feed("<p class=\"in").is_empty());
+/// assert_eq!(vec!["<p class=\"intro\">"], literals(sink.feed("tro\">Hello, ")));
+/// assert_eq!(vec!["Hello, world", "</p>"], literals(sink.feed("world</p>!")));
+/// assert_eq!(vec!["!"], literals(sink.finish()));
+/// ```
+#[derive(Debug, Default)]
+pub struct TokenSink {
+    /// Input not yet tokenized, which starts at the start of a token.
+    input: String,
+    /// Text tokenized but not yet returned, since more may follow it.
+    text: String,
+}
+
+impl TokenSink {
+    pub fn new() -> Self {
+        TokenSink::default()
+    }
+
+    /// feed pushes `chunk` onto the input, returning the tokens it completed.
+    pub fn feed(&mut self, chunk: &str) -> Vec<Token<String, String>> {
+        self.input.push_str(chunk);
+        // Every token is completed by a '>', so until one arrives the input
+        // can't be tokenized any further than it was.
+        if !chunk.contains('>') {
+            return vec![];
+        }
+        let mut tokenizer = StrTokenizer::new(&self.input);
+        let tokens: Vec<Token<&str, &str>> = tokenizer.by_ref().collect();
+        let complete = tokenizer.complete;
+        let mut out = vec![];
+        for token in tokens {
+            let end = token.literal.as_ptr() as usize - self.input.as_ptr() as usize
+                + token.literal.len();
+            if end > complete {
+                break;
+            }
+            merge(&mut self.text, token, &mut out);
+        }
+        self.input.drain(..complete);
+        out
+    }
+
+    /// finish ends the input, returning the tokens that were left, trailing
+    /// text included.
+    pub fn finish(mut self) -> Vec<Token<String, String>> {
+        let mut out = vec![];
+        for token in StrTokenizer::new(&self.input) {
+            merge(&mut self.text, token, &mut out);
+        }
+        if !self.text.is_empty() {
+            out.push(Token {
+                kind: Kind::Text(self.text.clone()),
+                literal: self.text,
+            });
+        }
+        out
+    }
+}
+
+/// merge pushes `token` onto `out`, unless it's text, which is held in `text`
+/// until a token that isn't text follows it.
+fn merge(text: &mut String, token: Token<&str, &str>, out: &mut Vec<Token<String, String>>) {
+    if let Kind::Text(more) = token.kind {
+        text.push_str(more);
+        return;
+    }
+    if !text.is_empty() {
+        let text = std::mem::take(text);
+        out.push(Token {
+            kind: Kind::Text(text.clone()),
+            literal: text,
+        });
+    }
+    out.push(Token::from(&token));
+}
+
+/// ReadChars decodes the characters of UTF-8 text as they are read, so that a
+/// `Tokenizer` can tokenize a document without it being read into memory
+/// first. A byte order mark at the start is skipped.
+///
+/// The characters end at the first error, which `error` returns: an error
+/// reading, or UTF-8 that isn't valid, with the byte offset it starts at.
+pub struct ReadChars<R> {
+    reader: R,
+    /// Bytes of input decoded so far.
+    offset: usize,
+    error: Option<io::Error>,
+}
+
+impl<R: BufRead> ReadChars<R> {
+    pub fn new(reader: R) -> Self {
+        ReadChars {
+            reader,
+            offset: 0,
+            error: None,
+        }
+    }
+
+    /// error returns the error that ended the characters early, if any.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// into_error returns the error that ended the characters early, if any.
+    pub fn into_error(self) -> Option<io::Error> {
+        self.error
+    }
+
+    /// read_char reads the next character, or `None` at the end of input.
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        let mut bytes = [0; 4];
+        let mut len = 0;
+        let mut width = 1;
+        while len < width {
+            let buf = match self.reader.fill_buf() {
+                Ok(buf) => buf,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            if buf.is_empty() {
+                return match len {
+                    0 => Ok(None),
+                    _ => Err(self.invalid()),
+                };
+            }
+            if len == 0 {
+                width = utf8_width(buf[0]);
+                if width == 0 {
+                    return Err(self.invalid());
+                }
+            }
+            let n = (width - len).min(buf.len());
+            bytes[len..len + n].copy_from_slice(&buf[..n]);
+            self.reader.consume(n);
+            len += n;
+        }
+        match std::str::from_utf8(&bytes[..width]) {
+            Ok(c) => {
+                self.offset += width;
+                Ok(c.chars().next())
+            }
+            Err(_) => Err(self.invalid()),
+        }
+    }
+
+    fn invalid(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid UTF-8 at byte {}", self.offset),
+        )
+    }
+}
+
+impl<R: BufRead> Iterator for ReadChars<R> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        let at_start = self.offset == 0;
+        match self.read_char() {
+            Ok(Some('\u{feff}')) if at_start => self.next(),
+            Ok(c) => c,
+            Err(err) => {
+                self.error = Some(err);
+                None
+            }
+        }
+    }
+}
+
+/// utf8_width returns the length of the UTF-8 sequence `first` starts, or 0
+/// if no sequence starts with it.
+fn utf8_width(first: u8) -> usize {
+    match first {
+        0x00..=0x7f => 1,
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => 0,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -233,31 +1009,6 @@ mod tests {
         pairs.iter().map(|(k, v)| (k.to_string(), *v)).collect()
     }
 
-    impl<K, L> Token<K, L>
-    where
-        K: Borrow<str>,
-        L: Borrow<str>,
-    {
-        fn to_owned(&self) -> Token<String, String> {
-            Token {
-                kind: match &self.kind {
-                    Kind::OpenTag { name, attributes } => Kind::OpenTag {
-                        name: name.borrow().to_string(),
-                        attributes: attributes
-                            .iter()
-                            .map(|(k, v)| (k.to_string(), v.borrow().to_string()))
-                            .collect(),
-                    },
-                    Kind::CloseTag { name } => Kind::CloseTag {
-                        name: name.borrow().to_string(),
-                    },
-                    Kind::Text(text) => Kind::Text(text.borrow().to_string()),
-                },
-                literal: self.literal.borrow().to_string(),
-            }
-        }
-    }
-
     #[test]
     fn tokenizer() {
         let tests = vec![
@@ -267,22 +1018,20 @@ mod tests {
                 vec![
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag-tag".into(),
+                            name: "tag-tag",
                             attributes: HashMap::new(),
                         },
                         literal: "<tag-tag/>",
                     },
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag-tag".into(),
+                            name: "tag-tag",
                             attributes: HashMap::new(),
                         },
                         literal: "<tag-tag>",
                     },
                     Token {
-                        kind: Kind::CloseTag {
-                            name: "tag-tag".into(),
-                        },
+                        kind: Kind::CloseTag { name: "tag-tag" },
                         literal: "</tag-tag>",
                     },
                 ],
@@ -293,18 +1042,18 @@ mod tests {
                 vec![
                     Token {
                         kind: Kind::OpenTag {
-                            name: "first".into(),
+                            name: "first",
                             attributes: HashMap::new(),
                         },
                         literal: "<first/>",
                     },
                     Token {
-                        kind: Kind::Text("text".into()),
+                        kind: Kind::Text("text"),
                         literal: "text",
                     },
                     Token {
                         kind: Kind::OpenTag {
-                            name: "second".into(),
+                            name: "second",
                             attributes: HashMap::new(),
                         },
                         literal: "<second />",
@@ -317,13 +1066,13 @@ mod tests {
                 vec![
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: HashMap::new(),
                         },
                         literal: "<tag>",
                     },
                     Token {
-                        kind: Kind::CloseTag { name: "tag".into() },
+                        kind: Kind::CloseTag { name: "tag" },
                         literal: "</tag>",
                     },
                 ],
@@ -334,58 +1083,78 @@ mod tests {
                 vec![
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: map(&[("one", "")]),
                         },
                         literal: r#"<tag one/>"#,
                     },
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: map(&[("one", ""), ("two", "two")]),
                         },
                         literal: r#"<tag one two="two"/>"#,
                     },
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: map(&[("one", ""), ("two", "two")]),
                         },
                         literal: r#"<tag one two="two">"#,
                     },
                     Token {
-                        kind: Kind::CloseTag { name: "tag".into() },
+                        kind: Kind::CloseTag { name: "tag" },
                         literal: "</tag>",
                     },
                 ],
             ),
+            (
+                "duplicate attributes - the first wins",
+                r#"<img src="a.png" src="b.png"/><p class="one" class="two" hidden hidden="hidden">"#,
+                vec![
+                    Token {
+                        kind: Kind::OpenTag {
+                            name: "img",
+                            attributes: map(&[("src", "a.png")]),
+                        },
+                        literal: r#"<img src="a.png" src="b.png"/>"#,
+                    },
+                    Token {
+                        kind: Kind::OpenTag {
+                            name: "p",
+                            attributes: map(&[("class", "one"), ("hidden", "")]),
+                        },
+                        literal: r#"<p class="one" class="two" hidden hidden="hidden">"#,
+                    },
+                ],
+            ),
             (
                 "tag with attributes - whitespace before end of open tag",
                 r#"<tag one /><tag one two="two" /><tag one two="two" ></tag>"#,
                 vec![
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: map(&[("one", "")]),
                         },
                         literal: r#"<tag one />"#,
                     },
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: map(&[("one", ""), ("two", "two")]),
                         },
                         literal: r#"<tag one two="two" />"#,
                     },
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: map(&[("one", ""), ("two", "two")]),
                         },
                         literal: r#"<tag one two="two" >"#,
                     },
                     Token {
-                        kind: Kind::CloseTag { name: "tag".into() },
+                        kind: Kind::CloseTag { name: "tag" },
                         literal: "</tag>",
                     },
                 ],
@@ -394,7 +1163,7 @@ mod tests {
                 "simple text",
                 "text",
                 vec![Token {
-                    kind: Kind::Text("text".into()),
+                    kind: Kind::Text("text"),
                     literal: "text",
                 }],
             ),
@@ -404,32 +1173,32 @@ mod tests {
                 vec![
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: HashMap::new(),
                         },
                         literal: "<tag>",
                     },
                     Token {
-                        kind: Kind::Text("text".into()),
+                        kind: Kind::Text("text"),
                         literal: "text",
                     },
                     Token {
-                        kind: Kind::CloseTag { name: "tag".into() },
+                        kind: Kind::CloseTag { name: "tag" },
                         literal: "</tag>",
                     },
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: HashMap::new(),
                         },
                         literal: "<tag>",
                     },
                     Token {
-                        kind: Kind::Text(" text ".into()),
+                        kind: Kind::Text(" text "),
                         literal: " text ",
                     },
                     Token {
-                        kind: Kind::CloseTag { name: "tag".into() },
+                        kind: Kind::CloseTag { name: "tag" },
                         literal: "</tag>",
                     },
                 ],
@@ -440,47 +1209,47 @@ mod tests {
                 vec![
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: HashMap::new(),
                         },
                         literal: "<tag>",
                     },
                     Token {
-                        kind: Kind::Text("text".into()),
+                        kind: Kind::Text("text"),
                         literal: "text",
                     },
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: HashMap::new(),
                         },
                         literal: "<tag/>",
                     },
                     Token {
-                        kind: Kind::Text("text".into()),
+                        kind: Kind::Text("text"),
                         literal: "text",
                     },
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: HashMap::new(),
                         },
                         literal: "<tag>",
                     },
                     Token {
-                        kind: Kind::Text("text".into()),
+                        kind: Kind::Text("text"),
                         literal: "text",
                     },
                     Token {
-                        kind: Kind::CloseTag { name: "tag".into() },
+                        kind: Kind::CloseTag { name: "tag" },
                         literal: "</tag>",
                     },
                     Token {
-                        kind: Kind::Text("text".into()),
+                        kind: Kind::Text("text"),
                         literal: "text",
                     },
                     Token {
-                        kind: Kind::CloseTag { name: "tag".into() },
+                        kind: Kind::CloseTag { name: "tag" },
                         literal: "</tag>",
                     },
                 ],
@@ -490,7 +1259,7 @@ mod tests {
                 "<!DOCTYPE html>",
                 vec![Token {
                     kind: Kind::OpenTag {
-                        name: "!DOCTYPE".into(),
+                        name: "!DOCTYPE",
                         attributes: map(&[("html", "")]),
                     },
                     literal: "<!DOCTYPE html>",
@@ -500,7 +1269,7 @@ mod tests {
                 "text with angle brackets",
                 "if (foo < bar || bar > foo) {throw new Error()}",
                 vec![Token {
-                    kind: Kind::Text("if (foo < bar || bar > foo) {throw new Error()}".into()),
+                    kind: Kind::Text("if (foo < bar || bar > foo) {throw new Error()}"),
                     literal: "if (foo < bar || bar > foo) {throw new Error()}",
                 }],
             ),
@@ -508,7 +1277,7 @@ mod tests {
                 "text: no whitespace around angle brackets",
                 "if (foo<bar || bar>foo) {throw new Error()}",
                 vec![Token {
-                    kind: Kind::Text("if (foo<bar || bar>foo) {throw new Error()}".into()),
+                    kind: Kind::Text("if (foo<bar || bar>foo) {throw new Error()}"),
                     literal: "if (foo<bar || bar>foo) {throw new Error()}",
                 }],
             ),
@@ -518,21 +1287,17 @@ mod tests {
                 vec![
                     Token {
                         kind: Kind::OpenTag {
-                            name: "script".into(),
+                            name: "script",
                             attributes: HashMap::new(),
                         },
                         literal: "<script>",
                     },
                     Token {
-                        kind: Kind::Text(
-                            r#"if (1 < 2) {alert("hi");}if (1 < 2) {alert("hi");}"#.into(),
-                        ),
+                        kind: Kind::Text(r#"if (1 < 2) {alert("hi");}if (1 < 2) {alert("hi");}"#),
                         literal: r#"if (1 < 2) {alert("hi");}if (1 < 2) {alert("hi");}"#,
                     },
                     Token {
-                        kind: Kind::CloseTag {
-                            name: "script".into(),
-                        },
+                        kind: Kind::CloseTag { name: "script" },
                         literal: "</script>",
                     },
                 ],
@@ -543,28 +1308,553 @@ mod tests {
                 vec![
                     Token {
                         kind: Kind::OpenTag {
-                            name: "tag".into(),
+                            name: "tag",
                             attributes: HashMap::new(),
                         },
                         literal: "<tag>",
                     },
                     Token {
                         kind: Kind::Text(
-                            "<><<<<<>>>>><<><><><><<> asdfajal;skjdf <<> >  >> <> <>><><".into(),
+                            "<><<<<<>>>>><<><><><><<> asdfajal;skjdf <<> >  >> <> <>><><",
                         ),
                         literal: "<><<<<<>>>>><<><><><><<> asdfajal;skjdf <<> >  >> <> <>><><",
                     },
                     Token {
-                        kind: Kind::CloseTag { name: "tag".into() },
+                        kind: Kind::CloseTag { name: "tag" },
                         literal: "</tag>",
                     },
                 ],
             ),
+            (
+                "comment",
+                "<!-- a comment -->",
+                vec![Token {
+                    kind: Kind::Comment(" a comment "),
+                    literal: "<!-- a comment -->",
+                }],
+            ),
+            (
+                "comment containing markup and angle brackets",
+                "text<!-- <tag a=\"b\"> a > b --><tag>",
+                vec![
+                    Token {
+                        kind: Kind::Text("text"),
+                        literal: "text",
+                    },
+                    Token {
+                        kind: Kind::Comment(r#" <tag a="b"> a > b "#),
+                        literal: r#"<!-- <tag a="b"> a > b -->"#,
+                    },
+                    Token {
+                        kind: Kind::OpenTag {
+                            name: "tag",
+                            attributes: HashMap::new(),
+                        },
+                        literal: "<tag>",
+                    },
+                ],
+            ),
+            (
+                "empty comments",
+                "<!----><!--><!--->",
+                vec![
+                    Token {
+                        kind: Kind::Comment(""),
+                        literal: "<!---->",
+                    },
+                    Token {
+                        kind: Kind::Comment(""),
+                        literal: "<!-->",
+                    },
+                    Token {
+                        kind: Kind::Comment(""),
+                        literal: "<!--->",
+                    },
+                ],
+            ),
+            (
+                "unterminated comment runs to end of input",
+                "<!-- a <tag>",
+                vec![Token {
+                    kind: Kind::Comment(" a <tag>"),
+                    literal: "<!-- a <tag>",
+                }],
+            ),
+            (
+                "xml declaration",
+                r#"<?xml version="1.0" encoding="UTF-8"?><svg>"#,
+                vec![
+                    Token {
+                        kind: Kind::ProcessingInstruction(r#"xml version="1.0" encoding="UTF-8""#),
+                        literal: r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                    },
+                    Token {
+                        kind: Kind::OpenTag {
+                            name: "svg",
+                            attributes: HashMap::new(),
+                        },
+                        literal: "<svg>",
+                    },
+                ],
+            ),
+            (
+                "processing instruction with a quoted end marker",
+                r#"<?xml-stylesheet href="a?>b.xsl"?>"#,
+                vec![Token {
+                    kind: Kind::ProcessingInstruction(r#"xml-stylesheet href="a?>b.xsl""#),
+                    literal: r#"<?xml-stylesheet href="a?>b.xsl"?>"#,
+                }],
+            ),
+            (
+                "multi-line php short tag containing angle brackets",
+                "<p><?\n  if ($a < $b && $c > $d) {\n    echo '<br>';\n  }\n?></p>",
+                vec![
+                    Token {
+                        kind: Kind::OpenTag {
+                            name: "p",
+                            attributes: HashMap::new(),
+                        },
+                        literal: "<p>",
+                    },
+                    Token {
+                        kind: Kind::ProcessingInstruction(
+                            "\n  if ($a < $b && $c > $d) {\n    echo '<br>';\n  }\n",
+                        ),
+                        literal: "<?\n  if ($a < $b && $c > $d) {\n    echo '<br>';\n  }\n?>",
+                    },
+                    Token {
+                        kind: Kind::CloseTag { name: "p" },
+                        literal: "</p>",
+                    },
+                ],
+            ),
         ];
         for (desc, input, want) in tests {
             let got: Vec<Token<_, _>> = Tokenizer::new(input.chars()).merged().collect();
-            let want: Vec<Token<_, _>> = want.into_iter().map(|t| t.to_owned()).collect();
+            let want: Vec<Token<_, _>> = want.iter().map(Token::from).collect();
             assert_eq!(want, got, "{}", desc,);
+            let borrowed: Vec<Token<_, _>> = StrTokenizer::new(input)
+                .merged()
+                .map(|t| Token::from(&t))
+                .collect();
+            assert_eq!(want, borrowed, "{}: borrowed", desc);
+        }
+    }
+
+    #[test]
+    fn tag_names() {
+        let tests = vec![
+            (
+                "headings",
+                "<h1>a</h1><h2>b</h2><h3>c</h3><h4>d</h4><h5>e</h5><h6>f</h6>",
+                vec!["h1", "h2", "h3", "h4", "h5", "h6"],
+            ),
+            (
+                "heading with attributes",
+                r#"<H2 class="title">b</H2>"#,
+                vec!["H2"],
+            ),
+            (
+                "custom elements",
+                "<my-widget><x-1></x-1></my-widget>",
+                vec!["my-widget", "x-1"],
+            ),
+            (
+                "svg",
+                r#"<svg><defs><font-face font-family="a"></font-face></defs></svg>"#,
+                vec!["svg", "defs", "font-face"],
+            ),
+            ("spaced", "a < b and c > d", vec![]),
+            ("leading digit", "<1>one</1>", vec![]),
+            ("leading hyphen", "<-x>", vec![]),
+            ("punctuation", "<h1!><a.b>", vec![]),
+        ];
+        for (desc, input, want) in tests {
+            let got: Vec<&str> = StrTokenizer::new(input)
+                .filter_map(|token| match token.kind {
+                    Kind::OpenTag { name, .. } => Some(name),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(want, got, "{}", desc);
+        }
+    }
+
+    #[test]
+    fn attribute_names() {
+        let tests = vec![
+            (
+                "data",
+                r#"<div data-app data-id="1">"#,
+                Some(vec![("data-app", ""), ("data-id", "1")]),
+            ),
+            (
+                "aria",
+                r#"<span aria-hidden="true" aria-label="close">"#,
+                Some(vec![("aria-hidden", "true"), ("aria-label", "close")]),
+            ),
+            (
+                "namespaced",
+                r#"<svg xmlns:xlink="http://www.w3.org/1999/xlink">"#,
+                Some(vec![("xmlns:xlink", "http://www.w3.org/1999/xlink")]),
+            ),
+            ("lone @click", "<button @click>", Some(vec![("@click", "")])),
+            (
+                "bindings",
+                r#"<input :value="name" @input="update" v-model="name" (click)="go()">"#,
+                Some(vec![
+                    (":value", "name"),
+                    ("@input", "update"),
+                    ("v-model", "name"),
+                    ("(click)", "go()"),
+                ]),
+            ),
+            (
+                "stray slash",
+                "<input disabled / required/>",
+                Some(vec![("disabled", ""), ("required", "")]),
+            ),
+            ("quote in a name", r#"<p a"b>"#, None),
+            ("operators", "<b || c>", None),
+        ];
+        for (desc, input, want) in tests {
+            let got = open_tag(input).map(|(_, attributes)| attributes);
+            assert_eq!(want, got, "{}", desc);
+        }
+        let got: Vec<Token<&str, &str>> = StrTokenizer::new("<div data-app>text</div>").collect();
+        assert_eq!(
+            Token {
+                kind: Kind::OpenTag {
+                    name: "div",
+                    attributes: map(&[("data-app", "")]),
+                },
+                literal: "<div data-app>",
+            },
+            got[0]
+        );
+    }
+
+    #[test]
+    fn attribute_values() {
+        let tests = vec![
+            (
+                "query string",
+                r#"<a href="page?id=5&mode=edit">"#,
+                Some(vec![("href", "page?id=5&mode=edit")]),
+            ),
+            (
+                "double equals",
+                r#"<img src="data:image/png;base64,AAAA==" alt="a==b">"#,
+                Some(vec![
+                    ("src", "data:image/png;base64,AAAA=="),
+                    ("alt", "a==b"),
+                ]),
+            ),
+            ("exactly =", r#"<p title="=">"#, Some(vec![("title", "=")])),
+            (
+                "url in a style",
+                r#"<p style="background:url(a=b)">"#,
+                Some(vec![("style", "background:url(a=b)")]),
+            ),
+            (
+                "whitespace in a quoted value",
+                r#"<p class="one two"  title=" a = b ">"#,
+                Some(vec![("class", "one two"), ("title", " a = b ")]),
+            ),
+            (
+                "single quotes",
+                r#"<p title='say "hi"' class='a=b'>"#,
+                Some(vec![("title", r#"say "hi""#), ("class", "a=b")]),
+            ),
+            (
+                "unquoted",
+                "<input type=text value=a/b disabled/>",
+                Some(vec![("type", "text"), ("value", "a/b"), ("disabled", "")]),
+            ),
+            (
+                "whitespace around =",
+                r#"<a href = "page.html" >"#,
+                Some(vec![("href", "page.html")]),
+            ),
+            (
+                "unterminated value",
+                r#"<a href="page.html>"#,
+                Some(vec![("href", "page.html")]),
+            ),
+            ("missing value", "<a href=>", None),
+            ("quote in an unquoted value", "<a href=a\"b>", None),
+            ("no name", r#"<="x">"#, None),
+        ];
+        for (desc, input, want) in tests {
+            let got = open_tag(input).map(|(_, attributes)| attributes);
+            assert_eq!(want, got, "{}", desc);
+        }
+        let got: Vec<Token<&str, &str>> =
+            StrTokenizer::new(r#"<a href="?a=1&b=2" title="x = y">link</a>"#).collect();
+        assert_eq!(
+            Token {
+                kind: Kind::OpenTag {
+                    name: "a",
+                    attributes: map(&[("href", "?a=1&b=2"), ("title", "x = y")]),
+                },
+                literal: r#"<a href="?a=1&b=2" title="x = y">"#,
+            },
+            got[0]
+        );
+    }
+
+    #[test]
+    fn duplicate_attributes() {
+        let tests = vec![
+            ("none", r#"<img src="a.png" alt="a"/>"#, vec![]),
+            ("src", r#"<img src="a.png" src="b.png"/>"#, vec!["src"]),
+            (
+                "reported once",
+                r#"<p class="a" id="x" class="b" class="c" id="y">"#,
+                vec!["class", "id"],
+            ),
+            ("boolean", "<input disabled disabled>", vec!["disabled"]),
+            ("close tag", "</p>", vec![]),
+            ("text", "src src", vec![]),
+        ];
+        for (desc, input, want) in tests {
+            for token in StrTokenizer::new(input) {
+                assert_eq!(want, token.duplicate_attributes(), "{}", desc);
+                assert_eq!(want, Token::from(&token).duplicate_attributes(), "{}", desc);
+            }
+        }
+    }
+
+    #[test]
+    fn read_chars() {
+        let tests = vec![
+            ("ascii", &b"<p>text</p>"[..], "<p>text</p>", None),
+            (
+                "multibyte",
+                "<p>é ✓ 😀</p>".as_bytes(),
+                "<p>é ✓ 😀</p>",
+                None,
+            ),
+            ("byte order mark", b"\xef\xbb\xbf<p>", "<p>", None),
+            (
+                "invalid byte",
+                b"<p>ok</p>\xff<p>",
+                "<p>ok</p>",
+                Some("invalid UTF-8 at byte 9"),
+            ),
+            (
+                "invalid continuation",
+                b"<p>\xc3(</p>",
+                "<p>",
+                Some("invalid UTF-8 at byte 3"),
+            ),
+            (
+                "truncated",
+                b"<p>\xe2\x9c",
+                "<p>",
+                Some("invalid UTF-8 at byte 3"),
+            ),
+        ];
+        for (desc, input, want, err) in tests {
+            // A buffer of one byte splits every multibyte character between
+            // reads.
+            for capacity in [1, 8 * 1024] {
+                let mut chars = ReadChars::new(io::BufReader::with_capacity(capacity, input));
+                let got: String = chars.by_ref().collect();
+                assert_eq!(want, got, "{}: {}", desc, capacity);
+                let got = chars.into_error().map(|err| err.to_string());
+                assert_eq!(err.map(str::to_owned), got, "{}: {}", desc, capacity);
+            }
+        }
+    }
+
+    #[test]
+    fn document_order() {
+        let tests = vec![
+            (
+                "text before the first tag",
+                "hello<br>",
+                vec!["hello", "<br>"],
+            ),
+            (
+                "text before several tags in one run",
+                "a<b<c>d",
+                vec!["a", "<b", "<c>", "d"],
+            ),
+            (
+                "text between tags",
+                "hello<em>big</em>world<br/>",
+                vec!["hello", "<em>", "big", "</em>", "world", "<br/>"],
+            ),
+            (
+                "text before a comment",
+                "hello<!-- c -->there<br>",
+                vec!["hello", "<!-- c -->", "there", "<br>"],
+            ),
+            (
+                "text before a processing instruction",
+                "hello<?php x ?>there<br>",
+                vec!["hello", "<?php x ?>", "there", "<br>"],
+            ),
+            (
+                "text with a stray '>'",
+                "a > b<br>",
+                vec!["a >", " b", "<br>"],
+            ),
+        ];
+        for (desc, input, want) in tests {
+            let owned: Vec<String> = Tokenizer::new(input.chars()).map(|t| t.literal).collect();
+            assert_eq!(want, owned, "{}", desc);
+            let borrowed: Vec<&str> = StrTokenizer::new(input).map(|t| t.literal).collect();
+            assert_eq!(want, borrowed, "{}: borrowed", desc);
+        }
+    }
+
+    #[test]
+    fn owned_from_borrowed() {
+        let input = String::from(r#"<a href="/" class=nav>Home</a><!-- x --><?pi?>"#);
+        let borrowed: Vec<Token<&str, &str>> = StrTokenizer::new(&input).collect();
+        let owned: Vec<Token<String, String>> = borrowed.iter().map(Token::from).collect();
+        drop(borrowed);
+        drop(input);
+        let want: Vec<Token<String, String>> =
+            Tokenizer::new(r#"<a href="/" class=nav>Home</a><!-- x --><?pi?>"#.chars()).collect();
+        assert_eq!(want, owned);
+        let token = StrTokenizer::new("<p>").next().unwrap();
+        #[allow(deprecated)]
+        let shim = token.to_owned();
+        assert_eq!(Token::from(&token), shim, "deprecated to_owned");
+    }
+
+    #[test]
+    fn borrowed_matches_owned() {
+        let inputs = vec![
+            "",
+            "a<b<c>d",
+            "<<>>",
+            "text<!-- comment -->more",
+            "<!---->",
+            "<!-->",
+            "<!--->",
+            "<!-- unterminated",
+            "a<!-b>",
+            r#"<?xml version="1.0"?><root/>"#,
+            "<?php echo '?>'; ?>",
+            "<??>",
+            "<? unterminated",
+            "if (a < b) { c > d }",
+            "<p>caf\u{e9} \u{1f980}</p><br/>",
+            "trailing <",
+            r#"<img src="a.png" alt="x"/>text</p>"#,
+            r#"a<iframe srcdoc="<p class='x'>hi</p>"></iframe>"#,
+            r#"<a title="x > y" href='<'>b</a>"#,
+            r#"<a href="page.html>unterminated"#,
+            r#"<a title="<b>" <i>"#,
+        ];
+        for input in inputs {
+            let owned: Vec<Token<_, _>> = Tokenizer::new(input.chars()).collect();
+            let borrowed: Vec<Token<_, _>> =
+                StrTokenizer::new(input).map(|t| Token::from(&t)).collect();
+            assert_eq!(owned, borrowed, "{:?}", input);
+            let owned: Vec<Token<_, _>> = Tokenizer::new(input.chars()).merged().collect();
+            let borrowed: Vec<Token<_, _>> = StrTokenizer::new(input)
+                .merged()
+                .map(|t| Token::from(&t))
+                .collect();
+            assert_eq!(owned, borrowed, "{:?}: merged", input);
+        }
+    }
+
+    #[test]
+    fn quoted_markup() {
+        let tests = vec![
+            (
+                "srcdoc",
+                r#"<iframe srcdoc="<p class='x'>hi &amp; bye</p>">"#,
+                vec![r#"<iframe srcdoc="<p class='x'>hi &amp; bye</p>">"#],
+            ),
+            (
+                "text before",
+                r#"a<b<img alt='1 > 0'>"#,
+                vec!["a", "<b", "<img alt='1 > 0'>"],
+            ),
+            (
+                "unterminated value",
+                r#"<a href="page.html>link"#,
+                vec![r#"<a href="page.html>"#, "link"],
+            ),
+            (
+                "quote that isn't a value",
+                r#"<p "x>">"#,
+                vec![r#"<p "x>"#, r#"">"#],
+            ),
+            (
+                "tag after the value",
+                r#"<a title="<b>" <i>"#,
+                vec![r#"<a title=""#, r#"<b>"#, r#"" "#, "<i>"],
+            ),
+        ];
+        for (desc, input, want) in tests {
+            let got: Vec<&str> = StrTokenizer::new(input).map(|t| t.literal).collect();
+            assert_eq!(want, got, "{}", desc);
+        }
+        let got: Vec<Token<&str, &str>> =
+            StrTokenizer::new(r#"<iframe srcdoc="<p>a &quot;b&quot;</p>" title=x>"#).collect();
+        assert_eq!(
+            Kind::OpenTag {
+                name: "iframe",
+                attributes: map(&[("srcdoc", "<p>a &quot;b&quot;</p>"), ("title", "x")]),
+            },
+            got[0].kind
+        );
+    }
+
+    #[test]
+    fn token_sink() {
+        let inputs = vec![
+            "",
+            "a<b<c>d",
+            "<<>>",
+            "text<!-- comment -->more",
+            "<!-- a > b -->",
+            "<!---->",
+            "<!-->",
+            "<!--->",
+            "<!-- unterminated",
+            "a<!-b>",
+            r#"<?xml version="1.0"?><root/>"#,
+            "<?php echo '?>'; ?>",
+            "<? unterminated",
+            "if (a < b) { c > d }",
+            "<p>caf\u{e9} \u{1f980}</p><br/>",
+            "trailing <",
+            r#"<img src="a.png" alt="x > y"/>text</p>"#,
+            "<p title='fish &amp; chips'>fish &amp; chips &#x1F41F;</p>",
+            "<script>if (a<b && c>d) {}</script>",
+            r#"<iframe srcdoc="<p class='x'>fish &amp; chips</p>"></iframe>"#,
+            r#"<a href="page.html>unterminated"#,
+        ];
+        for input in inputs {
+            let want: Vec<Token<_, _>> = StrTokenizer::new(input)
+                .merged()
+                .map(|t| Token::from(&t))
+                .collect();
+            // Split in two at every character.
+            for (split, _) in input.char_indices().skip(1) {
+                let mut sink = TokenSink::new();
+                let mut got = sink.feed(&input[..split]);
+                got.extend(sink.feed(&input[split..]));
+                got.extend(sink.finish());
+                assert_eq!(want, got, "{:?} split at {}", input, split);
+            }
+            // And one character at a time.
+            let mut sink = TokenSink::new();
+            let mut got = vec![];
+            for c in input.chars() {
+                got.extend(sink.feed(c.encode_utf8(&mut [0; 4])));
+            }
+            got.extend(sink.finish());
+            assert_eq!(want, got, "{:?} a character at a time", input);
         }
     }
 }
@@ -0,0 +1,352 @@
+use super::parse::{is_ended_by, Dom, Node, NodeRef};
+use super::serialize::is_void;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// Severity is how serious a `ValidationIssue` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Markup browsers repair, so the page works, but perhaps not as meant.
+    Warning,
+    /// Markup that's invalid, or that browsers restructure.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Rule is a check `Dom::validate_with` runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// An element is missing its close tag, or a close tag of another
+    /// element ended it, so the parser ended it where browsers would.
+    Unclosed,
+    /// More than one element has the same `id`.
+    DuplicateId,
+    /// An `<li>` isn't inside a `<ul>`, `<ol>` or `<menu>`.
+    StrayListItem,
+    /// An element is inside one that its start tag would end, such as a
+    /// `<div>` inside a `<p>`, which browsers split in two.
+    Nesting,
+    /// A void element, such as `<br>`, has children, which can't be written.
+    VoidChildren,
+    /// A close tag closed no open element, so the parser dropped it, as
+    /// browsers do.
+    StrayCloseTag,
+}
+
+impl Rule {
+    /// ALL is every rule, which `Dom::validate` runs.
+    pub const ALL: &'static [Rule] = &[
+        Rule::Unclosed,
+        Rule::DuplicateId,
+        Rule::StrayListItem,
+        Rule::Nesting,
+        Rule::VoidChildren,
+        Rule::StrayCloseTag,
+    ];
+
+    /// severity returns how serious breaking the rule is.
+    pub fn severity(self) -> Severity {
+        match self {
+            Rule::Unclosed | Rule::StrayListItem | Rule::StrayCloseTag => Severity::Warning,
+            Rule::DuplicateId | Rule::Nesting | Rule::VoidChildren => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Rule::Unclosed => "unclosed",
+            Rule::DuplicateId => "duplicate-id",
+            Rule::StrayListItem => "stray-list-item",
+            Rule::Nesting => "nesting",
+            Rule::VoidChildren => "void-children",
+            Rule::StrayCloseTag => "stray-close-tag",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "unclosed" => Ok(Rule::Unclosed),
+            "duplicate-id" => Ok(Rule::DuplicateId),
+            "stray-list-item" => Ok(Rule::StrayListItem),
+            "nesting" => Ok(Rule::Nesting),
+            "void-children" => Ok(Rule::VoidChildren),
+            "stray-close-tag" => Ok(Rule::StrayCloseTag),
+            _ => Err(format!("unknown rule: {}", s)),
+        }
+    }
+}
+
+/// ValidationIssue is a problem `Dom::validate` found with an element.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+    /// The element the problem is with, if it's with one: a stray close tag
+    /// was dropped before there was a tree.
+    pub node: Option<NodeRef>,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.severity, self.message, self.rule)
+    }
+}
+
+/// Elements that a start tag doesn't end an element outside of, as the
+/// scopes of the HTML parsing algorithm have it.
+const SCOPE_BOUNDARIES: &[&str] = &[
+    "applet", "button", "caption", "html", "marquee", "object", "table", "td", "template", "th",
+    "ol", "ul", "dl", "menu",
+];
+
+/// Elements an `<li>` belongs in.
+const LISTS: &[&str] = &["ul", "ol", "menu"];
+
+/// Visit is a step of the walk over the tree.
+enum Visit {
+    Enter(NodeRef),
+    /// Leave the element last entered.
+    Leave,
+}
+
+impl Dom {
+    /// validate checks the structure of the document by every rule, for
+    /// linting it before it's shipped, and returns the problems found in
+    /// document order, with the close tags the parser dropped last.
+    ///
+    /// ```
+    /// let dom = inliner::html::parse("<p>one<span><div>two</div></span></p>").unwrap();
+    /// let issues: Vec<String> = dom.validate().iter().map(ToString::to_string).collect();
+    /// assert_eq!(vec!["error: <div> can't be inside <p> (nesting)"], issues);
+    /// ```
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        self.validate_with(Rule::ALL)
+    }
+
+    /// validate_with checks the structure of the document by `rules`.
+    pub fn validate_with(&self, rules: &[Rule]) -> Vec<ValidationIssue> {
+        let mut issues = vec![];
+        let mut ids: HashMap<String, NodeRef> = HashMap::new();
+        let inert = self.template_content();
+        // The elements the walk is inside, innermost last.
+        let mut ancestors: Vec<NodeRef> = vec![];
+        let mut stack: Vec<Visit> = self.nodes.iter().rev().cloned().map(Visit::Enter).collect();
+        while let Some(visit) = stack.pop() {
+            let n = match visit {
+                Visit::Enter(n) => n,
+                Visit::Leave => {
+                    ancestors.pop();
+                    continue;
+                }
+            };
+            let node = n.borrow();
+            let (name, children) = match &*node {
+                Node::Tag { name, children, .. } if !name.starts_with('!') => (name, children),
+                _ => continue,
+            };
+            let mut issue = |rule: Rule, message: String| {
+                if rules.contains(&rule) {
+                    issues.push(ValidationIssue {
+                        rule,
+                        severity: rule.severity(),
+                        message,
+                        node: Some(n.clone()),
+                    });
+                }
+            };
+            let is_unclosed = self.source.get(&node).is_some_and(|raw| {
+                raw.close.is_none() && !raw.open.ends_with("/>") && !is_void(name)
+            });
+            if is_unclosed {
+                issue(Rule::Unclosed, format!("<{}> is never closed", name));
+            }
+            // Ids inside templates are only used once they're copied out.
+            if let Some(id) = node.attr("id") {
+                if !inert.contains(&Rc::as_ptr(&n)) {
+                    match ids.get(id) {
+                        Some(first) => issue(
+                            Rule::DuplicateId,
+                            format!(
+                                "<{}> has the id {:?} of an earlier <{}>",
+                                name,
+                                id,
+                                first.borrow().tag_name().unwrap_or_default()
+                            ),
+                        ),
+                        None => {
+                            ids.insert(id.to_owned(), n.clone());
+                        }
+                    }
+                }
+            }
+            let parent = ancestors.last().map(|a| a.borrow());
+            let in_list = parent.as_ref().is_some_and(|parent| {
+                LISTS.iter().any(|l| parent.is_element(l)) || parent.is_element("template")
+            });
+            if node.is_element("li") && !in_list {
+                issue(
+                    Rule::StrayListItem,
+                    "<li> isn't inside a <ul>, <ol> or <menu>".into(),
+                );
+            }
+            drop(parent);
+            for ancestor in ancestors.iter().rev() {
+                let ancestor = ancestor.borrow();
+                let ancestor = ancestor.tag_name().unwrap_or_default();
+                if is_ended_by(ancestor, name) {
+                    issue(
+                        Rule::Nesting,
+                        format!("<{}> can't be inside <{}>", name, ancestor),
+                    );
+                    break;
+                }
+                if SCOPE_BOUNDARIES
+                    .iter()
+                    .any(|b| b.eq_ignore_ascii_case(ancestor))
+                {
+                    break;
+                }
+            }
+            if is_void(name) && !children.is_empty() {
+                issue(
+                    Rule::VoidChildren,
+                    format!("<{}> is void, but has children", name),
+                );
+            }
+            ancestors.push(n.clone());
+            stack.push(Visit::Leave);
+            stack.extend(children.iter().rev().cloned().map(Visit::Enter));
+        }
+        if rules.contains(&Rule::StrayCloseTag) {
+            for (position, name) in &self.source.stray {
+                issues.push(ValidationIssue {
+                    rule: Rule::StrayCloseTag,
+                    severity: Rule::StrayCloseTag.severity(),
+                    message: format!("</{}> at {} closes no open element", name, position),
+                    node: None,
+                });
+            }
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    #[test]
+    fn rules() {
+        // The parser never gives void elements children, so the document
+        // breaking that rule is built as a script changing the tree would.
+        let void = Dom::from(vec![Node::Tag {
+            name: "p".into(),
+            attributes: HashMap::new(),
+            children: vec![Node::Tag {
+                name: "br".into(),
+                attributes: HashMap::new(),
+                children: vec![Node::Text("text".into()).into()],
+            }
+            .into()],
+        }
+        .into()]);
+        let tests = vec![
+            (
+                Rule::Unclosed,
+                parse("<div><p>one<p>two</div><ul><li>a<li>b</ul><img src=a.png><b/>"),
+                parse("<div><span>text</div><section><em>one</section>"),
+                vec![
+                    "warning: <span> is never closed (unclosed)",
+                    "warning: <em> is never closed (unclosed)",
+                ],
+            ),
+            (
+                Rule::DuplicateId,
+                parse(r#"<p id="a"></p><p id="b"></p><template><p id="a"></p></template>"#),
+                parse(r#"<p id="a"></p><div><span id="a"></span></div><p id="a"></p>"#),
+                vec![
+                    r#"error: <span> has the id "a" of an earlier <p> (duplicate-id)"#,
+                    r#"error: <p> has the id "a" of an earlier <p> (duplicate-id)"#,
+                ],
+            ),
+            (
+                Rule::StrayListItem,
+                parse("<ul><li>a</li></ul><ol><li>b</li></ol><menu><li>c</li></menu><template><li>d</li></template>"),
+                parse("<li>a</li><div><li>b</li></div>"),
+                vec![
+                    "warning: <li> isn't inside a <ul>, <ol> or <menu> (stray-list-item)",
+                    "warning: <li> isn't inside a <ul>, <ol> or <menu> (stray-list-item)",
+                ],
+            ),
+            (
+                Rule::Nesting,
+                parse("<p><span>a</span></p><ul><li><ul><li>b</li></ul></li></ul><p><button><div>c</div></button></p>"),
+                parse("<p><span><div>a</div></span></p><ul><li><b><li>b</li></b></li></ul>"),
+                vec![
+                    "error: <div> can't be inside <p> (nesting)",
+                    "error: <li> can't be inside <li> (nesting)",
+                ],
+            ),
+            (
+                Rule::VoidChildren,
+                parse("<p>text<br>text<img src=a.png></p>"),
+                void,
+                vec!["error: <br> is void, but has children (void-children)"],
+            ),
+            (
+                Rule::StrayCloseTag,
+                parse("<div><p>a</div><ul><li>b</ul><table><td>c</table>"),
+                parse("<div>a</span></div>\n</p>"),
+                vec![
+                    "warning: </span> at 1:7 closes no open element (stray-close-tag)",
+                    "warning: </p> at 2:1 closes no open element (stray-close-tag)",
+                ],
+            ),
+        ];
+        for (rule, valid, invalid, want) in tests {
+            assert_eq!(
+                Vec::<ValidationIssue>::new(),
+                valid.validate(),
+                "{}: valid",
+                rule
+            );
+            let got: Vec<String> = invalid
+                .validate_with(&[rule])
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            assert_eq!(want, got, "{}: invalid", rule);
+            let others: Vec<Rule> = Rule::ALL.iter().copied().filter(|r| *r != rule).collect();
+            let got: Vec<Rule> = invalid
+                .validate_with(&others)
+                .iter()
+                .map(|issue| issue.rule)
+                .collect();
+            assert!(!got.contains(&rule), "{}: disabled", rule);
+            assert_eq!(Ok(rule), rule.to_string().parse(), "{}: name", rule);
+        }
+    }
+}
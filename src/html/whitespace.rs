@@ -0,0 +1,180 @@
+//! The whitespace model of the serializer, for the formats that rewrite the
+//! page rather than preserving it.
+//!
+//! The parser trims text, so the whitespace between nodes is only known from
+//! the source. Where the source had whitespace between phrasing content, such
+//! as between two inline-block `<span>`s, it's written as a single space,
+//! which renders the same. Next to block-level elements, at the start and end
+//! of them, and inside elements such as `<table>` and `<head>` whose
+//! whitespace is never rendered, it's dropped. Preformatted elements keep it
+//! as it was. Whitespace that wasn't in the source is never written.
+
+use super::parse::Node;
+
+/// Elements laid out as blocks, which end the line before them and start
+/// another after them, so the whitespace around and inside their edges
+/// doesn't render. Every other element, custom elements included, is taken
+/// to be phrasing content, as browsers lay unknown elements out inline.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "caption",
+    "center",
+    "dd",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "frame",
+    "frameset",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "legend",
+    "li",
+    "listing",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "search",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+/// Elements whose whitespace text isn't rendered at all, as they only hold
+/// other elements, or text that isn't laid out.
+const UNRENDERED_ELEMENTS: &[&str] = &[
+    "colgroup", "datalist", "frameset", "head", "html", "optgroup", "script", "select", "style",
+    "table", "tbody", "template", "tfoot", "thead", "title", "tr",
+];
+
+/// Elements whose whitespace renders as it was written.
+const PREFORMATTED_ELEMENTS: &[&str] = &["listing", "plaintext", "pre", "textarea", "xmp"];
+
+/// spacing returns what to write for the `whitespace` the source had between
+/// `before` and `after`, the nodes either side of it inside `parent`. A side
+/// is `None` at the start or end of `parent`.
+pub(super) fn spacing<'a>(
+    whitespace: &'a str,
+    parent: &str,
+    before: Option<&Node>,
+    after: Option<&Node>,
+) -> &'a str {
+    if whitespace.is_empty() || is_one_of(parent, UNRENDERED_ELEMENTS) {
+        return "";
+    }
+    if is_one_of(parent, PREFORMATTED_ELEMENTS) {
+        return whitespace;
+    }
+    let is_inline = |side: Option<&Node>| match side {
+        Some(node) => !node
+            .tag_name()
+            .is_some_and(|name| name.starts_with('!') || is_one_of(name, BLOCK_ELEMENTS)),
+        None => !is_one_of(parent, BLOCK_ELEMENTS),
+    };
+    match is_inline(before) && is_inline(after) {
+        true => " ",
+        false => "",
+    }
+}
+
+/// is_one_of reports whether the element `name` is one of `names`, ignoring
+/// case.
+fn is_one_of(name: &str, names: &[&str]) -> bool {
+    names.iter().any(|n| n.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{parse, SerializeMode};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn rendering() {
+        let tests = vec![
+            (
+                "inline blocks apart",
+                "<div>\n  <span class=\"tile\">a</span>\n  <span class=\"tile\">b</span>\n</div>",
+                "<div><span class=\"tile\">a</span> <span class=\"tile\">b</span></div>",
+            ),
+            (
+                "inline blocks together",
+                "<div>\n  <span class=\"tile\">a</span><span class=\"tile\">b</span>\n</div>",
+                "<div><span class=\"tile\">a</span><span class=\"tile\">b</span></div>",
+            ),
+            (
+                "text and phrasing",
+                "<p>Hello <b>big </b>world<i> !</i></p>",
+                "<p>Hello <b>big </b>world<i> !</i></p>",
+            ),
+            (
+                "newlines collapse",
+                "<p>one\n  <em>two</em>\n  three</p>",
+                "<p>one <em>two</em> three</p>",
+            ),
+            (
+                "blocks",
+                "<body>\n  <h1>Title</h1>\n  text\n  <p>para</p>\n</body>",
+                "<body><h1>Title</h1>text<p>para</p></body>",
+            ),
+            (
+                "table",
+                "<table>\n  <tr>\n    <td> a </td>\n    <td><b>b</b> <i>c</i></td>\n  </tr>\n</table>",
+                "<table><tr><td>a</td><td><b>b</b> <i>c</i></td></tr></table>",
+            ),
+            (
+                "head",
+                "<head>\n  <meta charset=\"utf-8\">\n  <title> Page </title>\n  <link rel=\"stylesheet\" href=\"a.css\">\n</head>",
+                "<head><meta charset=\"utf-8\"><title>Page</title><link href=\"a.css\" rel=\"stylesheet\"></head>",
+            ),
+            (
+                "preformatted",
+                "<pre>\n  a\n  b\n</pre>",
+                "<pre>\n  a\n  b\n</pre>",
+            ),
+            (
+                "nothing synthesized",
+                "<p><b>a</b><i>b</i>c</p>",
+                "<p><b>a</b><i>b</i>c</p>",
+            ),
+        ];
+        for (desc, input, want) in tests {
+            let dom = parse(input).unwrap();
+            assert_eq!(
+                want,
+                dom.to_string_with(SerializeMode::Html5).trim_end(),
+                "{}",
+                desc
+            );
+        }
+    }
+}
@@ -1,74 +1,4219 @@
-use crate::html::{Node, NodeRef, Parser, Tokenizer};
+use crate::base::Base;
+use crate::csp::{self, CspMode, InlineHash};
+use crate::css;
+use crate::email;
+use crate::encoding;
+use crate::extract::is_javascript;
+use crate::fetch::FetchOptions;
+use crate::html::serialize::{to_srcdoc, unescape};
+use crate::html::{
+    Dom, Node, NodeRef, ParseMode, Parser, ReadChars, SerializeMode, StrTokenizer, Tokenizer,
+};
+use crate::literals;
+use crate::manifest;
+use crate::merge::{self, Inlined};
+use crate::mhtml;
+use crate::module;
+use crate::optimize::{ImageOptions, Optimization, Optimizer};
+use crate::resource::{Filesystem, Measured, ResourceError, Resources};
+use crate::sanitize::{is_event_handler, sanitize, Removal, SanitizeOptions};
+use crate::srcset::SrcSet;
+use crate::svg;
+use encoding_rs::{Encoding, UTF_8};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs;
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::path::Path;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
 
-/// Inline html resources into a single html buffer. Consumes input.
+/// Options control how resources are inlined.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Resources larger than this many bytes are left linked rather than
+    /// embedded. `None` embeds every resource regardless of size.
+    pub size_threshold: Option<u64>,
+    /// Leave elements whose resources fail to load as they are, with a
+    /// warning, rather than aborting. Images referenced by web app manifests
+    /// are skipped one by one.
+    pub keep_going: bool,
+    /// Embed empty files as data urls too. They're otherwise left linked,
+    /// with a warning, as a data url without content is a broken image.
+    pub embed_empty: bool,
+    /// Reduce `<picture>` elements to their fallback `<img>`, dropping the
+    /// `<source>` alternatives so only one image is embedded.
+    pub picture_collapse: bool,
+    /// Inline the resources inside downlevel-revealed conditional comments,
+    /// which are otherwise preserved verbatim. Downlevel-hidden conditional
+    /// comments are plain comments and always preserved verbatim.
+    pub inline_conditional_comments: bool,
+    /// Embed the files behind `<a>` and `<area>` links as data urls, so they
+    /// can be downloaded from the inlined document. Anchors are otherwise
+    /// left alone, since they link to other pages rather than load resources.
+    pub inline_anchors: bool,
+    /// Inline the resources inside `<template>` elements, whose content is
+    /// inert until a script puts a copy of it in the document. Templates are
+    /// otherwise left as they are.
+    pub inline_templates: bool,
+    /// Inline the resources of the document in the `srcdoc` of an iframe,
+    /// resolving its links as those of the page. It's otherwise kept as it
+    /// was written.
+    pub inline_srcdoc: bool,
+    /// Embed the images `<meta>` tags name by their `content`, such as the
+    /// Open Graph image, for archives to be complete. They're otherwise left
+    /// as they are, since the scrapers that read them don't read data urls,
+    /// and aren't counted by `strict` either.
+    pub inline_meta_images: bool,
+    /// Keep the image inside a `<noscript>` in place of the placeholder
+    /// `<img>` beside it, which a lazy loader would swap the image into, and
+    /// drop the placeholder. The resources inside `<noscript>` elements are
+    /// inlined either way, for browsers without scripts.
+    pub prefer_noscript_images: bool,
+    /// Embed the files that string literals in inline scripts and event
+    /// handlers link to, such as the `'hover.png'` of
+    /// `onmouseover="this.src='hover.png'"`, where the file exists. Links
+    /// are resolved against the document, as scripts resolve them, and
+    /// literals that only hold part of a link are left as they are.
+    pub rewrite_js_literals: bool,
+    /// Syntax the inlined document is written out with. By default what
+    /// isn't inlined is written as it was in the source.
+    pub format: SerializeMode,
+    /// Encoding of linked scripts and stylesheets that don't declare their
+    /// own. `None` means UTF-8. `inline_bytes` uses the document's encoding.
+    pub encoding: Option<&'static Encoding>,
+    /// Maximum number of resources `inline_async` reads at once. `None`
+    /// reads every resource it can at once.
+    pub concurrency: Option<usize>,
+    /// How many links deep to follow from the document, where a stylesheet
+    /// the document links to is one deep and the stylesheets it imports are
+    /// two deep. Deeper references are left as they are, with a warning.
+    pub max_depth: usize,
+    /// Remove `<base>` elements, which have nothing left to apply to once
+    /// the links they affect are inlined.
+    pub remove_base: bool,
+    /// Inline resources that resolve outside of the base directory, which
+    /// are refused by default so that a document can't read arbitrary files.
+    pub allow_outside_base: bool,
+    /// Bundle the module graphs of `<script type="module">` elements by
+    /// replacing the relative specifiers of their static imports with data
+    /// urls of the modules they import.
+    pub bundle_modules: bool,
+    /// Fail when the inlined document still references anything outside of
+    /// itself, as `Dom::external_references` finds, rather than produce a
+    /// document that isn't self-contained.
+    pub strict: bool,
+    /// Remove comments from the document, except for conditional comments
+    /// and license comments, which start with `!`.
+    pub strip_comments: bool,
+    /// Remove active content, as `sanitize` does, before inlining, so that
+    /// scripts are never read in. `None` leaves the document as it is.
+    pub sanitize: Option<SanitizeOptions>,
+    /// How markup that isn't well formed is parsed. Strict parsing fails at
+    /// the first problem, where lenient parsing repairs it.
+    pub parse_mode: ParseMode,
+    /// Re-encode PNGs and JPEGs before they are embedded, keeping those it
+    /// doesn't shrink as they are. It needs the `optimize-images` feature;
+    /// `None` embeds images as they are.
+    pub optimize_images: Option<ImageOptions>,
+    /// Inline the resources in the `data-src` and `data-srcset` attributes
+    /// that lazy loaders such as lazysizes swap in for `src` and `srcset`.
+    /// `None` leaves them as links.
+    pub lazy_attributes: Option<LazyAttributes>,
+    /// What to do about a content security policy `<meta>` tag that blocks
+    /// the inline scripts, inline styles or data urls of the inlined
+    /// document.
+    pub csp: CspMode,
+    /// Nonce to give the scripts and styles that resources are inlined into,
+    /// to match the nonce of a content security policy the document is
+    /// served with.
+    pub nonce: Option<String>,
+    /// Compute the hashes a content security policy allows the inline
+    /// scripts and styles of the inlined document by, into `Report::hashes`.
+    pub hashes: bool,
+    /// List the links to other local pages, such as those of anchors, into
+    /// `Report::links`, for knowing which files must travel together.
+    pub report_links: bool,
+    /// Rewrite the links to local pages that are inlined into outputs of
+    /// their own, so that they go to those outputs from the output of the
+    /// document. `None` leaves them as they're written.
+    pub rewrite_links: Option<LinkRewrite>,
+    /// Move the rules of `<style>` elements, once stylesheets are inlined
+    /// into them, into the `style` attributes of the elements they match,
+    /// for email clients that only honor those. Rules that can't be inlined,
+    /// such as media queries, are left in the `<style>` elements.
+    pub email: bool,
+    /// Merge the `<style>` elements stylesheets are inlined into into the
+    /// first of them, as far as that keeps the order of the cascade.
+    pub merge_css: bool,
+    /// Merge the `<script>` elements classic scripts are inlined into with
+    /// those of the scripts right before them.
+    pub merge_js: bool,
+    /// Write the same output for the same input wherever it's inlined, for
+    /// reproducible builds: inlined text has its line endings normalized to
+    /// `\n`, and the paths in warnings are relative to the document, with
+    /// `/` separators.
+    pub deterministic: bool,
+    /// Keep the line endings of inlined text as they are, even when
+    /// `deterministic` is set.
+    pub keep_line_endings: bool,
+    /// The headers, cookies and user agent sent when fetching a page and its
+    /// resources from the web, with the `remote` feature.
+    pub fetch: FetchOptions,
+    /// The transformations run on the inlined document before it's written
+    /// out, in order, as `with_transform` adds them.
+    pub transforms: Vec<Transform>,
+}
+
+impl Options {
+    /// with_transform adds `transform` to the transformations run on the
+    /// document once its resources are inlined, after those added before it.
+    /// It changes the document through its `NodeRef`s, which serializing
+    /// then writes out, so the output isn't parsed again. An error from it
+    /// stops inlining with a `TransformError`.
+    ///
+    /// ```
+    /// use inliner::html::Node;
+    /// use inliner::{inline_with, Options};
+    /// use std::collections::HashMap;
+    /// use std::path::Path;
+    ///
+    /// let options = Options::default().with_transform(|dom| {
+    ///     let head = dom.find_by_tag("head").pop().ok_or("no head")?;
+    ///     let generator = Node::element(
+    ///         "meta",
+    ///         HashMap::from([
+    ///             ("name".to_string(), "generator".to_string()),
+    ///             ("content".to_string(), "inliner".to_string()),
+    ///         ]),
+    ///     );
+    ///     let mut head = head.borrow_mut();
+    ///     head.children_mut().ok_or("head isn't an element")?.push(generator.into());
+    ///     Ok(())
+    /// });
+    /// let input = "<html><head><title>Home</title></head><body></body></html>";
+    /// let (output, _) = inline_with(input.into(), Path::new("."), &options).unwrap();
+    /// assert_eq!(
+    ///     r#"<html><head><title>Home</title><meta content="inliner" name="generator"></head><body></body></html>"#,
+    ///     output
+    /// );
+    /// ```
+    pub fn with_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(&mut Dom) -> Result<(), Box<dyn Error>> + Send + Sync + 'static,
+    {
+        self.transforms.push(Transform(Arc::new(transform)));
+        self
+    }
+}
+
+/// Transform is a transformation of the inlined document, which
+/// `Options::with_transform` adds.
+#[derive(Clone)]
+pub struct Transform(Arc<TransformFn>);
+
+type TransformFn = dyn Fn(&mut Dom) -> Result<(), Box<dyn Error>> + Send + Sync;
+
+impl fmt::Debug for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Transform")
+    }
+}
+
+/// LazyAttributes is what becomes of the `data-src` and `data-srcset`
+/// attributes of lazily loaded elements once they're inlined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LazyAttributes {
+    /// Keep the inlined resources in the data attributes, so that the lazy
+    /// loader still swaps them in.
+    Keep,
+    /// Move the inlined resources into `src` and `srcset`, replacing the
+    /// placeholders there, and drop the `lazyload` class, so that the lazy
+    /// loader has nothing left to do.
+    Promote,
+}
+
+impl FromStr for LazyAttributes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "keep" => Ok(LazyAttributes::Keep),
+            "promote" => Ok(LazyAttributes::Promote),
+            _ => Err(format!("unknown lazy attribute handling: {}", s)),
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            size_threshold: None,
+            keep_going: false,
+            embed_empty: false,
+            picture_collapse: false,
+            inline_conditional_comments: false,
+            inline_anchors: false,
+            inline_templates: true,
+            inline_srcdoc: false,
+            inline_meta_images: false,
+            prefer_noscript_images: false,
+            rewrite_js_literals: false,
+            format: SerializeMode::Preserve,
+            encoding: None,
+            concurrency: None,
+            max_depth: 8,
+            remove_base: false,
+            allow_outside_base: false,
+            bundle_modules: false,
+            strict: false,
+            strip_comments: false,
+            sanitize: None,
+            parse_mode: ParseMode::Lenient,
+            optimize_images: None,
+            lazy_attributes: None,
+            csp: CspMode::Warn,
+            nonce: None,
+            hashes: false,
+            report_links: false,
+            rewrite_links: None,
+            email: false,
+            merge_css: false,
+            merge_js: false,
+            deterministic: false,
+            keep_line_endings: false,
+            fetch: FetchOptions::default(),
+            transforms: vec![],
+        }
+    }
+}
+
+/// Report collects what happened during an inline run that did not stop it.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub warnings: Vec<Warning>,
+    /// The active content removed when sanitizing.
+    pub removed: Vec<Removal>,
+    /// The images considered for re-encoding, with their sizes.
+    pub optimized: Vec<Optimization>,
+    /// The hashes of the inline scripts and styles, when asked for.
+    pub hashes: Vec<InlineHash>,
+    /// The links of the document to other local pages, when asked for, in
+    /// document order. Those of its frames aren't included.
+    pub links: Vec<PageLink>,
+    /// The local files the document depends on, once each, in the order
+    /// they were linked to.
+    pub dependencies: Vec<Dependency>,
+    /// The links deliberately left as links, once each, such as those to
+    /// resources over `size_threshold` or past `max_depth`. `strict` doesn't
+    /// count them.
+    pub skipped: Vec<String>,
+    /// The resources embedded in the output, once each, in the order they
+    /// were first embedded.
+    pub embedded: Vec<Embedded>,
+    /// The size of the output in bytes, where the function returning the
+    /// report writes it.
+    pub output_size: u64,
+}
+
+impl Report {
+    /// largest_resources returns the `n` embedded resources that take up the
+    /// most of the output, largest first, for deciding what to leave out of
+    /// a bundle that's too large.
+    pub fn largest_resources(&self, n: usize) -> Vec<&Embedded> {
+        let mut largest: Vec<&Embedded> = self.embedded.iter().collect();
+        largest.sort_by_key(|e| Reverse(e.size));
+        largest.truncate(n);
+        largest
+    }
+}
+
+/// Embedded is a resource embedded in the output, with the bytes it takes up
+/// there: its size as it is for stylesheets and scripts, and base64-encoded
+/// for everything else, summed over each time it's embedded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Embedded {
+    /// The path the resource was read from.
+    pub path: String,
+    pub size: u64,
+}
+
+impl fmt::Display for Embedded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} bytes", self.path, self.size)
+    }
+}
+
+/// Warning describes a resource that was left untouched, or markup that was
+/// repaired, and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub element: String,
+    pub link: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<{}> {}: {}", self.element, self.link, self.reason)
+    }
+}
+
+/// LinkError is an error loading the resource an element links to, which
+/// stopped inlining.
+#[derive(Debug)]
+pub struct LinkError {
+    pub element: String,
+    pub link: String,
+    /// Why the resource couldn't be loaded, such as a `ResourceError`.
+    pub source: Box<dyn Error>,
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let source = self.source.to_string();
+        let reason = without_link(&source, &self.link);
+        write!(f, "<{}> {}: {}", self.element, self.link, reason)
+    }
+}
+
+/// without_link returns `message` less the `link` it starts with, as errors
+/// about a link do, so that the link isn't named twice where it's named
+/// already.
+fn without_link<'a>(message: &'a str, link: &str) -> &'a str {
+    message
+        .strip_prefix(link)
+        .and_then(|rest| rest.strip_prefix(": "))
+        .filter(|_| !link.is_empty())
+        .unwrap_or(message)
+}
+
+impl Error for LinkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+/// TransformError is an error from one of the transformations of
+/// `Options::transforms`, which stopped inlining.
+#[derive(Debug)]
+pub struct TransformError {
+    /// The position of the transformation among the transforms, from 0.
+    pub index: usize,
+    pub source: Box<dyn Error>,
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "transform {}: {}", self.index, self.source)
+    }
+}
+
+impl Error for TransformError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+/// Dependency is a local file a document links to, directly or by way of a
+/// stylesheet, module or other resource it links to, for build tools to
+/// watch or to key caches by.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dependency {
+    /// The link as it's written, in the document or in the resource that
+    /// links to the file.
+    pub link: String,
+    /// The absolute path of the file, which may not exist.
+    pub path: PathBuf,
+}
+
+/// PageLink is a link from the document to another local page, which
+/// navigating to it loads, so the page is left linked rather than inlined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageLink {
+    pub element: String,
+    /// The link as it's written.
+    pub link: String,
+    /// The absolute path of the page, which may not exist.
+    pub path: PathBuf,
+    /// The line of the document the link is on, if the element was parsed
+    /// from it.
+    pub line: Option<usize>,
+}
+
+/// LinkRewrite says where pages are inlined to, for links between them to
+/// be rewritten to go between their outputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkRewrite {
+    /// The path of each input page and the path its output is written to.
+    /// Links to pages that aren't among them are left alone.
+    pub pages: Vec<(PathBuf, PathBuf)>,
+    /// The path the output of the document is written to, which rewritten
+    /// links are relative to.
+    pub output: PathBuf,
+}
+
+/// InlineError is an error that stopped inlining. Unlike `Box<dyn Error>`
+/// it is `Send` and `Sync`, so it can be returned from futures run on a
+/// multi-threaded runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlineError(String);
+
+impl fmt::Display for InlineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for InlineError {}
+
+impl From<Box<dyn Error>> for InlineError {
+    fn from(err: Box<dyn Error>) -> Self {
+        InlineError(err.to_string())
+    }
+}
+
+/// Inline html resources into a single html buffer.
 /// Media files are base64 encoded in data urls, text files are directly
 /// embedded.
-pub fn inline(mut input: String, base: &Path) -> Result<String, Box<dyn Error>> {
-    let dom = Parser::new(Tokenizer::new(input.drain(..)).merged())
-        .parse()
-        .expect("parsing dom");
+pub fn inline(input: &str, base: &Path) -> Result<String, Box<dyn Error>> {
+    let options = Options::default();
+    let (dom, _) = inline_dom(input, base, &options, &Filesystem)?;
+    Ok(dom.to_string_with(options.format))
+}
+
+/// Inline an html document given as raw bytes, as `inline_with` does.
+/// The document is decoded according to its byte order mark or `<meta>`
+/// charset declaration, defaulting to UTF-8. The output is always UTF-8,
+/// with the charset declaration rewritten to say so.
+pub fn inline_bytes(
+    input: &[u8],
+    base: &Path,
+    options: &Options,
+) -> Result<(String, Report), Box<dyn Error>> {
+    let (input, options) = decode(input, options);
+    inline_with(input.into_owned(), base, &options)
+}
+
+/// Inline an html document given as raw bytes, as `inline_bytes` does, but
+/// stream the output to `w` rather than building it in memory.
+pub fn inline_bytes_to<W: io::Write>(
+    input: &[u8],
+    base: &Path,
+    options: &Options,
+    w: &mut W,
+) -> Result<Report, Box<dyn Error>> {
+    let (input, options) = decode(input, options);
+    let (dom, report) = inline_dom(&input, base, &options, &Filesystem)?;
+    serialize_to(report, w, |w| dom.to_writer_with(w, options.format))
+}
+
+/// Inline an html document given as raw bytes, as `inline_bytes_to` does,
+/// but write it to `w` as MHTML: a multipart document in which the resources
+/// that would be data urls are parts of their own, linked by `cid:` url.
+pub fn inline_mhtml_to<W: io::Write>(
+    input: &[u8],
+    base: &Path,
+    options: &Options,
+    w: &mut W,
+) -> Result<Report, Box<dyn Error>> {
+    let (input, options) = decode(input, options);
+    let (dom, report) = inline_dom(&input, base, &options, &Filesystem)?;
+    serialize_to(report, w, |w| mhtml::write(&dom, options.format, w))
+}
+
+/// Inline the html document `reader` reads, as `inline_with` does, decoding
+/// it as it's read. The document is decoded as `inline_bytes` decodes it, but
+/// only UTF-8 documents are decoded as they're read: one declaring another
+/// encoding is read whole first. Invalid UTF-8 is an error, which gives the
+/// byte offset it starts at, rather than replaced as `inline_bytes` does.
+pub fn inline_from<R: io::BufRead>(
+    reader: R,
+    base: &Path,
+    options: &Options,
+) -> Result<(String, Report), Box<dyn Error>> {
+    let (dom, report) = inline_read(reader, base, options)?;
+    Ok(serialize(&dom, options.format, report))
+}
+
+/// Inline the html document `reader` reads, as `inline_from` does, and stream
+/// the output to `w`.
+pub fn inline_to<R: io::BufRead, W: io::Write>(
+    reader: R,
+    base: &Path,
+    options: &Options,
+    w: &mut W,
+) -> Result<Report, Box<dyn Error>> {
+    let (dom, report) = inline_read(reader, base, options)?;
+    serialize_to(report, w, |w| dom.to_writer_with(w, options.format))
+}
+
+/// decode decodes a document given as raw bytes, returning it along with
+/// `options` set to decode its resources the same way by default.
+pub(crate) fn decode<'a>(input: &'a [u8], options: &Options) -> (Cow<'a, str>, Options) {
+    let encoding = encoding::sniff(input);
+    let (input, _, _) = encoding.decode(input);
+    let options = Options {
+        encoding: Some(encoding),
+        ..options.clone()
+    };
+    (input, options)
+}
+
+/// Inline html resources into a single html buffer, as `inline_with` does,
+/// reading the resources from the filesystem.
+pub fn inline_with(
+    input: String,
+    base: &Path,
+    options: &Options,
+) -> Result<(String, Report), Box<dyn Error>> {
+    inline_with_resources(input, base, options, &Filesystem)
+}
+
+/// Inline html resources into a single html buffer, as configured by
+/// `options`. Consumes input.
+/// Links resolve against `base`, or the document's `<base href>` within it,
+/// and are read through `resources`.
+/// Resources that were skipped are reported alongside the output.
+/// Inlining the output again, with the same options, gives the same output.
+pub fn inline_with_resources(
+    input: String,
+    base: &Path,
+    options: &Options,
+    resources: &dyn Resources,
+) -> Result<(String, Report), Box<dyn Error>> {
+    let (dom, report) = inline_dom(&input, base, options, resources)?;
+    Ok(serialize(&dom, options.format, report))
+}
+
+/// serialize writes out `dom` as `format` says, with its size in `report`.
+pub(crate) fn serialize(dom: &Dom, format: SerializeMode, mut report: Report) -> (String, Report) {
+    let output = dom.to_string_with(format);
+    report.output_size = output.len() as u64;
+    (output, report)
+}
+
+/// serialize_to writes the output to `w` with `write`, with its size in
+/// `report`.
+fn serialize_to<W: io::Write>(
+    mut report: Report,
+    w: &mut W,
+    write: impl FnOnce(&mut Counter<&mut W>) -> io::Result<()>,
+) -> Result<Report, Box<dyn Error>> {
+    let mut counter = Counter { w, count: 0 };
+    write(&mut counter)?;
+    report.output_size = counter.count;
+    Ok(report)
+}
+
+/// Counter counts the bytes written to `w`.
+struct Counter<W> {
+    w: W,
+    count: u64,
+}
+
+impl<W: io::Write> io::Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.w.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// inline_dom inlines `input` as `inline_with_resources` does, returning the
+/// DOM for the caller to serialize however suits it.
+pub(crate) fn inline_dom(
+    input: &str,
+    base: &Path,
+    options: &Options,
+    resources: &dyn Resources,
+) -> Result<(Dom, Report), Box<dyn Error>> {
+    let mut parser = Parser::new(StrTokenizer::new(input).merged())
+        .mode(options.parse_mode)
+        .normalize_case(!is_xml(input.as_bytes()));
+    let dom = parser.parse()?;
+    let root = root(base, options);
+    inline_tree(
+        dom,
+        parser.duplicates(),
+        base,
+        root,
+        &[],
+        options,
+        resources,
+    )
+}
+
+/// inline_frame inlines the document framed by an iframe of a page inlined
+/// from `dir`, as `inline_dom` does, with its links resolving against `base`.
+/// `frames` are the files of the frames the document is inside of.
+fn inline_frame(
+    input: &str,
+    dir: &Path,
+    base: Base,
+    frames: &[PathBuf],
+    options: &Options,
+    resources: &dyn Resources,
+) -> Result<(Dom, Report), Box<dyn Error>> {
+    let mut parser = Parser::new(StrTokenizer::new(input).merged())
+        .mode(options.parse_mode)
+        .normalize_case(!is_xml(input.as_bytes()));
+    let dom = parser.parse()?;
+    // The transformations are of the page, not of its frames.
+    let options = Options {
+        transforms: vec![],
+        ..options.clone()
+    };
+    let duplicates = parser.duplicates();
+    inline_tree(dom, duplicates, dir, base, frames, &options, resources)
+}
+
+/// root returns the base of a document inlined from `dir`.
+fn root(dir: &Path, options: &Options) -> Base {
+    match options.allow_outside_base {
+        true => Base::new(dir).allow_outside(),
+        false => Base::new(dir),
+    }
+}
+
+/// inline_read inlines the document `reader` reads, as `inline_from` does,
+/// returning the DOM for the caller to serialize however suits it.
+fn inline_read<R: io::BufRead>(
+    mut reader: R,
+    base: &Path,
+    options: &Options,
+) -> Result<(Dom, Report), Box<dyn Error>> {
+    // The encoding is sniffed from the start of the document. Only UTF-8 is
+    // decoded as it's read; a document in another encoding is read whole.
+    let mut head = vec![];
+    reader
+        .by_ref()
+        .take(encoding::PRESCAN_LIMIT as u64)
+        .read_to_end(&mut head)?;
+    if encoding::sniff(&head) != UTF_8 {
+        reader.read_to_end(&mut head)?;
+        let (input, options) = decode(&head, options);
+        return inline_dom(&input, base, &options, &Filesystem);
+    }
+    let options = Options {
+        encoding: Some(UTF_8),
+        ..options.clone()
+    };
+    let normalize_case = !is_xml(&head);
+    let mut chars = ReadChars::new(head.chain(reader));
+    let mut parser = Parser::new(Tokenizer::new(chars.by_ref()).merged())
+        .mode(options.parse_mode)
+        .normalize_case(normalize_case);
+    let dom = parser.parse();
+    let duplicates = parser.duplicates().to_vec();
+    // An error reading ends the input early, so it's the error whatever the
+    // parser made of the input before it.
+    if let Some(err) = chars.into_error() {
+        return Err(err.into());
+    }
+    let root = root(base, &options);
+    inline_tree(dom?, &duplicates, base, root, &[], &options, &Filesystem)
+}
+
+/// is_xml reports whether the document starting with `head` is XML, such as
+/// a standalone SVG document, by its XML declaration. The names in XML are
+/// case-sensitive, so they're parsed as written.
+fn is_xml(head: &[u8]) -> bool {
+    let head = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+    let start = head.iter().position(|b| !b.is_ascii_whitespace());
+    start.is_some_and(|start| head[start..].starts_with(b"<?xml"))
+}
+
+/// inline_tree inlines the resources of the parsed `dom`, inlined from `dir`
+/// with its links resolving against `root`, warning of the `duplicates`
+/// attributes the parser dropped. `frames` are the files of the frames the
+/// document is inside of.
+fn inline_tree(
+    mut dom: Dom,
+    duplicates: &[(String, String)],
+    dir: &Path,
+    root: Base,
+    frames: &[PathBuf],
+    options: &Options,
+    resources: &dyn Resources,
+) -> Result<(Dom, Report), Box<dyn Error>> {
+    // Documents in a srcdoc are inlined with resources of their own, which
+    // measure them for their own report.
+    let unmeasured = resources;
+    let optimizer = options
+        .optimize_images
+        .as_ref()
+        .map(|image_options| Optimizer::new(resources, image_options));
+    let resources: &dyn Resources = match &optimizer {
+        Some(optimizer) => optimizer,
+        None => resources,
+    };
+    let measured = Measured::new(resources);
+    let resources: &dyn Resources = &measured;
+    let report = RefCell::new(Report::default());
+    for (element, attribute) in duplicates {
+        report.borrow_mut().warnings.push(Warning {
+            element: element.clone(),
+            link: attribute.clone(),
+            reason: "duplicate attribute, only the first is kept".into(),
+        });
+    }
+    // Bases derived from the root record the links they resolve with it.
+    let links = root.clone();
+    let base = match base_href(&dom) {
+        Some(href) if root.resolves(&href) => root.with_href(&href)?,
+        Some(href) => {
+            report.borrow_mut().warnings.push(Warning {
+                element: "base".into(),
+                link: href,
+                reason: "links can only resolve against a local base".into(),
+            });
+            root
+        }
+        None => root,
+    };
+    let is_base = |node: &NodeRef| node.borrow().is_element("base");
+    // The elements copied in for `<use>` elements, by the file and id they
+    // were copied from, and the ids taken in the document.
+    let defs = RefCell::new(vec![]);
+    // The elements stylesheets and scripts are inlined into, for merging.
+    let inlined: RefCell<Inlined> = RefCell::new(HashSet::new());
+    // The modules bundled into scripts, whose preloads are removed.
+    let bundled: RefCell<HashSet<PathBuf>> = RefCell::new(HashSet::new());
+    let used: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+    // The reports of the documents inlined in srcdoc attributes.
+    let documents: RefCell<Vec<Report>> = RefCell::new(vec![]);
+    let ids: RefCell<HashSet<String>> = RefCell::new(
+        dom.find_by_attr("id", None)
+            .iter()
+            .filter_map(|n| n.borrow().attr("id").map(str::to_owned))
+            .collect(),
+    );
+    if options.remove_base {
+        dom.nodes.retain(|n| !is_base(n));
+    }
+    if options.strip_comments {
+        strip_comments(&mut dom.nodes);
+    }
+    if let Some(sanitize_options) = &options.sanitize {
+        report.borrow_mut().removed = sanitize(&mut dom, sanitize_options);
+    }
+    if options.prefer_noscript_images {
+        prefer_noscript_images(&mut dom.nodes);
+    }
+    // inline_element inlines the resources an element links to.
+    let inline_element = |n: &NodeRef| -> Result<(), Box<dyn Error>> {
+        let mut node = n.borrow_mut();
+        let name = match node.tag_name() {
+            Some(name) => name.to_ascii_lowercase(),
+            None => return Ok(()),
+        };
+        // Once links are inlined, a base has nothing left to apply to.
+        if options.remove_base {
+            if let Some(children) = node.children_mut() {
+                children.retain(|c| !is_base(c));
+            }
+        }
+        // Embedded objects reference their resource through an attribute
+        // that must survive inlining, so they always become data urls.
+        // Fallback children of `<object>` are left untouched.
+        if let Some(key) = embedded_attribute(&name) {
+            if let Some(link) = node.attr(key).filter(|link| base.resolves(link)) {
+                let path = base.resolve(link)?;
+                if exceeds_threshold(link, &path, &base, options, resources)? {
+                    return Ok(());
+                }
+                let data_url = data_url(
+                    &path,
+                    &media_type(node.attr("type"), link),
+                    options,
+                    resources,
+                )?;
+                node.set_attr(key, data_url);
+            }
+            return Ok(());
+        }
+        if name == "meta" {
+            if matches!(&*node, Node::Tag { attributes, .. } if is_meta_image(attributes)) {
+                if !options.inline_meta_images {
+                    return Ok(());
+                }
+                if let Some(link) = node.attr("content") {
+                    if let Some(data_url) = embed(link, None, &base, options, resources)? {
+                        node.set_attr("content", data_url);
+                    }
+                }
+                return Ok(());
+            }
+            // The output is UTF-8 whatever the input was.
+            if node.has_attr("charset") {
+                node.set_attr("charset", "utf-8");
+            } else if node
+                .attr("http-equiv")
+                .is_some_and(|h| h.trim().eq_ignore_ascii_case("content-type"))
+            {
+                node.set_attr("content", "text/html; charset=utf-8");
+            }
+            return Ok(());
+        }
+        // Links are dispatched on what they are to the document, which
+        // their `rel` says; the extension is only a fallback hint.
+        if name == "link" {
+            let href = match node.attr("href") {
+                Some(href) => href.to_owned(),
+                None => return Ok(()),
+            };
+            let (link, path) = match Link::new(node.attr("rel"), &href) {
+                Link::Untouched => return Ok(()),
+                Link::Unknown(rel) => {
+                    report.borrow_mut().warnings.push(Warning {
+                        element: name,
+                        link: href,
+                        reason: format!("unsupported link relation {:?}", rel),
+                    });
+                    return Ok(());
+                }
+                // Whether the module is bundled is only known once every
+                // script has been.
+                Link::ModulePreload => return Ok(()),
+                _ if !base.resolves(&href) => return Ok(()),
+                link => (link, base.resolve(&href)?),
+            };
+            if exceeds_threshold(&href, &path, &base, options, resources)? {
+                return Ok(());
+            }
+            match link {
+                Link::Untouched | Link::Unknown(_) | Link::ModulePreload => {}
+                Link::Alternate => {
+                    // A `<style>` would always apply, so the stylesheet is
+                    // embedded in the link, which keeps its `rel` and
+                    // `title` for the browser to offer it by.
+                    let content = css::inline_file(
+                        &path,
+                        &base,
+                        options,
+                        resources,
+                        &mut report.borrow_mut().warnings,
+                    )?;
+                    let content = line_endings(content, options);
+                    let data_url = format!("data:text/css;base64,{}", base64::encode(&content));
+                    node.set_attr("href", data_url);
+                }
+                Link::Stylesheet => {
+                    // The links in a stylesheet are relative to the
+                    // stylesheet, not to the document it now lives in.
+                    let content = css::inline_file(
+                        &path,
+                        &base,
+                        options,
+                        resources,
+                        &mut report.borrow_mut().warnings,
+                    )?;
+                    if let Node::Tag { name, .. } = &mut *node {
+                        *name = "style".to_string();
+                    }
+                    // What's left, such as `media`, `title` and `type`, means
+                    // the same on a `<style>`.
+                    for key in LINK_ONLY_ATTRIBUTES {
+                        node.remove_attr(key);
+                    }
+                    set_text(&mut node, line_endings(content, options));
+                    stamp_nonce(&mut node, options);
+                    inlined.borrow_mut().insert(Rc::as_ptr(n));
+                }
+                Link::Icon => {
+                    // Icons are often served without an extension, so their
+                    // type is sniffed from their content, and given to the
+                    // link if it hasn't one, for browsers to pick by.
+                    let content = read_media(&path, options, resources)?;
+                    let media_type = resources
+                        .media_type(&path)
+                        .or_else(|| node.attr("type").map(str::to_owned))
+                        .or_else(|| sniff_image(&content).map(str::to_owned))
+                        .unwrap_or_else(|| media_type(None, &href));
+                    if !node.has_attr("type") {
+                        node.set_attr("type", media_type.clone());
+                    }
+                    let data_url =
+                        format!("data:{};base64,{}", media_type, base64::encode(&content));
+                    node.set_attr("href", data_url);
+                }
+                Link::Manifest => {
+                    let data_url = manifest::inline(
+                        &path,
+                        &base,
+                        options,
+                        resources,
+                        &mut report.borrow_mut().warnings,
+                    )?;
+                    node.set_attr("href", data_url);
+                }
+            }
+            return Ok(());
+        }
+        if name == "style" {
+            let mut embedded = false;
+            for child in node.children() {
+                if let Node::Text(text) = &mut *child.borrow_mut() {
+                    let css = css::inline(
+                        text,
+                        &base,
+                        options,
+                        resources,
+                        &mut report.borrow_mut().warnings,
+                    )?;
+                    embedded |= css != *text;
+                    *text = css;
+                }
+            }
+            if embedded {
+                stamp_nonce(&mut node, options);
+            }
+            return Ok(());
+        }
+        let is_module = name == "script" && node.attr("type") == Some("module");
+        if is_module && options.bundle_modules && !node.has_attr("src") {
+            let mut embedded = false;
+            for child in node.children() {
+                if let Node::Text(text) = &mut *child.borrow_mut() {
+                    let code = module::bundle(
+                        text,
+                        &base,
+                        options,
+                        resources,
+                        &mut bundled.borrow_mut(),
+                        &mut report.borrow_mut().warnings,
+                    )?;
+                    embedded |= code != *text;
+                    *text = code;
+                }
+            }
+            if embedded {
+                stamp_nonce(&mut node, options);
+            }
+            return Ok(());
+        }
+        // A collapsed picture keeps only its fallback image, when it has
+        // one, so the sources the browser would choose between are dropped.
+        if name == "picture" && options.picture_collapse {
+            if let Some(children) = node.children_mut() {
+                if children.iter().any(|c| c.borrow().is_element("img")) {
+                    children.retain(|c| !c.borrow().is_element("source"));
+                }
+            }
+            return Ok(());
+        }
+        // The elements `<use>` references in other files are copied into the
+        // document, since browsers only use data urls of whole files.
+        if name == "use" {
+            let key = match svg::href_attribute(&node) {
+                Some(key) => key,
+                None => return Ok(()),
+            };
+            let (file, id) = match node.attr(key).and_then(|link| link.split_once('#')) {
+                Some((file, id)) if !file.is_empty() && !id.is_empty() && base.resolves(file) => {
+                    (file.to_owned(), id.to_owned())
+                }
+                _ => return Ok(()),
+            };
+            let path = base.resolve(&file)?;
+            let reference = format!("{}#{}", path.display(), id);
+            let mut used = used.borrow_mut();
+            let local = match used.get(&reference) {
+                Some(local) => local.clone(),
+                None => {
+                    let copies = svg::extract(&path, &id, &base, options, resources)?;
+                    let copies = svg::localize(&path, copies, &mut used, &mut ids.borrow_mut())?;
+                    defs.borrow_mut().extend(copies);
+                    used[&reference].clone()
+                }
+            };
+            node.set_attr(key, format!("#{}", local));
+            return Ok(());
+        }
+        // A srcdoc holds a whole document, written escaped in the attribute,
+        // which is inlined as the page is and escaped again. Its links
+        // resolve against the page's base, as they do in a browser.
+        if name == "iframe" && options.inline_srcdoc {
+            if let Some(srcdoc) = node.attr("srcdoc") {
+                let (document, frame) = inline_frame(
+                    &unescape(srcdoc),
+                    dir,
+                    base.clone(),
+                    frames,
+                    options,
+                    unmeasured,
+                )?;
+                node.set_attr("srcdoc", to_srcdoc(&document, options.format));
+                documents.borrow_mut().push(frame);
+            }
+        }
+        if let Some(lazy) = options.lazy_attributes {
+            inline_lazy(&mut node, lazy, &base, options, resources)?;
+        }
+        if let Some(style) = node.attr("style") {
+            let warnings = &mut report.borrow_mut().warnings;
+            let style = css::rewrite_urls(style, |url| {
+                css::embed_or_warn(&name, url, &base, options, resources, warnings)
+            })?;
+            node.set_attr("style", style);
+        }
+        if let Some(srcset) = node.attr("srcset") {
+            let srcset = inline_srcset(srcset, node.attr("type"), &base, options, resources)?;
+            node.set_attr("srcset", srcset);
+        }
+        // Only elements that load a resource are inlined. Anchors and
+        // forms navigate to their links, so they're left alone.
+        let key = match resource_attribute(&node, options) {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        let link = match node.attr(key) {
+            Some(link) if !link.is_empty() && base.resolves(link) => link.to_owned(),
+            _ => return Ok(()),
+        };
+        let path = base.resolve(&link)?;
+        if exceeds_threshold(&link, &path, &base, options, resources)? {
+            return Ok(());
+        }
+        // A framed page is inlined into a srcdoc, with its links resolving
+        // against its own directory. A srcdoc already there takes the place
+        // of the src, so the src is left alone.
+        if name == "iframe" && media_type(None, &link) == "text/html" {
+            if node.attr("srcdoc").is_some() {
+                return Ok(());
+            }
+            if frames.contains(&path) || frames.len() >= options.max_depth {
+                base.skip(&link);
+                report.borrow_mut().warnings.push(Warning {
+                    element: name,
+                    link,
+                    reason: format!(
+                        "left linked, frames nest more than {} deep or frame themselves",
+                        options.max_depth
+                    ),
+                });
+                return Ok(());
+            }
+            let content = encoding::read_text(&path, None, options, resources)?;
+            let frames: Vec<PathBuf> = frames.iter().cloned().chain([path.clone()]).collect();
+            let (document, frame) =
+                inline_frame(&content, dir, base.of(&path), &frames, options, unmeasured)?;
+            node.remove_attr(key);
+            node.set_attr("srcdoc", to_srcdoc(&document, options.format));
+            documents.borrow_mut().push(frame);
+            return Ok(());
+        }
+        let embedding = match key {
+            "src" => Embedding::new(&link),
+            _ => Embedding::DataUrl,
+        };
+        match embedding {
+            Embedding::DataUrl => {
+                let data_url = data_url(&path, &media_type(None, &link), options, resources)?;
+                node.set_attr(key, data_url);
+            }
+            Embedding::Text => {
+                let charset = node.attr("charset");
+                let content = match is_module && options.bundle_modules {
+                    true => module::bundle_file(
+                        &path,
+                        &base,
+                        charset,
+                        options,
+                        resources,
+                        &mut bundled.borrow_mut(),
+                        &mut report.borrow_mut().warnings,
+                    )?,
+                    false => encoding::read_text(&path, charset, options, resources)?,
+                };
+                node.remove_attr(key);
+                set_text(&mut node, line_endings(content, options));
+                if name == "script" || name == "style" {
+                    stamp_nonce(&mut node, options);
+                    inlined.borrow_mut().insert(Rc::as_ptr(n));
+                }
+            }
+        };
+        Ok(())
+    };
+    // Whether the walk is between the markers of a revealed conditional
+    // comment. Markers are siblings, so in a depth first walk everything
+    // visited between them is inside the conditional. A start marker without
+    // an end marker after it doesn't start one, or nothing after it would be
+    // inlined.
+    let revealed = RefCell::new(false);
+    let unterminated = unterminated_conditionals(&dom.nodes);
+    for _ in &unterminated {
+        report.borrow_mut().warnings.push(Warning {
+            element: "comment".into(),
+            link: String::new(),
+            reason: "conditional comment without an end marker, so what follows it is inlined"
+                .into(),
+        });
+    }
+    let inert = match options.inline_templates {
+        true => HashSet::new(),
+        false => dom.template_content(),
+    };
     dom.depth_first(&|n: NodeRef| {
-        if let Node::Tag {
+        if inert.contains(&Rc::as_ptr(&n)) {
+            return Ok(());
+        }
+        match Conditional::new(&n.borrow()) {
+            Some(Conditional::Start) if unterminated.contains(&Rc::as_ptr(&n)) => {}
+            Some(Conditional::Start) => *revealed.borrow_mut() = true,
+            Some(Conditional::End) => *revealed.borrow_mut() = false,
+            None => {}
+        }
+        if *revealed.borrow() && !options.inline_conditional_comments {
+            return Ok(());
+        }
+        // With keep_going, an element whose resources fail to load is left
+        // as it was, with a warning, and the walk carries on. So is the link
+        // of one to an empty file, which is no error, though its other links
+        // may have been inlined by then. Only keep_going needs the element
+        // as it was.
+        let tag = match &*n.borrow() {
+            Node::Tag {
+                name, attributes, ..
+            } => Some((
+                name.clone(),
+                link_of(attributes).unwrap_or_default().to_owned(),
+            )),
+            _ => None,
+        };
+        let (name, link) = match tag {
+            Some(tag) => tag,
+            None => return inline_element(&n),
+        };
+        let before = match &*n.borrow() {
+            Node::Tag {
+                attributes,
+                children,
+                ..
+            } if options.keep_going => Some(Node::Tag {
+                name: name.clone(),
+                attributes: attributes.clone(),
+                children: children.clone(),
+            }),
+            _ => None,
+        };
+        match inline_element(&n) {
+            Err(err) if options.keep_going || is_empty_resource(&*err) => {
+                report.borrow_mut().warnings.push(Warning {
+                    element: name,
+                    reason: without_link(&err.to_string(), &link).to_owned(),
+                    link,
+                });
+                if let Some(before) = before {
+                    *n.borrow_mut() = before;
+                }
+                Ok(())
+            }
+            Err(source) => Err(LinkError {
+                element: name,
+                link,
+                source,
+            }
+            .into()),
+            Ok(()) => Ok(()),
+        }
+    })?;
+    let defs = defs.into_inner();
+    if !defs.is_empty() {
+        if let Some(root) = dom.find_by_tag("svg").first() {
+            if let Some(children) = root.borrow_mut().children_mut() {
+                children.insert(0, svg::defs(defs));
+            }
+        }
+    }
+    let bundled: HashSet<PathBuf> = bundled.into_inner().iter().map(|p| absolute(p)).collect();
+    for n in dom.find_by_tag("link") {
+        let node = n.borrow();
+        let href = match node.attr("href") {
+            Some(href) if Link::new(node.attr("rel"), href) == Link::ModulePreload => {
+                href.to_owned()
+            }
+            _ => continue,
+        };
+        drop(node);
+        if !base.resolves(&href) {
+            continue;
+        }
+        // One that can't be resolved can't have been bundled either.
+        match base.resolve(&href) {
+            Ok(path) if bundled.contains(&absolute(&path)) => dom.remove(&n),
+            Ok(_) => {}
+            Err(err) => report.borrow_mut().warnings.push(Warning {
+                element: "link".into(),
+                link: href,
+                reason: err.to_string(),
+            }),
+        }
+    }
+    if options.report_links {
+        report.borrow_mut().links = page_links(&dom, &base)?;
+    }
+    if let Some(rewrite) = &options.rewrite_links {
+        rewrite_page_links(&dom, &base, rewrite)?;
+    }
+    if options.rewrite_js_literals {
+        dom.depth_first(&|n: NodeRef| {
+            if inert.contains(&Rc::as_ptr(&n)) {
+                return Ok(());
+            }
+            let warnings = &mut report.borrow_mut().warnings;
+            rewrite_js_literals(&mut n.borrow_mut(), &base, options, resources, warnings)
+        })?;
+    }
+    if options.merge_css {
+        merge::merge_styles(&mut dom, &inlined.borrow());
+    }
+    if options.merge_js {
+        merge::merge_scripts(&mut dom, &inlined.borrow());
+    }
+    if options.email {
+        email::inline_styles(&mut dom);
+    }
+    for (index, transform) in options.transforms.iter().enumerate() {
+        (transform.0)(&mut dom).map_err(|source| TransformError { index, source })?;
+    }
+    csp::adjust(&mut dom, options.csp, &mut report.borrow_mut().warnings);
+    if options.hashes {
+        report.borrow_mut().hashes = csp::inline_hashes(&dom);
+    }
+    if options.strict {
+        let skipped = links.skipped();
+        let mut references = dom.external_references_with(options.inline_meta_images);
+        references.retain(|reference| !skipped.contains(&reference.url));
+        let runtime = dom.runtime_references();
+        if !references.is_empty() {
+            let mut message = String::from("the output isn't self-contained, it references:");
+            for reference in references {
+                message.push_str(&format!("\n  {}", reference));
+            }
+            if !runtime.is_empty() {
+                message.push_str("\nand its scripts possibly load:");
+                for reference in runtime {
+                    message.push_str(&format!("\n  {}", reference));
+                }
+            }
+            return Err(message.into());
+        }
+        let warnings = &mut report.borrow_mut().warnings;
+        for reference in runtime {
+            warnings.push(Warning {
+                element: reference.element,
+                link: reference.url,
+                reason: match reference.attribute {
+                    Some(handler) => format!("possibly external at runtime, from {}", handler),
+                    None => "possibly external at runtime".into(),
+                },
+            });
+        }
+    }
+    let mut report = report.into_inner();
+    report.embedded = measured.embedded.into_inner();
+    if let Some(optimizer) = optimizer {
+        report.optimized = optimizer.optimized.into_inner();
+    }
+    report.dependencies = dependencies(links.resolved());
+    report.skipped = links.skipped();
+    if options.deterministic {
+        for warning in &mut report.warnings {
+            warning.link = relative_to(&warning.link, dir);
+            warning.reason = relative_to(&warning.reason, dir);
+        }
+        for optimization in &mut report.optimized {
+            optimization.path = relative_to(&optimization.path, dir);
+        }
+        for embedded in &mut report.embedded {
+            embedded.path = relative_to(&embedded.path, dir);
+        }
+    }
+    for document in documents.into_inner() {
+        report.warnings.extend(document.warnings);
+        report.removed.extend(document.removed);
+        report.optimized.extend(document.optimized);
+        report.hashes.extend(document.hashes);
+        report.embedded.extend(document.embedded);
+        for link in document.skipped {
+            if !report.skipped.contains(&link) {
+                report.skipped.push(link);
+            }
+        }
+        for dependency in document.dependencies {
+            if !report
+                .dependencies
+                .iter()
+                .any(|d| d.path == dependency.path)
+            {
+                report.dependencies.push(dependency);
+            }
+        }
+    }
+    Ok((dom, report))
+}
+
+/// rewrite_js_literals replaces the asset links in the event handlers of
+/// `node`, and in its text if it's a script, as `Dom::runtime_references`
+/// finds them, with data urls of the files they resolve to against `base`.
+/// Links to files that don't exist are left as they are, as are links that
+/// can't be inlined, such as those outside of the base directory. Handlers
+/// are rewritten as they're written, so links in quotes written as
+/// character references are left too.
+fn rewrite_js_literals(
+    node: &mut Node,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+    warnings: &mut Vec<Warning>,
+) -> Result<(), Box<dyn Error>> {
+    let is_script = node.is_element("script") && is_javascript(node.attr("type"));
+    let (name, attributes, children) = match node {
+        Node::Tag {
             name,
             attributes,
             children,
-        } = &mut *n.borrow_mut()
-        {
-            let attr = attributes;
-            if let Some(link) = attr.get("href").or(attr.get("src")) {
-                let link = link.trim_matches('/');
-                let path = base.join(link);
-                let is_plain_text = ["html", "js", "css"].into_iter().fold(false, |acc, ext| {
-                    if acc {
-                        true
-                    } else {
-                        link.ends_with(ext)
-                    }
+        } => (name, attributes, children),
+        _ => return Ok(()),
+    };
+    let mut embed_existing = |link: &str| -> Result<Option<String>, Box<dyn Error>> {
+        match base.resolve(link) {
+            Ok(path) if resources.size(&path).is_ok() => {}
+            _ => return Ok(None),
+        }
+        match embed(link, None, base, options, resources) {
+            Err(err) if is_empty_resource(&*err) => Ok(None),
+            Err(err) if options.keep_going => {
+                warnings.push(Warning {
+                    element: name.clone(),
+                    link: link.into(),
+                    reason: err.to_string(),
                 });
-                match is_plain_text {
-                    false => {
-                        let file = fs::File::open(&path)
-                            .map_err(|e| format!("{}: {:?}", &path.to_string_lossy(), e))?;
-                        let content = base64::encode(
-                            BufReader::new(file)
-                                .bytes()
-                                .map(Result::ok)
-                                .filter_map(|b| b)
-                                .collect::<Vec<u8>>()
-                                .as_slice(),
-                        );
-                        let data_url = format!(
-                            "data:{media_type};bas64,{data}",
-                            media_type = mime_guess::from_path(&link).first_or_octet_stream(),
-                            data = content
-                        );
-                        if attr.contains_key("href") {
-                            attr.insert("href".into(), data_url);
-                        } else if attr.contains_key("src") {
-                            attr.insert("src".into(), data_url);
-                        }
-                    }
-                    true => {
-                        let content = fs::read_to_string(&path)
-                            .map_err(|e| format!("{}: {:?}", &path.to_string_lossy(), e))?;
-                        if link.ends_with("css") {
-                            *name = "style".to_string();
-                            attr.remove("rel");
-                        }
-                        attr.remove("href");
-                        attr.remove("src");
-                        children.clear();
-                        children.push(Node::Text(content).into());
-                    }
-                };
+                Ok(None)
+            }
+            Err(source) => Err(LinkError {
+                element: name.clone(),
+                link: link.into(),
+                source,
             }
+            .into()),
+            result => result,
         }
+    };
+    for (key, value) in attributes.iter_mut() {
+        if is_event_handler(key) {
+            *value = literals::rewrite_links(value, &mut embed_existing)?;
+        }
+    }
+    if is_script {
+        for child in children {
+            if let Node::Text(text) = &mut *child.borrow_mut() {
+                *text = literals::rewrite_links(text, &mut embed_existing)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Attributes of a stylesheet `<link>` that only apply to fetching it, and
+/// are dropped once it's inlined into a `<style>`.
+const LINK_ONLY_ATTRIBUTES: &[&str] = &["rel", "href", "integrity", "crossorigin", "as"];
+
+/// set_text replaces the children of `node` with `text`, trimmed as the
+/// parser trims text, so that the output parses back to the same tree. Text
+/// that's empty once trimmed, as an empty file is, leaves no children, as
+/// the parser leaves none.
+pub(crate) fn set_text(node: &mut Node, text: String) {
+    if let Some(children) = node.children_mut() {
+        *children = match text.trim() {
+            "" => vec![],
+            text => vec![Node::Text(text.to_owned()).into()],
+        };
+    }
+}
+
+/// dependencies returns the files `resolved` links resolved to, once each,
+/// with the first link to each.
+fn dependencies(resolved: Vec<(String, PathBuf)>) -> Vec<Dependency> {
+    let mut seen = HashSet::new();
+    resolved
+        .into_iter()
+        .map(|(link, path)| Dependency {
+            link,
+            path: absolute(&path),
+        })
+        .filter(|dependency| seen.insert(dependency.path.clone()))
+        .collect()
+}
+
+/// page_links returns the links of `dom` to other local pages, resolved
+/// against `base`: those of anchors, image map areas and forms.
+fn page_links(dom: &Dom, base: &Base) -> Result<Vec<PageLink>, Box<dyn Error>> {
+    let links = RefCell::new(vec![]);
+    dom.depth_first(&|n: NodeRef| {
+        let node = n.borrow();
+        let (element, key) = match node.tag_name() {
+            Some(name @ ("a" | "area")) => (name, "href"),
+            Some(name @ "form") => (name, "action"),
+            _ => return Ok(()),
+        };
+        let link = match node.attr(key) {
+            Some(link) if !link.is_empty() && !link.starts_with('#') && is_local(link) => link,
+            _ => return Ok(()),
+        };
+        // A page of a site on the web is named by its url.
+        let path = base.locate(link)?;
+        let path = match is_local(&path.to_string_lossy()) {
+            true => absolute(&path),
+            false => path,
+        };
+        links.borrow_mut().push(PageLink {
+            element: element.to_owned(),
+            link: link.to_owned(),
+            path,
+            line: dom.position(&n).map(|position| position.line),
+        });
         Ok(())
     })?;
-    Ok(dom.to_string())
+    Ok(links.into_inner())
+}
+
+/// rewrite_page_links rewrites the links of `dom` to the local pages of
+/// `rewrite`, resolved against `base`, to go to their outputs from the
+/// output of the document. Their query and fragment are kept.
+fn rewrite_page_links(dom: &Dom, base: &Base, rewrite: &LinkRewrite) -> Result<(), Box<dyn Error>> {
+    let pages: Vec<_> = rewrite
+        .pages
+        .iter()
+        .map(|(input, output)| (absolute(input), absolute(output)))
+        .collect();
+    let output = absolute(&rewrite.output);
+    let from = output.parent().unwrap_or(&output);
+    dom.depth_first(&|n: NodeRef| {
+        let mut node = n.borrow_mut();
+        let key = match node.tag_name() {
+            Some("a" | "area") => "href",
+            Some("form") => "action",
+            _ => return Ok(()),
+        };
+        let link = match node.attr(key) {
+            Some(link) if !link.is_empty() && !link.starts_with('#') && is_local(link) => {
+                link.to_owned()
+            }
+            _ => return Ok(()),
+        };
+        let path = absolute(&base.locate(&link)?);
+        let to = match pages.iter().find(|(input, _)| *input == path) {
+            Some((_, to)) => to,
+            None => return Ok(()),
+        };
+        let suffix = link.find(['?', '#']).map_or("", |at| &link[at..]);
+        let rewritten = format!("{}{}", relative_path(from, to), suffix);
+        node.set_attr(key, rewritten);
+        Ok(())
+    })
+}
+
+/// relative_path returns the link from a document in the directory `from`
+/// to the file `to`, both absolute.
+fn relative_path(from: &Path, to: &Path) -> String {
+    let from: Vec<_> = from.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let up = from[common..].iter().map(|_| "..".to_owned());
+    let down = to[common..]
+        .iter()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned());
+    up.chain(down).collect::<Vec<_>>().join("/")
+}
+
+/// absolute returns `path` relative to the working directory, if it isn't
+/// absolute already, without the `.` and `..` components that links leave
+/// in it.
+fn absolute(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+    let mut clean = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                clean.pop();
+            }
+            component => clean.push(component),
+        }
+    }
+    clean
+}
+
+/// line_endings turns the `\r\n` and lone `\r` line endings of inlined
+/// `text` into `\n` when `options` asks for deterministic output, so that it
+/// doesn't depend on how the resources were checked out.
+fn line_endings(text: String, options: &Options) -> String {
+    if !options.deterministic || options.keep_line_endings || !text.contains('\r') {
+        return text;
+    }
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// relative_to rewrites the paths under `dir` in `message` relative to it,
+/// with `/` separators, so that the message reads the same whichever
+/// directory, and platform, the document was inlined from. Only whole
+/// components match, so a path with spaces is rewritten whole, while
+/// `dir2/a.png` isn't under `dir`.
+fn relative_to(message: &str, dir: &Path) -> String {
+    let dir = dir.to_string_lossy();
+    let dir = dir.trim_end_matches(MAIN_SEPARATOR);
+    // Characters that may go on a component, rather than end it.
+    let in_component = |c: char| c.is_alphanumeric() || matches!(c, '.' | '-' | '_');
+    let mut out = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(at) = rest.find(dir).filter(|_| !dir.is_empty()) {
+        let (before, after) = (&rest[..at], &rest[at + dir.len()..]);
+        out.push_str(before);
+        rest = after;
+        let inside = before.ends_with(|c| in_component(c) || c == MAIN_SEPARATOR || c == '/');
+        if inside || after.starts_with(in_component) {
+            out.push_str(dir);
+            continue;
+        }
+        match after.strip_prefix([MAIN_SEPARATOR, '/']) {
+            Some(path) => rest = path,
+            None => out.push('.'),
+        }
+    }
+    out.push_str(rest);
+    out.replace(MAIN_SEPARATOR, "/")
+}
+
+/// stamp_nonce gives the inlined script or style `node` the nonce of
+/// `options`, if there is one.
+fn stamp_nonce(node: &mut Node, options: &Options) {
+    if let Some(nonce) = &options.nonce {
+        node.set_attr("nonce", nonce.clone());
+    }
+}
+
+/// link_of returns the link an element with `attributes` is most likely
+/// inlined for, to name it in warnings.
+fn link_of(attributes: &HashMap<String, String>) -> Option<&str> {
+    ["src", "href", "xlink:href", "data", "srcset", "content"]
+        .iter()
+        .find_map(|key| attributes.get(*key))
+        .map(String::as_str)
+}
+
+/// strip_comments removes the comments in `nodes` and their descendants,
+/// keeping conditional comments, which are markup for some browsers, and
+/// license comments, which start with `!` so that minifiers keep them too.
+fn strip_comments(nodes: &mut Vec<NodeRef>) {
+    nodes.retain(|n| match &*n.borrow() {
+        Node::Comment(text) => {
+            let text = text.trim_start();
+            text.starts_with('!') || text.starts_with("[if") || text.trim_end() == "<![endif]"
+        }
+        _ => true,
+    });
+    for node in nodes {
+        if let Node::Tag { children, .. } = &mut *node.borrow_mut() {
+            strip_comments(children);
+        }
+    }
+}
+
+/// prefer_noscript_images replaces the placeholder `<img>` beside each
+/// `<noscript>` in `nodes` and their descendants that holds only an image
+/// with that image, and drops the `<noscript>`. The placeholder is the nearest
+/// sibling element. A `<noscript>` without one is left as it is.
+fn prefer_noscript_images(nodes: &mut Vec<NodeRef>) {
+    let mut ii = 0;
+    while ii < nodes.len() {
+        let image = noscript_image(&nodes[ii].borrow());
+        if let Some(image) = image {
+            if let Some(placeholder) = placeholder(nodes, ii) {
+                // The image takes the place of the placeholder, and the
+                // whitespace before it with it.
+                let image = std::mem::replace(&mut *image.borrow_mut(), Node::Text(String::new()));
+                *nodes[placeholder].borrow_mut() = image;
+                nodes.remove(ii);
+                continue;
+            }
+        }
+        if let Node::Tag { children, .. } = &mut *nodes[ii].borrow_mut() {
+            prefer_noscript_images(children);
+        }
+        ii += 1;
+    }
+}
+
+/// noscript_image returns the image `node` holds if it's a `<noscript>`
+/// holding nothing but an `<img>`.
+fn noscript_image(node: &Node) -> Option<NodeRef> {
+    if !node.is_element("noscript") {
+        return None;
+    }
+    let mut elements = node.children().iter().filter(|c| match &*c.borrow() {
+        Node::Text(text) => !text.trim().is_empty(),
+        Node::Comment(_) => false,
+        _ => true,
+    });
+    match (elements.next(), elements.next()) {
+        (Some(image), None) if image.borrow().is_element("img") => Some(image.clone()),
+        _ => None,
+    }
+}
+
+/// placeholder returns the index of the `<img>` in `nodes` that is the
+/// nearest element before the one at `ii`, or else after it.
+fn placeholder(nodes: &[NodeRef], ii: usize) -> Option<usize> {
+    let is_element = |n: &NodeRef| n.borrow().tag_name().is_some();
+    let before = nodes[..ii].iter().rposition(is_element);
+    let after = nodes[ii + 1..]
+        .iter()
+        .position(is_element)
+        .map(|jj| ii + 1 + jj);
+    before
+        .or(after)
+        .filter(|&jj| nodes[jj].borrow().is_element("img"))
+}
+
+/// Conditional is a marker of a downlevel-revealed conditional comment, the
+/// content of which is markup for every browser except old versions of IE.
+/// Both `<!--[if !IE]><!-->...<!--<![endif]-->` and the older
+/// `<![if !IE]>...<![endif]>` forms are recognised.
+#[derive(Debug, PartialEq)]
+enum Conditional {
+    Start,
+    End,
+}
+
+impl Conditional {
+    fn new(node: &Node) -> Option<Self> {
+        let (start, end) = match node {
+            Node::Comment(text) => {
+                let text = text.trim();
+                (
+                    text.starts_with("[if") && text.ends_with("<!"),
+                    text == "<![endif]",
+                )
+            }
+            Node::Text(text) => {
+                let text = text.trim();
+                (
+                    text.starts_with("<![if") && text.ends_with("]>"),
+                    text == "<![endif]>",
+                )
+            }
+            _ => (false, false),
+        };
+        if start {
+            Some(Conditional::Start)
+        } else if end {
+            Some(Conditional::End)
+        } else {
+            None
+        }
+    }
+}
+
+/// unterminated_conditionals returns the start markers of the conditional
+/// comments in `nodes` and their descendants that aren't followed by an end
+/// marker among their siblings before the next start marker.
+fn unterminated_conditionals(nodes: &[NodeRef]) -> HashSet<*const RefCell<Node>> {
+    let mut unterminated = HashSet::new();
+    let mut siblings = vec![nodes.to_vec()];
+    while let Some(nodes) = siblings.pop() {
+        let mut start: Option<&NodeRef> = None;
+        for n in &nodes {
+            match Conditional::new(&n.borrow()) {
+                Some(Conditional::Start) => {
+                    unterminated.extend(start.map(Rc::as_ptr));
+                    start = Some(n);
+                }
+                Some(Conditional::End) => start = None,
+                None => {}
+            }
+            let children = n.borrow().children().to_vec();
+            if !children.is_empty() {
+                siblings.push(children);
+            }
+        }
+        unterminated.extend(start.map(Rc::as_ptr));
+    }
+    unterminated
+}
+
+/// The `property` or `name` of the `<meta>` tags whose `content` is the url of
+/// an image: those of Open Graph, Twitter cards and Windows tiles.
+const META_IMAGES: &[&str] = &[
+    "og:image",
+    "og:image:url",
+    "og:image:secure_url",
+    "twitter:image",
+    "twitter:image:src",
+    "msapplication-tileimage",
+    "msapplication-square70x70logo",
+    "msapplication-square150x150logo",
+    "msapplication-wide310x150logo",
+    "msapplication-square310x310logo",
+];
+
+/// is_meta_image reports whether the `<meta>` with `attributes` names an
+/// image by its `content`.
+pub(crate) fn is_meta_image(attributes: &HashMap<String, String>) -> bool {
+    ["property", "name"].iter().any(|key| {
+        attributes
+            .get(*key)
+            .is_some_and(|value| META_IMAGES.contains(&value.to_ascii_lowercase().as_str()))
+    })
+}
+
+/// Link relations that reference something other than a resource, such as
+/// another page, so the link is left as it is.
+pub(crate) const NON_RESOURCE_RELATIONS: &[&str] = &["canonical", "alternate", "license"];
+
+/// Link relations of the icons of a page: its favicon, the icons iOS puts on
+/// the home screen and the monochrome icon Safari shows pinned tabs with.
+const ICON_RELATIONS: &[&str] = &[
+    "icon",
+    "apple-touch-icon",
+    "apple-touch-icon-precomposed",
+    "mask-icon",
+];
+
+/// Link is the treatment a `<link>` element gets.
+#[derive(Debug, PartialEq)]
+enum Link {
+    /// Embedded into a `<style>` element.
+    Stylesheet,
+    /// Embedded as a data url in place of the href.
+    Icon,
+    /// A web app manifest, embedded as a data url along with its images.
+    Manifest,
+    /// A module fetched ahead of the scripts importing it, which is removed
+    /// once the module is bundled into them.
+    ModulePreload,
+    /// A stylesheet that only applies once it's picked from the browser's
+    /// styles, so it's embedded in its link: in a `<style>`, it would
+    /// always apply.
+    Alternate,
+    /// References something other than a resource, such as another page.
+    Untouched,
+    /// A relation we don't know how to inline.
+    Unknown(String),
+}
+
+impl Link {
+    /// new determines the treatment from the `rel` attribute, which may hold
+    /// several space separated relations. Without a `rel`, the extension of
+    /// `href` is used as a hint.
+    fn new(rel: Option<&str>, href: &str) -> Self {
+        let rel = rel.unwrap_or("").to_ascii_lowercase();
+        let rels: Vec<&str> = rel.split_whitespace().collect();
+        let has = |names: &[&str]| rels.iter().any(|r| names.contains(r));
+        if rels.is_empty() {
+            if extension(href).as_deref() == Some("css") {
+                Link::Stylesheet
+            } else {
+                Link::Unknown(rel)
+            }
+        } else if has(&["alternate"]) && has(&["stylesheet"]) {
+            Link::Alternate
+        } else if has(NON_RESOURCE_RELATIONS) {
+            Link::Untouched
+        } else if has(&["stylesheet"]) {
+            Link::Stylesheet
+        } else if has(ICON_RELATIONS) {
+            Link::Icon
+        } else if has(&["manifest"]) {
+            Link::Manifest
+        } else if has(&["modulepreload"]) {
+            Link::ModulePreload
+        } else {
+            Link::Unknown(rel)
+        }
+    }
+}
+
+/// embedded_attribute returns the attribute holding the resource of an
+/// embedded object element, if `name` is one.
+fn embedded_attribute(name: &str) -> Option<&'static str> {
+    match name {
+        "object" => Some("data"),
+        "embed" | "track" => Some("src"),
+        _ => None,
+    }
+}
+
+/// base_href returns the href of the first `<base>` element that has one.
+fn base_href(dom: &Dom) -> Option<String> {
+    dom.find_by_tag("base")
+        .iter()
+        .find_map(|n| n.borrow().attr("href").map(str::to_owned))
+}
+
+/// resource_attribute returns the attribute holding the resource an element
+/// loads, if it loads one. Anchors only load their target when the user
+/// follows them, so they are inlined only if `options` asks for it. The
+/// `action` of forms and `formaction` of their buttons are where the form is
+/// sent, so they're never inlined.
+fn resource_attribute(node: &Node, options: &Options) -> Option<&'static str> {
+    match node.tag_name()?.to_ascii_lowercase().as_str() {
+        "script" | "img" | "source" | "video" | "audio" | "iframe" => Some("src"),
+        "input"
+            if node
+                .attr("type")
+                .is_some_and(|t| t.eq_ignore_ascii_case("image")) =>
+        {
+            Some("src")
+        }
+        "a" | "area" if options.inline_anchors => Some("href"),
+        "image" | "feimage" => svg::href_attribute(node),
+        _ => None,
+    }
+}
+
+/// inline_lazy inlines the `data-src` and `data-srcset` of a lazily loaded
+/// element, promoting them to `src` and `srcset` if `lazy` says to.
+fn inline_lazy(
+    node: &mut Node,
+    lazy: LazyAttributes,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+) -> Result<(), Box<dyn Error>> {
+    let mut lazy_loaded = false;
+    if let Some(link) = node.attr("data-src") {
+        if let Some(url) = embed(link, node.attr("type"), base, options, resources)? {
+            node.set_attr("data-src", url);
+            lazy_loaded = true;
+        }
+    }
+    if let Some(srcset) = node.attr("data-srcset") {
+        let srcset = inline_srcset(srcset, node.attr("type"), base, options, resources)?;
+        node.set_attr("data-srcset", srcset);
+        lazy_loaded = true;
+    }
+    if !lazy_loaded || lazy == LazyAttributes::Keep {
+        return Ok(());
+    }
+    // A placeholder srcset would win over a promoted src, so it goes even
+    // without a data-srcset to replace it.
+    if node.has_attr("data-src") && !node.has_attr("data-srcset") {
+        node.remove_attr("srcset");
+    }
+    for (from, to) in [("data-src", "src"), ("data-srcset", "srcset")] {
+        if let Some(value) = node.remove_attr(from) {
+            node.set_attr(to, value);
+        }
+    }
+    let class = node.attr("class").map(|class| {
+        class
+            .split_ascii_whitespace()
+            .filter(|c| *c != "lazyload")
+            .collect::<Vec<_>>()
+            .join(" ")
+    });
+    match class {
+        Some(class) if class.is_empty() => drop(node.remove_attr("class")),
+        Some(class) => node.set_attr("class", class),
+        None => {}
+    }
+    Ok(())
+}
+
+/// inline_srcset embeds each local candidate of a `srcset` as a data url.
+/// The `type` of a `<source>` applies to all of its candidates.
+fn inline_srcset(
+    srcset: &str,
+    declared: Option<&str>,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+) -> Result<String, Box<dyn Error>> {
+    let mut srcset = SrcSet::parse(srcset);
+    for candidate in &mut srcset.0 {
+        if let Some(url) = embed(&candidate.url, declared, base, options, resources)? {
+            candidate.url = url;
+        }
+    }
+    Ok(srcset.to_string())
+}
+
+/// embed returns a data url for the resource at `link`, or `None` if the link
+/// is not local, is a fragment of the document itself, as the `url(#id)`s of
+/// SVG paint are, or the resource is too large to embed.
+pub(crate) fn embed(
+    link: &str,
+    declared: Option<&str>,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if link.is_empty() || link.starts_with('#') || !base.resolves(link) {
+        return Ok(None);
+    }
+    let path = base.resolve(link)?;
+    if exceeds_threshold(link, &path, base, options, resources)? {
+        return Ok(None);
+    }
+    data_url(&path, &media_type(declared, link), options, resources).map(Some)
+}
+
+/// media_type is the `declared` type when there is one, otherwise it is
+/// guessed from the extension of `link`.
+fn media_type(declared: Option<&str>, link: &str) -> String {
+    match declared {
+        Some(t) if !t.is_empty() => t.to_owned(),
+        _ => mime_guess::from_path(link_path(link))
+            .first_or_octet_stream()
+            .to_string(),
+    }
+}
+
+/// sniff_image returns the media type of the image `content` holds, by the
+/// signature it starts with, or `None` if it's not one it knows.
+fn sniff_image(content: &[u8]) -> Option<&'static str> {
+    let signatures: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\x00\x00\x01\x00", "image/x-icon"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"\xFF\xD8\xFF", "image/jpeg"),
+    ];
+    if let Some((_, media_type)) = signatures.iter().find(|(s, _)| content.starts_with(s)) {
+        return Some(media_type);
+    }
+    if content.starts_with(b"RIFF") && content.get(8..12) == Some(b"WEBP") {
+        return Some("image/webp");
+    }
+    // SVG is XML, which may start with a declaration, a doctype or comments.
+    let head = String::from_utf8_lossy(&content[..content.len().min(1024)]);
+    match head.trim_start().starts_with('<') && head.contains("<svg") {
+        true => Some("image/svg+xml"),
+        false => None,
+    }
+}
+
+/// Embedding is how the file behind a `src` is embedded.
+#[derive(Debug, PartialEq)]
+enum Embedding {
+    /// As the text content of the element, for markup, scripts and styles.
+    Text,
+    /// As a data url in place of the link.
+    DataUrl,
+}
+
+impl Embedding {
+    /// new decides the embedding from the extension of `link`.
+    fn new(link: &str) -> Self {
+        match extension(link).as_deref() {
+            Some("html" | "htm" | "js" | "mjs" | "css") => Embedding::Text,
+            _ => Embedding::DataUrl,
+        }
+    }
+}
+
+/// extension returns the extension of the file `link` refers to, in lower
+/// case, ignoring any query or fragment.
+fn extension(link: &str) -> Option<String> {
+    Path::new(link_path(link))
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+/// link_path returns `link` without its query or fragment.
+fn link_path(link: &str) -> &str {
+    link.split(['?', '#']).next().unwrap_or(link)
+}
+
+/// is_local reports whether `link` refers to a local resource, as opposed
+/// to a remote url or one that already contains its data.
+pub(crate) fn is_local(link: &str) -> bool {
+    let scheme = link.find(':').map(|ii| &link[..ii]).filter(|s| {
+        s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    });
+    scheme.is_none() && !link.starts_with("//")
+}
+
+/// exceeds_threshold reports whether the file at `path`, which `link`
+/// resolved to against `base`, is too large to be embedded according to
+/// `options`. A link to one that is is recorded as skipped with `base`.
+pub(crate) fn exceeds_threshold(
+    link: &str,
+    path: &Path,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+) -> Result<bool, Box<dyn Error>> {
+    let exceeds = match options.size_threshold {
+        Some(threshold) => resources.size(path)? > threshold,
+        None => false,
+    };
+    if exceeds {
+        base.skip(link);
+    }
+    Ok(exceeds)
+}
+
+/// data_url reads the resource at `path` into a base64 encoded data url.
+pub(crate) fn data_url(
+    path: &Path,
+    media_type: &str,
+    options: &Options,
+    resources: &dyn Resources,
+) -> Result<String, Box<dyn Error>> {
+    let content = read_media(path, options, resources)?;
+    let media_type = resources
+        .media_type(path)
+        .unwrap_or_else(|| media_type.to_owned());
+    Ok(format!(
+        "data:{media_type};base64,{data}",
+        media_type = media_type,
+        data = base64::encode(&content)
+    ))
+}
+
+/// read_media reads the resource at `path` to embed it in a data url, which
+/// an empty file is only embedded in if `options` says so.
+fn read_media(
+    path: &Path,
+    options: &Options,
+    resources: &dyn Resources,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let content = resources.read(path)?;
+    if content.is_empty() && !options.embed_empty {
+        return Err(ResourceError::Empty(path.to_owned()).into());
+    }
+    Ok(content)
+}
+
+/// is_empty_resource reports whether `err` is that of an empty file, which
+/// is left linked with a warning rather than stopping inlining.
+pub(crate) fn is_empty_resource(err: &(dyn Error + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<ResourceError>(),
+        Some(ResourceError::Empty(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::Memory;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join(name)
+    }
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    fn encoded(path: PathBuf, media_type: &str) -> String {
+        format!(
+            "data:{};base64,{}",
+            media_type,
+            base64::encode(&fs::read(path).unwrap())
+        )
+    }
+
+    #[test]
+    fn embedded_objects() {
+        let base = fixture("embedded");
+        let tests = vec![
+            (
+                "object keeps fallback content, type attribute is the media type",
+                r#"<object data="manual.pdf" type="application/pdf"><p>Read the manual.</p></object>"#
+                    .to_string(),
+                format!(
+                    r#"<object data="{}" type="application/pdf"><p>Read the manual.</p></object>"#,
+                    encoded(base.join("manual.pdf"), "application/pdf")
+                ),
+            ),
+            (
+                "object without type attribute guesses the media type",
+                r#"<object data="manual.pdf"></object>"#.to_string(),
+                format!(
+                    r#"<object data="{}"></object>"#,
+                    encoded(base.join("manual.pdf"), "application/pdf")
+                ),
+            ),
+            (
+                "embed",
+                r#"<embed src="plugin.swf"/>"#.to_string(),
+                format!(
+                    r#"<embed src="{}"/>"#,
+                    encoded(base.join("plugin.swf"), "application/x-shockwave-flash")
+                ),
+            ),
+            (
+                "track",
+                r#"<video><track src="captions.vtt"/></video>"#.to_string(),
+                format!(
+                    r#"<video><track src="{}"/></video>"#,
+                    encoded(base.join("captions.vtt"), "text/vtt")
+                ),
+            ),
+        ];
+        for (desc, input, want) in tests {
+            let got = inline(&input, &base).expect(desc);
+            assert_eq!(parse(&want), parse(&got), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn size_threshold() {
+        let base = fixture("embedded");
+        let input = r#"<object data="manual.pdf" type="application/pdf"></object>"#;
+        let size = fs::metadata(base.join("manual.pdf")).unwrap().len();
+        let options = Options {
+            size_threshold: Some(size - 1),
+            ..Options::default()
+        };
+        let (got, _) = inline_with(input.into(), &base, &options).unwrap();
+        assert_eq!(parse(input), parse(&got));
+        let options = Options {
+            size_threshold: Some(size),
+            ..Options::default()
+        };
+        let (got, _) = inline_with(input.into(), &base, &options).unwrap();
+        assert_eq!(
+            parse(&format!(
+                r#"<object data="{}" type="application/pdf"></object>"#,
+                encoded(base.join("manual.pdf"), "application/pdf")
+            )),
+            parse(&got)
+        );
+    }
+
+    #[test]
+    fn links_dispatch_on_rel() {
+        let base = fixture("links");
+        let input = r#"
+            <head>
+                <link rel="stylesheet" href="theme"/>
+                <link rel="icon" href="favicon.png"/>
+                <link rel="apple-touch-icon" href="touch.png"/>
+                <link rel="canonical" href="https://example.com/"/>
+                <link rel="alternate" href="feed.xml"/>
+                <link rel="license" href="license.html"/>
+                <link rel="preconnect" href="https://cdn.example.com"/>
+            </head>
+        "#;
+        let want = format!(
+            r#"
+            <head>
+                <style>body{{color:#333}}</style>
+                <link rel="icon" href="{icon}" type="image/png"/>
+                <link rel="apple-touch-icon" href="{touch}" type="image/png"/>
+                <link rel="canonical" href="https://example.com/"/>
+                <link rel="alternate" href="feed.xml"/>
+                <link rel="license" href="license.html"/>
+                <link rel="preconnect" href="https://cdn.example.com"/>
+            </head>
+            "#,
+            icon = encoded(base.join("favicon.png"), "image/png"),
+            touch = encoded(base.join("touch.png"), "image/png"),
+        );
+        let (got, report) = inline_with(input.into(), &base, &Options::default()).unwrap();
+        assert_eq!(parse(&want), parse(&got));
+        assert_eq!(
+            vec![Warning {
+                element: "link".into(),
+                link: "https://cdn.example.com".into(),
+                reason: r#"unsupported link relation "preconnect""#.into(),
+            }],
+            report.warnings
+        );
+    }
+
+    #[test]
+    fn icons() {
+        let base = fixture("icons");
+        let input = r##"<head>
+            <link rel="icon" href="favicon.ico" sizes="32x32">
+            <link rel="apple-touch-icon" href="touch-icon" sizes="180x180">
+            <link rel="mask-icon" href="safari.svg" color="#000">
+            <link rel="shortcut icon" href="touch-icon" type="image/vnd.custom">
+        </head>"##;
+        let want = format!(
+            r##"<head>
+            <link rel="icon" href="{ico}" sizes="32x32" type="image/x-icon">
+            <link rel="apple-touch-icon" href="{png}" sizes="180x180" type="image/png">
+            <link rel="mask-icon" href="{svg}" color="#000" type="image/svg+xml">
+            <link rel="shortcut icon" href="{custom}" type="image/vnd.custom">
+        </head>"##,
+            ico = encoded(base.join("favicon.ico"), "image/x-icon"),
+            png = encoded(base.join("touch-icon"), "image/png"),
+            svg = encoded(base.join("safari.svg"), "image/svg+xml"),
+            custom = encoded(base.join("touch-icon"), "image/vnd.custom"),
+        );
+        let (got, report) = inline_with(input.into(), &base, &Options::default()).unwrap();
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        assert_eq!(parse(&want), parse(&got));
+    }
+
+    #[test]
+    fn stylesheet_attributes() {
+        let base = fixture("links");
+        let input = r#"
+            <link rel="stylesheet" href="print.css" media="print" type="text/css"/>
+            <link rel="stylesheet" href="theme" title="Dark" id="theme" class="themes" integrity="sha384-x" crossorigin="anonymous" as="style"/>
+        "#;
+        let want = r#"
+            <style media="print" type="text/css">nav{display:none}</style>
+            <style title="Dark" id="theme" class="themes">body{color:#333}</style>
+        "#;
+        let (got, _) = inline_with(input.into(), &base, &Options::default()).unwrap();
+        assert_eq!(parse(want), parse(&got));
+    }
+
+    #[test]
+    fn link_treatment() {
+        let tests = vec![
+            ("stylesheet", Some("stylesheet"), "theme", Link::Stylesheet),
+            (
+                "relations are case insensitive",
+                Some("StyleSheet"),
+                "a.css",
+                Link::Stylesheet,
+            ),
+            ("no rel, css extension", None, "style.css", Link::Stylesheet),
+            (
+                "no rel, other extension",
+                None,
+                "x.png",
+                Link::Unknown("".into()),
+            ),
+            (
+                "icon ignores extension",
+                Some("icon"),
+                "favicon.css",
+                Link::Icon,
+            ),
+            (
+                "multiple relations",
+                Some("shortcut icon"),
+                "favicon.ico",
+                Link::Icon,
+            ),
+            (
+                "alternate stylesheet",
+                Some("alternate stylesheet"),
+                "a.css",
+                Link::Alternate,
+            ),
+            (
+                "alternate version of the page",
+                Some("alternate"),
+                "fr.html",
+                Link::Untouched,
+            ),
+            (
+                "touch icon",
+                Some("apple-touch-icon-precomposed"),
+                "touch",
+                Link::Icon,
+            ),
+            ("mask icon", Some("mask-icon"), "safari.svg", Link::Icon),
+            (
+                "canonical",
+                Some("canonical"),
+                "index.html",
+                Link::Untouched,
+            ),
+            (
+                "manifest",
+                Some("manifest"),
+                "site.webmanifest",
+                Link::Manifest,
+            ),
+            (
+                "unknown",
+                Some("dns-prefetch"),
+                "//cdn.example.com",
+                Link::Unknown("dns-prefetch".into()),
+            ),
+        ];
+        for (desc, rel, href, want) in tests {
+            assert_eq!(want, Link::new(rel, href), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn stylesheets() {
+        let base = fixture("stylesheets");
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        let (got, report) = inline_with(input, &base, &Options::default()).unwrap();
+        let head = &got[got.find("<head>").unwrap()..got.find("</head>").unwrap()];
+        let want = r#"<head>
+  <style>body { color: #222; background: #fff; }</style>
+  <style media="(prefers-color-scheme: dark)">body { color: #eee; background: #111; }</style>
+  <style media="print" title="Default">body { font-size: 12pt; }</style>
+  <link href="{}" rel="alternate stylesheet" title="High contrast">
+"#;
+        let contrast = encoded(base.join("contrast.css"), "text/css");
+        assert_eq!(want.replace("{}", &contrast), head);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        // Embedded, the alternate is no link left for strict mode.
+        let options = Options {
+            strict: true,
+            ..Options::default()
+        };
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        inline_with(input, &base, &options).expect("self-contained");
+    }
+
+    #[test]
+    fn whitespace() {
+        let base = fixture("whitespace");
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        let want = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>Whitespace</title><style>.tile { display: inline-block; width: 4em; }</style></head><body><h1>Tiles</h1><div class="apart"><span class="tile">one</span> <span class="tile">two</span></div><div class="together"><span class="tile">one</span><span class="tile">two</span></div><p>Some <em>emphasized</em> and <strong>strong</strong> text.</p><table><thead><tr><th>Name</th><th>Size</th></tr></thead><tbody><tr><td><code>tiles.css</code> <small>(stylesheet)</small></td><td>1 KB</td></tr></tbody></table><pre>
+  indented
+    more</pre></body></html>
+"#;
+        for format in [SerializeMode::Html5, SerializeMode::Xhtml] {
+            let options = Options {
+                format,
+                ..Options::default()
+            };
+            let (got, _) = inline_with(input.clone(), &base, &options).unwrap();
+            let want = match format {
+                SerializeMode::Xhtml => want.replace("utf-8\">", "utf-8\" />"),
+                _ => want.into(),
+            };
+            assert_eq!(want, got, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let base = fixture("merge");
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        let options = Options {
+            merge_css: true,
+            merge_js: true,
+            ..Options::default()
+        };
+        let (got, report) = inline_with(input.clone(), &base, &options).unwrap();
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        let want = r#"<!DOCTYPE html>
+<html>
+<head>
+  <style>.reset { margin: 0; }
+.layout { margin: 0; }</style>
+  <style media="print">.print { margin: 0; }</style>
+  <style>.theme { margin: 0; }</style>
+  <style>.page { color: red; }</style>
+  <style>.overrides { margin: 0; }</style>
+  <script>console.log("polyfill")
+;
+console.log("analytics")</script>
+  <!-- analytics -->
+  <script defer>console.log("deferred")</script>
+  <script async>console.log("async")</script>
+  <script type="module">console.log("module")</script>
+</head>
+<body>
+  <p class="page">text</p>
+  <script>console.log("app")
+;
+console.log("widgets")</script>
+</body>
+</html>
+"#;
+        assert_eq!(want, got);
+        let (unmerged, _) = inline_with(input, &base, &Options::default()).unwrap();
+        assert_eq!(13, unmerged.matches("</s").count(), "{}", unmerged);
+    }
+
+    #[test]
+    fn picture() {
+        let base = fixture("picture");
+        let input = r#"
+            <picture>
+                <source srcset="hero.webp" type="image/webp"/>
+                <source srcset="hero.jpg" media="(min-width:800px)"/>
+                <img src="fallback.jpg" alt="hero"/>
+            </picture>
+        "#;
+        let webp = encoded(base.join("hero.webp"), "image/webp");
+        let jpg = encoded(base.join("hero.jpg"), "image/jpeg");
+        let fallback = encoded(base.join("fallback.jpg"), "image/jpeg");
+        let tests = vec![
+            (
+                "every candidate is embedded",
+                false,
+                format!(
+                    r#"
+                    <picture>
+                        <source srcset="{}" type="image/webp"/>
+                        <source srcset="{}" media="(min-width:800px)"/>
+                        <img src="{}" alt="hero"/>
+                    </picture>
+                    "#,
+                    webp, jpg, fallback
+                ),
+            ),
+            (
+                "collapsed to the fallback image",
+                true,
+                format!(r#"<picture><img src="{}" alt="hero"/></picture>"#, fallback),
+            ),
+        ];
+        for (desc, picture_collapse, want) in tests {
+            let options = Options {
+                picture_collapse,
+                ..Options::default()
+            };
+            let (got, _) = inline_with(input.into(), &base, &options).expect(desc);
+            assert_eq!(parse(&want), parse(&got), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn lazy_attributes() {
+        let base = fixture("lazy");
+        let input = r#"<img data-src="big.jpg" data-srcset="big.jpg 1x, big-2x.jpg 2x" src="placeholder.gif" srcset="placeholder.gif 1x" class="lazyload hero"/>"#;
+        let big = encoded(base.join("big.jpg"), "image/jpeg");
+        let big_2x = encoded(base.join("big-2x.jpg"), "image/jpeg");
+        let placeholder = encoded(base.join("placeholder.gif"), "image/gif");
+        let tests = vec![
+            (
+                "left as links",
+                None,
+                format!(
+                    r#"<img data-src="big.jpg" data-srcset="big.jpg 1x, big-2x.jpg 2x" src="{0}" srcset="{0} 1x" class="lazyload hero"/>"#,
+                    placeholder
+                ),
+            ),
+            (
+                "kept in the data attributes",
+                Some(LazyAttributes::Keep),
+                format!(
+                    r#"<img data-src="{0}" data-srcset="{0} 1x, {1} 2x" src="{2}" srcset="{2} 1x" class="lazyload hero"/>"#,
+                    big, big_2x, placeholder
+                ),
+            ),
+            (
+                "promoted over the placeholders",
+                Some(LazyAttributes::Promote),
+                format!(
+                    r#"<img src="{0}" srcset="{0} 1x, {1} 2x" class="hero"/>"#,
+                    big, big_2x
+                ),
+            ),
+        ];
+        for (desc, lazy_attributes, want) in tests {
+            let options = Options {
+                lazy_attributes,
+                ..Options::default()
+            };
+            let (got, _) = inline_with(input.into(), &base, &options).unwrap();
+            assert_eq!(parse(&want), parse(&got), "{}", desc);
+        }
+        let (got, _) = inline_with(
+            r#"<iframe data-src="big.jpg" class="lazyload"></iframe>"#.into(),
+            &base,
+            &Options {
+                lazy_attributes: Some(LazyAttributes::Promote),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let want = format!(r#"<iframe src="{}"></iframe>"#, big);
+        assert_eq!(parse(&want), parse(&got), "an emptied class is removed");
+        let (got, _) = inline_with(
+            r#"<img data-src="big.jpg" src="placeholder.gif" srcset="placeholder.gif 1x"/>"#.into(),
+            &base,
+            &Options {
+                lazy_attributes: Some(LazyAttributes::Promote),
+                ..Options::default()
+            },
+        )
+        .unwrap();
+        let want = format!(r#"<img src="{}"/>"#, big);
+        assert_eq!(parse(&want), parse(&got), "a placeholder srcset is removed");
+    }
+
+    #[test]
+    fn style_attribute_urls() {
+        let base = fixture("picture");
+        let input = r#"<div style="background:url('hero.jpg'),url(fallback.jpg);mask-image:url(hero.webp)"><span style="color:red"></span></div>"#;
+        let want = format!(
+            r#"<div style="background:url('{}'),url({});mask-image:url({})"><span style="color:red"></span></div>"#,
+            encoded(base.join("hero.jpg"), "image/jpeg"),
+            encoded(base.join("fallback.jpg"), "image/jpeg"),
+            encoded(base.join("hero.webp"), "image/webp"),
+        );
+        let got = inline(input, &base).unwrap();
+        assert_eq!(parse(&want), parse(&got));
+        // Absolute and data urls are left alone.
+        let input = r#"<div style="background:url(https://example.com/a.png),url(data:image/gif;base64,R0lGOD)"></div>"#;
+        let got = inline(input, &base).unwrap();
+        assert_eq!(parse(input), parse(&got));
+    }
+
+    #[test]
+    fn style_elements() {
+        let base = fixture("style");
+        let input = r#"
+            <head>
+                <style>body{background:url(img/texture.png)}</style>
+                <link rel="stylesheet" href="css/site.css"/>
+            </head>
+        "#;
+        let document = encoded(base.join("img/texture.png"), "image/png");
+        let stylesheet = encoded(base.join("css/img/texture.png"), "image/png");
+        assert_ne!(document, stylesheet);
+        let want = format!(
+            "<head><style>body{{background:url({doc})}}</style><style>\
+             h1{{background:url({doc})}}\n\
+             @media print {{\nbody{{color:black}}\n}}\n\
+             .card{{background:url({sheet})}}\
+             </style></head>",
+            doc = document,
+            sheet = stylesheet,
+        );
+        let got = inline(input, &base).unwrap();
+        assert_eq!(parse(&want), parse(&got));
+    }
+
+    #[test]
+    fn conditional_comments() {
+        let base = fixture("conditional");
+        let hidden = r#"<!--[if IE 9]><link rel="stylesheet" href="ie9.css"><![endif]-->"#;
+        let input = format!(
+            r#"
+            <head>
+                {}
+                <!--[if !IE]><!--><link rel="stylesheet" href="modern.css"/><!--<![endif]-->
+                <![if !IE]><link rel="stylesheet" href="legacy.css"/><![endif]>
+                <link rel="stylesheet" href="base.css"/>
+            </head>
+            "#,
+            hidden
+        );
+        let tests = vec![
+            (
+                "revealed conditionals are preserved",
+                false,
+                format!(
+                    r#"
+                    <head>
+                        {}
+                        <!--[if !IE]><!--><link rel="stylesheet" href="modern.css"/><!--<![endif]-->
+                        <![if !IE]><link rel="stylesheet" href="legacy.css"/><![endif]>
+                        <style>.base{{}}</style>
+                    </head>
+                    "#,
+                    hidden
+                ),
+            ),
+            (
+                "revealed conditionals are inlined",
+                true,
+                format!(
+                    r#"
+                    <head>
+                        {}
+                        <!--[if !IE]><!--><style>.modern{{}}</style><!--<![endif]-->
+                        <![if !IE]><style>.legacy{{}}</style><![endif]>
+                        <style>.base{{}}</style>
+                    </head>
+                    "#,
+                    hidden
+                ),
+            ),
+        ];
+        for (desc, inline_conditional_comments, want) in tests {
+            let options = Options {
+                inline_conditional_comments,
+                ..Options::default()
+            };
+            let (got, _) = inline_with(input.clone(), &base, &options).expect(desc);
+            assert!(got.contains(hidden), "{}: hidden comment changed", desc);
+            assert_eq!(parse(&want), parse(&got), "{}", desc);
+        }
+
+        // Without its end marker, a conditional doesn't hide the rest.
+        let input = r#"<head><!--[if !IE]><!--><link rel="stylesheet" href="modern.css"/><link rel="stylesheet" href="base.css"/></head>"#;
+        let (got, report) = inline_with(input.into(), &base, &Options::default()).unwrap();
+        let want =
+            r#"<head><!--[if !IE]><!--><style>.modern{}</style><style>.base{}</style></head>"#;
+        assert_eq!(parse(want), parse(&got));
+        assert_eq!(
+            vec![Warning {
+                element: "comment".into(),
+                link: "".into(),
+                reason: "conditional comment without an end marker, so what follows it is inlined"
+                    .into(),
+            }],
+            report.warnings
+        );
+    }
+
+    #[test]
+    fn non_utf8_documents() {
+        let base = fixture("encoding");
+        let input = fs::read(base.join("page.html")).unwrap();
+        assert!(String::from_utf8(input.clone()).is_err());
+        let (got, _) = inline_bytes(&input, &base, &Options::default()).unwrap();
+        let want = r#"
+            <html><head>
+                <meta charset="utf-8"/>
+                <style>
+.price::after{content:"€"}</style>
+                <script>var greeting = "¡Olé!";</script>
+            </head><body><p>Café “crème”</p></body></html>
+        "#;
+        assert_eq!(parse(want), parse(&got));
+        let mut streamed = vec![];
+        inline_bytes_to(&input, &base, &Options::default(), &mut streamed).unwrap();
+        assert_eq!(parse(want), parse(&String::from_utf8(streamed).unwrap()));
+        let (got, _) = inline_from(&input[..], &base, &Options::default()).unwrap();
+        assert_eq!(parse(want), parse(&got), "read");
+    }
+
+    #[test]
+    fn charset_declarations() {
+        let base = fixture("preserve");
+        let tests = vec![
+            (
+                "charset",
+                r#"<meta charset="windows-1252">"#,
+                r#"<meta charset="utf-8">"#,
+            ),
+            (
+                "content type",
+                r#"<meta http-equiv="Content-Type" content="text/html; charset=windows-1252">"#,
+                r#"<meta http-equiv="Content-Type" content="text/html; charset=utf-8">"#,
+            ),
+            (
+                "content type in another case",
+                r#"<meta HTTP-EQUIV="content-type" content="text/html; charset=iso-8859-1">"#,
+                r#"<meta http-equiv="content-type" content="text/html; charset=utf-8">"#,
+            ),
+            (
+                "description",
+                r#"<meta name="description" content="Save it with charset=utf-8">"#,
+                r#"<meta name="description" content="Save it with charset=utf-8">"#,
+            ),
+            (
+                "refresh",
+                r#"<meta http-equiv="refresh" content="5; url=page.html?charset=latin1">"#,
+                r#"<meta http-equiv="refresh" content="5; url=page.html?charset=latin1">"#,
+            ),
+        ];
+        for (desc, input, want) in tests {
+            let (got, _) = inline_with(input.into(), &base, &Options::default()).unwrap();
+            assert_eq!(parse(want), parse(&got), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn readers() {
+        let base = fixture("preserve");
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        let (want, _) = inline_with(input.clone(), &base, &Options::default()).unwrap();
+        // A small buffer splits characters and tags between reads.
+        let reader = io::BufReader::with_capacity(3, input.as_bytes());
+        let (got, _) = inline_from(reader, &base, &Options::default()).unwrap();
+        assert_eq!(want, got);
+        let mut written = vec![];
+        inline_to(input.as_bytes(), &base, &Options::default(), &mut written).unwrap();
+        assert_eq!(want, String::from_utf8(written).unwrap());
+        let input = b"<p>caf\xc3\xa9</p><p>\xe9</p>";
+        let err = inline_from(&input[..], &base, &Options::default()).unwrap_err();
+        assert_eq!("invalid UTF-8 at byte 15", err.to_string());
+        let mut written = vec![];
+        assert!(inline_to(&input[..], &base, &Options::default(), &mut written).is_err());
+        assert!(written.is_empty(), "nothing is written for invalid input");
+    }
+
+    #[test]
+    fn custom_resources() {
+        // Any function reading a path is a `Resources`.
+        let files = Memory::new()
+            .with("site/app.css", "body{background:url(dot.png)}")
+            .with("site/dot.png", "dot");
+        let resources = |path: &Path| files.read(path);
+        let input = r#"<head><link rel="stylesheet" href="app.css"/></head>"#;
+        let (got, _) = inline_with_resources(
+            input.into(),
+            Path::new("site"),
+            &Options::default(),
+            &resources,
+        )
+        .unwrap();
+        let want =
+            r#"<head><style>body{background:url(data:image/png;base64,ZG90)}</style></head>"#;
+        assert_eq!(parse(want), parse(&got));
+        let input = r#"<img src="missing.png"/>"#;
+        let err = inline_with_resources(
+            input.into(),
+            Path::new("site"),
+            &Options::default(),
+            &resources,
+        )
+        .unwrap_err();
+        assert_eq!(
+            "<img> missing.png: site/missing.png: not found",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn memory_resources() {
+        let resources = Memory::new()
+            .with("site/app.css", "body{background:url(dot)}")
+            .with_media_type("site/dot", "<svg/>", "image/svg+xml");
+        let input = r#"<head><link rel="stylesheet" href="app.css"/></head><img src="dot"/>"#;
+        let (got, _) = inline_with_resources(
+            input.into(),
+            Path::new("site"),
+            &Options::default(),
+            &resources,
+        )
+        .unwrap();
+        let want = r#"<head><style>body{background:url(data:image/svg+xml;base64,PHN2Zy8+)}</style></head><img src="data:image/svg+xml;base64,PHN2Zy8+"/>"#;
+        assert_eq!(parse(want), parse(&got));
+    }
+
+    #[test]
+    fn embedding() {
+        let tests = vec![
+            ("script", "app.js", Embedding::Text),
+            ("stylesheet", "style.css", Embedding::Text),
+            ("page", "frame.html", Embedding::Text),
+            ("uppercase", "STYLE.CSS", Embedding::Text),
+            ("mixed case", "App.Js", Embedding::Text),
+            ("multi-dot name", "app.min.js", Embedding::Text),
+            ("query", "app.js?v=2", Embedding::Text),
+            ("fragment", "frame.html#top", Embedding::Text),
+            ("nested", "../lib/v1.2/util.mjs", Embedding::Text),
+            ("image", "logo.png", Embedding::DataUrl),
+            ("no extension", "LICENSE", Embedding::DataUrl),
+            ("dotted directory", "v1.2/app", Embedding::DataUrl),
+            ("hidden file", ".css", Embedding::DataUrl),
+            ("misleading name", "notcss", Embedding::DataUrl),
+            ("suffix without dot", "fancy-js", Embedding::DataUrl),
+            ("suffix in name", "index-html.png", Embedding::DataUrl),
+            ("extension in query", "image?format=.js", Embedding::DataUrl),
+        ];
+        for (desc, link, want) in tests {
+            assert_eq!(want, Embedding::new(link), "{}: {}", desc, link);
+        }
+    }
+
+    #[test]
+    fn query_and_fragment() {
+        let literals = fixture("literals");
+        let svg = fixture("svg");
+        let script = fs::read_to_string(literals.join("app.js")).unwrap();
+        let tests = vec![
+            (
+                "query",
+                &literals,
+                r#"<script src="app.js?v=2"></script>"#,
+                format!("<script>{}</script>", script.trim_end()),
+            ),
+            (
+                "fragment",
+                &svg,
+                r#"<img src="drawing.svg#icon"/>"#,
+                format!(
+                    r#"<img src="{}"/>"#,
+                    encoded(svg.join("drawing.svg"), "image/svg+xml")
+                ),
+            ),
+        ];
+        for (desc, base, input, want) in tests {
+            let (got, _) = inline_with(input.into(), base, &Options::default()).expect(desc);
+            assert_eq!(parse(&want), parse(&got), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn anchors() {
+        let base = fixture("anchors");
+        let nav = r#"<nav><a href="about.html">About</a><a href="report.pdf">Report</a><a href="/">Home</a></nav><map><area href="about.html"/></map><form action="about.html"></form>"#;
+        let (got, _) = inline_with(nav.into(), &base, &Options::default()).unwrap();
+        assert_eq!(nav, got, "navigation is untouched");
+
+        let input =
+            r#"<a href="about.html">About</a><a href="report.pdf">Report</a><img src="logo.png"/>"#;
+        let options = Options {
+            inline_anchors: true,
+            ..Options::default()
+        };
+        let (got, _) = inline_with(input.into(), &base, &options).unwrap();
+        let want = format!(
+            r#"<a href="{}">About</a><a href="{}">Report</a><img src="{}"/>"#,
+            encoded(base.join("about.html"), "text/html"),
+            encoded(base.join("report.pdf"), "application/pdf"),
+            encoded(base.join("logo.png"), "image/png"),
+        );
+        assert_eq!(parse(&want), parse(&got));
+    }
+
+    #[test]
+    fn form_controls() {
+        let base = fixture("anchors");
+        let logo = encoded(base.join("logo.png"), "image/png");
+        let form = |image: &str| {
+            format!(
+                r#"<form action="about.html" method="post"><input type="image" src="{image}" alt="Send"/><input TYPE="IMAGE" src="{image}"/><input type="submit" formaction="about.html" value="Send"/><input type="text" src="logo.png"/><button formaction="about.html">Save</button></form>"#,
+                image = image
+            )
+        };
+        for inline_anchors in [false, true] {
+            let options = Options {
+                inline_anchors,
+                ..Options::default()
+            };
+            let (got, report) = inline_with(form("logo.png"), &base, &options).unwrap();
+            assert_eq!(
+                parse(&form(&logo)),
+                parse(&got),
+                "inline anchors: {}",
+                inline_anchors
+            );
+            assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        }
+    }
+
+    #[test]
+    fn base_element() {
+        let root = fixture("base");
+        let logo = encoded(root.join("static/logo.png"), "image/png");
+        let top = encoded(root.join("img/root.png"), "image/png");
+        let remote = r#"<img src="//cdn.example.com/x.png"/>"#;
+        let tests = vec![
+            (
+                "base href",
+                false,
+                format!(
+                    r#"<head><base href="/static/"/><link rel="stylesheet" href="app.css"/></head><body><img src="logo.png"/><img src="/img/root.png"/>{}</body>"#,
+                    remote
+                ),
+                format!(
+                    r#"<head><base href="/static/"/><style>.app{{}}</style></head><body><img src="{}"/><img src="{}"/>{}</body>"#,
+                    logo, top, remote
+                ),
+            ),
+            (
+                "no base",
+                false,
+                r#"<body><img src="static/logo.png"/><img src="/img/root.png"/></body>"#.into(),
+                format!(r#"<body><img src="{}"/><img src="{}"/></body>"#, logo, top),
+            ),
+            (
+                "base removed",
+                true,
+                r#"<head><base href="static/index.html"/></head><body><img src="logo.png"/></body>"#.into(),
+                format!(r#"<head></head><body><img src="{}"/></body>"#, logo),
+            ),
+        ];
+        for (desc, remove_base, input, want) in tests {
+            let options = Options {
+                remove_base,
+                ..Options::default()
+            };
+            let (got, _) = inline_with(input, &root, &options).expect(desc);
+            assert_eq!(parse(&want), parse(&got), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn outside_base() {
+        let root = fixture("base").join("static");
+        let top = encoded(fixture("base").join("img/root.png"), "image/png");
+        let input = r#"<img src="../img/root.png"/>"#;
+        let err = inline_with(input.into(), &root, &Options::default()).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("<img> ../img/root.png: resolves outside"),
+            "{}",
+            err
+        );
+        let options = Options {
+            allow_outside_base: true,
+            ..Options::default()
+        };
+        let (got, _) = inline_with(input.into(), &root, &options).unwrap();
+        assert_eq!(parse(&format!(r#"<img src="{}"/>"#, top)), parse(&got));
+    }
+
+    #[test]
+    fn percent_encoded_links() {
+        let base = fixture("escaped");
+        let cafe = encoded(base.join("caf\u{e9}.png"), "image/png");
+        let plus = encoded(base.join("a+b.txt"), "text/plain");
+        let tests = vec![
+            (
+                "space",
+                None,
+                r#"<link rel="stylesheet" href="my%20styles.css"/>"#,
+                "<style>.styles{}</style>".to_string(),
+            ),
+            (
+                "non-ascii",
+                None,
+                r#"<img src="caf%C3%A9.png"/>"#,
+                format!(r#"<img src="{}"/>"#, cafe),
+            ),
+            (
+                "plus",
+                None,
+                r#"<img src="a+b.txt"/>"#,
+                format!(r#"<img src="{}"/>"#, plus),
+            ),
+            (
+                "left as it was",
+                Some(1),
+                r#"<img src="caf%C3%A9.png"/>"#,
+                r#"<img src="caf%C3%A9.png"/>"#.to_string(),
+            ),
+        ];
+        for (desc, size_threshold, input, want) in tests {
+            let options = Options {
+                size_threshold,
+                ..Options::default()
+            };
+            let (got, _) = inline_with(input.into(), &base, &options).expect(desc);
+            assert_eq!(parse(&want), parse(&got), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn module_scripts() {
+        let base = fixture("modules");
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        let (got, _) = inline_with(input.clone(), &base, &Options::default()).unwrap();
+        assert!(got.contains(r#"from "./lib/util.js""#), "{}", got);
+        let options = Options {
+            bundle_modules: true,
+            ..Options::default()
+        };
+        let (got, report) = inline_with(input, &base, &options).unwrap();
+        assert!(!got.contains("./lib/"), "{}", got);
+        assert!(
+            got.contains(r#"from "data:text/javascript;base64,"#),
+            "{}",
+            got
+        );
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        let input = r#"<script type="module">import "./cycle/a.js";</script>"#;
+        let (got, report) = inline_with(input.into(), &base, &options).unwrap();
+        assert!(got.contains("data:text/javascript;base64,"), "{}", got);
+        assert_eq!(1, report.warnings.len(), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn module_preloads() {
+        let base = fixture("vite");
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        let preloads = [
+            r#"<link rel="modulepreload" crossorigin href="/assets/vendor-9b1e3d.js">"#,
+            r#"<link rel="modulepreload" crossorigin href="/assets/chunk-2d7a0c.js">"#,
+        ];
+        let (got, report) = inline_with(input.clone(), &base, &Options::default()).unwrap();
+        for preload in preloads {
+            assert!(got.contains(preload), "{}", got);
+        }
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        let options = Options {
+            bundle_modules: true,
+            ..Options::default()
+        };
+        let (got, report) = inline_with(input.clone(), &base, &options).unwrap();
+        assert!(!got.contains("modulepreload"), "{}", got);
+        assert!(
+            got.contains("<script crossorigin type=\"module\">import { h } from \"data:"),
+            "{}",
+            got
+        );
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        // A preload of a module that isn't bundled is still needed.
+        let input = input.replace("chunk-2d7a0c.js\">", "lazy.js\">");
+        let (got, _) = inline_with(input.clone(), &base, &options).unwrap();
+        assert!(got.contains(r#"href="/assets/lazy.js""#), "{}", got);
+        assert!(!got.contains("vendor-9b1e3d.js"), "{}", got);
+
+        // As is one outside of the base directory, with a warning.
+        let input = input.replace("/assets/lazy.js", "../lazy.js");
+        let (got, report) = inline_with(input, &base, &options).unwrap();
+        assert!(got.contains(r#"href="../lazy.js""#), "{}", got);
+        assert_eq!(1, report.warnings.len(), "{:?}", report.warnings);
+        assert_eq!("../lazy.js", report.warnings[0].link);
+    }
+
+    #[test]
+    fn srcdoc() {
+        let base = fixture("srcdoc");
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        // The document in a srcdoc, and what's in it.
+        let documents = |html: &str| -> Vec<(String, String, String)> {
+            parse(html)
+                .find_by_tag("iframe")
+                .iter()
+                .map(|iframe| {
+                    let document = parse(&unescape(iframe.borrow().attr("srcdoc").unwrap()));
+                    let img = document.find_by_tag("img");
+                    let src = img[0].borrow().attr("src").unwrap().to_owned();
+                    let p = document.find_by_tag("p");
+                    let title = p[0].borrow().attr("title").unwrap_or_default().to_owned();
+                    (document.text(), title, src)
+                })
+                .collect()
+        };
+        let want = vec![
+            (
+                "Fish & chips, \"fresh\"".to_string(),
+                "".to_string(),
+                "dot.gif".to_string(),
+            ),
+            ("1 > 0 &".into(), "quoted".into(), "dot.gif".into()),
+        ];
+        assert_eq!(want, documents(&input));
+        // Kept verbatim, and escaped as the attribute needs when rewritten.
+        let (got, _) = inline_with(input.clone(), &base, &Options::default()).unwrap();
+        assert_eq!(input, got);
+        for format in [SerializeMode::Html5, SerializeMode::Xhtml] {
+            let options = Options {
+                format,
+                ..Options::default()
+            };
+            let (got, _) = inline_with(input.clone(), &base, &options).unwrap();
+            assert_eq!(want, documents(&got), "{:?}", format);
+        }
+
+        let options = Options {
+            inline_srcdoc: true,
+            ..Options::default()
+        };
+        let (got, report) = inline_with(input.clone(), &base, &options).unwrap();
+        let dot = format!(
+            "data:image/gif;base64,{}",
+            base64::encode(&fs::read(base.join("dot.gif")).unwrap())
+        );
+        let inlined: Vec<_> = want
+            .iter()
+            .map(|(text, title, _)| (text.clone(), title.clone(), dot.clone()))
+            .collect();
+        assert_eq!(inlined, documents(&got));
+        assert!(
+            got.contains(r#"srcdoc="<p class='note'>Fish &amp;amp; chips, &quot;fresh&quot;</p>"#),
+            "{}",
+            got
+        );
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        assert_eq!(2, report.embedded.len());
+        assert_eq!(1, report.dependencies.len());
+        let (twice, _) = inline_with(got.clone(), &base, &options).unwrap();
+        assert_eq!(got, twice);
+    }
+
+    #[test]
+    fn frames() {
+        let base = fixture("frames");
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        let dot = encoded(base.join("dot.gif"), "image/gif");
+        let button = encoded(base.join("pages/button.png"), "image/png");
+        let (got, report) = inline_with(input, &base, &Options::default()).unwrap();
+        let dom = parse(&got);
+        let iframes = dom.find_by_tag("iframe");
+        assert_eq!(2, iframes.len());
+        // The framed pages, with their links resolved against their own
+        // directory, and the frame that frames itself left linked.
+        let documents: Vec<Dom> = iframes
+            .iter()
+            .map(|iframe| {
+                assert_eq!(None, iframe.borrow().attr("src"), "{}", got);
+                parse(&unescape(iframe.borrow().attr("srcdoc").unwrap()))
+            })
+            .collect();
+        let srcs = |dom: &Dom, tag: &str| -> Vec<String> {
+            dom.find_by_tag(tag)
+                .iter()
+                .map(|n| n.borrow().attr("src").unwrap().to_owned())
+                .collect()
+        };
+        assert_eq!(vec![button], srcs(&documents[0], "img"));
+        assert_eq!("Fish & chips", documents[0].text());
+        assert_eq!(vec!["loop.html"], srcs(&documents[1], "iframe"));
+        assert_eq!(
+            vec![Warning {
+                element: "iframe".into(),
+                link: "loop.html".into(),
+                reason: "left linked, frames nest more than 8 deep or frame themselves".into(),
+            }],
+            report.warnings
+        );
+        assert_eq!(vec![dot.clone()], srcs(&dom, "img"));
+
+        // A srcdoc resolves against the base of the page.
+        let input =
+            r#"<base href="pages/"><iframe srcdoc="<img src=&quot;../dot.gif&quot;>"></iframe>"#;
+        let options = Options {
+            inline_srcdoc: true,
+            ..Options::default()
+        };
+        let (got, _) = inline_with(input.into(), &base, &options).unwrap();
+        let dom = parse(&got);
+        let iframe = dom.find_by_tag("iframe");
+        let document = parse(&unescape(iframe[0].borrow().attr("srcdoc").unwrap()));
+        assert_eq!(vec![dot], srcs(&document, "img"));
+    }
+
+    #[test]
+    fn meta_images() {
+        let base = fixture("meta");
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        let contents = |html: &str| -> Vec<String> {
+            parse(html)
+                .find_by_tag("meta")
+                .iter()
+                .filter_map(|meta| meta.borrow().attr("content").map(str::to_owned))
+                .collect()
+        };
+        let (got, report) = inline_with(input.clone(), &base, &Options::default()).unwrap();
+        assert_eq!(input, got, "left alone for scrapers");
+        assert!(report.embedded.is_empty());
+
+        let options = Options {
+            inline_meta_images: true,
+            ..Options::default()
+        };
+        let (got, report) = inline_with(input.clone(), &base, &options).unwrap();
+        let png = format!(
+            "data:image/png;base64,{}",
+            base64::encode(&fs::read(base.join("tile.png")).unwrap())
+        );
+        assert_eq!(
+            vec![
+                "logo.png is not an image here".to_string(),
+                png.clone(),
+                "https://example.com/card.png".into(),
+                png,
+            ],
+            contents(&got)
+        );
+        assert_eq!(2, report.embedded.len());
+
+        // Strict counts the images it was asked to inline, but couldn't.
+        let strict = |inline_meta_images| Options {
+            inline_meta_images,
+            strict: true,
+            ..Options::default()
+        };
+        inline_with(input.clone(), &base, &strict(false)).unwrap();
+        let err = inline_with(input, &base, &strict(true)).unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("<meta> content: https://example.com/card.png"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn transforms() {
+        let input = r#"<body><a href="about.html">About</a></body>"#;
+        let options = Options::default()
+            .with_transform(|dom| {
+                let body = dom.find_by_tag("body").pop().ok_or("no body")?;
+                let banner = parse(r#"<p id="banner">Draft</p>"#).nodes.remove(0);
+                body.borrow_mut().children_mut().unwrap().insert(0, banner);
+                Ok(())
+            })
+            // Runs after the first, so the banner is there to change.
+            .with_transform(|dom| {
+                let banner = dom.find_by_attr("id", Some("banner")).pop();
+                banner
+                    .ok_or("no banner")?
+                    .borrow_mut()
+                    .set_attr("class", "draft");
+                for a in dom.find_by_tag("a") {
+                    a.borrow_mut().set_attr("href", "/about/");
+                }
+                Ok(())
+            });
+        let (got, _) = inline_with(input.into(), Path::new("."), &options).unwrap();
+        assert_eq!(
+            r#"<body><p class="draft" id="banner">Draft</p><a href="/about/">About</a></body>"#,
+            got
+        );
+
+        let options = options
+            .with_transform(|_| Ok(()))
+            .with_transform(|_| Err("no timestamp".into()));
+        let err = inline_with(input.into(), Path::new("."), &options).unwrap_err();
+        let err = err.downcast_ref::<TransformError>().unwrap();
+        assert_eq!(3, err.index);
+        assert_eq!("transform 3: no timestamp", err.to_string());
+    }
+
+    #[test]
+    fn noscript() {
+        let base = fixture("noscript");
+        let data_url = |name: &str, media_type: &str| {
+            let content = fs::read(base.join("images").join(name)).unwrap();
+            format!("data:{};base64,{}", media_type, base64::encode(&content))
+        };
+        let fallback = data_url("fallback.png", "image/png");
+        let photo = data_url("photo.png", "image/png");
+        let placeholder = data_url("placeholder.gif", "image/gif");
+        let sources = |html: &str| -> Vec<String> {
+            parse(html)
+                .find_by_tag("img")
+                .iter()
+                .map(|img| img.borrow().attr("src").unwrap().to_owned())
+                .collect()
+        };
+
+        // What's inside a noscript is markup, inlined as any other.
+        let input = fs::read_to_string(base.join("plain.html")).unwrap();
+        let noscripts = parse(&input).find_by_tag("noscript");
+        assert!(noscripts[1].borrow().children()[0]
+            .borrow()
+            .is_element("img"));
+        let (got, report) = inline_with(input, &base, &Options::default()).unwrap();
+        assert!(
+            got.contains("<noscript><style>.needs-script { display: none; }</style></noscript>"),
+            "{}",
+            got
+        );
+        assert_eq!(vec![fallback.clone()], sources(&got));
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        let input = fs::read_to_string(base.join("lazy.html")).unwrap();
+        let (got, _) = inline_with(input.clone(), &base, &Options::default()).unwrap();
+        assert_eq!(
+            vec![placeholder, photo.clone(), fallback.clone()],
+            sources(&got)
+        );
+        assert_eq!(2, parse(&got).find_by_tag("noscript").len());
+
+        let options = Options {
+            prefer_noscript_images: true,
+            ..Options::default()
+        };
+        let (got, report) = inline_with(input, &base, &options).unwrap();
+        assert_eq!(vec![photo, fallback], sources(&got));
+        let dom = parse(&got);
+        let figure = dom.find_by_tag("figure");
+        let children: Vec<String> = figure[0]
+            .borrow()
+            .children()
+            .iter()
+            .filter_map(|c| c.borrow().tag_name().map(str::to_owned))
+            .collect();
+        assert_eq!(vec!["img", "figcaption"], children);
+        assert_eq!(
+            1,
+            dom.find_by_tag("noscript").len(),
+            "without a placeholder"
+        );
+        assert!(
+            report
+                .embedded
+                .iter()
+                .all(|e| !e.path.ends_with("placeholder.gif")),
+            "{:?}",
+            report.embedded
+        );
+    }
+
+    #[test]
+    fn js_literals() {
+        let base = fixture("literals");
+        let data_url = |name: &str| {
+            let content = fs::read(base.join("images").join(name)).unwrap();
+            format!("data:image/png;base64,{}", base64::encode(&content))
+        };
+        let (button, hover) = (data_url("button.png"), data_url("hover.png"));
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        let inlined = |options: &Options| {
+            let (output, report) = inline_with(input.clone(), &base, options).unwrap();
+            let img = parse(&output).find_by_tag("img").remove(0);
+            let handler = |key: &str| img.borrow().attr(key).unwrap().to_owned();
+            let handlers = (handler("onmouseover"), handler("onmouseout"));
+            (handlers, output, report)
+        };
+        let runtime = |report: &Report| -> Vec<String> {
+            report.warnings.iter().map(ToString::to_string).collect()
+        };
+
+        // Left as they are by default, and only warned of when strict.
+        let (handlers, output, report) = inlined(&Options::default());
+        assert_eq!(
+            (
+                "this.src='images/hover.png'".to_string(),
+                "this.src='images/button.png'".to_string()
+            ),
+            handlers
+        );
+        assert!(
+            output.contains("hover.src = 'images/hover.png';"),
+            "{}",
+            output
+        );
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        let strict = Options {
+            strict: true,
+            ..Options::default()
+        };
+        let (_, _, report) = inlined(&strict);
+        assert_eq!(
+            vec![
+                "<img> images/button.png: possibly external at runtime, from onmouseout",
+                "<img> images/hover.png: possibly external at runtime, from onmouseover",
+                "<script> images/hover.png: possibly external at runtime",
+                "<script> images/missing.png: possibly external at runtime",
+            ],
+            runtime(&report)
+        );
+
+        let options = Options {
+            rewrite_js_literals: true,
+            strict: true,
+            ..Options::default()
+        };
+        let (handlers, output, report) = inlined(&options);
+        assert_eq!(
+            (
+                format!("this.src='{}'", hover),
+                format!("this.src='{}'", button)
+            ),
+            handlers
+        );
+        assert!(
+            output.contains(&format!("hover.src = '{}';", hover)),
+            "{}",
+            output
+        );
+        // Comments, sentences, links built up at runtime and links to files
+        // that don't exist are left alone.
+        for untouched in [
+            "// Preload the hover image, rather than 'images/pressed.png'.",
+            r#"const hint = "Save the menu as button.png";"#,
+            "const sprite = (name) => 'images/' + name + '.png';",
+            "const missing = 'images/missing.png';",
+        ] {
+            assert!(output.contains(untouched), "{}: {}", untouched, output);
+        }
+        assert_eq!(
+            vec!["<script> images/missing.png: possibly external at runtime"],
+            runtime(&report)
+        );
+    }
+
+    #[test]
+    fn deterministic() {
+        let base = fixture("style");
+        let input = r#"<link rel="stylesheet" href="depth/1.css">"#;
+        let options = Options {
+            max_depth: 2,
+            deterministic: true,
+            ..Options::default()
+        };
+        let (_, report) = inline_with(input.into(), &base, &options).unwrap();
+        let reasons: Vec<&str> = report.warnings.iter().map(|w| w.reason.as_str()).collect();
+        assert_eq!(
+            vec!["exceeds the maximum depth of 2: depth/1.css -> depth/2.css -> 3.css"],
+            reasons
+        );
+
+        let resources = Memory::new().with("app.js", "a();\r\nb();\rc();\n");
+        let input = r#"<script src="app.js"></script>"#;
+        let tests = vec![
+            ("default", Options::default(), "a();\r\nb();\rc();"),
+            ("deterministic", options.clone(), "a();\nb();\nc();"),
+            (
+                "keep line endings",
+                Options {
+                    keep_line_endings: true,
+                    ..options
+                },
+                "a();\r\nb();\rc();",
+            ),
+        ];
+        for (desc, options, want) in tests {
+            let (got, _) =
+                inline_with_resources(input.into(), Path::new(""), &options, &resources).unwrap();
+            assert_eq!(format!("<script>{}</script>", want), got, "{}", desc);
+        }
+    }
+
+    #[test]
+    fn relative_paths() {
+        let dir = Path::new("/home/me/site");
+        let tests = vec![
+            ("path", "/home/me/site/img/a.png", "img/a.png"),
+            ("dir", "/home/me/site", "."),
+            (
+                "in a message",
+                "/home/me/site/img/a.png: not found",
+                "img/a.png: not found",
+            ),
+            (
+                "with spaces",
+                "<img> /home/me/site/my photos/a b.png: is empty",
+                "<img> my photos/a b.png: is empty",
+            ),
+            (
+                "more than one",
+                "a.css -> /home/me/site/b.css, from /home/me/site",
+                "a.css -> b.css, from .",
+            ),
+            (
+                "longer name",
+                "/home/me/site2/a.png",
+                "/home/me/site2/a.png",
+            ),
+            (
+                "not at the start",
+                "/x/home/me/site/a.png",
+                "/x/home/me/site/a.png",
+            ),
+            ("elsewhere", "../outside.png", "../outside.png"),
+        ];
+        for (desc, message, want) in tests {
+            assert_eq!(want, relative_to(message, dir), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn dependencies() {
+        let root = fixture("dependencies");
+        let input = fs::read_to_string(root.join("index.html")).unwrap();
+        let want: Vec<Dependency> = vec![
+            ("css/site.css", "css/site.css"),
+            ("fonts.css", "css/fonts.css"),
+            ("../fonts/body.woff2", "fonts/body.woff2"),
+            ("../img/bg.png", "img/bg.png"),
+            ("img/logo.png", "img/logo.png"),
+            ("frame.html", "frame.html"),
+            ("js/app.js", "js/app.js"),
+        ]
+        .into_iter()
+        .map(|(link, path)| Dependency {
+            link: link.into(),
+            path: root.join(path),
+        })
+        .collect();
+        // Tests run in the root of the crate, which relative bases are
+        // relative to.
+        for base in [root.clone(), PathBuf::from("tests/fixtures/dependencies")] {
+            let (_, report) = inline_with(input.clone(), &base, &Options::default()).unwrap();
+            assert_eq!(want, report.dependencies, "{}", base.display());
+        }
+    }
+
+    #[test]
+    fn page_links() {
+        let root = fixture("site");
+        let link = |element: &str, link: &str, path: &str, line| PageLink {
+            element: element.into(),
+            link: link.into(),
+            path: root.join(path),
+            line: Some(line),
+        };
+        let tests = vec![
+            (
+                "index.html",
+                vec![
+                    link("a", "about.html", "about.html", 8),
+                    link("a", "blog/post.html", "blog/post.html", 9),
+                ],
+            ),
+            (
+                "about.html",
+                vec![
+                    link("a", "index.html", "index.html", 7),
+                    link("form", "blog/post.html#comments", "blog/post.html", 8),
+                ],
+            ),
+            (
+                "blog/post.html",
+                vec![
+                    link("area", "../index.html", "index.html", 7),
+                    link("a", "../about.html", "about.html", 8),
+                ],
+            ),
+        ];
+        let options = Options {
+            report_links: true,
+            ..Options::default()
+        };
+        for (page, want) in tests {
+            let path = root.join(page);
+            let input = fs::read_to_string(&path).unwrap();
+            let base = path.parent().unwrap();
+            let (got, report) = inline_with(input.clone(), base, &options).unwrap();
+            assert_eq!(want, report.links, "{}", page);
+            assert_eq!(input, got, "{}: the links are left alone", page);
+            let (_, report) = inline_with(input, base, &Options::default()).unwrap();
+            assert!(report.links.is_empty(), "{}: only when asked for", page);
+        }
+    }
+
+    #[test]
+    fn rewrite_links() {
+        let root = fixture("site");
+        let out = root.join("out");
+        let pages = vec![
+            (root.join("index.html"), out.join("index.html")),
+            (root.join("about.html"), out.join("about-us.html")),
+            (root.join("blog/post.html"), out.join("2024/post.html")),
+        ];
+        let tests = vec![
+            (
+                "index.html",
+                "index.html",
+                vec![
+                    (r#""about.html""#, r#""about-us.html""#),
+                    (r#""blog/post.html""#, r#""2024/post.html""#),
+                ],
+            ),
+            (
+                "about.html",
+                "about-us.html",
+                vec![
+                    (r#""index.html""#, r#""index.html""#),
+                    (
+                        r#""blog/post.html#comments""#,
+                        r#""2024/post.html#comments""#,
+                    ),
+                ],
+            ),
+            (
+                "blog/post.html",
+                "2024/post.html",
+                vec![
+                    (r#""../index.html""#, r#""../index.html""#),
+                    (r#""../about.html""#, r#""../about-us.html""#),
+                ],
+            ),
+        ];
+        for (page, output, replacements) in tests {
+            let path = root.join(page);
+            let input = fs::read_to_string(&path).unwrap();
+            let options = Options {
+                rewrite_links: Some(LinkRewrite {
+                    pages: pages.clone(),
+                    output: out.join(output),
+                }),
+                ..Options::default()
+            };
+            let (got, _) = inline_with(input.clone(), path.parent().unwrap(), &options).unwrap();
+            let want = replacements
+                .iter()
+                .fold(input, |want, (from, to)| want.replace(from, to));
+            assert_eq!(want, got, "{}", page);
+        }
+    }
+
+    #[test]
+    fn rewrite_links_unmapped() {
+        let root = fixture("site");
+        let path = root.join("blog/post.html");
+        let input = fs::read_to_string(&path).unwrap();
+        let options = Options {
+            rewrite_links: Some(LinkRewrite {
+                pages: vec![(root.join("about.html"), root.join("about-us.html"))],
+                output: path.clone(),
+            }),
+            ..Options::default()
+        };
+        let (got, _) = inline_with(input.clone(), path.parent().unwrap(), &options).unwrap();
+        let want = input.replace(r#""../about.html""#, r#""../about-us.html""#);
+        assert_eq!(want, got, "only the pages mapped are rewritten");
+    }
+
+    #[test]
+    fn largest_resources() {
+        let base = fixture("budget");
+        let input = fs::read(base.join("index.html")).unwrap();
+        let (got, report) = inline_bytes(&input, &base, &Options::default()).unwrap();
+        assert_eq!(got.len() as u64, report.output_size);
+        let largest: Vec<(String, u64)> = report
+            .largest_resources(2)
+            .into_iter()
+            .map(|e| (e.path.clone(), e.size))
+            .collect();
+        let vendor = fs::metadata(base.join("vendor.js")).unwrap().len();
+        let logo = fs::read(base.join("logo.png")).unwrap();
+        let path = |name: &str| base.join(name).to_string_lossy().into_owned();
+        assert_eq!(
+            vec![
+                (path("vendor.js"), vendor),
+                (path("logo.png"), base64::encode(&logo).len() as u64),
+            ],
+            largest
+        );
+        assert_eq!(4, report.embedded.len(), "{:?}", report.embedded);
+
+        let mut written = vec![];
+        let report = inline_bytes_to(&input, &base, &Options::default(), &mut written).unwrap();
+        assert_eq!(written.len() as u64, report.output_size, "streamed");
+    }
+
+    #[test]
+    fn keep_going() {
+        let resources = Memory::new().with("site/dot.png", "dot");
+        let input = r#"<head><link rel="icon" href="favicon.ico"/><link rel="stylesheet" href="missing.css"/></head><body><img src="missing.png" srcset="dot.png"/><img src="dot.png"/></body>"#;
+        let err = inline_with_resources(
+            input.into(),
+            Path::new("site"),
+            &Options::default(),
+            &resources,
+        )
+        .unwrap_err();
+        assert_eq!(
+            "<link> favicon.ico: site/favicon.ico: not found",
+            err.to_string()
+        );
+        let options = Options {
+            keep_going: true,
+            ..Options::default()
+        };
+        let (got, report) =
+            inline_with_resources(input.into(), Path::new("site"), &options, &resources).unwrap();
+        let want = r#"<head><link rel="icon" href="favicon.ico"/><link rel="stylesheet" href="missing.css"/></head><body><img src="missing.png" srcset="dot.png"/><img src="data:image/png;base64,ZG90"/></body>"#;
+        assert_eq!(parse(want), parse(&got));
+        let warning = |element: &str, link: &str| Warning {
+            element: element.into(),
+            link: link.into(),
+            reason: format!("site/{}: not found", link),
+        };
+        assert_eq!(
+            vec![
+                warning("link", "favicon.ico"),
+                warning("link", "missing.css"),
+                warning("img", "missing.png"),
+            ],
+            report.warnings
+        );
+    }
+
+    #[test]
+    fn empty_files() {
+        let base = fixture("unreadable");
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        let (got, report) = inline_with(input.clone(), &base, &Options::default()).unwrap();
+        assert!(
+            got.contains(r#"<img src="empty.png" alt="empty">"#),
+            "{}",
+            got
+        );
+        assert!(got.contains("url(empty.png)"), "{}", got);
+        assert!(got.contains("<style></style>"), "{}", got);
+        let empty = base.join("empty.png");
+        let warning = |element: &str| Warning {
+            element: element.into(),
+            link: "empty.png".into(),
+            reason: format!("{}: is empty", empty.display()),
+        };
+        assert_eq!(vec![warning("img"), warning("p")], report.warnings);
+
+        let options = Options {
+            embed_empty: true,
+            ..Options::default()
+        };
+        let (got, report) = inline_with(input, &base, &options).unwrap();
+        assert!(got.contains(r#"<img alt="empty" src="data:image/png;base64,">"#));
+        assert!(got.contains("url(data:image/png;base64,)"), "{}", got);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn unreadable_files() {
+        // IsError reports whether an error is the one expected.
+        type IsError = fn(&ResourceError) -> bool;
+        let is_directory = |err: &ResourceError| matches!(err, ResourceError::IsDirectory(_));
+        let is_denied = |err: &ResourceError| matches!(err, ResourceError::PermissionDenied(_));
+        let mut tests: Vec<(&str, PathBuf, &str, IsError)> =
+            vec![("directory", fixture("unreadable"), "images/", is_directory)];
+        // Permissions don't keep root from reading a file, so the test only
+        // runs for other users.
+        #[cfg(unix)]
+        let locked = tempfile::tempdir().unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let path = locked.path().join("locked.png");
+            fs::write(&path, "png").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o000)).unwrap();
+            if fs::read(&path).is_err() {
+                tests.push((
+                    "permission denied",
+                    locked.path().into(),
+                    "locked.png",
+                    is_denied,
+                ));
+            }
+        }
+        for (desc, base, link, is_error) in tests {
+            let input = format!(r#"<img src="{}">"#, link);
+            let err = inline_with(input.clone(), &base, &Options::default()).unwrap_err();
+            let err = err.downcast_ref::<LinkError>().expect(desc);
+            assert_eq!(("img", link), (&*err.element, &*err.link), "{}", desc);
+            let resource_err = err.source.downcast_ref::<ResourceError>().expect(desc);
+            assert!(is_error(resource_err), "{}: {:?}", desc, resource_err);
+            assert_eq!(base.join(link), resource_err.path(), "{}", desc);
+
+            let options = Options {
+                keep_going: true,
+                ..Options::default()
+            };
+            let (got, report) = inline_with(input.clone(), &base, &options).unwrap();
+            assert_eq!(input, got, "{}", desc);
+            assert_eq!(
+                vec![format!("<img> {}: {}", link, resource_err)],
+                report
+                    .warnings
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+                "{}",
+                desc
+            );
+        }
+    }
+
+    #[test]
+    fn duplicate_attributes() {
+        let resources = Memory::new()
+            .with("site/a.png", "a")
+            .with("site/b.png", "b");
+        let input =
+            r#"<img src="a.png" src="b.png" alt="x" alt="y"/><p hidden hidden="hidden"></p>"#;
+        let (got, report) = inline_with_resources(
+            input.into(),
+            Path::new("site"),
+            &Options::default(),
+            &resources,
+        )
+        .unwrap();
+        let want = r#"<img src="data:image/png;base64,YQ==" alt="x"/><p hidden></p>"#;
+        assert_eq!(parse(want), parse(&got));
+        assert_eq!(1, got.matches("src=").count(), "{}", got);
+        let warning = |element: &str, attribute: &str| Warning {
+            element: element.into(),
+            link: attribute.into(),
+            reason: "duplicate attribute, only the first is kept".into(),
+        };
+        assert_eq!(
+            vec![
+                warning("img", "src"),
+                warning("img", "alt"),
+                warning("p", "hidden")
+            ],
+            report.warnings
+        );
+    }
+
+    #[test]
+    fn inlined_code_is_not_escaped() {
+        let resources = Memory::new()
+            .with("site/app.js", "if (a < b && c > d) { x = '&amp;'; }")
+            .with("site/app.css", "a > b::after { content: '&' }");
+        let input = r#"<head><link rel="stylesheet" href="app.css"/></head><body><p>Tom & Jerry</p><script src="app.js"></script></body>"#;
+        let options = Options {
+            format: SerializeMode::Html5,
+            ..Options::default()
+        };
+        let (got, _) =
+            inline_with_resources(input.into(), Path::new("site"), &options, &resources).unwrap();
+        assert!(
+            got.contains("if (a < b && c > d) { x = '&amp;'; }"),
+            "{}",
+            got
+        );
+        assert!(got.contains("a > b::after { content: '&' }"), "{}", got);
+        assert!(got.contains("Tom &amp; Jerry"), "{}", got);
+    }
+
+    #[test]
+    fn empty_elements_are_closed() {
+        let resources = Memory::new();
+        let input =
+            r#"<body><div id="app"/><script src="https://example.com/app.js"/><p>after</p></body>"#;
+        let (got, _) = inline_with_resources(
+            input.into(),
+            Path::new("site"),
+            &Options::default(),
+            &resources,
+        )
+        .unwrap();
+        assert!(!got.contains("/>"), "{}", got);
+        assert!(got.contains(r#"<div id="app"></div>"#), "{}", got);
+        assert!(
+            got.contains(r#"<script src="https://example.com/app.js"></script>"#),
+            "{}",
+            got
+        );
+    }
+
+    #[test]
+    fn untouched_source_is_preserved() {
+        let base = fixture("preserve");
+        let input = fs::read_to_string(base.join("index.html")).unwrap();
+        let (got, _) = inline_with(input.clone(), &base, &Options::default()).unwrap();
+        let want = input
+            .replace(
+                r#"<link rel="stylesheet" href="style.css">"#,
+                "<style>body { margin: 0 }</style>",
+            )
+            .replace(
+                r#"<img src="logo.png">"#,
+                &format!(
+                    r#"<img src="{}">"#,
+                    encoded(base.join("logo.png"), "image/png")
+                ),
+            );
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn mhtml() {
+        let base = fixture("preserve");
+        let input = fs::read(base.join("index.html")).unwrap();
+        let mut out = vec![];
+        inline_mhtml_to(&input, &base, &Options::default(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let boundary = out
+            .split("boundary=\"")
+            .nth(1)
+            .and_then(|b| b.split('"').next())
+            .unwrap();
+        assert_eq!(2, out.matches(&format!("--{}\r\n", boundary)).count());
+        assert!(out.ends_with(&format!("--{}--\r\n", boundary)));
+        let logo = fs::read(base.join("logo.png")).unwrap();
+        let logo: String = base64::encode(&logo).chars().take(76).collect();
+        assert!(out.contains(&logo), "{}", out);
+        assert!(!out.contains("data:image/png"));
+    }
+
+    #[test]
+    fn strict() {
+        let base = fixture("preserve");
+        let options = Options {
+            strict: true,
+            ..Options::default()
+        };
+        let input = r#"<img src="logo.png"/><a href="other.html">other</a>"#;
+        let (got, _) = inline_with(input.into(), &base, &options).expect("self-contained");
+        assert!(got.contains("data:image/png;base64,"), "{}", got);
+        let input = r#"<img src="logo.png"/><script src="https://example.com/app.js"></script><p style="background:url(//example.com/bg.png)"></p>"#;
+        let err = inline_with(input.into(), &base, &options).unwrap_err();
+        assert_eq!(
+            "the output isn't self-contained, it references:\n  <script> src: https://example.com/app.js\n  <p> style: //example.com/bg.png",
+            err.to_string()
+        );
+        let (_, report) = inline_with(input.into(), &base, &Options::default()).unwrap();
+        assert_eq!(
+            Vec::<Warning>::new(),
+            report.warnings,
+            "only strict mode checks"
+        );
+        // Left linked on purpose, the logo isn't a reference strict fails on.
+        let threshold = Options {
+            size_threshold: Some(1),
+            ..options
+        };
+        let input = r#"<img src="logo.png"/><img src="logo.png"/>"#;
+        let (got, report) = inline_with(input.into(), &base, &threshold).expect("skipped");
+        assert!(got.contains(r#"src="logo.png""#), "{}", got);
+        assert_eq!(vec!["logo.png"], report.skipped);
+    }
+
+    #[test]
+    fn templates() {
+        let base = fixture("preserve");
+        let input = r#"<template id="row"><div class="row"><img src="logo.png"/></div></template><img src="logo.png"/>"#;
+        let tests = vec![("inlined by default", true, 2), ("left alone", false, 1)];
+        for (desc, inline_templates, want) in tests {
+            let options = Options {
+                inline_templates,
+                ..Options::default()
+            };
+            let (got, _) = inline_with(input.into(), &base, &options).unwrap();
+            let dom = parse(&got);
+            let template = dom.find_by_tag("template")[0].clone();
+            let img = template.borrow().template_content().unwrap()[0]
+                .borrow()
+                .children()[0]
+                .clone();
+            let inlined = dom
+                .find_by_tag("img")
+                .iter()
+                .filter(|img| {
+                    img.borrow()
+                        .attr("src")
+                        .unwrap()
+                        .starts_with("data:image/png")
+                })
+                .count();
+            assert_eq!(want, inlined, "{}", desc);
+            assert_eq!(
+                inline_templates,
+                img.borrow().attr("src") != Some("logo.png"),
+                "{}: template",
+                desc
+            );
+        }
+    }
+
+    #[test]
+    fn parse_mode() {
+        let base = fixture("preserve");
+        let options = Options {
+            parse_mode: ParseMode::Strict,
+            ..Options::default()
+        };
+        let input = "<div>\n<span>text</div>";
+        let err = inline_with(input.into(), &base, &options).unwrap_err();
+        assert_eq!("2:11: </div> doesn't close <span>", err.to_string());
+        let (got, _) = inline_with(input.into(), &base, &Options::default()).unwrap();
+        assert_eq!(parse(input), parse(&got));
+    }
+
+    #[test]
+    fn content_security_policy() {
+        let base = fixture("preserve");
+        let input = r#"<meta http-equiv="Content-Security-Policy" content="img-src 'self'"><img src="logo.png"/>"#;
+        let (_, report) = inline_with(input.into(), &base, &Options::default()).unwrap();
+        assert_eq!(
+            vec![Warning {
+                element: "meta".into(),
+                link: "img-src".into(),
+                reason: "the content security policy blocks data: images".into(),
+            }],
+            report.warnings
+        );
+        let options = Options {
+            csp: CspMode::Rewrite,
+            ..Options::default()
+        };
+        let (got, report) = inline_with(input.into(), &base, &options).unwrap();
+        let want = format!(
+            r#"<meta http-equiv="Content-Security-Policy" content="img-src 'self' data:"><img src="{}"/>"#,
+            encoded(base.join("logo.png"), "image/png")
+        );
+        assert_eq!(parse(&want), parse(&got));
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn nonces_and_hashes() {
+        let resources = Memory::new()
+            .with("site/app.css", "p{}")
+            .with("site/app.js", "go()")
+            .with("site/dot.gif", "GIF");
+        let input = r#"<link rel="stylesheet" href="app.css"/><style>b{background:url(dot.gif)}</style><style>i{}</style><script src="app.js"></script><script>run()</script>"#;
+        let options = Options {
+            nonce: Some("r4nd".into()),
+            hashes: true,
+            ..Options::default()
+        };
+        let (got, report) =
+            inline_with_resources(input.into(), Path::new("site"), &options, &resources).unwrap();
+        let want = r#"<style nonce="r4nd">p{}</style><style nonce="r4nd">b{background:url(data:image/gif;base64,R0lG)}</style><style>i{}</style><script nonce="r4nd">go()</script><script>run()</script>"#;
+        assert_eq!(
+            parse(want),
+            parse(&got),
+            "only what's inlined into is stamped"
+        );
+        let hashes: Vec<String> = report.hashes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            vec![
+                "<style> 'sha256-gG2yISYereRMiG2lMXrbiUgi0Ubw9p7QCeWcroOvy9Y='",
+                "<style> 'sha256-AhEtMvlcVKk1bf3vk0PSMFXDYd0/uiCWEUAVncPVY9o='",
+                "<style> 'sha256-e6jWDOyGxCCjebQhFRQYe5n6sSPlNhc3njC4Jta3SyM='",
+                "<script> 'sha256-5KYv+PUboo5h+0+YAtGRPbwv5d/QxzHslP4YGnUaxRw='",
+                "<script> 'sha256-AvyuiL0SD1mVY3NNxR+V2uo+lhk6RMFrytWmRt6CrJQ='",
+            ],
+            hashes
+        );
+    }
+
+    #[test]
+    fn email() {
+        let resources = Memory::new().with(
+            "site/mail.css",
+            "p { color: red; margin: 0 } .note { color: gray } @media (max-width: 600px) { p { margin: 8px } }",
+        );
+        let input = r#"<link rel="stylesheet" href="mail.css"/><p>a</p><p class="note" style="margin: 4px">b</p>"#;
+        let options = Options {
+            email: true,
+            ..Options::default()
+        };
+        let (got, _) =
+            inline_with_resources(input.into(), Path::new("site"), &options, &resources).unwrap();
+        let want = r#"<style>@media (max-width: 600px) { p { margin: 8px } }</style><p style="color: red; margin: 0">a</p><p class="note" style="color: gray; margin: 4px">b</p>"#;
+        assert_eq!(parse(want), parse(&got));
+    }
+
+    #[test]
+    fn svg_documents() {
+        let base = fixture("svg");
+        let input = fs::read_to_string(base.join("drawing.svg")).unwrap();
+        let options = Options {
+            strict: true,
+            ..Options::default()
+        };
+        let (got, report) = inline_with(input.clone(), &base, &options).unwrap();
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        let photo = encoded(base.join("photo.jpg"), "image/jpeg");
+        let dot = encoded(base.join("icons/dot.gif"), "image/gif");
+        let want = input
+            .replace(
+                r#"viewBox="0 0 10 10">"#,
+                &format!(
+                    r#"viewBox="0 0 10 10"> <defs> <symbol id="icon" viewBox="0 0 4 4"> <image href="{}" width="4" height="4"/></symbol> <symbol id="star-2" viewBox="0 0 4 4"> <path d="M2 0L4 4L0 4Z" fill="url(#g-2)"/></symbol> <linearGradient id="g-2"> <stop offset="1"/></linearGradient></defs>"#,
+                    dot
+                ),
+            )
+            .replace("photo.jpg", &photo)
+            .replace("icons/defs.svg#icon", "#icon")
+            .replace("icons/defs.svg#star", "#star-2");
+        assert_eq!(parse(&want), parse(&got));
+        assert!(got.starts_with(&input[..input.find("<svg").unwrap()]));
+    }
+
+    #[test]
+    fn name_case() {
+        let resources = Memory::new()
+            .with("site/a.css", "p{margin:0}")
+            .with("site/dot.gif", "GIF");
+        let options = Options {
+            format: SerializeMode::Html5,
+            ..Options::default()
+        };
+        let tests = vec![
+            (
+                "html",
+                r#"<HTML><HEAD><LINK REL="stylesheet" HREF="a.css"></HEAD><BODY><IMG SRC="dot.gif"><svg viewBox="0 0 1 1"><IMAGE HREF="dot.gif"/></svg></BODY></HTML>"#,
+                r#"<html><head><style>p{margin:0}</style></head><body><img src="data:image/gif;base64,R0lG"><svg viewBox="0 0 1 1"><IMAGE HREF="data:image/gif;base64,R0lG"></IMAGE></svg></body></html>"#,
+            ),
+            (
+                "xml",
+                r#"<?xml version="1.0"?><Drawing viewBox="0 0 1 1"><IMG SRC="dot.gif"/></Drawing>"#,
+                r#"<?xml version="1.0"?>
+<Drawing viewBox="0 0 1 1"><IMG SRC="data:image/gif;base64,R0lG"></Drawing>"#,
+            ),
+        ];
+        for (desc, input, want) in tests {
+            let (got, _) =
+                inline_with_resources(input.into(), Path::new("site"), &options, &resources)
+                    .unwrap();
+            assert_eq!(want, got.trim_end(), "{}", desc);
+        }
+        assert!(is_xml(b"\xEF\xBB\xBF\n<?xml version=\"1.0\"?><svg/>"));
+        assert!(!is_xml(b"<!DOCTYPE html><html></html>"));
+    }
+
+    #[test]
+    fn strip_comments() {
+        let input = r#"<!--! Licensed under MIT --><html><!-- template comment --><head><!--[if IE]><link rel="stylesheet" href="ie.css"/><![endif]--></head><body><!--[if !IE]><!--><p>modern</p><!--<![endif]--><div><!-- TODO --><p>text</p><!--<p>old</p>--></div></body></html>"#;
+        let want = r#"<!--! Licensed under MIT --><html><head><!--[if IE]><link rel="stylesheet" href="ie.css"/><![endif]--></head><body><!--[if !IE]><!--><p>modern</p><!--<![endif]--><div><p>text</p></div></body></html>"#;
+        let base = fixture("preserve");
+        let options = Options {
+            strip_comments: true,
+            ..Options::default()
+        };
+        let (got, _) = inline_with(input.into(), &base, &options).unwrap();
+        assert_eq!(parse(want), parse(&got));
+        let (got, _) = inline_with(input.into(), &base, &Options::default()).unwrap();
+        assert_eq!(parse(input), parse(&got), "comments are kept by default");
+    }
+
+    #[test]
+    fn sanitize() {
+        let base = fixture("modules");
+        let input = r#"<body onload="go()"><script src="main.js"></script><a href="javascript:go()">a</a><iframe src="https://example.com/"></iframe></body>"#;
+        let options = Options {
+            sanitize: Some(SanitizeOptions::default()),
+            ..Options::default()
+        };
+        let (got, report) = inline_with(input.into(), &base, &options).unwrap();
+        assert_eq!(
+            parse(r#"<body><a>a</a><iframe src="https://example.com/"></iframe></body>"#),
+            parse(&got)
+        );
+        let removed: Vec<String> = report.removed.iter().map(ToString::to_string).collect();
+        assert_eq!(vec!["<body> onload", "<script>", "<a> href"], removed);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn idempotent() {
+        fn documents(dir: &Path, found: &mut Vec<PathBuf>) {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                match path.extension().and_then(|ext| ext.to_str()) {
+                    // Its pages are served over HTTP, by the tests of remote.
+                    _ if path.ends_with("remote") => {}
+                    _ if path.is_dir() => documents(&path, found),
+                    Some("html" | "svg") => found.push(path),
+                    _ => {}
+                }
+            }
+        }
+        let mut found = vec![];
+        documents(&fixture(""), &mut found);
+        found.sort();
+        assert!(found.len() > 5, "{:?}", found);
+        for path in found {
+            let base = path.parent().unwrap();
+            let input = fs::read(&path).unwrap();
+            for format in [
+                SerializeMode::Preserve,
+                SerializeMode::Html5,
+                SerializeMode::Xhtml,
+            ] {
+                let options = Options {
+                    format,
+                    ..Options::default()
+                };
+                let (once, first) = inline_bytes(&input, base, &options).unwrap();
+                let (twice, second) = inline_bytes(once.as_bytes(), base, &options).unwrap();
+                assert_eq!(once, twice, "{}, {:?}", path.display(), format);
+                // Only what's deliberately left as it was, such as empty
+                // files, is warned of again.
+                let left: Vec<&Warning> = first
+                    .warnings
+                    .iter()
+                    .filter(|w| w.reason.ends_with(": is empty"))
+                    .collect();
+                assert_eq!(left, second.warnings.iter().collect::<Vec<_>>());
+            }
+        }
+    }
 }
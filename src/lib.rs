@@ -1,4 +1,46 @@
+#[cfg(feature = "async")]
+mod asynchronous;
+mod base;
+#[cfg(feature = "remote")]
+mod cache;
+mod csp;
+mod css;
+mod email;
+mod encoding;
+mod extract;
+mod fetch;
 pub mod html;
 mod inline;
+mod literals;
+mod manifest;
+mod merge;
+mod mhtml;
+mod module;
+mod optimize;
+#[cfg(feature = "remote")]
+mod remote;
+mod resource;
+mod sanitize;
+mod srcset;
+mod svg;
+mod verify;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-pub use inline::inline;
+pub use csp::{CspMode, InlineHash};
+pub use extract::{extract, extract_to, Asset};
+pub use fetch::{Cookie, FetchOptions};
+pub use inline::{
+    inline, inline_bytes, inline_bytes_to, inline_from, inline_mhtml_to, inline_to, inline_with,
+    inline_with_resources, Dependency, Embedded, InlineError, LazyAttributes, LinkError,
+    LinkRewrite, Options, PageLink, Report, Transform, TransformError, Warning,
+};
+pub use optimize::{ImageOptions, Optimization};
+#[cfg(feature = "remote")]
+pub use remote::{inline_url, Http};
+pub use resource::{Filesystem, Memory, ResourceError, Resources};
+pub use sanitize::{sanitize, Removal, SanitizeOptions};
+pub use verify::ExternalReference;
+
+#[cfg(feature = "async")]
+pub use asynchronous::{inline_async, inline_async_with};
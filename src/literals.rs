@@ -0,0 +1,144 @@
+//! The string literals of scripts that look like links to assets, which the
+//! scripts may load once they run.
+
+use crate::inline::is_local;
+use crate::module::{skip_comment, string_end};
+use std::error::Error;
+
+/// Extensions of the files a string literal is taken to link to: images,
+/// audio, video and fonts.
+const ASSET_EXTENSIONS: &[&str] = &[
+    "avif", "bmp", "gif", "ico", "jpeg", "jpg", "png", "svg", "webp", "mp3", "mp4", "ogg", "wav",
+    "webm", "otf", "ttf", "woff", "woff2",
+];
+
+/// asset_links returns the byte ranges of the string literals in `js` that
+/// look like relative links to assets, inside their quotes, in order. Only
+/// quoted strings are considered: identifiers and comments never are, and
+/// neither are template literals, which may be built up as the script runs.
+/// As in module scripts, regular expression literals aren't recognised.
+pub(crate) fn asset_links(js: &str) -> Vec<(usize, usize)> {
+    let b = js.as_bytes();
+    let mut links = vec![];
+    let mut ii = 0;
+    while ii < b.len() {
+        if let Some(end) = skip_comment(b, ii) {
+            ii = end;
+        } else if matches!(b[ii], b'"' | b'\'' | b'`') {
+            let end = string_end(b, ii);
+            let closed = end > ii + 1 && b[end - 1] == b[ii];
+            if b[ii] != b'`' && closed && is_asset_link(&js[ii + 1..end - 1]) {
+                links.push((ii + 1, end - 1));
+            }
+            ii = end;
+        } else {
+            ii += 1;
+        }
+    }
+    links
+}
+
+/// Rewrite the asset links of `js`, as `asset_links` finds them, with the
+/// result of `f`. `f` returns `None` to leave a link as it is. Everything
+/// else, including the quotes around the links, is copied through untouched.
+pub(crate) fn rewrite_links<F>(js: &str, mut f: F) -> Result<String, Box<dyn Error>>
+where
+    F: FnMut(&str) -> Result<Option<String>, Box<dyn Error>>,
+{
+    let mut out = String::with_capacity(js.len());
+    let mut copied = 0;
+    for (start, end) in asset_links(js) {
+        if let Some(link) = f(&js[start..end])? {
+            out.push_str(&js[copied..start]);
+            out.push_str(&link);
+            copied = end;
+        }
+    }
+    out.push_str(&js[copied..]);
+    Ok(out)
+}
+
+/// is_asset_link reports whether `literal` looks like a relative link to an
+/// asset: a local link without spaces or escapes, naming a file with a stem
+/// and one of `ASSET_EXTENSIONS`. Literals that only hold part of a path,
+/// such as `'sprites/'` or `'.png'`, are ambiguous, so they don't.
+fn is_asset_link(literal: &str) -> bool {
+    let unusual = |c: char| c.is_whitespace() || matches!(c, '\\' | '"' | '\'' | '`' | '<' | '>');
+    if literal.contains(unusual) || !is_local(literal) || literal.starts_with('#') {
+        return false;
+    }
+    let path = literal.split(['?', '#']).next().unwrap_or_default();
+    let file = path.rsplit('/').next().unwrap_or_default();
+    file.rsplit_once('.').is_some_and(|(stem, extension)| {
+        !stem.is_empty() && ASSET_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn scan() {
+        let tests = vec![
+            ("single quotes", "img.src = 'hover.png';", vec!["hover.png"]),
+            (
+                "double quotes",
+                r#"load("images/sprite.svg?v=2")"#,
+                vec!["images/sprite.svg?v=2"],
+            ),
+            (
+                "relative and root-relative",
+                "['../a.jpg', './b.webp', '/fonts/c.woff2']",
+                vec!["../a.jpg", "./b.webp", "/fonts/c.woff2"],
+            ),
+            ("case", "'LOGO.PNG'", vec!["LOGO.PNG"]),
+            (
+                "handler",
+                "this.src='hover.png'; this.alt=\"hover\"",
+                vec!["hover.png"],
+            ),
+            ("built up", "'sprites/' + name + '.png'", vec![]),
+            ("template", "`sprites/${name}.png`, `plain.png`", vec![]),
+            ("identifier", "const hover.png = 1; icon.png()", vec![]),
+            ("comment", "// 'old.png'\n/* \"older.png\" */", vec![]),
+            ("sentence", "alert('save it as photo.png')", vec![]),
+            ("escaped", r"'a\'b.png'", vec![]),
+            (
+                "other extensions",
+                "'app.js', 'page.html', 'data.json'",
+                vec![],
+            ),
+            (
+                "remote",
+                "'https://example.com/a.png', '//cdn.example.com/b.png'",
+                vec![],
+            ),
+            ("data url", "'data:image/png;base64,AAAA'", vec![]),
+            ("fragment", "'#icon.svg'", vec![]),
+            ("unterminated", "'hover.png", vec![]),
+        ];
+        for (desc, js, want) in tests {
+            let got: Vec<&str> = asset_links(js)
+                .into_iter()
+                .map(|(start, end)| &js[start..end])
+                .collect();
+            assert_eq!(want, got, "{}", desc);
+        }
+    }
+
+    #[test]
+    fn rewrite() {
+        let js = "a = 'a.png'; b = 'missing.png'; c = \"a.png\"; // 'a.png'";
+        let got = rewrite_links(js, |link| match link {
+            "a.png" => Ok(Some("data:image/png;base64,AAAA".into())),
+            _ => Ok(None),
+        })
+        .unwrap();
+        assert_eq!(
+            "a = 'data:image/png;base64,AAAA'; b = 'missing.png'; c = \"data:image/png;base64,AAAA\"; // 'a.png'",
+            got
+        );
+    }
+}
@@ -1,20 +1,32 @@
-mod html;
-mod inline;
-
 use clap::{App, Arg};
-use inline::inline;
+use encoding_rs::WINDOWS_1252;
+use inliner::html::{ParseMode, Parser, Rule, Severity, StrTokenizer};
+use inliner::{
+    extract_to, inline_bytes_to, inline_mhtml_to, inline_to, Cookie, FetchOptions, ImageOptions,
+    LinkRewrite, Options, Report, SanitizeOptions, Warning,
+};
+use std::error::Error;
 use std::fs;
-use std::io::prelude::*;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
 fn main() {
     let cli = App::new("inliner")
         .author("Jack Mordaunt <jackmordaunt@gmail.com>")
         .about("Take html resources and bundle them into a single html file.")
+        .after_help(
+            "Exits with 1 when no output could be produced and with 2 when output was \
+             produced with warnings.",
+        )
         .arg(
             Arg::with_name("input")
                 .required(true)
                 .takes_value(true)
-                .help("Path to html file"),
+                .help(
+                    "Path to html file, - to read it from standard input, or the url of a page \
+                     to fetch",
+                ),
         )
         .arg(
             Arg::with_name("base")
@@ -23,23 +35,800 @@ fn main() {
                 .default_value(".")
                 .help("Directory which links will be resolved against"),
         )
+        .arg(
+            Arg::with_name("size-threshold")
+                .long("size-threshold")
+                .takes_value(true)
+                .help("Leave resources larger than this many bytes as links"),
+        )
+        .arg(
+            Arg::with_name("budget")
+                .long("budget")
+                .takes_value(true)
+                .conflicts_with("budget-warn")
+                .help(
+                    "Fail, writing nothing, if the output is larger than this, such as 500k or 2M",
+                ),
+        )
+        .arg(
+            Arg::with_name("budget-warn")
+                .long("budget-warn")
+                .takes_value(true)
+                .help("Warn if the output is larger than this, such as 500k or 2M"),
+        )
+        .arg(
+            Arg::with_name("max-depth")
+                .long("max-depth")
+                .takes_value(true)
+                .help("Leave references more than this many links deep as links [default: 8]"),
+        )
+        .arg(
+            Arg::with_name("keep-going")
+                .long("keep-going")
+                .help("Leave resources that fail to load as links, with a warning"),
+        )
+        .arg(
+            Arg::with_name("embed-empty")
+                .long("embed-empty")
+                .help("Embed empty files as data urls, rather than leaving them linked"),
+        )
+        .arg(
+            Arg::with_name("picture-collapse")
+                .long("picture-collapse")
+                .help("Embed only the fallback image of picture elements"),
+        )
+        .arg(
+            Arg::with_name("inline-conditional-comments")
+                .long("inline-conditional-comments")
+                .help("Inline resources inside downlevel-revealed conditional comments"),
+        )
+        .arg(
+            Arg::with_name("inline-anchors")
+                .long("inline-anchors")
+                .help("Embed the files anchors link to as data urls"),
+        )
+        .arg(
+            Arg::with_name("skip-templates")
+                .long("skip-templates")
+                .help("Leave the resources inside <template> elements as links"),
+        )
+        .arg(
+            Arg::with_name("inline-srcdoc")
+                .long("inline-srcdoc")
+                .help("Inline the documents in the srcdoc of iframes as the page is"),
+        )
+        .arg(
+            Arg::with_name("inline-meta-images")
+                .long("inline-meta-images")
+                .help("Embed the images of Open Graph, Twitter card and tile meta tags, which --strict then checks"),
+        )
+        .arg(
+            Arg::with_name("prefer-noscript-images")
+                .long("prefer-noscript-images")
+                .help("Keep the image inside a <noscript> in place of the placeholder image beside it"),
+        )
+        .arg(
+            Arg::with_name("rewrite-js-literals")
+                .long("rewrite-js-literals")
+                .help("Embed the existing files that string literals in inline scripts and event handlers link to"),
+        )
+        .arg(
+            Arg::with_name("lazy-attrs")
+                .long("lazy-attrs")
+                .takes_value(true)
+                .possible_values(&["keep", "promote"])
+                .help(
+                    "Inline data-src and data-srcset, keeping them there or promoting them to \
+                     src and srcset",
+                ),
+        )
+        .arg(
+            Arg::with_name("remove-base")
+                .long("remove-base")
+                .help("Remove <base> elements once the links they apply to are inlined"),
+        )
+        .arg(
+            Arg::with_name("allow-outside-base")
+                .long("allow-outside-base")
+                .help("Inline resources that resolve outside of the base directory"),
+        )
+        .arg(
+            Arg::with_name("bundle-modules")
+                .long("bundle-modules")
+                .help("Bundle the imports of module scripts into data urls"),
+        )
+        .arg(
+            Arg::with_name("strip-comments")
+                .long("strip-comments")
+                .help("Remove comments, except for conditional and license (<!--! -->) comments"),
+        )
+        .arg(
+            Arg::with_name("sanitize")
+                .long("sanitize")
+                .help("Remove scripts, event handlers and javascript: urls before inlining"),
+        )
+        .arg(
+            Arg::with_name("sanitize-embeds")
+                .long("sanitize-embeds")
+                .help("Remove iframes, objects and embeds too; implies --sanitize"),
+        )
+        .arg(
+            Arg::with_name("optimize-images")
+                .long("optimize-images")
+                .help("Re-encode PNGs and JPEGs before embedding them, when that shrinks them"),
+        )
+        .arg(
+            Arg::with_name("image-quality")
+                .long("image-quality")
+                .takes_value(true)
+                .help("Quality to re-encode JPEGs at, from 1 to 100 [default: 80]"),
+        )
+        .arg(
+            Arg::with_name("csp")
+                .long("csp")
+                .takes_value(true)
+                .possible_values(&["warn", "rewrite", "remove"])
+                .default_value("warn")
+                .help("What to do about a Content-Security-Policy <meta> that blocks the output"),
+        )
+        .arg(
+            Arg::with_name("nonce")
+                .long("nonce")
+                .takes_value(true)
+                .help("Nonce to give the scripts and styles that resources are inlined into"),
+        )
+        .arg(
+            Arg::with_name("print-hashes")
+                .long("print-hashes")
+                .help("Print the CSP hashes of the inline scripts and styles of the output"),
+        )
+        .arg(
+            Arg::with_name("report-links")
+                .long("report-links")
+                .help("Print the links to other local pages, which must travel with the output"),
+        )
+        .arg(
+            Arg::with_name("rewrite-links")
+                .long("rewrite-links")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("input=output,...")
+                .help(
+                    "Rewrite the links to local pages to go to where they're inlined to, given \
+                     as input and output paths; repeatable",
+                ),
+        )
+        .arg(
+            Arg::with_name("email")
+                .long("email")
+                .help("Move stylesheet rules into style attributes, for HTML email"),
+        )
+        .arg(
+            Arg::with_name("merge-css")
+                .long("merge-css")
+                .help("Merge the inlined stylesheets into one <style>"),
+        )
+        .arg(
+            Arg::with_name("merge-js")
+                .long("merge-js")
+                .help("Merge the inlined scripts that run one after another into one <script>"),
+        )
+        .arg(
+            Arg::with_name("deterministic").long("deterministic").help(
+                "Write the same output wherever the page is inlined, for reproducible builds",
+            ),
+        )
+        .arg(
+            Arg::with_name("keep-line-endings")
+                .long("keep-line-endings")
+                .requires("deterministic")
+                .help("Keep the line endings of inlined text, rather than normalizing them to \\n"),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .long("strict")
+                .help("Fail if the output still references anything outside of itself"),
+        )
+        .arg(
+            Arg::with_name("strict-parse")
+                .long("strict-parse")
+                .help("Fail on markup that isn't well formed rather than repair it"),
+        )
+        .arg(Arg::with_name("check").long("check").help(
+            "Print the structural problems of the input rather than inlining it, \
+                     failing if any is an error",
+        ))
+        .arg(
+            Arg::with_name("allow")
+                .long("allow")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("rule")
+                .requires("check")
+                .help("Rule not to check, such as duplicate-id; repeatable"),
+        )
+        .arg(
+            Arg::with_name("json-ast")
+                .long("json-ast")
+                .help("Print the parsed input as a JSON tree rather than inlining it"),
+        )
+        .arg(
+            Arg::with_name("header")
+                .long("header")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("name: value")
+                .help("Header to send when fetching a page and its resources; repeatable"),
+        )
+        .arg(
+            Arg::with_name("user-agent")
+                .long("user-agent")
+                .takes_value(true)
+                .help("User-Agent to send when fetching a page and its resources"),
+        )
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .takes_value(true)
+                .value_name("name:password")
+                .help("Credentials to send by basic auth when fetching"),
+        )
+        .arg(
+            Arg::with_name("cookie")
+                .long("cookie")
+                .takes_value(true)
+                .value_name("name=value; ...")
+                .help("Cookies to send when fetching"),
+        )
+        .arg(
+            Arg::with_name("cookie-file")
+                .long("cookie-file")
+                .takes_value(true)
+                .help("Netscape cookie file whose cookies to send to the hosts they're for"),
+        )
+        .arg(
+            Arg::with_name("credentials-origin")
+                .long("credentials-origin")
+                .takes_value(true)
+                .value_name("origin")
+                .help(
+                    "Send headers, credentials and cookies to this origin, such as \
+                     https://example.com, rather than the page's own",
+                ),
+        )
+        .arg(
+            Arg::with_name("credentials-everywhere")
+                .long("credentials-everywhere")
+                .help("Send headers, credentials and cookies with requests to every origin"),
+        )
+        .arg(
+            Arg::with_name("cache-dir")
+                .long("cache-dir")
+                .takes_value(true)
+                .value_name("path")
+                .help("Cache fetched responses in <path>, and revalidate them on later runs"),
+        )
+        .arg(
+            Arg::with_name("offline")
+                .long("offline")
+                .requires("cache-dir")
+                .help("Only use responses from the cache, failing on any that aren't in it"),
+        )
+        .arg(
+            Arg::with_name("extract")
+                .long("extract")
+                .takes_value(true)
+                .value_name("dir")
+                .help(
+                    "Take the data urls, scripts and styles out of a bundled input into files \
+                     in <dir>, rather than inlining it",
+                ),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["preserve", "html5", "xhtml", "mhtml"])
+                .default_value("preserve")
+                .help("Serialization syntax of the output"),
+        )
         .get_matches();
-    let input = match fs::read_to_string(cli.value_of("input").unwrap()) {
-        Ok(content) => content,
+    let size_threshold = match cli.value_of("size-threshold").map(str::parse) {
+        Some(Ok(size)) => Some(size),
+        Some(Err(err)) => {
+            eprintln!("error: parsing size threshold: {}", err);
+            process::exit(1);
+        }
+        None => None,
+    };
+    let budget = match (cli.value_of("budget"), cli.value_of("budget-warn")) {
+        (Some(size), _) => Some((size, true)),
+        (None, Some(size)) => Some((size, false)),
+        (None, None) => None,
+    };
+    let budget = budget.map(|(size, strict)| match parse_size(size) {
+        Ok(size) => Budget { size, strict },
+        Err(err) => {
+            eprintln!("error: parsing budget: {}", err);
+            process::exit(1);
+        }
+    });
+    let max_depth = match cli.value_of("max-depth").map(str::parse) {
+        Some(Ok(depth)) => depth,
+        Some(Err(err)) => {
+            eprintln!("error: parsing max depth: {}", err);
+            process::exit(1);
+        }
+        None => Options::default().max_depth,
+    };
+    // MHTML packages the page, which is otherwise written as it would be.
+    let mhtml = cli.value_of("format") == Some("mhtml");
+    let format = match cli.value_of("format").unwrap().parse() {
+        _ if mhtml => Options::default().format,
+        Ok(format) => format,
+        Err(err) => {
+            eprintln!("error: parsing format: {}", err);
+            process::exit(1);
+        }
+    };
+    let sanitize = match cli.is_present("sanitize") || cli.is_present("sanitize-embeds") {
+        true => Some(SanitizeOptions {
+            remove_embeds: cli.is_present("sanitize-embeds"),
+        }),
+        false => None,
+    };
+    let lazy_attributes = match cli.value_of("lazy-attrs").map(str::parse) {
+        Some(Ok(lazy)) => Some(lazy),
+        Some(Err(err)) => {
+            eprintln!("error: parsing lazy attributes: {}", err);
+            process::exit(1);
+        }
+        None => None,
+    };
+    let optimize_images = match cli.value_of("image-quality").map(str::parse::<u8>) {
+        _ if !cli.is_present("optimize-images") => None,
+        _ if !cfg!(feature = "optimize-images") => {
+            eprintln!(
+                "error: --optimize-images needs inliner built with the optimize-images feature"
+            );
+            process::exit(1);
+        }
+        Some(Ok(quality)) if (1..=100).contains(&quality) => Some(ImageOptions { quality }),
+        Some(Ok(quality)) => {
+            eprintln!(
+                "error: parsing image quality: {} isn't from 1 to 100",
+                quality
+            );
+            process::exit(1);
+        }
+        Some(Err(err)) => {
+            eprintln!("error: parsing image quality: {}", err);
+            process::exit(1);
+        }
+        None => Some(ImageOptions::default()),
+    };
+    let csp = match cli.value_of("csp").unwrap().parse() {
+        Ok(csp) => csp,
         Err(err) => {
-            eprintln!("error: opening input file: {}", err);
+            eprintln!("error: parsing csp mode: {}", err);
+            process::exit(1);
+        }
+    };
+    let mut headers = vec![];
+    for header in cli.values_of("header").into_iter().flatten() {
+        match header.split_once(':') {
+            Some((name, value)) if !name.trim().is_empty() => {
+                headers.push((name.trim().to_owned(), value.trim().to_owned()))
+            }
+            _ => {
+                eprintln!("error: parsing header: {:?} isn't \"name: value\"", header);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(user) = cli.value_of("user") {
+        let credentials = base64::encode(user.as_bytes());
+        headers.push(("Authorization".into(), format!("Basic {}", credentials)));
+    }
+    if let Some(cookie) = cli.value_of("cookie") {
+        headers.push(("Cookie".into(), cookie.to_owned()));
+    }
+    let cookies = match cli.value_of("cookie-file").map(fs::read_to_string) {
+        Some(Ok(file)) => match Cookie::parse_file(&file) {
+            Ok(cookies) => cookies,
+            Err(err) => {
+                eprintln!("error: parsing cookie file: {}", err);
+                process::exit(1);
+            }
+        },
+        Some(Err(err)) => {
+            eprintln!("error: reading cookie file: {}", err);
+            process::exit(1);
+        }
+        None => vec![],
+    };
+    let fetch = FetchOptions {
+        headers,
+        user_agent: cli.value_of("user-agent").map(str::to_owned),
+        cookies,
+        credentials_origin: cli.value_of("credentials-origin").map(str::to_owned),
+        credentials_everywhere: cli.is_present("credentials-everywhere"),
+        cache_dir: cli.value_of("cache-dir").map(PathBuf::from),
+        offline: cli.is_present("offline"),
+    };
+    let parse_mode = match cli.is_present("strict-parse") {
+        true => ParseMode::Strict,
+        false => ParseMode::Lenient,
+    };
+    let mut rules = Rule::ALL.to_vec();
+    for allowed in cli.values_of("allow").into_iter().flatten() {
+        match allowed.parse() {
+            Ok(allowed) => rules.retain(|rule| *rule != allowed),
+            Err(err) => {
+                eprintln!("error: parsing allowed rule: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+    let input = cli.value_of("input").unwrap();
+    let mut pages = vec![];
+    for mapping in cli.values_of("rewrite-links").into_iter().flatten() {
+        for pair in mapping.split(',').filter(|pair| !pair.is_empty()) {
+            match pair.split_once('=') {
+                Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+                    pages.push((PathBuf::from(from), PathBuf::from(to)))
+                }
+                _ => {
+                    eprintln!(
+                        "error: parsing link rewrite: {:?} isn't \"input=output\"",
+                        pair
+                    );
+                    process::exit(1);
+                }
+            }
+        }
+    }
+    let rewrite_links = match pages.is_empty() {
+        true => None,
+        false if input == "-" || is_url(input) => {
+            eprintln!("error: --rewrite-links needs the input to be a file");
+            process::exit(1);
+        }
+        // An input that isn't mapped is taken to be inlined in place.
+        false => {
+            let output = pages
+                .iter()
+                .find(|(from, _)| same_file(from, input.as_ref()))
+                .map_or_else(|| PathBuf::from(input), |(_, to)| to.clone());
+            Some(LinkRewrite { pages, output })
+        }
+    };
+    let options = Options {
+        size_threshold,
+        keep_going: cli.is_present("keep-going"),
+        embed_empty: cli.is_present("embed-empty"),
+        picture_collapse: cli.is_present("picture-collapse"),
+        inline_conditional_comments: cli.is_present("inline-conditional-comments"),
+        inline_anchors: cli.is_present("inline-anchors"),
+        inline_templates: !cli.is_present("skip-templates"),
+        inline_srcdoc: cli.is_present("inline-srcdoc"),
+        inline_meta_images: cli.is_present("inline-meta-images"),
+        prefer_noscript_images: cli.is_present("prefer-noscript-images"),
+        rewrite_js_literals: cli.is_present("rewrite-js-literals"),
+        remove_base: cli.is_present("remove-base"),
+        allow_outside_base: cli.is_present("allow-outside-base"),
+        bundle_modules: cli.is_present("bundle-modules"),
+        strict: cli.is_present("strict"),
+        strip_comments: cli.is_present("strip-comments"),
+        sanitize,
+        parse_mode,
+        optimize_images,
+        lazy_attributes,
+        csp,
+        nonce: cli.value_of("nonce").map(str::to_owned),
+        hashes: cli.is_present("print-hashes"),
+        report_links: cli.is_present("report-links"),
+        rewrite_links,
+        email: cli.is_present("email"),
+        merge_css: cli.is_present("merge-css"),
+        merge_js: cli.is_present("merge-js"),
+        deterministic: cli.is_present("deterministic"),
+        keep_line_endings: cli.is_present("keep-line-endings"),
+        fetch,
+        format,
+        max_depth,
+        ..Options::default()
+    };
+    if is_url(input) {
+        if mhtml {
+            eprintln!("error: --format mhtml isn't supported for urls");
+            process::exit(1);
+        }
+        inline_page(input, &options, budget.as_ref());
+        return;
+    }
+    let mut reader: Box<dyn BufRead> = match input {
+        "-" => Box::new(io::stdin().lock()),
+        path => match fs::File::open(path) {
+            Ok(file) => Box::new(io::BufReader::new(file)),
+            Err(err) => {
+                eprintln!("error: opening input file: {}", err);
+                process::exit(1);
+            }
+        },
+    };
+    let base = cli.value_of("base").unwrap().as_ref();
+    // Output that may be over budget is held back until it's known not to be.
+    let mut held = vec![];
+    let mut stdout = io::stdout().lock();
+    let mut out: &mut dyn Write = match budget {
+        Some(_) => &mut held,
+        None => &mut stdout,
+    };
+    // The tree and MHTML need the whole input, where inlining reads it as it
+    // goes.
+    let whole = cli.is_present("json-ast") || cli.is_present("extract") || cli.is_present("check");
+    let result = if whole || mhtml {
+        let mut input = vec![];
+        if let Err(err) = reader.read_to_end(&mut input) {
+            eprintln!("error: reading input: {}", err);
+            process::exit(1);
+        }
+        if cli.is_present("json-ast") {
+            print_ast(&input);
             return;
         }
+        if cli.is_present("check") {
+            check(&input, parse_mode, &rules);
+            return;
+        }
+        if let Some(dir) = cli.value_of("extract") {
+            extract(&input, dir.as_ref());
+            return;
+        }
+        inline_mhtml_to(&input, base, &options, &mut out)
+    } else {
+        match inline_to(reader, base, &options, &mut out) {
+            Err(err) if input != "-" && is_invalid_utf8(&*err) => {
+                inline_windows_1252(input, base, &options, &mut out, &*err)
+            }
+            result => result,
+        }
     };
-    let inlined = match inline(input, cli.value_of("base").unwrap().as_ref()) {
-        Ok(output) => output,
+    let report = match result {
+        Ok(report) => report,
         Err(err) => {
             eprintln!("error: inlining html: {}", err);
-            return;
+            process::exit(1);
         }
     };
-    if let Err(err) = std::io::stdout().write_all(inlined.as_bytes()) {
+    let over = budget.is_some_and(|budget| check_budget(&budget, &report));
+    if let Err(err) = stdout.write_all(&held) {
         eprintln!("error: writing to stdout: {}", err);
-        return;
+        process::exit(1);
+    }
+    print_report(input, &report);
+    if over {
+        process::exit(2);
+    }
+}
+
+/// is_invalid_utf8 reports whether reading the input stopped at bytes that
+/// aren't UTF-8.
+fn is_invalid_utf8(err: &(dyn Error + 'static)) -> bool {
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|err| err.kind() == io::ErrorKind::InvalidData)
+}
+
+/// inline_windows_1252 inlines the file `input`, which isn't the UTF-8 it's
+/// taken to be when it declares no encoding, as windows-1252, as browsers
+/// read such pages, warning of `err`, which said it wasn't UTF-8.
+fn inline_windows_1252(
+    input: &str,
+    base: &Path,
+    options: &Options,
+    mut out: &mut dyn Write,
+    err: &dyn Error,
+) -> Result<Report, Box<dyn Error>> {
+    let bytes = fs::read(input)?;
+    let (text, _, _) = WINDOWS_1252.decode(&bytes);
+    let mut report = inline_bytes_to(text.as_bytes(), base, options, &mut out)?;
+    report.warnings.insert(
+        0,
+        Warning {
+            element: "html".into(),
+            link: input.into(),
+            reason: format!("{}, so it was read as windows-1252", err),
+        },
+    );
+    Ok(report)
+}
+
+/// Budget is the size the output is to keep to.
+struct Budget {
+    size: u64,
+    /// Whether going over fails the run, rather than warning.
+    strict: bool,
+}
+
+/// How many of the largest resources are listed when the output is over its
+/// budget.
+const LARGEST: usize = 5;
+
+/// check_budget reports whether the output of a run is over `budget`,
+/// listing the largest resources in it if so, and exits with 3 if the budget
+/// is strict.
+fn check_budget(budget: &Budget, report: &Report) -> bool {
+    if report.output_size <= budget.size {
+        return false;
+    }
+    let level = match budget.strict {
+        true => "error",
+        false => "warning",
     };
+    eprintln!(
+        "{}: the output is {} bytes, over the budget of {} bytes",
+        level, report.output_size, budget.size
+    );
+    for embedded in report.largest_resources(LARGEST) {
+        eprintln!("  {}", embedded);
+    }
+    if budget.strict {
+        process::exit(3);
+    }
+    true
+}
+
+/// parse_size parses a size in bytes, or in KiB, MiB or GiB with a `k`, `M`
+/// or `G` suffix, such as `500k`.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let (digits, unit) = match size.char_indices().last() {
+        Some((ii, 'k' | 'K')) => (&size[..ii], 1 << 10),
+        Some((ii, 'm' | 'M')) => (&size[..ii], 1 << 20),
+        Some((ii, 'g' | 'G')) => (&size[..ii], 1 << 30),
+        _ => (size, 1),
+    };
+    let n = digits
+        .trim()
+        .parse::<u64>()
+        .map_err(|err| format!("{}: {}", size, err))?;
+    n.checked_mul(unit)
+        .ok_or_else(|| format!("{}: too large", size))
+}
+
+/// print_report prints what happened during the run on `input` to stderr,
+/// and exits with 2 if there were warnings.
+fn print_report(input: &str, report: &Report) {
+    for link in &report.links {
+        match link.line {
+            Some(line) => eprintln!("link: {}:{}: <{}> {}", input, line, link.element, link.link),
+            None => eprintln!("link: {}: <{}> {}", input, link.element, link.link),
+        }
+    }
+    for removal in &report.removed {
+        eprintln!("removed: {}", removal);
+    }
+    for hash in &report.hashes {
+        eprintln!("hash: {}", hash);
+    }
+    for optimization in &report.optimized {
+        eprintln!("optimized: {}", optimization);
+    }
+    for warning in &report.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if !report.warnings.is_empty() {
+        process::exit(2);
+    }
+}
+
+/// is_url reports whether the input is a url rather than a path.
+/// same_file reports whether the paths `a` and `b` name the same file, as
+/// `index.html` and `./index.html` do.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// inline_page fetches the page at `url` and prints it inlined.
+#[cfg(feature = "remote")]
+fn inline_page(url: &str, options: &Options, budget: Option<&Budget>) {
+    let (output, report) = match inliner::inline_url(url, options) {
+        Ok(inlined) => inlined,
+        Err(err) => {
+            eprintln!("error: inlining html: {}", err);
+            process::exit(1);
+        }
+    };
+    let over = budget.is_some_and(|budget| check_budget(budget, &report));
+    if let Err(err) = io::stdout().lock().write_all(output.as_bytes()) {
+        eprintln!("error: writing to stdout: {}", err);
+        process::exit(1);
+    }
+    print_report(url, &report);
+    if over {
+        process::exit(2);
+    }
+}
+
+#[cfg(not(feature = "remote"))]
+fn inline_page(_: &str, _: &Options, _: Option<&Budget>) {
+    eprintln!("error: inlining a url needs inliner built with the remote feature");
+    process::exit(1);
+}
+
+/// extract takes the assets out of `input` into `dir`, printing the document
+/// that links to them.
+fn extract(input: &[u8], dir: &Path) {
+    let input = String::from_utf8_lossy(input);
+    let (output, assets) = match extract_to(&input, dir) {
+        Ok(extracted) => extracted,
+        Err(err) => {
+            eprintln!("error: extracting assets: {}", err);
+            process::exit(1);
+        }
+    };
+    if let Err(err) = io::stdout().lock().write_all(output.as_bytes()) {
+        eprintln!("error: writing to stdout: {}", err);
+        process::exit(1);
+    }
+    for asset in &assets {
+        eprintln!("extracted: {}", asset);
+    }
+}
+
+/// check prints the problems `rules` find with the structure of `input`,
+/// and exits with 1 if any is an error.
+fn check(input: &[u8], mode: ParseMode, rules: &[Rule]) {
+    let input = String::from_utf8_lossy(input);
+    let dom = match Parser::new(StrTokenizer::new(&input).merged())
+        .mode(mode)
+        .parse()
+    {
+        Ok(dom) => dom,
+        Err(err) => {
+            eprintln!("error: parsing html: {}", err);
+            process::exit(1);
+        }
+    };
+    let issues = dom.validate_with(rules);
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    if issues.iter().any(|issue| issue.severity == Severity::Error) {
+        process::exit(1);
+    }
+}
+
+/// print_ast prints the tree `input` parses to as JSON.
+#[cfg(feature = "serde")]
+fn print_ast(input: &[u8]) {
+    let input = String::from_utf8_lossy(input);
+    let dom = match inliner::html::parse(&input) {
+        Ok(dom) => dom,
+        Err(err) => {
+            eprintln!("error: parsing html: {}", err);
+            process::exit(1);
+        }
+    };
+    if let Err(err) = serde_json::to_writer_pretty(io::stdout(), &dom) {
+        eprintln!("error: writing to stdout: {}", err);
+        process::exit(1);
+    }
+    println!();
+}
+
+#[cfg(not(feature = "serde"))]
+fn print_ast(_: &[u8]) {
+    eprintln!("error: --json-ast needs inliner built with the serde feature");
+    process::exit(1);
 }
@@ -0,0 +1,266 @@
+use crate::base::Base;
+use crate::inline::{data_url, exceeds_threshold, is_empty_resource, Options, Warning};
+use crate::resource::Resources;
+use serde_json::Value;
+use std::error::Error;
+use std::path::Path;
+
+/// Media type of an embedded web app manifest.
+const MEDIA_TYPE: &str = "application/manifest+json";
+
+/// Inline the web app manifest at `path` into a data url.
+/// Images the manifest references are resolved relative to the manifest and
+/// embedded first, so the manifest is self contained.
+pub(crate) fn inline(
+    path: &Path,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+    warnings: &mut Vec<Warning>,
+) -> Result<String, Box<dyn Error>> {
+    let content = resources.read(path)?;
+    let mut manifest: Value = serde_json::from_slice(&content)
+        .map_err(|e| format!("{}: invalid manifest: {}", &path.to_string_lossy(), e))?;
+    inline_images(
+        manifest.get_mut("icons"),
+        path,
+        base,
+        options,
+        resources,
+        warnings,
+    )?;
+    inline_images(
+        manifest.get_mut("screenshots"),
+        path,
+        base,
+        options,
+        resources,
+        warnings,
+    )?;
+    if let Some(Value::Array(shortcuts)) = manifest.get_mut("shortcuts") {
+        for shortcut in shortcuts {
+            inline_images(
+                shortcut.get_mut("icons"),
+                path,
+                base,
+                options,
+                resources,
+                warnings,
+            )?;
+        }
+    }
+    Ok(format!(
+        "data:{media_type};base64,{data}",
+        media_type = MEDIA_TYPE,
+        data = base64::encode(manifest.to_string().as_bytes())
+    ))
+}
+
+/// inline_images replaces the `src` of each image object in the list with a
+/// data url. Images are objects like `{"src": "icon.png", "type": "image/png"}`
+/// and are relative to the `manifest` that lists them, which is linked from a
+/// document with the given `base`.
+fn inline_images(
+    images: Option<&mut Value>,
+    manifest: &Path,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+    warnings: &mut Vec<Warning>,
+) -> Result<(), Box<dyn Error>> {
+    let images = match images {
+        Some(Value::Array(images)) => images,
+        _ => return Ok(()),
+    };
+    let base = base.of(manifest);
+    for image in images {
+        let src = match image.get("src").and_then(Value::as_str) {
+            Some(src) if base.resolves(src) => src.to_owned(),
+            _ => continue,
+        };
+        // Images are referenced by the manifest, which the document links to.
+        if options.max_depth < 2 {
+            warnings.push(Warning {
+                element: "link".into(),
+                link: src.clone(),
+                reason: format!(
+                    "exceeds the maximum depth of {}: {} -> {}",
+                    options.max_depth,
+                    manifest.display(),
+                    src
+                ),
+            });
+            continue;
+        }
+        let path = base.resolve(&src)?;
+        let media_type = match image.get("type").and_then(Value::as_str) {
+            Some(t) if !t.is_empty() => t.to_owned(),
+            _ => mime_guess::from_path(&src)
+                .first_or_octet_stream()
+                .to_string(),
+        };
+        let embedded =
+            exceeds_threshold(&src, &path, &base, options, resources).and_then(|exceeds| {
+                if exceeds {
+                    Ok(None)
+                } else {
+                    data_url(&path, &media_type, options, resources).map(Some)
+                }
+            });
+        match embedded {
+            Ok(Some(url)) => image["src"] = Value::String(url),
+            Ok(None) => {}
+            Err(err) if options.keep_going || is_empty_resource(&*err) => warnings.push(Warning {
+                element: "link".into(),
+                link: src,
+                reason: format!("manifest image: {}", err),
+            }),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::Filesystem;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join("manifest")
+            .join(name)
+    }
+
+    fn encoded(name: &str, media_type: &str) -> String {
+        data_url(&fixture(name), media_type, &Options::default(), &Filesystem).unwrap()
+    }
+
+    fn decode(url: &str) -> Value {
+        let data = url
+            .strip_prefix("data:application/manifest+json;base64,")
+            .expect("manifest data url");
+        serde_json::from_slice(&base64::decode(data).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn images_are_embedded() {
+        let mut warnings = vec![];
+        let got = inline(
+            &fixture("app/site.webmanifest"),
+            &Base::new(&fixture("")),
+            &Options::default(),
+            &Filesystem,
+            &mut warnings,
+        )
+        .unwrap();
+        let want = json!({
+            "name": "Notes",
+            "start_url": "/",
+            "icons": [
+                {
+                    "src": encoded("app/icons/192.png", "image/png"),
+                    "sizes": "192x192",
+                    "type": "image/png"
+                },
+                { "src": encoded("app/icons/512.png", "image/png"), "sizes": "512x512" }
+            ],
+            "shortcuts": [
+                {
+                    "name": "New note",
+                    "url": "/new",
+                    "icons": [{ "src": encoded("app/icons/compose.gif", "image/gif"), "sizes": "96x96" }]
+                }
+            ]
+        });
+        assert_eq!(want, decode(&got));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn malformed_manifest_names_the_file() {
+        let err = inline(
+            &fixture("app/broken.webmanifest"),
+            &Base::new(&fixture("")),
+            &Options::default(),
+            &Filesystem,
+            &mut vec![],
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("broken.webmanifest: invalid manifest"),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn missing_images() {
+        let path = fixture("app/missing.webmanifest");
+        assert!(inline(
+            &path,
+            &Base::new(&fixture("")),
+            &Options::default(),
+            &Filesystem,
+            &mut vec![]
+        )
+        .is_err());
+        let options = Options {
+            keep_going: true,
+            ..Options::default()
+        };
+        let mut warnings = vec![];
+        let got = inline(
+            &path,
+            &Base::new(&fixture("")),
+            &options,
+            &Filesystem,
+            &mut warnings,
+        )
+        .unwrap();
+        assert_eq!(
+            json!({ "icons": [
+                { "src": encoded("app/icons/192.png", "image/png") },
+                { "src": "icons/missing.png" }
+            ]}),
+            decode(&got)
+        );
+        assert_eq!(1, warnings.len());
+        assert_eq!("icons/missing.png", warnings[0].link);
+    }
+
+    #[test]
+    fn max_depth() {
+        let options = Options {
+            max_depth: 1,
+            ..Options::default()
+        };
+        let mut warnings = vec![];
+        let got = inline(
+            &fixture("app/missing.webmanifest"),
+            &Base::new(&fixture("")),
+            &options,
+            &Filesystem,
+            &mut warnings,
+        )
+        .unwrap();
+        assert_eq!(
+            json!({ "icons": [{ "src": "icons/192.png" }, { "src": "icons/missing.png" }]}),
+            decode(&got)
+        );
+        assert_eq!(2, warnings.len());
+        assert!(
+            warnings[0]
+                .reason
+                .ends_with("missing.webmanifest -> icons/192.png"),
+            "{}",
+            warnings[0].reason
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Merging of the `<style>` and `<script>` elements that stylesheets and
+//! scripts were inlined into, for a page with one block of each.
+//!
+//! Stylesheets are merged into the first of them in document order, since
+//! where a stylesheet is doesn't matter, only the order of the cascade. A
+//! style that isn't merged, such as one for other media or one that was in
+//! the page already, would then come before stylesheets that came after it,
+//! so it ends the group and the next stylesheet starts another.
+//!
+//! A script only sees the page parsed before it, so scripts are only merged
+//! with the scripts right after them, with nothing but comments between.
+//! Scripts with `async`, `defer`, `nomodule` or a `type` other than
+//! JavaScript run at other times than in order, so they're never merged.
+
+use crate::html::{Dom, Node, NodeRef};
+use crate::inline::set_text;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// Stylesheets are joined by a line break.
+const STYLE_SEPARATOR: &str = "\n";
+
+/// Scripts are joined by a semicolon on a line of its own, so that neither a
+/// last statement without one nor a trailing line comment runs into the next
+/// script.
+const SCRIPT_SEPARATOR: &str = "\n;\n";
+
+/// Inlined is the set of elements a stylesheet or script was inlined into.
+pub(crate) type Inlined = HashSet<*const RefCell<Node>>;
+
+/// merge_styles merges the `<style>` elements of `inlined` into the first of
+/// each group of them, in document order.
+pub(crate) fn merge_styles(dom: &mut Dom, inlined: &Inlined) {
+    let inert = dom.template_content();
+    let mut merged = vec![];
+    let mut group: Option<(NodeRef, Vec<String>)> = None;
+    for n in dom.iter_elements() {
+        if inert.contains(&Rc::as_ptr(&n)) {
+            continue;
+        }
+        let node = n.borrow();
+        if !is_style(&node) {
+            continue;
+        }
+        if !inlined.contains(&Rc::as_ptr(&n)) || !is_mergeable_style(&node) {
+            finish(group.take(), STYLE_SEPARATOR);
+            continue;
+        }
+        let text = node.child_text();
+        drop(node);
+        match &mut group {
+            Some((_, texts)) => {
+                texts.push(text);
+                merged.push(n);
+            }
+            None => group = Some((n, vec![text])),
+        }
+    }
+    finish(group, STYLE_SEPARATOR);
+    for n in merged {
+        dom.remove(&n);
+    }
+}
+
+/// merge_scripts merges each run of the `<script>` elements of `inlined`
+/// that are siblings into the first of the run.
+pub(crate) fn merge_scripts(dom: &mut Dom, inlined: &Inlined) {
+    let inert = dom.template_content();
+    merge_siblings(&mut dom.nodes, inlined);
+    for n in dom.iter_elements() {
+        if inert.contains(&Rc::as_ptr(&n)) {
+            continue;
+        }
+        if let Some(children) = n.borrow_mut().children_mut() {
+            merge_siblings(children, inlined);
+        }
+    }
+}
+
+/// merge_siblings merges the runs of mergeable scripts in `nodes`.
+fn merge_siblings(nodes: &mut Vec<NodeRef>, inlined: &Inlined) {
+    let mut merged: HashSet<*const RefCell<Node>> = HashSet::new();
+    let mut group: Option<(NodeRef, Vec<String>)> = None;
+    for n in nodes.iter() {
+        let node = n.borrow();
+        if matches!(&*node, Node::Comment(_)) {
+            continue;
+        }
+        let is_mergeable = node.is_element("script")
+            && inlined.contains(&Rc::as_ptr(n))
+            && is_mergeable_script(&node);
+        if !is_mergeable {
+            finish(group.take(), SCRIPT_SEPARATOR);
+            continue;
+        }
+        let text = node.child_text();
+        match &mut group {
+            Some((_, texts)) => {
+                texts.push(text);
+                merged.insert(Rc::as_ptr(n));
+            }
+            None => group = Some((n.clone(), vec![text])),
+        }
+    }
+    finish(group, SCRIPT_SEPARATOR);
+    if !merged.is_empty() {
+        nodes.retain(|n| !merged.contains(&Rc::as_ptr(n)));
+    }
+}
+
+/// finish sets the text of the first element of `group` to the texts of the
+/// group, joined by `separator`.
+fn finish(group: Option<(NodeRef, Vec<String>)>, separator: &str) {
+    if let Some((first, texts)) = group {
+        if texts.len() > 1 {
+            set_text(&mut first.borrow_mut(), texts.join(separator));
+        }
+    }
+}
+
+/// is_style reports whether `node` brings styles into the page.
+fn is_style(node: &Node) -> bool {
+    node.is_element("style")
+        || (node.is_element("link")
+            && node.attr("rel").is_some_and(|rel| {
+                rel.split_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("stylesheet"))
+            }))
+}
+
+/// is_mergeable_style reports whether the `<style>` `node` applies as any
+/// other would, so that its rules can be moved into another.
+fn is_mergeable_style(node: &Node) -> bool {
+    // An `@import` must come before any rules, so it can't be appended.
+    only_attributes(node, &["type", "nonce"])
+        && !node.child_text().to_ascii_lowercase().contains("@import")
+}
+
+/// is_mergeable_script reports whether the `<script>` `node` is a classic
+/// script, which runs as soon as it's parsed.
+fn is_mergeable_script(node: &Node) -> bool {
+    let is_classic = node.attr("type").is_none_or(|t| {
+        matches!(
+            t.trim().to_ascii_lowercase().as_str(),
+            "" | "text/javascript" | "application/javascript"
+        )
+    });
+    is_classic
+        && only_attributes(
+            node,
+            &["type", "nonce", "charset", "crossorigin", "integrity"],
+        )
+}
+
+/// only_attributes reports whether `node` has no attributes but `allowed`.
+fn only_attributes(node: &Node, allowed: &[&str]) -> bool {
+    match node {
+        Node::Tag { attributes, .. } => attributes
+            .keys()
+            .all(|key| allowed.iter().any(|a| a.eq_ignore_ascii_case(key))),
+        _ => false,
+    }
+}
@@ -0,0 +1,298 @@
+//! MHTML output (RFC 2557): a `multipart/related` document with the page as
+//! its first part and each embedded resource as a part of its own, which the
+//! page refers to by `cid:` url. Mail clients and archival tools that don't
+//! take data urls open these instead.
+
+use crate::css;
+use crate::html::{Dom, Node, NodeRef, SerializeMode};
+use crate::srcset::SrcSet;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, Write};
+
+/// Lines of base64 are at most this long, as MIME requires.
+const LINE_LENGTH: usize = 76;
+
+/// Part is a resource taken out of the page.
+struct Part {
+    media_type: String,
+    data: Vec<u8>,
+}
+
+/// Parts collects the data urls of a page as parts, once each.
+#[derive(Default)]
+struct Parts {
+    parts: Vec<Part>,
+    by_url: HashMap<String, usize>,
+}
+
+impl Parts {
+    /// add returns the index of the part holding the data of `url`, if it
+    /// is a base64 data url.
+    fn add(&mut self, url: &str) -> Option<usize> {
+        if let Some(&index) = self.by_url.get(url) {
+            return Some(index);
+        }
+        let (media_type, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+        let data = base64::decode(data).ok()?;
+        let media_type = match media_type {
+            "" => "text/plain".to_owned(),
+            media_type => media_type.to_owned(),
+        };
+        self.parts.push(Part { media_type, data });
+        self.by_url.insert(url.to_owned(), self.parts.len() - 1);
+        Some(self.parts.len() - 1)
+    }
+}
+
+/// write writes `dom` to `w` as MHTML, with the page serialized as `mode`
+/// says. Every base64 data url in the page's attributes and styles becomes a
+/// part, which the page then refers to by Content-ID; the DOM is changed to
+/// do so.
+pub(crate) fn write<W: io::Write>(dom: &Dom, mode: SerializeMode, w: &mut W) -> io::Result<()> {
+    // Ids are derived from the page as it was given, data urls and all, so
+    // the same page always gives the same output, whichever build of
+    // inliner writes it.
+    let digest = Sha256::digest(dom.to_string_with(mode).as_bytes());
+    let id: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    let cid = |index: usize| format!("part{}.{}@inliner", index + 1, id);
+    let parts = RefCell::new(Parts::default());
+    dom.depth_first(&|n: NodeRef| {
+        replace_urls(&mut n.borrow_mut(), &mut |url| {
+            let index = parts.borrow_mut().add(url)?;
+            Some(format!("cid:{}", cid(index)))
+        })
+    })
+    .map_err(|err| io::Error::other(err.to_string()))?;
+    // The boundary can't turn up in the parts, since "=_" is never base64.
+    let boundary = format!("----=_NextPart_{}", id);
+    let mut w = io::BufWriter::new(w);
+    header(&mut w, "MIME-Version", "1.0")?;
+    header(
+        &mut w,
+        "Content-Type",
+        &format!(
+            "multipart/related; type=\"text/html\"; boundary=\"{}\"",
+            boundary
+        ),
+    )?;
+    write!(w, "\r\n")?;
+    write!(w, "--{}\r\n", boundary)?;
+    header(&mut w, "Content-Type", "text/html; charset=\"utf-8\"")?;
+    header(&mut w, "Content-Transfer-Encoding", "base64")?;
+    write!(w, "\r\n")?;
+    write_base64(&mut w, dom.to_string_with(mode).as_bytes())?;
+    for (index, part) in parts.into_inner().parts.iter().enumerate() {
+        write!(w, "--{}\r\n", boundary)?;
+        header(&mut w, "Content-Type", &part.media_type)?;
+        header(&mut w, "Content-Transfer-Encoding", "base64")?;
+        header(&mut w, "Content-ID", &format!("<{}>", cid(index)))?;
+        write!(w, "\r\n")?;
+        write_base64(&mut w, &part.data)?;
+    }
+    write!(w, "--{}--\r\n", boundary)?;
+    io::Write::flush(&mut w)
+}
+
+/// replace_urls replaces the urls in the attributes and style sheet of
+/// `node` with those `f` returns for them. `f` returns `None` to leave a url
+/// as it is.
+pub(crate) fn replace_urls<F>(node: &mut Node, f: &mut F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    if let Node::Tag {
+        name,
+        attributes,
+        children,
+    } = node
+    {
+        // Attributes are visited in order of name, so that urls are always
+        // met in the same order.
+        let mut attributes: Vec<_> = attributes.iter_mut().collect();
+        attributes.sort_by_key(|(key, _)| key.as_str());
+        for (key, value) in attributes {
+            match key.as_str() {
+                "srcset" => {
+                    let mut srcset = SrcSet::parse(value);
+                    for candidate in srcset.0.iter_mut() {
+                        if let Some(url) = f(&candidate.url) {
+                            candidate.url = url;
+                        }
+                    }
+                    *value = srcset.to_string();
+                }
+                "style" => *value = css::rewrite_urls(value, |url| Ok(f(url)))?,
+                _ => {
+                    if let Some(url) = f(value) {
+                        *value = url;
+                    }
+                }
+            }
+        }
+        if name.eq_ignore_ascii_case("style") {
+            for child in children.iter() {
+                if let Node::Text(text) = &mut *child.borrow_mut() {
+                    *text = css::rewrite_urls(text, |url| Ok(f(url)))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// header writes a header line, folding it between parameters so that no
+/// line is longer than 76 characters where that can be helped.
+fn header<W: io::Write>(w: &mut W, name: &str, value: &str) -> io::Result<()> {
+    let params: Vec<&str> = value.split("; ").collect();
+    let mut line = format!("{}:", name);
+    for (ii, param) in params.iter().enumerate() {
+        let separator = if ii + 1 < params.len() { ";" } else { "" };
+        if ii > 0 && line.len() + 1 + param.len() + separator.len() > LINE_LENGTH {
+            write!(w, "{}\r\n", line)?;
+            line = String::from("\t");
+        } else {
+            line.push(' ');
+        }
+        line.push_str(param);
+        line.push_str(separator);
+    }
+    write!(w, "{}\r\n", line)
+}
+
+/// write_base64 writes `data` base64 encoded, in lines of at most 76
+/// characters.
+fn write_base64<W: io::Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
+    let encoded = base64::encode(data);
+    for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+        w.write_all(line)?;
+        w.write_all(b"\r\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    /// Message is a parsed MHTML document.
+    struct Message {
+        headers: String,
+        parts: Vec<(String, Vec<u8>)>,
+    }
+
+    /// split splits MHTML into its headers and parts, checking the framing
+    /// on the way.
+    fn split(mhtml: &str) -> Message {
+        assert!(!mhtml.replace("\r\n", "").contains('\n'), "bare line feed");
+        let (headers, body) = mhtml.split_once("\r\n\r\n").expect("headers");
+        let boundary = headers
+            .split("boundary=\"")
+            .nth(1)
+            .and_then(|b| b.split('"').next())
+            .expect("boundary");
+        let close = format!("--{}--\r\n", boundary);
+        assert!(body.ends_with(&close), "closing boundary");
+        let body = body.strip_suffix(&close).unwrap();
+        let parts = body
+            .split(&format!("--{}\r\n", boundary))
+            .skip(1)
+            .map(|part| {
+                let (headers, content) = part.split_once("\r\n\r\n").expect("part headers");
+                for line in content.split_terminator("\r\n") {
+                    assert!(line.len() <= LINE_LENGTH, "long line: {}", line);
+                }
+                let content = base64::decode(&content.replace("\r\n", "")).expect("base64");
+                (headers.to_owned(), content)
+            })
+            .collect();
+        Message {
+            headers: headers.to_owned(),
+            parts,
+        }
+    }
+
+    #[test]
+    fn parts() {
+        let png = base64::encode(b"png");
+        let input = format!(
+            r#"<html><head><style>body {{ background: url("data:image/png;base64,{png}") }}</style></head><body><img src="data:image/png;base64,{png}" srcset="data:image/gif;base64,R0lG"/><a href="data:text/plain,plain">kept</a><p style="background:url(data:image/svg+xml;base64,PHN2Zy8+)">x</p></body></html>"#,
+            png = png
+        );
+        let dom = parse(&input);
+        let mut out = vec![];
+        write(&dom, SerializeMode::Html5, &mut out).unwrap();
+        let message = split(&String::from_utf8(out).unwrap());
+        assert!(message.headers.starts_with("MIME-Version: 1.0\r\n"));
+        assert!(message
+            .headers
+            .contains("Content-Type: multipart/related; type=\"text/html\";\r\n\tboundary="));
+        let headers: Vec<Vec<&str>> = message
+            .parts
+            .iter()
+            .map(|(headers, _)| headers.split("\r\n").collect())
+            .collect();
+        assert_eq!(4, message.parts.len(), "the page and three resources");
+        assert_eq!(
+            vec![
+                "Content-Type: text/html; charset=\"utf-8\"",
+                "Content-Transfer-Encoding: base64",
+            ],
+            headers[0]
+        );
+        let mut cids = vec![];
+        for (headers, (media_type, data)) in headers.iter().skip(1).zip(vec![
+            ("image/png", b"png".to_vec()),
+            ("image/gif", base64::decode("R0lG").unwrap()),
+            ("image/svg+xml", b"<svg/>".to_vec()),
+        ]) {
+            assert_eq!(format!("Content-Type: {}", media_type), headers[0]);
+            assert_eq!("Content-Transfer-Encoding: base64", headers[1]);
+            let cid = headers[2]
+                .strip_prefix("Content-ID: <")
+                .and_then(|cid| cid.strip_suffix('>'))
+                .expect("content id");
+            cids.push(cid.to_owned());
+            let part = message.parts.iter().find(|(h, _)| h.contains(cid)).unwrap();
+            assert_eq!(data, part.1);
+        }
+        let page = String::from_utf8(message.parts[0].1.clone()).unwrap();
+        assert!(!page.contains("base64"), "{}", page);
+        assert!(page.contains("data:text/plain,plain"), "{}", page);
+        assert_eq!(2, page.matches(&format!("cid:{}", cids[0])).count());
+        for cid in &cids[1..] {
+            assert_eq!(1, page.matches(&format!("cid:{}", cid)).count());
+        }
+    }
+
+    #[test]
+    fn folding() {
+        let tests = vec![
+            ("short", "Content-Type", "image/png", "Content-Type: image/png\r\n"),
+            (
+                "parameters that fit",
+                "Content-Type",
+                "text/html; charset=\"utf-8\"",
+                "Content-Type: text/html; charset=\"utf-8\"\r\n",
+            ),
+            (
+                "long parameters",
+                "Content-Type",
+                "multipart/related; type=\"text/html\"; boundary=\"----=_NextPart_0123456789abcdef\"",
+                "Content-Type: multipart/related; type=\"text/html\";\r\n\tboundary=\"----=_NextPart_0123456789abcdef\"\r\n",
+            ),
+        ];
+        for (desc, name, value, want) in tests {
+            let mut out = vec![];
+            header(&mut out, name, value).unwrap();
+            assert_eq!(want, String::from_utf8(out).unwrap(), "{}", desc);
+        }
+    }
+}
@@ -0,0 +1,462 @@
+use crate::base::Base;
+use crate::encoding;
+use crate::inline::{is_local, Options, Warning};
+use crate::resource::Resources;
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Bundle the module graph of an inline module script whose links resolve
+/// against `base`. Each relative specifier of a static `import` or
+/// `export ... from` is replaced by a data url of the module it refers to,
+/// bundled in turn, so that the graph no longer depends on where the document
+/// lives. The path of each module bundled is added to `bundled`.
+pub(crate) fn bundle(
+    js: &str,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+    bundled: &mut HashSet<PathBuf>,
+    warnings: &mut Vec<Warning>,
+) -> Result<String, Box<dyn Error>> {
+    bundle_module(js, base, options, resources, &mut vec![], bundled, warnings)
+}
+
+/// Bundle the module at `path`, as `bundle` does, where `base` is the base of
+/// the document linking to it.
+pub(crate) fn bundle_file(
+    path: &Path,
+    base: &Base,
+    charset: Option<&str>,
+    options: &Options,
+    resources: &dyn Resources,
+    bundled: &mut HashSet<PathBuf>,
+    warnings: &mut Vec<Warning>,
+) -> Result<String, Box<dyn Error>> {
+    let content = encoding::read_text(path, charset, options, resources)?;
+    bundled.insert(path.to_owned());
+    bundle_module(
+        &content,
+        &base.of(path),
+        options,
+        resources,
+        &mut vec![path.to_owned()],
+        bundled,
+        warnings,
+    )
+}
+
+/// bundle_module bundles `js`, where `ancestors` are the modules currently
+/// being bundled. A module importing one of its ancestors is a cycle, which
+/// data urls can't express, so the import is left as it is with a warning.
+/// So are imports deeper than `options.max_depth`.
+fn bundle_module(
+    js: &str,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+    ancestors: &mut Vec<PathBuf>,
+    bundled: &mut HashSet<PathBuf>,
+    warnings: &mut Vec<Warning>,
+) -> Result<String, Box<dyn Error>> {
+    let mut out = String::with_capacity(js.len());
+    let mut copied = 0;
+    for reference in references(js) {
+        let (start, end) = match reference {
+            Reference::Static(start, end) => (start, end),
+            Reference::Dynamic(expression) => {
+                warnings.push(warning(
+                    expression,
+                    "dynamic imports are left as they are".into(),
+                ));
+                continue;
+            }
+        };
+        let specifier = &js[start..end];
+        if !is_relative(specifier) {
+            if is_local(specifier) {
+                warnings.push(warning(
+                    specifier.into(),
+                    "bare specifiers need an import map".into(),
+                ));
+            }
+            continue;
+        }
+        let path = base.resolve(specifier)?;
+        if ancestors.contains(&path) {
+            warnings.push(warning(
+                specifier.into(),
+                format!("import cycle: {}", chain(ancestors, specifier)),
+            ));
+            continue;
+        }
+        if ancestors.len() >= options.max_depth {
+            base.skip(specifier);
+            warnings.push(warning(
+                specifier.into(),
+                format!(
+                    "exceeds the maximum depth of {}: {}",
+                    options.max_depth,
+                    chain(ancestors, specifier)
+                ),
+            ));
+            continue;
+        }
+        let content = encoding::read_text(&path, None, options, resources)?;
+        bundled.insert(path.clone());
+        ancestors.push(path.clone());
+        let module = bundle_module(
+            &content,
+            &base.of(&path),
+            options,
+            resources,
+            ancestors,
+            bundled,
+            warnings,
+        );
+        ancestors.pop();
+        out.push_str(&js[copied..start]);
+        out.push_str("data:text/javascript;base64,");
+        out.push_str(&base64::encode(module?.as_bytes()));
+        copied = end;
+    }
+    out.push_str(&js[copied..]);
+    Ok(out)
+}
+
+/// is_relative reports whether `specifier` is a relative url, the only kind
+/// of module specifier that resolves without an import map besides absolute
+/// urls.
+fn is_relative(specifier: &str) -> bool {
+    specifier.starts_with("./")
+        || specifier.starts_with("../")
+        || (specifier.starts_with('/') && !specifier.starts_with("//"))
+}
+
+fn warning(link: String, reason: String) -> Warning {
+    Warning {
+        element: "script".into(),
+        link,
+        reason,
+    }
+}
+
+/// chain names the modules leading to `specifier`.
+fn chain(ancestors: &[PathBuf], specifier: &str) -> String {
+    let mut chain: Vec<String> = ancestors.iter().map(|p| p.display().to_string()).collect();
+    chain.push(specifier.to_owned());
+    chain.join(" -> ")
+}
+
+/// Reference is a reference from a module to another module.
+#[derive(Debug, PartialEq)]
+enum Reference {
+    /// The byte range of the specifier of a static import or export, inside
+    /// its quotes.
+    Static(usize, usize),
+    /// The argument of a dynamic `import()`.
+    Dynamic(String),
+}
+
+/// references returns the references `js` makes to other modules, in order.
+/// Comments, strings and template literals are skipped so that what they
+/// contain isn't mistaken for an import. Regular expression literals aren't
+/// recognised.
+fn references(js: &str) -> Vec<Reference> {
+    let b = js.as_bytes();
+    let mut references = vec![];
+    let mut ii = 0;
+    while ii < b.len() {
+        if let Some(end) = skip_comment(b, ii) {
+            ii = end;
+        } else if matches!(b[ii], b'"' | b'\'' | b'`') {
+            ii = string_end(b, ii);
+        } else if is_keyword(b, ii, "import") {
+            let jj = skip_trivia(b, ii + "import".len());
+            match b.get(jj) {
+                Some(b'(') => {
+                    let end = b[jj..]
+                        .iter()
+                        .position(|c| *c == b')')
+                        .map(|kk| jj + kk)
+                        .unwrap_or(b.len());
+                    references.push(Reference::Dynamic(js[jj + 1..end].trim().to_owned()));
+                }
+                Some(b'"') | Some(b'\'') => references.extend(specifier(b, jj)),
+                _ => references.extend(from_clause(b, jj)),
+            }
+            ii = jj;
+        } else if is_keyword(b, ii, "export") {
+            let jj = skip_trivia(b, ii + "export".len());
+            if matches!(b.get(jj), Some(b'{') | Some(b'*')) {
+                references.extend(from_clause(b, jj));
+            }
+            ii = jj;
+        } else {
+            ii += 1;
+        }
+    }
+    references
+}
+
+/// from_clause returns the specifier of the `from "specifier"` ending the
+/// import or export clause that starts at `start`, if it has one.
+fn from_clause(b: &[u8], start: usize) -> Option<Reference> {
+    let mut ii = start;
+    let mut word = &b[0..0];
+    loop {
+        ii = skip_trivia(b, ii);
+        match b.get(ii)? {
+            b'"' | b'\'' if word == b"from" => return specifier(b, ii),
+            b'{' | b'}' | b',' | b'*' => {
+                word = &b[0..0];
+                ii += 1;
+            }
+            c if is_identifier(*c) => {
+                let end = b[ii..]
+                    .iter()
+                    .position(|c| !is_identifier(*c))
+                    .map(|kk| ii + kk)
+                    .unwrap_or(b.len());
+                word = &b[ii..end];
+                ii = end;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// specifier returns the specifier in the string starting at `ii`, unless the
+/// string is never closed.
+fn specifier(b: &[u8], ii: usize) -> Option<Reference> {
+    let end = string_end(b, ii);
+    match b.get(end - 1) {
+        Some(c) if end > ii + 1 && *c == b[ii] => Some(Reference::Static(ii + 1, end - 1)),
+        _ => None,
+    }
+}
+
+/// is_keyword reports whether `keyword` starts at `ii` as a word of its own,
+/// rather than as part of an identifier or a property access.
+fn is_keyword(b: &[u8], ii: usize, keyword: &str) -> bool {
+    let end = ii + keyword.len();
+    b[ii..].starts_with(keyword.as_bytes())
+        && (ii == 0 || !is_identifier(b[ii - 1]) && b[ii - 1] != b'.')
+        && !b.get(end).is_some_and(|c| is_identifier(*c))
+}
+
+fn is_identifier(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c >= 0x80
+}
+
+/// skip_comment returns the end of the comment starting at `ii`, if one does.
+pub(crate) fn skip_comment(b: &[u8], ii: usize) -> Option<usize> {
+    let rest = &b[ii..];
+    let end = if rest.starts_with(b"//") {
+        rest.iter().position(|c| *c == b'\n').unwrap_or(rest.len())
+    } else if rest.starts_with(b"/*") {
+        rest.windows(2)
+            .skip(2)
+            .position(|w| w == b"*/")
+            .map(|kk| kk + 4)
+            .unwrap_or(rest.len())
+    } else {
+        return None;
+    };
+    Some(ii + end)
+}
+
+/// skip_trivia returns the offset of the first byte from `ii` that isn't
+/// whitespace or part of a comment.
+fn skip_trivia(b: &[u8], mut ii: usize) -> usize {
+    loop {
+        while b.get(ii).is_some_and(u8::is_ascii_whitespace) {
+            ii += 1;
+        }
+        match skip_comment(b, ii) {
+            Some(end) if ii < b.len() => ii = end,
+            _ => return ii,
+        }
+    }
+}
+
+/// string_end returns the offset just past the closing quote of the string or
+/// template literal starting at `ii`.
+pub(crate) fn string_end(b: &[u8], ii: usize) -> usize {
+    let quote = b[ii];
+    let mut jj = ii + 1;
+    while jj < b.len() {
+        match b[jj] {
+            b'\\' => jj += 2,
+            c if c == quote => return jj + 1,
+            _ => jj += 1,
+        }
+    }
+    b.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::Filesystem;
+    use pretty_assertions::assert_eq;
+
+    fn specifiers(js: &str) -> Vec<&str> {
+        references(js)
+            .into_iter()
+            .map(|r| match r {
+                Reference::Static(start, end) => &js[start..end],
+                Reference::Dynamic(_) => "<dynamic>",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scan() {
+        let tests = vec![
+            ("side effect", r#"import "./a.js";"#, vec!["./a.js"]),
+            ("default", "import a from './a.js'", vec!["./a.js"]),
+            (
+                "named",
+                r#"import { a, b as c } from "./a.js";"#,
+                vec!["./a.js"],
+            ),
+            (
+                "namespace",
+                r#"import * as a from "./a.js";"#,
+                vec!["./a.js"],
+            ),
+            (
+                "default and named",
+                r#"import a, { b } from "./a.js";"#,
+                vec!["./a.js"],
+            ),
+            ("no space", r#"import{a}from"./a.js""#, vec!["./a.js"]),
+            (
+                "multiple lines",
+                "import {\n  a, // first\n  b,\n} from \"./a.js\";",
+                vec!["./a.js"],
+            ),
+            (
+                "re-export",
+                r#"export { a } from "./a.js";"#,
+                vec!["./a.js"],
+            ),
+            (
+                "re-export all",
+                r#"export * from "./a.js";"#,
+                vec!["./a.js"],
+            ),
+            ("export", "export { a };\nexport const b = 'x';", vec![]),
+            ("dynamic", r#"import("./a.js").then(f)"#, vec!["<dynamic>"]),
+            ("import.meta", "console.log(import.meta.url)", vec![]),
+            (
+                "in a string",
+                r#"let s = "import a from './a.js'";"#,
+                vec![],
+            ),
+            ("in a comment", "// import a from './a.js'\n", vec![]),
+            ("in a block comment", "/* import './a.js' */", vec![]),
+            ("in a template", "`${x} import './a.js'`", vec![]),
+            ("property", r#"x.import("./a.js")"#, vec![]),
+            ("identifier", r#"reimport("./a.js")"#, vec![]),
+            ("unterminated", r#"import "./a.js"#, vec![]),
+            (
+                "several",
+                "import a from './a.js';\nimport b from '../b.js';",
+                vec!["./a.js", "../b.js"],
+            ),
+        ];
+        for (desc, js, want) in tests {
+            assert_eq!(want, specifiers(js), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn graph() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/modules");
+        let mut warnings = vec![];
+        let mut bundled = HashSet::new();
+        let main = bundle_file(
+            &root.join("main.js"),
+            &Base::new(&root),
+            None,
+            &Options::default(),
+            &Filesystem,
+            &mut bundled,
+            &mut warnings,
+        )
+        .unwrap();
+        // Every relative specifier in the graph is replaced, all the way down.
+        let mut modules = vec![main];
+        let mut seen = 0;
+        while let Some(module) = modules.pop() {
+            seen += 1;
+            for reference in references(&module) {
+                if let Reference::Static(start, end) = reference {
+                    let url = &module[start..end];
+                    let data = url
+                        .strip_prefix("data:text/javascript;base64,")
+                        .unwrap_or_else(|| panic!("{} not bundled", url));
+                    modules.push(String::from_utf8(base64::decode(data).unwrap()).unwrap());
+                }
+            }
+        }
+        assert_eq!(4, seen);
+        // One of them is imported twice.
+        assert_eq!(3, bundled.len(), "{:?}", bundled);
+        assert_eq!(Vec::<Warning>::new(), warnings);
+    }
+
+    #[test]
+    fn left_alone() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/modules");
+        let tests = vec![
+            (
+                "bare specifier",
+                "import { html } from 'lit';",
+                "bare specifiers need an import map",
+            ),
+            (
+                "dynamic import",
+                "import(`./lang/${lang}.js`);",
+                "dynamic imports are left as they are",
+            ),
+            ("cycle", "import './cycle/a.js';", "import cycle: "),
+        ];
+        for (desc, js, reason) in tests {
+            let mut warnings = vec![];
+            let got = bundle(
+                js,
+                &Base::new(&root),
+                &Options::default(),
+                &Filesystem,
+                &mut HashSet::new(),
+                &mut warnings,
+            )
+            .unwrap();
+            if desc != "cycle" {
+                assert_eq!(js, got, "{}", desc);
+            }
+            assert_eq!(1, warnings.len(), "{}: {:?}", desc, warnings);
+            assert!(
+                warnings[0].reason.starts_with(reason),
+                "{}: {:?}",
+                desc,
+                warnings
+            );
+        }
+        let mut warnings = vec![];
+        let got = bundle(
+            "import 'https://example.com/a.js';",
+            &Base::new(&root),
+            &Options::default(),
+            &Filesystem,
+            &mut HashSet::new(),
+            &mut warnings,
+        )
+        .unwrap();
+        assert_eq!("import 'https://example.com/a.js';", got);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+}
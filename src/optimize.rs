@@ -0,0 +1,227 @@
+//! Re-encoding of images before they are embedded, since base64 makes them a
+//! third larger again. It needs the `optimize-images` feature; without it
+//! images are embedded as they are.
+
+use crate::resource::Resources;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// ImageOptions control how PNGs and JPEGs are re-encoded. Other images,
+/// including animated PNGs, are embedded as they are, as are images that
+/// re-encoding doesn't shrink.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageOptions {
+    /// Quality JPEGs are re-encoded at, from 1 to 100. PNGs are re-encoded
+    /// losslessly at the best compression.
+    pub quality: u8,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions { quality: 80 }
+    }
+}
+
+/// Optimization is an image that was considered for re-encoding, and its size
+/// before and after. The sizes are the same if it was embedded as it was.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Optimization {
+    pub path: String,
+    pub original: u64,
+    pub embedded: u64,
+}
+
+impl fmt::Display for Optimization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} bytes, embedded as {} bytes",
+            self.path, self.original, self.embedded
+        )
+    }
+}
+
+/// Optimizer re-encodes the images read from `resources`, noting the sizes
+/// of each.
+pub(crate) struct Optimizer<'a> {
+    resources: &'a dyn Resources,
+    options: &'a ImageOptions,
+    pub(crate) optimized: RefCell<Vec<Optimization>>,
+}
+
+impl<'a> Optimizer<'a> {
+    pub(crate) fn new(resources: &'a dyn Resources, options: &'a ImageOptions) -> Self {
+        Optimizer {
+            resources,
+            options,
+            optimized: RefCell::new(vec![]),
+        }
+    }
+}
+
+impl Resources for Optimizer<'_> {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+        let content = self.resources.read(path)?;
+        let encoded = match reencode(&content, self.options) {
+            Some(encoded) => encoded,
+            None => return Ok(content),
+        };
+        let original = content.len() as u64;
+        let content = match encoded.len() < content.len() {
+            true => encoded,
+            false => content,
+        };
+        self.optimized.borrow_mut().push(Optimization {
+            path: path.to_string_lossy().into_owned(),
+            original,
+            embedded: content.len() as u64,
+        });
+        Ok(content)
+    }
+
+    /// size is the size of the resource as it is, so that thresholds don't
+    /// re-encode images to find out.
+    fn size(&self, path: &Path) -> Result<u64, Box<dyn Error>> {
+        self.resources.size(path)
+    }
+
+    fn media_type(&self, path: &Path) -> Option<String> {
+        self.resources.media_type(path)
+    }
+}
+
+/// reencode returns `content` re-encoded if it's a still PNG or a JPEG, or
+/// `None` if it's anything else or can't be decoded. JPEGs are rotated as
+/// their EXIF orientation says, since the metadata isn't carried over.
+#[cfg(feature = "optimize-images")]
+fn reencode(content: &[u8], options: &ImageOptions) -> Option<Vec<u8>> {
+    use image::codecs::jpeg::{JpegDecoder, JpegEncoder};
+    use image::codecs::png::{CompressionType, FilterType, PngDecoder, PngEncoder};
+    use image::{DynamicImage, ImageDecoder, ImageFormat};
+    use std::io::Cursor;
+
+    let mut encoded = vec![];
+    match image::guess_format(content).ok()? {
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(Cursor::new(content)).ok()?;
+            if decoder.is_apng().ok()? {
+                return None;
+            }
+            let image = DynamicImage::from_decoder(decoder).ok()?;
+            let encoder = PngEncoder::new_with_quality(
+                &mut encoded,
+                CompressionType::Best,
+                FilterType::Adaptive,
+            );
+            image.write_with_encoder(encoder).ok()?;
+        }
+        ImageFormat::Jpeg => {
+            let mut decoder = JpegDecoder::new(Cursor::new(content)).ok()?;
+            let orientation = decoder.orientation().ok()?;
+            let mut image = DynamicImage::from_decoder(decoder).ok()?;
+            image.apply_orientation(orientation);
+            let encoder = JpegEncoder::new_with_quality(&mut encoded, options.quality);
+            image.write_with_encoder(encoder).ok()?;
+        }
+        _ => return None,
+    }
+    Some(encoded)
+}
+
+#[cfg(not(feature = "optimize-images"))]
+fn reencode(_: &[u8], _: &ImageOptions) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(all(test, feature = "optimize-images"))]
+mod tests {
+    use super::*;
+    use crate::resource::Memory;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+    use image::{DynamicImage, ImageEncoder, RgbImage};
+    use pretty_assertions::assert_eq;
+
+    /// photo is a noisy image, which JPEG at full quality keeps every bit of.
+    fn photo(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            let n = (x * 7919 + y * 104729) ^ (x * y);
+            image::Rgb([n as u8, (n >> 3) as u8, (x + y) as u8])
+        })
+    }
+
+    fn jpeg(image: &RgbImage, exif: Option<Vec<u8>>) -> Vec<u8> {
+        let mut encoded = vec![];
+        let mut encoder = JpegEncoder::new_with_quality(&mut encoded, 100);
+        if let Some(exif) = exif {
+            encoder.set_exif_metadata(exif).unwrap();
+        }
+        encoder
+            .write_image(
+                image.as_raw(),
+                image.width(),
+                image.height(),
+                image::ExtendedColorType::Rgb8,
+            )
+            .unwrap();
+        encoded
+    }
+
+    /// exif is EXIF metadata that holds only an orientation.
+    fn exif(orientation: u8) -> Vec<u8> {
+        let mut exif =
+            b"MM\x00\x2a\x00\x00\x00\x08\x00\x01\x01\x12\x00\x03\x00\x00\x00\x01\x00".to_vec();
+        exif.extend([orientation, 0, 0, 0, 0, 0, 0]);
+        exif
+    }
+
+    #[test]
+    fn images() {
+        let flat = RgbImage::from_pixel(64, 64, image::Rgb([200, 10, 10]));
+        let mut png = vec![];
+        PngEncoder::new_with_quality(&mut png, CompressionType::Fast, FilterType::NoFilter)
+            .write_image(flat.as_raw(), 64, 64, image::ExtendedColorType::Rgb8)
+            .unwrap();
+        let photo = jpeg(&photo(64, 32), None);
+        let mut small = vec![];
+        PngEncoder::new_with_quality(&mut small, CompressionType::Best, FilterType::Adaptive)
+            .write_image(&[0, 0, 0], 1, 1, image::ExtendedColorType::Rgb8)
+            .unwrap();
+        let resources = Memory::new()
+            .with("flat.png", png.clone())
+            .with("photo.jpg", photo.clone())
+            .with("small.png", small.clone())
+            .with("anim.gif", b"GIF89a".to_vec())
+            .with("app.css", "body{}");
+        let options = ImageOptions { quality: 50 };
+        let optimizer = Optimizer::new(&resources, &options);
+        let read = |path: &str| optimizer.read(Path::new(path)).unwrap();
+        assert!(read("flat.png").len() < png.len(), "png is recompressed");
+        assert!(read("photo.jpg").len() < photo.len(), "jpeg is re-encoded");
+        assert_eq!(small, read("small.png"), "kept when it doesn't shrink");
+        assert_eq!(b"GIF89a".to_vec(), read("anim.gif"), "gifs pass through");
+        assert_eq!(b"body{}".to_vec(), read("app.css"));
+        let size = optimizer.size(Path::new("flat.png")).unwrap();
+        assert_eq!(png.len() as u64, size, "size is of the original");
+        let optimized = optimizer.optimized.into_inner();
+        let paths: Vec<&str> = optimized.iter().map(|o| o.path.as_str()).collect();
+        assert_eq!(vec!["flat.png", "photo.jpg", "small.png"], paths);
+        assert_eq!(png.len() as u64, optimized[0].original);
+        assert!(optimized[0].embedded < optimized[0].original);
+        assert_eq!(optimized[2].original, optimized[2].embedded);
+    }
+
+    #[test]
+    fn orientation() {
+        // Orientation 6 is rotated 90 degrees clockwise.
+        let rotated = jpeg(&photo(64, 32), Some(exif(6)));
+        let resources = Memory::new().with("photo.jpg", rotated);
+        let options = ImageOptions { quality: 50 };
+        let optimizer = Optimizer::new(&resources, &options);
+        let content = optimizer.read(Path::new("photo.jpg")).unwrap();
+        let image: DynamicImage = image::load_from_memory(&content).unwrap();
+        assert_eq!((32, 64), (image.width(), image.height()));
+    }
+}
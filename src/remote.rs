@@ -0,0 +1,526 @@
+//! Fetching of pages and the resources they link to over HTTP. It needs the
+//! `remote` feature.
+
+use crate::base::{origin, url_dir, Base};
+use crate::cache::{self, Cache, Entry};
+use crate::fetch::FetchOptions;
+use crate::inline::{decode, inline_dom, serialize, Options, Report};
+use crate::resource::Resources;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// REDIRECTS is how many redirects are followed for a request.
+const REDIRECTS: usize = 10;
+
+/// Http reads resources over HTTP, where the path of each is its url, as
+/// links resolve against the url of a page. Responses are kept, so that a
+/// resource linked more than once is only fetched once, and cached on disk
+/// between runs if `FetchOptions::cache_dir` is set.
+#[derive(Debug)]
+pub struct Http {
+    options: FetchOptions,
+    agent: ureq::Agent,
+    cache: Option<Cache>,
+    responses: RefCell<HashMap<PathBuf, Response>>,
+}
+
+#[derive(Debug, Clone)]
+struct Response {
+    /// The url the response came from, once redirects are followed.
+    url: String,
+    media_type: String,
+    content: Vec<u8>,
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        Http::with_options(FetchOptions::default())
+    }
+}
+
+impl Http {
+    pub fn new() -> Self {
+        Http::default()
+    }
+
+    /// with_options returns an `Http` that sends the headers, cookies and
+    /// user agent `options` holds, and caches responses as it says.
+    pub fn with_options(options: FetchOptions) -> Self {
+        Http {
+            cache: options.cache_dir.as_deref().map(Cache::new),
+            options,
+            // Redirects are followed here, so that each request gets the
+            // headers for where it goes.
+            agent: ureq::AgentBuilder::new().redirects(0).build(),
+            responses: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// get returns the response to a request for `path`. With a cache, a
+    /// fresh response comes from it, and a stale one is revalidated.
+    fn get(&self, path: &Path) -> Result<Response, Box<dyn Error>> {
+        if let Some(response) = self.responses.borrow().get(path) {
+            return Ok(response.clone());
+        }
+        let requested = path.to_string_lossy();
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(&requested));
+        let now = cache::now();
+        let response = match cached {
+            Some(entry) if self.options.offline || entry.is_fresh(now) => Response::from(entry),
+            None if self.options.offline => {
+                return Err(format!("{}: not cached, and fetching is off", requested).into())
+            }
+            cached => self.fetch(&requested, cached, now)?,
+        };
+        self.responses
+            .borrow_mut()
+            .insert(path.to_owned(), response.clone());
+        Ok(response)
+    }
+
+    /// fetch requests `requested`, following redirects, and caches the
+    /// response. A `cached` response with an ETag or Last-Modified date is
+    /// revalidated instead, and kept if the server says it hasn't changed.
+    fn fetch(
+        &self,
+        requested: &str,
+        cached: Option<Entry>,
+        now: u64,
+    ) -> Result<Response, Box<dyn Error>> {
+        let cached = cached.filter(|entry| entry.etag.is_some() || entry.last_modified.is_some());
+        let mut url = cached
+            .as_ref()
+            .map_or_else(|| requested.to_owned(), |entry| entry.url.clone());
+        let mut redirects = 0;
+        let response = loop {
+            let mut request = self.agent.get(&url);
+            for (name, value) in self.options.headers(&url) {
+                request = request.set(&name, &value);
+            }
+            if let Some(entry) = cached.as_ref().filter(|_| redirects == 0) {
+                if let Some(etag) = &entry.etag {
+                    request = request.set("If-None-Match", etag);
+                }
+                if let Some(last_modified) = &entry.last_modified {
+                    request = request.set("If-Modified-Since", last_modified);
+                }
+            }
+            let response = match request.call() {
+                Ok(response) => response,
+                Err(ureq::Error::Status(code, response)) => {
+                    return Err(
+                        format!("{}: {} {}", requested, code, response.status_text()).into(),
+                    )
+                }
+                Err(err) => return Err(format!("{}: {}", requested, err).into()),
+            };
+            let location = match response.header("Location") {
+                Some(location) if (300..400).contains(&response.status()) => location,
+                _ => break response,
+            };
+            redirects += 1;
+            if redirects > REDIRECTS {
+                return Err(format!("{}: too many redirects", requested).into());
+            }
+            let next = Base::new(Path::new(url_dir(&url))).resolve(location)?;
+            url = next.to_string_lossy().into_owned();
+        };
+        let last_modified = response.header("Last-Modified").map(str::to_owned);
+        let max_age = cache::max_age(
+            response.header("Cache-Control"),
+            last_modified.as_deref(),
+            now,
+        );
+        let entry = match (response.status(), cached) {
+            (304, Some(entry)) => Entry {
+                fetched: now,
+                max_age: max_age.unwrap_or(0),
+                ..entry
+            },
+            _ => {
+                let etag = response.header("ETag").map(str::to_owned);
+                let media_type = response.content_type().to_owned();
+                let mut content = vec![];
+                response
+                    .into_reader()
+                    .read_to_end(&mut content)
+                    .map_err(|e| format!("{}: {}", requested, e))?;
+                Entry {
+                    url,
+                    media_type,
+                    etag,
+                    last_modified,
+                    fetched: now,
+                    max_age: max_age.unwrap_or(0),
+                    content,
+                }
+            }
+        };
+        if let (Some(cache), Some(_)) = (&self.cache, max_age) {
+            cache.put(requested, &entry)?;
+        }
+        Ok(Response::from(entry))
+    }
+}
+
+impl From<Entry> for Response {
+    fn from(entry: Entry) -> Self {
+        Response {
+            url: entry.url,
+            media_type: entry.media_type,
+            content: entry.content,
+        }
+    }
+}
+
+impl Resources for Http {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.get(path).map(|response| response.content)
+    }
+
+    fn media_type(&self, path: &Path) -> Option<String> {
+        self.responses
+            .borrow()
+            .get(path)
+            .map(|response| response.media_type.clone())
+    }
+}
+
+/// inline_url fetches the html page at `url` and inlines it as
+/// `inline_with` does, fetching the resources it links to as well, with the
+/// headers and cookies `options.fetch` holds. They're only sent to the
+/// origin of `url` unless `options.fetch` names another. Links
+/// resolve against the url the page came from, once redirects are followed,
+/// or the `<base href>` within it. It's an error if `url` isn't an html page.
+pub fn inline_url(url: &str, options: &Options) -> Result<(String, Report), Box<dyn Error>> {
+    let mut fetch = options.fetch.clone();
+    if fetch.credentials_origin.is_none() {
+        fetch.credentials_origin = Some(origin(url).to_owned());
+    }
+    let http = Http::with_options(fetch);
+    let page = http.get(Path::new(url))?;
+    if !matches!(
+        page.media_type.as_str(),
+        "text/html" | "application/xhtml+xml"
+    ) {
+        return Err(format!("{}: not an html page, but {}", url, page.media_type).into());
+    }
+    let (input, options) = decode(&page.content, options);
+    let base = Path::new(url_dir(&page.url));
+    let (dom, report) = inline_dom(&input, base, &options, &http)?;
+    Ok(serialize(&dom, options.format, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::Cookie;
+    use crate::html::Dom;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Server is a server of the files of the `remote` fixture, on a port of
+    /// its own, which notes the headers of each request it gets.
+    struct Server {
+        host: String,
+        requests: Requests,
+    }
+
+    /// Requests are the paths requested, with the headers of each request.
+    type Requests = Arc<Mutex<Vec<(String, Vec<String>)>>>;
+
+    impl Server {
+        /// headers returns the headers of the requests for `path`.
+        fn headers(&self, path: &str) -> Vec<Vec<String>> {
+            let requests = self.requests.lock().unwrap();
+            requests
+                .iter()
+                .filter(|(p, _)| p == path)
+                .map(|(_, headers)| headers.clone())
+                .collect()
+        }
+    }
+
+    /// serve starts a server. Pages have `{host}` replaced by its address,
+    /// and `{other}` by `other`, and requests for `/moved/...` are redirected
+    /// to `other`. Requests that already have a file, going by its ETag, are
+    /// answered with 304 Not Modified.
+    fn serve(other: &str) -> Server {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join("remote");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let server = Server {
+            host: host.clone(),
+            requests: requests.clone(),
+        };
+        let other = other.to_owned();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut headers = vec![];
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    headers.push(header.trim().to_owned());
+                }
+                let path = request.split(' ').nth(1).unwrap_or("/").to_owned();
+                requests.lock().unwrap().push((path.clone(), headers));
+                if let Some(rest) = path.strip_prefix("/moved/") {
+                    write!(
+                        stream,
+                        "HTTP/1.1 302 Found\r\nLocation: http://{}/{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        other, rest
+                    )
+                    .unwrap();
+                    continue;
+                }
+                let file = root.join(path.trim_start_matches('/'));
+                let (status, media_type, body) = match fs::read(&file) {
+                    Ok(body) => {
+                        let media_type = mime_guess::from_path(&file)
+                            .first_or_octet_stream()
+                            .to_string();
+                        let body = match media_type.as_str() {
+                            "text/html" => String::from_utf8(body)
+                                .unwrap()
+                                .replace("{host}", &host)
+                                .replace("{other}", &other)
+                                .into_bytes(),
+                            _ => body,
+                        };
+                        ("200 OK", media_type, body)
+                    }
+                    Err(_) => ("404 Not Found", "text/plain".into(), b"not found".to_vec()),
+                };
+                // Files are tagged by their length, and stylesheets are fresh
+                // for an hour.
+                let etag = format!("\"{}\"", body.len());
+                let mut caching = format!("ETag: {}\r\n", etag);
+                if media_type == "text/css" {
+                    caching.push_str("Cache-Control: max-age=3600\r\n");
+                }
+                let unchanged = format!("If-None-Match: {}", etag);
+                if status == "200 OK"
+                    && requests
+                        .lock()
+                        .unwrap()
+                        .last()
+                        .unwrap()
+                        .1
+                        .contains(&unchanged)
+                {
+                    write!(
+                        stream,
+                        "HTTP/1.1 304 Not Modified\r\n{}Connection: close\r\n\r\n",
+                        caching
+                    )
+                    .unwrap();
+                    continue;
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+                    status,
+                    media_type,
+                    body.len(),
+                    caching
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        server
+    }
+
+    #[test]
+    fn snapshot() {
+        let server = serve("");
+        let url = format!("http://{}/pages/index.html", server.host);
+        let (got, report) = inline_url(&url, &Options::default()).unwrap();
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        let logo = format!(
+            "data:image/png;base64,{}",
+            base64::encode(&fs::read("tests/fixtures/remote/site/img/logo.png").unwrap())
+        );
+        let want = format!(
+            r#"<!DOCTYPE html>
+<html>
+  <head>
+    <base href="/site/">
+    <style>body {{ background: url({logo}) }}
+</style>
+  </head>
+  <body>
+    <img src="{logo}" alt="root relative">
+    <img src="{logo}" alt="protocol relative">
+    <a href="page.html">links to other pages are left as they are</a>
+  </body>
+</html>
+"#,
+            logo = logo
+        );
+        assert_eq!(want.parse::<Dom>().unwrap(), got.parse::<Dom>().unwrap());
+    }
+
+    #[test]
+    fn errors() {
+        let host = serve("").host;
+        let tests = vec![
+            (
+                "not html",
+                "site/data.json",
+                "not an html page, but application/json",
+            ),
+            ("missing", "missing.html", "404 Not Found"),
+        ];
+        for (desc, path, want) in tests {
+            let url = format!("http://{}/{}", host, path);
+            let err = inline_url(&url, &Options::default()).unwrap_err();
+            assert_eq!(format!("{}: {}", url, want), err.to_string(), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn credentials() {
+        let b = serve("");
+        let a = serve(&b.host);
+        let cookies = format!(
+            "127.0.0.1\tFALSE\t/\tFALSE\t0\tsession\ts3cr3t\n{}",
+            "127.0.0.1\tFALSE\t/pages\tFALSE\t0\tpages\tonly\n"
+        );
+        // The page's origin is the one credentials are kept to by default.
+        let fetch = FetchOptions {
+            headers: vec![("Authorization".into(), "Bearer t0ken".into())],
+            user_agent: Some("snapshot/1.0".into()),
+            cookies: Cookie::parse_file(&cookies).unwrap(),
+            ..FetchOptions::default()
+        };
+        let options = Options {
+            fetch: fetch.clone(),
+            ..Options::default()
+        };
+        let url = format!("http://{}/pages/third-party.html", a.host);
+        inline_url(&url, &options).unwrap();
+        let credentials = |headers: &[String]| {
+            headers
+                .iter()
+                .filter(|h| h.starts_with("Authorization:") || h.starts_with("Cookie:"))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let page = &a.headers("/pages/third-party.html")[0];
+        assert_eq!(
+            vec![
+                "Authorization: Bearer t0ken",
+                "Cookie: session=s3cr3t; pages=only"
+            ],
+            credentials(page)
+        );
+        assert!(page.contains(&"User-Agent: snapshot/1.0".to_owned()));
+        let image = &a.headers("/site/img/logo.png")[0];
+        assert_eq!(
+            vec!["Authorization: Bearer t0ken", "Cookie: session=s3cr3t"],
+            credentials(image),
+            "cookies are sent to their paths"
+        );
+        let third_party = b.headers("/site/img/logo.png");
+        assert_eq!(2, third_party.len(), "linked, and redirected to");
+        for headers in &third_party {
+            assert!(credentials(headers).is_empty(), "leaked: {:?}", headers);
+            assert!(headers.contains(&"User-Agent: snapshot/1.0".to_owned()));
+        }
+        // Another origin can be named instead, which the page's isn't.
+        let options = Options {
+            fetch: FetchOptions {
+                credentials_origin: Some(format!("http://{}/", b.host)),
+                ..fetch.clone()
+            },
+            ..Options::default()
+        };
+        inline_url(&url, &options).unwrap();
+        assert!(credentials(&a.headers("/pages/third-party.html")[1]).is_empty());
+        let third_party = b.headers("/site/img/logo.png");
+        assert!(third_party[2].contains(&"Authorization: Bearer t0ken".to_owned()));
+        // Sending them everywhere is asked for.
+        let options = Options {
+            fetch: FetchOptions {
+                credentials_everywhere: true,
+                ..fetch
+            },
+            ..Options::default()
+        };
+        inline_url(&url, &options).unwrap();
+        let third_party = b.headers("/site/img/logo.png");
+        assert!(third_party[4].contains(&"Authorization: Bearer t0ken".to_owned()));
+        assert!(!credentials(&a.headers("/pages/third-party.html")[2]).is_empty());
+    }
+
+    #[test]
+    fn cache() {
+        let server = serve("");
+        let dir = tempfile::tempdir().unwrap();
+        let fetch = FetchOptions {
+            cache_dir: Some(dir.path().join("cache")),
+            ..FetchOptions::default()
+        };
+        let options = Options {
+            fetch: fetch.clone(),
+            ..Options::default()
+        };
+        let url = format!("http://{}/pages/index.html", server.host);
+        let (first, _) = inline_url(&url, &options).unwrap();
+        let (second, _) = inline_url(&url, &options).unwrap();
+        let parse = |page: &str| page.parse::<Dom>().unwrap();
+        assert_eq!(parse(&first), parse(&second));
+        let revalidations = |path: &str| {
+            server
+                .headers(path)
+                .iter()
+                .map(|headers| headers.iter().any(|h| h.starts_with("If-None-Match:")))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![false, true], revalidations("/pages/index.html"));
+        assert_eq!(vec![false, true], revalidations("/site/img/logo.png"));
+        assert_eq!(
+            vec![false],
+            revalidations("/site/css/style.css"),
+            "fresh for an hour"
+        );
+        let offline = Options {
+            fetch: FetchOptions {
+                offline: true,
+                ..fetch
+            },
+            ..Options::default()
+        };
+        let (third, _) = inline_url(&url, &offline).unwrap();
+        assert_eq!(parse(&first), parse(&third));
+        assert_eq!(
+            2,
+            server.headers("/pages/index.html").len(),
+            "nothing fetched"
+        );
+        let uncached = format!("http://{}/pages/third-party.html", server.host);
+        assert_eq!(
+            format!("{}: not cached, and fetching is off", uncached),
+            inline_url(&uncached, &offline).unwrap_err().to_string()
+        );
+    }
+}
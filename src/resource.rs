@@ -0,0 +1,291 @@
+use crate::inline::Embedded;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Resources provides the content of the files a document links to, so that
+/// inlining doesn't depend on a filesystem being available.
+/// Any `Fn(&Path) -> Result<Vec<u8>, Box<dyn Error>>` is a `Resources`.
+pub trait Resources {
+    /// read returns the content of the resource at `path`.
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    /// size returns the size of the resource at `path` in bytes. By default
+    /// the resource is read to find out.
+    fn size(&self, path: &Path) -> Result<u64, Box<dyn Error>> {
+        self.read(path).map(|content| content.len() as u64)
+    }
+
+    /// media_type returns the media type of the resource at `path` if it's
+    /// known, as a server knows it from its `Content-Type`, which wins over
+    /// the one guessed from the link. By default it isn't known.
+    fn media_type(&self, _path: &Path) -> Option<String> {
+        None
+    }
+}
+
+/// Filesystem reads resources from the local filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Filesystem;
+
+impl Resources for Filesystem {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+        fs::read(path).map_err(|err| ResourceError::from_io(path, err).into())
+    }
+
+    fn size(&self, path: &Path) -> Result<u64, Box<dyn Error>> {
+        match fs::metadata(path) {
+            Ok(meta) if meta.is_dir() => Err(ResourceError::IsDirectory(path.to_owned()).into()),
+            Ok(meta) => Ok(meta.len()),
+            Err(err) => Err(ResourceError::from_io(path, err).into()),
+        }
+    }
+}
+
+/// ResourceError is why the resource at a path couldn't be embedded.
+#[derive(Debug)]
+pub enum ResourceError {
+    /// There is nothing at the path.
+    NotFound(PathBuf),
+    /// The file can't be read with the permissions inliner runs with.
+    PermissionDenied(PathBuf),
+    /// The path is a directory, as `src="images/"` is.
+    IsDirectory(PathBuf),
+    /// The file is empty, which a data url of would be a broken image.
+    Empty(PathBuf),
+    /// The file couldn't be read for another reason.
+    Io(PathBuf, io::Error),
+}
+
+impl ResourceError {
+    /// from_io returns the error reading the file at `path` failed with.
+    fn from_io(path: &Path, err: io::Error) -> Self {
+        let path = path.to_owned();
+        // Windows denies reading directories, rather than saying what they
+        // are.
+        if path.is_dir() {
+            return ResourceError::IsDirectory(path);
+        }
+        match err.kind() {
+            io::ErrorKind::NotFound => ResourceError::NotFound(path),
+            io::ErrorKind::PermissionDenied => ResourceError::PermissionDenied(path),
+            io::ErrorKind::IsADirectory => ResourceError::IsDirectory(path),
+            _ => ResourceError::Io(path, err),
+        }
+    }
+
+    /// path returns the path of the resource.
+    pub fn path(&self) -> &Path {
+        match self {
+            ResourceError::NotFound(path)
+            | ResourceError::PermissionDenied(path)
+            | ResourceError::IsDirectory(path)
+            | ResourceError::Empty(path)
+            | ResourceError::Io(path, _) => path,
+        }
+    }
+}
+
+impl fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path().display();
+        match self {
+            ResourceError::NotFound(_) => write!(f, "{}: not found", path),
+            ResourceError::PermissionDenied(_) => write!(f, "{}: permission denied", path),
+            ResourceError::IsDirectory(_) => write!(f, "{}: is a directory", path),
+            ResourceError::Empty(_) => write!(f, "{}: is empty", path),
+            ResourceError::Io(_, err) => write!(f, "{}: {}", path, err),
+        }
+    }
+}
+
+impl Error for ResourceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ResourceError::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl<F> Resources for F
+where
+    F: Fn(&Path) -> Result<Vec<u8>, Box<dyn Error>>,
+{
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+        self(path)
+    }
+}
+
+/// Memory serves resources held in memory, keyed by the path they resolve to,
+/// for tests and for documents whose resources don't come from a filesystem,
+/// such as those in an archive.
+#[derive(Debug, Clone, Default)]
+pub struct Memory {
+    files: HashMap<PathBuf, File>,
+}
+
+#[derive(Debug, Clone)]
+struct File {
+    content: Vec<u8>,
+    media_type: Option<String>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Memory::default()
+    }
+
+    /// with adds a resource at `path`, whose media type is guessed from the
+    /// links to it.
+    pub fn with(mut self, path: impl Into<PathBuf>, content: impl Into<Vec<u8>>) -> Self {
+        self.files.insert(
+            path.into(),
+            File {
+                content: content.into(),
+                media_type: None,
+            },
+        );
+        self
+    }
+
+    /// with_media_type adds a resource at `path` of the media type given.
+    pub fn with_media_type(
+        mut self,
+        path: impl Into<PathBuf>,
+        content: impl Into<Vec<u8>>,
+        media_type: &str,
+    ) -> Self {
+        self.files.insert(
+            path.into(),
+            File {
+                content: content.into(),
+                media_type: Some(media_type.to_owned()),
+            },
+        );
+        self
+    }
+
+    fn get(&self, path: &Path) -> Result<&File, Box<dyn Error>> {
+        self.files
+            .get(path)
+            .ok_or_else(|| ResourceError::NotFound(path.to_owned()).into())
+    }
+}
+
+impl Resources for Memory {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.get(path).map(|file| file.content.clone())
+    }
+
+    fn size(&self, path: &Path) -> Result<u64, Box<dyn Error>> {
+        self.get(path).map(|file| file.content.len() as u64)
+    }
+
+    fn media_type(&self, path: &Path) -> Option<String> {
+        self.files
+            .get(path)
+            .and_then(|file| file.media_type.clone())
+    }
+}
+
+/// Measured reads resources through another `Resources`, keeping the size
+/// each takes up once it's embedded, every time it's read. Whether it's
+/// embedded as text or in base64 is guessed from the extension of its path,
+/// as the element linking to it isn't known here: `.css`, `.js` and `.mjs`
+/// files are taken for text, so a stylesheet without one is measured as if
+/// it were base64-encoded.
+pub(crate) struct Measured<'a> {
+    resources: &'a dyn Resources,
+    pub embedded: RefCell<Vec<Embedded>>,
+}
+
+impl<'a> Measured<'a> {
+    pub fn new(resources: &'a dyn Resources) -> Self {
+        Measured {
+            resources,
+            embedded: RefCell::new(vec![]),
+        }
+    }
+}
+
+impl Resources for Measured<'_> {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+        let content = self.resources.read(path)?;
+        // Stylesheets and scripts are embedded as text, and everything else
+        // in base64.
+        let is_text = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("css" | "js" | "mjs")
+        );
+        let size = match is_text {
+            true => content.len(),
+            false => content.len().div_ceil(3) * 4,
+        } as u64;
+        let path = path.to_string_lossy();
+        let mut embedded = self.embedded.borrow_mut();
+        match embedded.iter_mut().find(|e| e.path == path) {
+            Some(e) => e.size += size,
+            None => embedded.push(Embedded {
+                path: path.into_owned(),
+                size,
+            }),
+        }
+        Ok(content)
+    }
+
+    fn size(&self, path: &Path) -> Result<u64, Box<dyn Error>> {
+        self.resources.size(path)
+    }
+
+    fn media_type(&self, path: &Path) -> Option<String> {
+        self.resources.media_type(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn memory() {
+        let resources = Memory::new().with("site/app.js", "go()").with_media_type(
+            "site/logo",
+            vec![1, 2, 3],
+            "image/png",
+        );
+        let tests = vec![
+            (
+                "read",
+                resources.read(Path::new("site/app.js")).ok(),
+                Some(b"go()".to_vec()),
+            ),
+            (
+                "bytes",
+                resources.read(Path::new("site/logo")).ok(),
+                Some(vec![1, 2, 3]),
+            ),
+            (
+                "missing",
+                resources.read(Path::new("site/app.css")).ok(),
+                None,
+            ),
+        ];
+        for (desc, got, want) in tests {
+            assert_eq!(want, got, "{}", desc);
+        }
+        assert_eq!(4, resources.size(Path::new("site/app.js")).unwrap());
+        assert_eq!(None, resources.media_type(Path::new("site/app.js")));
+        assert_eq!(
+            Some("image/png".to_owned()),
+            resources.media_type(Path::new("site/logo"))
+        );
+        let err = resources.read(Path::new("app.js")).unwrap_err();
+        assert_eq!("app.js: not found", err.to_string());
+    }
+}
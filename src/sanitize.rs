@@ -0,0 +1,273 @@
+//! Removal of active content, so that untrusted pages can be inlined and
+//! viewed without running anything.
+
+use crate::html::serialize::{to_srcdoc, unescape};
+use crate::html::{Dom, Node, NodeRef, ParseError, SerializeMode};
+use std::fmt;
+
+/// SanitizeOptions control what `sanitize` removes on top of scripts, event
+/// handlers and script urls, which are always removed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SanitizeOptions {
+    /// Remove `<iframe>`, `<frame>`, `<object>`, `<embed>` and `<applet>`
+    /// elements, which embed documents and plugins that may be active.
+    pub remove_embeds: bool,
+}
+
+/// Removal is an element, or an attribute of one, that `sanitize` removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Removal {
+    pub element: String,
+    /// The attribute removed from the element, or `None` if the element
+    /// itself was removed.
+    pub attribute: Option<String>,
+}
+
+impl fmt::Display for Removal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.attribute {
+            Some(attribute) => write!(f, "<{}> {}", self.element, attribute),
+            None => write!(f, "<{}>", self.element),
+        }
+    }
+}
+
+/// Elements that embed other documents or plugins.
+const EMBED_ELEMENTS: &[&str] = &["iframe", "frame", "object", "embed", "applet"];
+
+/// sanitize removes the active content of `dom`: `<script>` elements, event
+/// handler attributes such as `onclick`, and attributes whose value is a
+/// `javascript:` or `vbscript:` url, such as an `href` or `formaction`. The
+/// documents in the `srcdoc` of iframes are sanitized in turn. It returns
+/// what was removed, in document order.
+pub fn sanitize(dom: &mut Dom, options: &SanitizeOptions) -> Vec<Removal> {
+    let mut removed = vec![];
+    sanitize_nodes(&mut dom.nodes, options, &mut removed);
+    removed
+}
+
+fn sanitize_nodes(nodes: &mut Vec<NodeRef>, options: &SanitizeOptions, removed: &mut Vec<Removal>) {
+    nodes.retain(|n| {
+        let mut node = n.borrow_mut();
+        let (name, attributes, children) = match &mut *node {
+            Node::Tag {
+                name,
+                attributes,
+                children,
+            } => (name, attributes, children),
+            _ => return true,
+        };
+        let lower = name.to_ascii_lowercase();
+        if lower == "script" || (options.remove_embeds && EMBED_ELEMENTS.contains(&lower.as_str()))
+        {
+            removed.push(Removal {
+                element: name.clone(),
+                attribute: None,
+            });
+            return false;
+        }
+        let mut active: Vec<String> = attributes
+            .iter()
+            .filter(|(key, value)| is_event_handler(key) || is_script_url(value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        active.sort();
+        for key in active {
+            attributes.remove(&key);
+            removed.push(Removal {
+                element: name.clone(),
+                attribute: Some(key),
+            });
+        }
+        if lower == "iframe" {
+            if let Some(document) = attributes.get("srcdoc") {
+                match sanitize_srcdoc(document, options, removed) {
+                    Ok(Some(document)) => {
+                        attributes.insert("srcdoc".into(), document);
+                    }
+                    Ok(None) => {}
+                    // A document that can't be sanitized can't be kept.
+                    Err(_) => {
+                        attributes.remove("srcdoc");
+                        removed.push(Removal {
+                            element: name.clone(),
+                            attribute: Some("srcdoc".into()),
+                        });
+                    }
+                }
+            }
+        }
+        sanitize_nodes(children, options, removed);
+        true
+    });
+}
+
+/// sanitize_srcdoc sanitizes the document written escaped in the `srcdoc`
+/// of an iframe, returning it escaped again if anything was removed from it.
+fn sanitize_srcdoc(
+    document: &str,
+    options: &SanitizeOptions,
+    removed: &mut Vec<Removal>,
+) -> Result<Option<String>, ParseError> {
+    let mut dom: Dom = unescape(document).parse()?;
+    let before = removed.len();
+    sanitize_nodes(&mut dom.nodes, options, removed);
+    if removed.len() == before {
+        return Ok(None);
+    }
+    Ok(Some(to_srcdoc(&dom, SerializeMode::Preserve)))
+}
+
+/// is_event_handler reports whether the attribute `name` is an event handler,
+/// ignoring case. Any attribute starting with "on" is taken to be one, so
+/// that handlers for events browsers add later are caught too.
+pub(crate) fn is_event_handler(name: &str) -> bool {
+    name.get(..2)
+        .is_some_and(|on| on.eq_ignore_ascii_case("on"))
+}
+
+/// is_script_url reports whether `value` is a url that runs script when it
+/// is followed or loaded. Browsers ignore leading spaces and control
+/// characters, and tabs and newlines anywhere, so they're ignored here too.
+fn is_script_url(value: &str) -> bool {
+    let url: String = value
+        .trim_start_matches(|c: char| c <= ' ')
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .take("javascript:".len())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    url.starts_with("javascript:") || url.starts_with("vbscript:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    #[test]
+    fn sanitize() {
+        let tests = vec![
+            (
+                "inert",
+                r#"<p class="intro">text</p><img src="a.png" alt="a"/>"#,
+                r#"<p class="intro">text</p><img src="a.png" alt="a"/>"#,
+                vec![],
+            ),
+            (
+                "scripts",
+                r#"<script>alert(1)</script><div><script src="app.js"></script><p>text</p></div><SCRIPT>alert(2)</SCRIPT>"#,
+                r#"<div><p>text</p></div>"#,
+                vec!["<script>", "<script>", "<script>"],
+            ),
+            (
+                "event handlers, ignoring case",
+                r#"<body onload="go()"><a onClick="go()" href="/">home</a><img ONERROR="go()" src="a.png"/></body>"#,
+                r#"<body><a href="/">home</a><img src="a.png"/></body>"#,
+                vec!["<body> onload", "<a> onclick", "<img> onerror"],
+            ),
+            (
+                "handlers of events not known yet",
+                r#"<p onbeforecopy="go()" OnContentVisibilityAutoStateChange="go()" data-onclick="z">text</p>"#,
+                r#"<p data-onclick="z">text</p>"#,
+                vec!["<p> onbeforecopy", "<p> oncontentvisibilityautostatechange"],
+            ),
+            (
+                "srcdoc",
+                r#"<iframe srcdoc="&lt;script&gt;alert(1)&lt;/script&gt;&lt;p onclick=&quot;go()&quot; title=&quot;Fish &amp;amp; chips&quot;&gt;hi&lt;/p&gt;"></iframe>"#,
+                r#"<iframe srcdoc="<p title=&quot;Fish &amp;amp; chips&quot;>hi</p>"></iframe>"#,
+                vec!["<script>", "<p> onclick"],
+            ),
+            (
+                "inert srcdoc",
+                r#"<iframe srcdoc="&lt;p&gt;hi&lt;/p&gt;"></iframe>"#,
+                r#"<iframe srcdoc="&lt;p&gt;hi&lt;/p&gt;"></iframe>"#,
+                vec![],
+            ),
+            (
+                "svg",
+                r#"<svg onload="go()"><script>alert(1)</script><circle onmouseover="go()" r="1"></circle><animate onbegin="go()"></animate></svg>"#,
+                r#"<svg><circle r="1"></circle><animate></animate></svg>"#,
+                vec![
+                    "<svg> onload",
+                    "<script>",
+                    "<circle> onmouseover",
+                    "<animate> onbegin",
+                ],
+            ),
+            (
+                "script urls",
+                r#"<a href="javascript:go()">a</a><a href="JavaScript:go()">b</a><a href="vbscript:go()">c</a><a href="/javascript:go()">d</a>"#,
+                r#"<a>a</a><a>b</a><a>c</a><a href="/javascript:go()">d</a>"#,
+                vec!["<a> href", "<a> href", "<a> href"],
+            ),
+            (
+                "formaction",
+                r#"<form action="javascript:go()"><button formaction="javascript:go()">go</button><input type="submit" formaction="/submit"/></form>"#,
+                r#"<form><button>go</button><input type="submit" formaction="/submit"/></form>"#,
+                vec!["<form> action", "<button> formaction"],
+            ),
+            (
+                "embeds are kept by default",
+                r#"<iframe src="page.html"></iframe><object data="a.swf"></object><embed src="a.swf"/>"#,
+                r#"<iframe src="page.html"></iframe><object data="a.swf"></object><embed src="a.swf"/>"#,
+                vec![],
+            ),
+            (
+                "several handlers",
+                r#"<div onmouseout="a()" onmouseover="b()" id="x"></div>"#,
+                r#"<div id="x"></div>"#,
+                vec!["<div> onmouseout", "<div> onmouseover"],
+            ),
+        ];
+        for (desc, input, want, removed) in tests {
+            let mut dom = parse(input);
+            let got: Vec<String> = super::sanitize(&mut dom, &SanitizeOptions::default())
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            assert_eq!(removed, got, "{}: removed", desc);
+            assert_eq!(parse(want), dom, "{}", desc);
+        }
+    }
+
+    #[test]
+    fn script_urls() {
+        let tests = vec![
+            ("javascript", "javascript:go()", true),
+            ("case", "JaVaScRiPt:go()", true),
+            ("vbscript", "VBScript:go()", true),
+            ("leading space", "  javascript:go()", true),
+            ("leading control character", "\u{1}javascript:go()", true),
+            ("tab inside", "java\tscript:go()", true),
+            ("newline inside", "javascript\n:go()", true),
+            ("relative", "/javascript:go()", false),
+            ("other scheme", "https://example.com/javascript:", false),
+            ("too short", "javascript", false),
+            ("empty", "", false),
+        ];
+        for (desc, value, want) in tests {
+            assert_eq!(want, is_script_url(value), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn remove_embeds() {
+        let mut dom = parse(
+            r#"<div><iframe src="page.html"></iframe><object data="a.swf"><p>fallback</p></object><EMBED src="a.swf"/><img src="a.png"/></div>"#,
+        );
+        let options = SanitizeOptions {
+            remove_embeds: true,
+        };
+        let got: Vec<String> = super::sanitize(&mut dom, &options)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(vec!["<iframe>", "<object>", "<embed>"], got);
+        assert_eq!(parse(r#"<div><img src="a.png"/></div>"#), dom);
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+
+/// Candidate is one image candidate of a `srcset` attribute: a url with an
+/// optional width or density descriptor.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Candidate {
+    pub url: String,
+    pub descriptor: String,
+}
+
+/// SrcSet is a parsed `srcset` attribute.
+#[derive(Debug, PartialEq)]
+pub(crate) struct SrcSet(pub Vec<Candidate>);
+
+impl SrcSet {
+    /// parse splits a srcset into candidates, following the algorithm browsers
+    /// use: a url runs until whitespace, so commas inside urls (such as in
+    /// data urls) do not split candidates, and a trailing comma ends the
+    /// candidate early.
+    pub fn parse(srcset: &str) -> Self {
+        let mut candidates = vec![];
+        let mut chars = srcset.chars().peekable();
+        loop {
+            while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+            if chars.peek().is_none() {
+                break;
+            }
+            let mut url = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                url.push(c);
+            }
+            let mut descriptor = String::new();
+            if url.ends_with(',') {
+                url = url.trim_end_matches(',').to_owned();
+            } else {
+                // Descriptors run until a comma that isn't inside parens.
+                let mut depth = 0;
+                while let Some(c) = chars.next_if(|c| depth > 0 || *c != ',') {
+                    match c {
+                        '(' => depth += 1,
+                        ')' if depth > 0 => depth -= 1,
+                        _ => {}
+                    }
+                    descriptor.push(c);
+                }
+            }
+            candidates.push(Candidate {
+                url,
+                descriptor: descriptor.trim().to_owned(),
+            });
+        }
+        SrcSet(candidates)
+    }
+}
+
+impl fmt::Display for SrcSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (ii, candidate) in self.0.iter().enumerate() {
+            if ii > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", candidate.url)?;
+            if !candidate.descriptor.is_empty() {
+                write!(f, " {}", candidate.descriptor)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn candidate(url: &str, descriptor: &str) -> Candidate {
+        Candidate {
+            url: url.into(),
+            descriptor: descriptor.into(),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let tests = vec![
+            ("single url", "a.png", vec![candidate("a.png", "")]),
+            (
+                "density descriptors",
+                "a.png 1x, b.png 2x",
+                vec![candidate("a.png", "1x"), candidate("b.png", "2x")],
+            ),
+            (
+                "width descriptors, no space after comma",
+                "a.png 480w,b.png 800w",
+                vec![candidate("a.png", "480w"), candidate("b.png", "800w")],
+            ),
+            (
+                "trailing comma ends the url",
+                "a.png, b.png 2x",
+                vec![candidate("a.png", ""), candidate("b.png", "2x")],
+            ),
+            (
+                "data url containing a comma",
+                "data:image/png;base64,AAAA 1x, b.png 2x",
+                vec![
+                    candidate("data:image/png;base64,AAAA", "1x"),
+                    candidate("b.png", "2x"),
+                ],
+            ),
+            (
+                "surrounding whitespace",
+                "  a.png   1x ,\n b.png 2x  ",
+                vec![candidate("a.png", "1x"), candidate("b.png", "2x")],
+            ),
+            (
+                "commas are part of a url followed by whitespace",
+                "a.png,b.png 2x",
+                vec![candidate("a.png,b.png", "2x")],
+            ),
+            ("empty", "", vec![]),
+        ];
+        for (desc, input, want) in tests {
+            assert_eq!(SrcSet(want), SrcSet::parse(input), "{}", desc);
+        }
+    }
+
+    #[test]
+    fn display() {
+        let srcset = SrcSet(vec![
+            candidate("data:image/png;base64,AAAA", "1x"),
+            candidate("b.png", ""),
+        ]);
+        assert_eq!("data:image/png;base64,AAAA 1x, b.png", srcset.to_string());
+        assert_eq!(srcset, SrcSet::parse(&srcset.to_string()));
+    }
+}
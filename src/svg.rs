@@ -0,0 +1,297 @@
+//! Inlining of the elements SVG `<use>` elements reference in other files,
+//! which can't be embedded as data urls since browsers don't load them from
+//! there.
+
+use crate::base::Base;
+use crate::css;
+use crate::encoding;
+use crate::html::{Node, NodeRef, Parser, StrTokenizer};
+use crate::inline::{embed, Options};
+use crate::resource::Resources;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+
+/// HREF_ATTRIBUTES are the attributes SVG elements link with, the second
+/// only in SVG 1.1 documents.
+pub(crate) const HREF_ATTRIBUTES: &[&str] = &["href", "xlink:href"];
+
+/// href_attribute returns the attribute `node` links with, preferring `href`
+/// to `xlink:href` as browsers do.
+pub(crate) fn href_attribute(node: &Node) -> Option<&'static str> {
+    HREF_ATTRIBUTES
+        .iter()
+        .copied()
+        .find(|key| node.has_attr(key))
+}
+
+/// extract reads the SVG file at `path` and returns a copy of its element
+/// with the id `id`, followed by copies of the elements elsewhere in the file
+/// that it references by id, with `href="#…"` or `url(#…)`, and those that
+/// they reference in turn. The images they reference are embedded as data
+/// urls, since they are relative to the file rather than the document the
+/// copies go into. References to ids the file hasn't got are left as they
+/// are, as browsers ignore them.
+pub(crate) fn extract(
+    path: &Path,
+    id: &str,
+    base: &Base,
+    options: &Options,
+    resources: &dyn Resources,
+) -> Result<Vec<NodeRef>, Box<dyn Error>> {
+    let content = encoding::read_text(path, None, options, resources)?;
+    // The file is XML, whose names are case-sensitive.
+    let dom = Parser::new(StrTokenizer::new(&content).merged())
+        .normalize_case(false)
+        .parse()?;
+    if dom.find_by_id(id).is_none() {
+        return Err(format!("{}: no element with id {:?}", path.display(), id).into());
+    }
+    let base = base.of(path);
+    let mut copies = vec![];
+    // The ids copied so far, and those referenced, in the order they were.
+    let mut copied = HashSet::new();
+    let mut referenced = vec![id.to_owned()];
+    let mut ii = 0;
+    while ii < referenced.len() {
+        let id = &referenced[ii];
+        ii += 1;
+        let element = match dom.find_by_id(id) {
+            Some(element) if !copied.contains(id) => element.borrow().deep_clone(),
+            _ => continue,
+        };
+        let mut nodes = vec![element.clone()];
+        while let Some(n) = nodes.pop() {
+            let mut node = n.borrow_mut();
+            if let Some(id) = node.attr("id") {
+                copied.insert(id.to_owned());
+            }
+            for reference in references(&node)? {
+                if !referenced.contains(&reference) {
+                    referenced.push(reference);
+                }
+            }
+            if node.is_element("image") || node.is_element("feImage") {
+                if let Some(key) = href_attribute(&node) {
+                    let link = node.attr(key).unwrap_or_default();
+                    if let Some(url) = embed(link, None, &base, options, resources)? {
+                        node.set_attr(key, url);
+                    }
+                }
+            }
+            if let Some(style) = node.attr("style") {
+                let style =
+                    css::rewrite_urls(style, |url| embed(url, None, &base, options, resources))?;
+                node.set_attr("style", style);
+            }
+            // In document order, for the copies to be.
+            nodes.extend(node.children().iter().rev().cloned());
+        }
+        copies.push(element);
+    }
+    Ok(copies)
+}
+
+/// references returns the ids `node` references, with an `href` of `#…` or
+/// with `url(#…)` in any of its attributes, such as `fill` or `style`, in
+/// the order of the attributes' names.
+fn references(node: &Node) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut ids = vec![];
+    let mut attributes: Vec<_> = match node {
+        Node::Tag { attributes, .. } => attributes.iter().collect(),
+        _ => return Ok(ids),
+    };
+    attributes.sort();
+    for (key, value) in attributes {
+        if HREF_ATTRIBUTES.contains(&key.as_str()) {
+            if let Some(id) = value.strip_prefix('#') {
+                ids.push(id.to_owned());
+            }
+        } else if value.contains("url(") {
+            css::rewrite_urls(value, |url| {
+                if let Some(id) = url.strip_prefix('#') {
+                    ids.push(id.to_owned());
+                }
+                Ok(None)
+            })?;
+        }
+    }
+    Ok(ids)
+}
+
+/// localize gives the `copies` that `extract` made of the elements of the
+/// file at `path` ids the document hasn't `taken`, renaming the references
+/// between them to match, and returns those to copy in. An element that was
+/// already copied in, as `used` records by file and id, isn't copied again:
+/// the references to it are renamed to the copy there is.
+pub(crate) fn localize(
+    path: &Path,
+    copies: Vec<NodeRef>,
+    used: &mut HashMap<String, String>,
+    taken: &mut HashSet<String>,
+) -> Result<Vec<NodeRef>, Box<dyn Error>> {
+    let mut renamed: HashMap<String, String> = HashMap::new();
+    let mut elements = vec![];
+    for element in copies {
+        let id = element.borrow().attr("id").unwrap_or_default().to_owned();
+        let reference = format!("{}#{}", path.display(), id);
+        if let Some(local) = used.get(&reference) {
+            renamed.insert(id, local.clone());
+            continue;
+        }
+        let mut nodes = vec![element.clone()];
+        while let Some(n) = nodes.pop() {
+            let node = n.borrow();
+            if let Some(id) = node.attr("id") {
+                let mut local = id.to_owned();
+                let mut n = 1;
+                while taken.contains(&local) {
+                    n += 1;
+                    local = format!("{}-{}", id, n);
+                }
+                taken.insert(local.clone());
+                renamed.insert(id.to_owned(), local);
+            }
+            nodes.extend(node.children().iter().cloned());
+        }
+        used.insert(reference, renamed[&id].clone());
+        elements.push(element);
+    }
+    for element in &elements {
+        let mut nodes = vec![element.clone()];
+        while let Some(n) = nodes.pop() {
+            if let Node::Tag {
+                attributes,
+                children,
+                ..
+            } = &mut *n.borrow_mut()
+            {
+                for (key, value) in attributes.iter_mut() {
+                    let local = |id: &str| renamed.get(id).map(|local| format!("#{}", local));
+                    if key == "id" {
+                        if let Some(local) = renamed.get(value.as_str()) {
+                            *value = local.clone();
+                        }
+                    } else if HREF_ATTRIBUTES.contains(&key.as_str()) {
+                        if let Some(local) = value.strip_prefix('#').and_then(local) {
+                            *value = local;
+                        }
+                    } else if value.contains("url(") {
+                        *value = css::rewrite_urls(value, |url| {
+                            Ok(url.strip_prefix('#').and_then(local))
+                        })?;
+                    }
+                }
+                nodes.extend(children.iter().cloned());
+            }
+        }
+    }
+    Ok(elements)
+}
+
+/// defs returns a `<defs>` element holding `elements`.
+pub(crate) fn defs(elements: Vec<NodeRef>) -> NodeRef {
+    Node::Tag {
+        name: "defs".into(),
+        attributes: Default::default(),
+        children: elements,
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::Dom;
+    use crate::resource::Memory;
+    use pretty_assertions::assert_eq;
+
+    /// parse parses `xml` keeping the case of its names, as `extract` does.
+    fn parse(xml: &str) -> Dom {
+        Parser::new(StrTokenizer::new(xml).merged())
+            .normalize_case(false)
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn extract() {
+        let resources = Memory::new()
+            .with(
+                "site/icons/defs.svg",
+                r##"<svg><symbol id="icon"><image href="dot.gif"/><path d="M0 0" style="fill:url(#g)"/></symbol><symbol id="other"/><symbol id="star"><use href="#other"/><path fill="url('#g')" clip-path="url(#clip)"/><clipPath id="clip"><rect/></clipPath></symbol><linearGradient id="g" xlink:href="#stops"/><linearGradient id="stops"><stop offset="0"/></linearGradient></svg>"##,
+            )
+            .with("site/icons/dot.gif", "GIF");
+        let base = Base::new(Path::new("site"));
+        let path = Path::new("site/icons/defs.svg");
+        let options = Options::default();
+        let tests = vec![
+            (
+                "image and reference",
+                "icon",
+                vec![
+                    r#"<symbol id="icon"><image href="data:image/gif;base64,R0lG"/><path d="M0 0" style="fill:url(#g)"/></symbol>"#,
+                    r##"<linearGradient id="g" xlink:href="#stops"/>"##,
+                    r#"<linearGradient id="stops"><stop offset="0"/></linearGradient>"#,
+                ],
+            ),
+            (
+                "references and descendants",
+                "star",
+                vec![
+                    r##"<symbol id="star"><use href="#other"/><path fill="url('#g')" clip-path="url(#clip)"/><clipPath id="clip"><rect/></clipPath></symbol>"##,
+                    r#"<symbol id="other"/>"#,
+                    r##"<linearGradient id="g" xlink:href="#stops"/>"##,
+                    r#"<linearGradient id="stops"><stop offset="0"/></linearGradient>"#,
+                ],
+            ),
+            ("no references", "other", vec![r#"<symbol id="other"/>"#]),
+        ];
+        for (desc, id, want) in tests {
+            let want: Vec<NodeRef> = want
+                .into_iter()
+                .map(|xml| parse(xml).nodes.remove(0))
+                .collect();
+            let got = super::extract(path, id, &base, &options, &resources).unwrap();
+            assert_eq!(want, got, "{}", desc);
+        }
+        let err = super::extract(path, "missing", &base, &options, &resources).unwrap_err();
+        assert_eq!(
+            r#"site/icons/defs.svg: no element with id "missing""#,
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn localize() {
+        let path = Path::new("defs.svg");
+        let copies = |xml: &str| parse(xml).nodes;
+        let mut used = HashMap::new();
+        let mut taken: HashSet<String> = vec!["g".to_string()].into_iter().collect();
+        let got = super::localize(
+            path,
+            copies(r##"<symbol id="icon"><path fill="url(#g)"/></symbol><linearGradient id="g" href="#stops"/><linearGradient id="stops"/>"##),
+            &mut used,
+            &mut taken,
+        )
+        .unwrap();
+        let want = copies(
+            r##"<symbol id="icon"><path fill="url(#g-2)"/></symbol><linearGradient id="g-2" href="#stops"/><linearGradient id="stops"/>"##,
+        );
+        assert_eq!(want, got, "taken ids are renamed");
+        // Copied in already, the gradient is shared.
+        let got = super::localize(
+            path,
+            copies(r##"<symbol id="star" style="fill:url(#g)"/><linearGradient id="g" href="#stops"/><linearGradient id="stops"/>"##),
+            &mut used,
+            &mut taken,
+        )
+        .unwrap();
+        assert_eq!(
+            copies(r##"<symbol id="star" style="fill:url(#g-2)"/>"##),
+            got,
+            "copies are shared"
+        );
+        assert_eq!(Some(&"star".to_string()), used.get("defs.svg#star"));
+    }
+}
@@ -0,0 +1,364 @@
+//! Verification that a document is self-contained: that it loads nothing
+//! from outside of itself.
+
+use crate::css;
+use crate::extract::is_javascript;
+use crate::html::serialize::unescape;
+use crate::html::{Dom, Node, NodeRef};
+use crate::inline::{is_meta_image, NON_RESOURCE_RELATIONS};
+use crate::literals::asset_links;
+use crate::sanitize::is_event_handler;
+use crate::srcset::SrcSet;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// ExternalReference is a url left in a document that refers to something
+/// outside of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalReference {
+    /// Name of the element holding the url.
+    pub element: String,
+    /// Attribute holding the url, or `None` for the text of a `<style>`.
+    pub attribute: Option<String>,
+    pub url: String,
+}
+
+impl fmt::Display for ExternalReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.attribute {
+            Some(attribute) => write!(f, "<{}> {}: {}", self.element, attribute, self.url),
+            None => write!(f, "<{}>: {}", self.element, self.url),
+        }
+    }
+}
+
+impl Dom {
+    /// external_references returns the urls of the resources the document
+    /// would load from outside of itself, in document order: those in the
+    /// `src`, `href`, `srcset`, `poster`, `data` and `style` attributes and
+    /// the `url()`s of `<style>` elements that aren't data urls or fragments.
+    /// Links that are navigated to rather than loaded, such as those of
+    /// anchors, forms and canonical `<link>`s, aren't references, and neither
+    /// are the links inside `<template>` elements, which are never loaded.
+    /// Nor are the images `<meta>` tags name for scrapers to fetch.
+    pub fn external_references(&self) -> Vec<ExternalReference> {
+        self.external_references_with(false)
+    }
+
+    /// external_references_with returns the urls `external_references` does,
+    /// and the images named by `<meta>` tags too if `meta_images` is set, as
+    /// they are when a document is archived with them.
+    pub fn external_references_with(&self, meta_images: bool) -> Vec<ExternalReference> {
+        let found = RefCell::new(vec![]);
+        let inert = self.template_content();
+        self.depth_first(&|n: NodeRef| {
+            if inert.contains(&Rc::as_ptr(&n)) {
+                return Ok(());
+            }
+            if let Node::Tag {
+                name,
+                attributes,
+                children,
+            } = &*n.borrow()
+            {
+                let mut found = found.borrow_mut();
+                let mut push = |attribute: Option<&str>, url: &str| {
+                    if is_external(url) {
+                        found.push(ExternalReference {
+                            element: name.clone(),
+                            attribute: attribute.map(str::to_owned),
+                            url: url.to_owned(),
+                        });
+                    }
+                };
+                let mut keys: Vec<&String> = attributes.keys().collect();
+                keys.sort();
+                for key in keys {
+                    let value = &attributes[key];
+                    match key.to_ascii_lowercase().as_str() {
+                        "srcset" => {
+                            for candidate in SrcSet::parse(value).0 {
+                                push(Some(key), &candidate.url);
+                            }
+                        }
+                        "style" => {
+                            css::rewrite_urls(value, |url| {
+                                push(Some(key), url);
+                                Ok(None)
+                            })?;
+                        }
+                        "href" | "xlink:href" if !is_loaded(name, attributes) => {}
+                        // Only image buttons load their `src`.
+                        "src"
+                            if name.eq_ignore_ascii_case("input")
+                                && !is_image_input(attributes) => {}
+                        "src" | "href" | "xlink:href" | "poster" | "data" => push(Some(key), value),
+                        "content"
+                            if meta_images
+                                && name.eq_ignore_ascii_case("meta")
+                                && is_meta_image(attributes) =>
+                        {
+                            push(Some(key), value)
+                        }
+                        _ => {}
+                    }
+                }
+                if name.eq_ignore_ascii_case("style") {
+                    for child in children {
+                        if let Node::Text(text) = &*child.borrow() {
+                            css::rewrite_urls(text, |url| {
+                                push(None, url);
+                                Ok(None)
+                            })?;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })
+        .expect("finding references never fails");
+        found.into_inner()
+    }
+
+    /// runtime_references returns the links the scripts of the document may
+    /// load once they run, in document order: the string literals of inline
+    /// scripts and event handler attributes that look like relative links to
+    /// assets, such as the `'hover.png'` of `this.src='hover.png'`. What a
+    /// script loads can't be known without running it, so they're only
+    /// guesses, and aren't counted among `external_references`.
+    pub fn runtime_references(&self) -> Vec<ExternalReference> {
+        let found = RefCell::new(vec![]);
+        let inert = self.template_content();
+        self.depth_first(&|n: NodeRef| {
+            if inert.contains(&Rc::as_ptr(&n)) {
+                return Ok(());
+            }
+            if let Node::Tag {
+                name,
+                attributes,
+                children,
+            } = &*n.borrow()
+            {
+                let mut found = found.borrow_mut();
+                let mut push = |attribute: Option<&str>, js: &str| {
+                    for (start, end) in asset_links(js) {
+                        found.push(ExternalReference {
+                            element: name.clone(),
+                            attribute: attribute.map(str::to_owned),
+                            url: js[start..end].to_owned(),
+                        });
+                    }
+                };
+                let mut keys: Vec<&String> = attributes.keys().collect();
+                keys.sort();
+                for key in keys.into_iter().filter(|key| is_event_handler(key)) {
+                    push(Some(key), &unescape(&attributes[key]));
+                }
+                if name.eq_ignore_ascii_case("script")
+                    && is_javascript(attributes.get("type").map(String::as_str))
+                {
+                    for child in children {
+                        if let Node::Text(text) = &*child.borrow() {
+                            push(None, text);
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })
+        .expect("finding references never fails");
+        found.into_inner()
+    }
+}
+
+/// is_loaded reports whether the `href` of the element `name` is a resource
+/// that is loaded along with the document, rather than navigated to.
+fn is_loaded(name: &str, attributes: &HashMap<String, String>) -> bool {
+    match name.to_ascii_lowercase().as_str() {
+        "a" | "area" | "base" => false,
+        // Alternate stylesheets are loaded too, for the user to switch to.
+        "link" => !attributes.get("rel").is_some_and(|rel| {
+            let rel = rel.to_ascii_lowercase();
+            let rels: Vec<&str> = rel.split_whitespace().collect();
+            !rels.contains(&"stylesheet")
+                && rels.iter().any(|rel| NON_RESOURCE_RELATIONS.contains(rel))
+        }),
+        _ => true,
+    }
+}
+
+/// is_image_input reports whether the `<input>` with `attributes` is an
+/// image button.
+fn is_image_input(attributes: &HashMap<String, String>) -> bool {
+    attributes
+        .get("type")
+        .is_some_and(|t| t.eq_ignore_ascii_case("image"))
+}
+
+/// is_external reports whether `url` refers to something outside of the
+/// document it is in.
+fn is_external(url: &str) -> bool {
+    let url = url.trim();
+    let scheme = url.split(':').next().unwrap_or("").to_ascii_lowercase();
+    !(url.is_empty() || url.starts_with('#') || scheme == "data" || url == "about:blank")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn parse(input: &str) -> Dom {
+        input.parse().expect("parsing dom")
+    }
+
+    #[test]
+    fn external_references() {
+        let tests = vec![
+            ("nothing", "<p>text</p>", vec![]),
+            (
+                "data urls and fragments",
+                r##"<img src="data:image/png;base64,AAAA"/><svg><use href="#icon"></use></svg><iframe src="about:blank"></iframe>"##,
+                vec![],
+            ),
+            (
+                "sources",
+                r#"<script src="app.js"></script><img src="https://example.com/logo.png"/>"#,
+                vec![
+                    "<script> src: app.js",
+                    "<img> src: https://example.com/logo.png",
+                ],
+            ),
+            (
+                "srcset",
+                r#"<img srcset="data:image/png;base64,AAAA"/><img srcset="small.png"/>"#,
+                vec!["<img> srcset: small.png"],
+            ),
+            (
+                "style attribute",
+                r#"<p style="background:url(bg.png)"></p>"#,
+                vec!["<p> style: bg.png"],
+            ),
+            (
+                "style element",
+                r#"<style>body { background: url("bg.png") } p { background: url(data:image/png;base64,AAAA) }</style>"#,
+                vec!["<style>: bg.png"],
+            ),
+            (
+                "svg links",
+                r##"<svg><image xlink:href="photo.jpg"/><use href="defs.svg#icon"/><a xlink:href="page.html"></a></svg>"##,
+                vec!["<image> xlink:href: photo.jpg", "<use> href: defs.svg#icon"],
+            ),
+            (
+                "poster and data",
+                r#"<video poster="poster.jpg"></video><object data="manual.pdf"></object>"#,
+                vec!["<video> poster: poster.jpg", "<object> data: manual.pdf"],
+            ),
+            (
+                "loaded links",
+                r#"<link rel="stylesheet" href="style.css"/><link rel="icon" href="favicon.ico"/><link rel="alternate stylesheet" href="contrast.css"/>"#,
+                vec![
+                    "<link> href: style.css",
+                    "<link> href: favicon.ico",
+                    "<link> href: contrast.css",
+                ],
+            ),
+            (
+                "form controls",
+                r#"<form action="send.php"><input type="image" src="go.png"/><input type="text" src="x.png"/><button formaction="save.php">save</button></form>"#,
+                vec!["<input> src: go.png"],
+            ),
+            (
+                "navigation",
+                r#"<a href="page.html">page</a><area href="map.html"/><base href="/"/><link rel="canonical" href="https://example.com/"/>"#,
+                vec![],
+            ),
+            (
+                "template content",
+                r#"<template><img src="row.png"/></template><img src="logo.png"/>"#,
+                vec!["<img> src: logo.png"],
+            ),
+            (
+                "nested",
+                r#"<div><picture><source srcset="big.webp"/><img src="big.png"/></picture></div>"#,
+                vec!["<source> srcset: big.webp", "<img> src: big.png"],
+            ),
+        ];
+        for (desc, input, want) in tests {
+            let got: Vec<String> = parse(input)
+                .external_references()
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            assert_eq!(want, got, "{}", desc);
+        }
+    }
+
+    #[test]
+    fn runtime_references() {
+        let tests = vec![
+            ("nothing", r#"<img src="logo.png"/>"#, vec![]),
+            (
+                "handler",
+                r#"<img src="a.png" onmouseover="this.src='hover.png'" onmouseout="this.src='a.png'"/>"#,
+                vec!["<img> onmouseout: a.png", "<img> onmouseover: hover.png"],
+            ),
+            (
+                "escaped handler",
+                r#"<img onclick="this.src=&quot;open.png&quot;"/>"#,
+                vec!["<img> onclick: open.png"],
+            ),
+            (
+                "script",
+                r#"<script>img.src = 'sprites/' + name + '.png'; icon.src = "icons/close.svg";</script>"#,
+                vec!["<script>: icons/close.svg"],
+            ),
+            (
+                "data",
+                r#"<script type="application/json">{"icon": "icon.png"}</script>"#,
+                vec![],
+            ),
+            (
+                "template content",
+                r#"<template><img onload="this.src='row.png'"/></template>"#,
+                vec![],
+            ),
+            (
+                "not a handler",
+                r#"<div data-src="lazy.png" data-onclick="this.src='x.png'"></div>"#,
+                vec![],
+            ),
+        ];
+        for (desc, input, want) in tests {
+            let got: Vec<String> = parse(input)
+                .runtime_references()
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            assert_eq!(want, got, "{}", desc);
+        }
+    }
+
+    #[test]
+    fn meta_images() {
+        let input = r#"<meta property="og:image" content="https://example.com/card.png"/><meta name="msapplication-TileImage" content="tile.png"/><meta name="description" content="about.png"/><meta property="og:image" content="data:image/png;base64,AAAA"/>"#;
+        assert_eq!(
+            Vec::<ExternalReference>::new(),
+            parse(input).external_references()
+        );
+        let got: Vec<String> = parse(input)
+            .external_references_with(true)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            vec![
+                "<meta> content: https://example.com/card.png",
+                "<meta> content: tile.png",
+            ],
+            got
+        );
+    }
+}
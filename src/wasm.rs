@@ -0,0 +1,34 @@
+use crate::inline::{inline_with_resources as inline, Options};
+use js_sys::{Function, Uint8Array};
+use std::error::Error;
+use std::path::Path;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+/// Inline `html`, reading each linked resource through `resolver`.
+///
+/// `resolver` is called with the relative path of a resource and returns its
+/// content as a `Uint8Array` or a string, or `null` or `undefined` if there
+/// is no such resource. It can't be async, so resources fetched over the
+/// network need to be fetched before inlining starts.
+#[wasm_bindgen(js_name = inlineWithResources)]
+pub fn inline_with_resources(html: &str, resolver: Function) -> Result<String, JsValue> {
+    let resources = |path: &Path| -> Result<Vec<u8>, Box<dyn Error>> {
+        let link = path.to_string_lossy();
+        let content = resolver
+            .call1(&JsValue::NULL, &JsValue::from_str(&link))
+            .map_err(|e| format!("{}: {:?}", link, e))?;
+        if let Some(text) = content.as_string() {
+            Ok(text.into_bytes())
+        } else if let Some(bytes) = content.dyn_ref::<Uint8Array>() {
+            Ok(bytes.to_vec())
+        } else if content.is_null() || content.is_undefined() {
+            Err(format!("{}: not found", link).into())
+        } else {
+            Err(format!("{}: expected a Uint8Array or a string", link).into())
+        }
+    };
+    inline(html.into(), Path::new(""), &Options::default(), &resources)
+        .map(|(output, _)| output)
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
@@ -0,0 +1,63 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("budget")
+}
+
+/// inline runs inliner over the fixture with `args`.
+fn inline(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_inliner"))
+        .current_dir(fixture())
+        .args(args)
+        .args(["index.html", "."])
+        .output()
+        .expect("running inliner")
+}
+
+#[test]
+fn over_budget() {
+    let output = inline(&["--budget", "2k"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(Some(3), output.status.code(), "{}", stderr);
+    assert!(output.stdout.is_empty(), "nothing is written");
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert!(
+        lines[0].starts_with("error: the output is ")
+            && lines[0].ends_with("over the budget of 2048 bytes"),
+        "{}",
+        stderr
+    );
+    assert!(lines[1].starts_with("  ./vendor.js: "), "{}", stderr);
+
+    let output = inline(&["--budget-warn", "2k"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(Some(2), output.status.code(), "{}", stderr);
+    assert!(stderr.starts_with("warning: the output is "), "{}", stderr);
+    assert!(!output.stdout.is_empty(), "the output is written");
+}
+
+#[test]
+fn within_budget() {
+    let unbudgeted = inline(&[]);
+    for budget in ["--budget", "--budget-warn"] {
+        let output = inline(&[budget, "1M"]);
+        assert!(output.status.success(), "{}", budget);
+        assert!(output.stderr.is_empty(), "{}", budget);
+        assert_eq!(unbudgeted.stdout, output.stdout, "{}", budget);
+    }
+}
+
+#[test]
+fn budget_too_large() {
+    let output = inline(&["--budget", "99999999999999999G"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(Some(1), output.status.code(), "{}", stderr);
+    assert_eq!(
+        "error: parsing budget: 99999999999999999G: too large\n",
+        stderr
+    );
+}
@@ -0,0 +1,68 @@
+use pretty_assertions::assert_eq;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("deterministic")
+}
+
+/// inline runs inliner in `dir` with `args`, returning what it wrote.
+fn inline(dir: &Path, args: &[&str]) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_inliner"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .expect("running inliner");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+#[test]
+fn same_bytes_wherever_run() {
+    let dir = fixture();
+    let page = dir.join("index.html");
+    let once = inline(&dir, &["--deterministic", "index.html"]);
+    let twice = inline(&dir, &["--deterministic", "index.html"]);
+    assert_eq!(once, twice, "run twice");
+    let elsewhere = tempfile::tempdir().unwrap();
+    let from_elsewhere = inline(
+        elsewhere.path(),
+        &[
+            "--deterministic",
+            page.to_str().unwrap(),
+            dir.to_str().unwrap(),
+        ],
+    );
+    assert_eq!(once, from_elsewhere, "run from another directory");
+    let from_root = inline(
+        Path::new(env!("CARGO_MANIFEST_DIR")),
+        &[
+            "--deterministic",
+            "tests/fixtures/deterministic/index.html",
+            "tests/fixtures/deterministic",
+        ],
+    );
+    assert_eq!(once, from_root, "run with relative paths");
+
+    let output = String::from_utf8(once).unwrap();
+    assert!(
+        output.contains("document.title = \"deterministic\";\nconsole.log"),
+        "{}",
+        output
+    );
+    assert!(output.contains("body {\n  margin: 0;\n}"), "{}", output);
+    let kept = inline(
+        &dir,
+        &["--deterministic", "--keep-line-endings", "index.html"],
+    );
+    assert!(String::from_utf8(kept)
+        .unwrap()
+        .contains("document.title = \"deterministic\";\r\nconsole.log"));
+}
@@ -0,0 +1,32 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("legacy")
+}
+
+/// inline runs inliner over the `input` fixture page.
+fn inline(input: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_inliner"))
+        .current_dir(fixture())
+        .args([input, "."])
+        .output()
+        .expect("running inliner")
+}
+
+#[test]
+fn undeclared_legacy_encoding() {
+    let output = inline("page.html");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(Some(2), output.status.code(), "{}", stderr);
+    assert_eq!(
+        "warning: <html> page.html: invalid UTF-8 at byte 42, so it was read as windows-1252\n",
+        stderr
+    );
+    let stdout = String::from_utf8(output.stdout).expect("the output is UTF-8");
+    assert!(stdout.contains("<title>Café</title>"), "{}", stdout);
+    assert!(stdout.contains("<p>Café “crème”</p>"), "{}", stdout);
+}
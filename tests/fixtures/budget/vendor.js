@@ -0,0 +1,200 @@
+var vendor0 = function (x) { return x * 0; };
+var vendor1 = function (x) { return x * 1; };
+var vendor2 = function (x) { return x * 2; };
+var vendor3 = function (x) { return x * 3; };
+var vendor4 = function (x) { return x * 4; };
+var vendor5 = function (x) { return x * 5; };
+var vendor6 = function (x) { return x * 6; };
+var vendor7 = function (x) { return x * 7; };
+var vendor8 = function (x) { return x * 8; };
+var vendor9 = function (x) { return x * 9; };
+var vendor10 = function (x) { return x * 10; };
+var vendor11 = function (x) { return x * 11; };
+var vendor12 = function (x) { return x * 12; };
+var vendor13 = function (x) { return x * 13; };
+var vendor14 = function (x) { return x * 14; };
+var vendor15 = function (x) { return x * 15; };
+var vendor16 = function (x) { return x * 16; };
+var vendor17 = function (x) { return x * 17; };
+var vendor18 = function (x) { return x * 18; };
+var vendor19 = function (x) { return x * 19; };
+var vendor20 = function (x) { return x * 20; };
+var vendor21 = function (x) { return x * 21; };
+var vendor22 = function (x) { return x * 22; };
+var vendor23 = function (x) { return x * 23; };
+var vendor24 = function (x) { return x * 24; };
+var vendor25 = function (x) { return x * 25; };
+var vendor26 = function (x) { return x * 26; };
+var vendor27 = function (x) { return x * 27; };
+var vendor28 = function (x) { return x * 28; };
+var vendor29 = function (x) { return x * 29; };
+var vendor30 = function (x) { return x * 30; };
+var vendor31 = function (x) { return x * 31; };
+var vendor32 = function (x) { return x * 32; };
+var vendor33 = function (x) { return x * 33; };
+var vendor34 = function (x) { return x * 34; };
+var vendor35 = function (x) { return x * 35; };
+var vendor36 = function (x) { return x * 36; };
+var vendor37 = function (x) { return x * 37; };
+var vendor38 = function (x) { return x * 38; };
+var vendor39 = function (x) { return x * 39; };
+var vendor40 = function (x) { return x * 40; };
+var vendor41 = function (x) { return x * 41; };
+var vendor42 = function (x) { return x * 42; };
+var vendor43 = function (x) { return x * 43; };
+var vendor44 = function (x) { return x * 44; };
+var vendor45 = function (x) { return x * 45; };
+var vendor46 = function (x) { return x * 46; };
+var vendor47 = function (x) { return x * 47; };
+var vendor48 = function (x) { return x * 48; };
+var vendor49 = function (x) { return x * 49; };
+var vendor50 = function (x) { return x * 50; };
+var vendor51 = function (x) { return x * 51; };
+var vendor52 = function (x) { return x * 52; };
+var vendor53 = function (x) { return x * 53; };
+var vendor54 = function (x) { return x * 54; };
+var vendor55 = function (x) { return x * 55; };
+var vendor56 = function (x) { return x * 56; };
+var vendor57 = function (x) { return x * 57; };
+var vendor58 = function (x) { return x * 58; };
+var vendor59 = function (x) { return x * 59; };
+var vendor60 = function (x) { return x * 60; };
+var vendor61 = function (x) { return x * 61; };
+var vendor62 = function (x) { return x * 62; };
+var vendor63 = function (x) { return x * 63; };
+var vendor64 = function (x) { return x * 64; };
+var vendor65 = function (x) { return x * 65; };
+var vendor66 = function (x) { return x * 66; };
+var vendor67 = function (x) { return x * 67; };
+var vendor68 = function (x) { return x * 68; };
+var vendor69 = function (x) { return x * 69; };
+var vendor70 = function (x) { return x * 70; };
+var vendor71 = function (x) { return x * 71; };
+var vendor72 = function (x) { return x * 72; };
+var vendor73 = function (x) { return x * 73; };
+var vendor74 = function (x) { return x * 74; };
+var vendor75 = function (x) { return x * 75; };
+var vendor76 = function (x) { return x * 76; };
+var vendor77 = function (x) { return x * 77; };
+var vendor78 = function (x) { return x * 78; };
+var vendor79 = function (x) { return x * 79; };
+var vendor80 = function (x) { return x * 80; };
+var vendor81 = function (x) { return x * 81; };
+var vendor82 = function (x) { return x * 82; };
+var vendor83 = function (x) { return x * 83; };
+var vendor84 = function (x) { return x * 84; };
+var vendor85 = function (x) { return x * 85; };
+var vendor86 = function (x) { return x * 86; };
+var vendor87 = function (x) { return x * 87; };
+var vendor88 = function (x) { return x * 88; };
+var vendor89 = function (x) { return x * 89; };
+var vendor90 = function (x) { return x * 90; };
+var vendor91 = function (x) { return x * 91; };
+var vendor92 = function (x) { return x * 92; };
+var vendor93 = function (x) { return x * 93; };
+var vendor94 = function (x) { return x * 94; };
+var vendor95 = function (x) { return x * 95; };
+var vendor96 = function (x) { return x * 96; };
+var vendor97 = function (x) { return x * 97; };
+var vendor98 = function (x) { return x * 98; };
+var vendor99 = function (x) { return x * 99; };
+var vendor100 = function (x) { return x * 100; };
+var vendor101 = function (x) { return x * 101; };
+var vendor102 = function (x) { return x * 102; };
+var vendor103 = function (x) { return x * 103; };
+var vendor104 = function (x) { return x * 104; };
+var vendor105 = function (x) { return x * 105; };
+var vendor106 = function (x) { return x * 106; };
+var vendor107 = function (x) { return x * 107; };
+var vendor108 = function (x) { return x * 108; };
+var vendor109 = function (x) { return x * 109; };
+var vendor110 = function (x) { return x * 110; };
+var vendor111 = function (x) { return x * 111; };
+var vendor112 = function (x) { return x * 112; };
+var vendor113 = function (x) { return x * 113; };
+var vendor114 = function (x) { return x * 114; };
+var vendor115 = function (x) { return x * 115; };
+var vendor116 = function (x) { return x * 116; };
+var vendor117 = function (x) { return x * 117; };
+var vendor118 = function (x) { return x * 118; };
+var vendor119 = function (x) { return x * 119; };
+var vendor120 = function (x) { return x * 120; };
+var vendor121 = function (x) { return x * 121; };
+var vendor122 = function (x) { return x * 122; };
+var vendor123 = function (x) { return x * 123; };
+var vendor124 = function (x) { return x * 124; };
+var vendor125 = function (x) { return x * 125; };
+var vendor126 = function (x) { return x * 126; };
+var vendor127 = function (x) { return x * 127; };
+var vendor128 = function (x) { return x * 128; };
+var vendor129 = function (x) { return x * 129; };
+var vendor130 = function (x) { return x * 130; };
+var vendor131 = function (x) { return x * 131; };
+var vendor132 = function (x) { return x * 132; };
+var vendor133 = function (x) { return x * 133; };
+var vendor134 = function (x) { return x * 134; };
+var vendor135 = function (x) { return x * 135; };
+var vendor136 = function (x) { return x * 136; };
+var vendor137 = function (x) { return x * 137; };
+var vendor138 = function (x) { return x * 138; };
+var vendor139 = function (x) { return x * 139; };
+var vendor140 = function (x) { return x * 140; };
+var vendor141 = function (x) { return x * 141; };
+var vendor142 = function (x) { return x * 142; };
+var vendor143 = function (x) { return x * 143; };
+var vendor144 = function (x) { return x * 144; };
+var vendor145 = function (x) { return x * 145; };
+var vendor146 = function (x) { return x * 146; };
+var vendor147 = function (x) { return x * 147; };
+var vendor148 = function (x) { return x * 148; };
+var vendor149 = function (x) { return x * 149; };
+var vendor150 = function (x) { return x * 150; };
+var vendor151 = function (x) { return x * 151; };
+var vendor152 = function (x) { return x * 152; };
+var vendor153 = function (x) { return x * 153; };
+var vendor154 = function (x) { return x * 154; };
+var vendor155 = function (x) { return x * 155; };
+var vendor156 = function (x) { return x * 156; };
+var vendor157 = function (x) { return x * 157; };
+var vendor158 = function (x) { return x * 158; };
+var vendor159 = function (x) { return x * 159; };
+var vendor160 = function (x) { return x * 160; };
+var vendor161 = function (x) { return x * 161; };
+var vendor162 = function (x) { return x * 162; };
+var vendor163 = function (x) { return x * 163; };
+var vendor164 = function (x) { return x * 164; };
+var vendor165 = function (x) { return x * 165; };
+var vendor166 = function (x) { return x * 166; };
+var vendor167 = function (x) { return x * 167; };
+var vendor168 = function (x) { return x * 168; };
+var vendor169 = function (x) { return x * 169; };
+var vendor170 = function (x) { return x * 170; };
+var vendor171 = function (x) { return x * 171; };
+var vendor172 = function (x) { return x * 172; };
+var vendor173 = function (x) { return x * 173; };
+var vendor174 = function (x) { return x * 174; };
+var vendor175 = function (x) { return x * 175; };
+var vendor176 = function (x) { return x * 176; };
+var vendor177 = function (x) { return x * 177; };
+var vendor178 = function (x) { return x * 178; };
+var vendor179 = function (x) { return x * 179; };
+var vendor180 = function (x) { return x * 180; };
+var vendor181 = function (x) { return x * 181; };
+var vendor182 = function (x) { return x * 182; };
+var vendor183 = function (x) { return x * 183; };
+var vendor184 = function (x) { return x * 184; };
+var vendor185 = function (x) { return x * 185; };
+var vendor186 = function (x) { return x * 186; };
+var vendor187 = function (x) { return x * 187; };
+var vendor188 = function (x) { return x * 188; };
+var vendor189 = function (x) { return x * 189; };
+var vendor190 = function (x) { return x * 190; };
+var vendor191 = function (x) { return x * 191; };
+var vendor192 = function (x) { return x * 192; };
+var vendor193 = function (x) { return x * 193; };
+var vendor194 = function (x) { return x * 194; };
+var vendor195 = function (x) { return x * 195; };
+var vendor196 = function (x) { return x * 196; };
+var vendor197 = function (x) { return x * 197; };
+var vendor198 = function (x) { return x * 198; };
+var vendor199 = function (x) { return x * 199; };
@@ -0,0 +1,2 @@
+document.title = "deterministic";
+console.log(document.title);
@@ -0,0 +1 @@
+var greeting = "Ol!";
\ No newline at end of file
@@ -0,0 +1,6 @@
+// Preload the hover image, rather than 'images/pressed.png'.
+const hover = new Image();
+hover.src = 'images/hover.png';
+const hint = "Save the menu as button.png";
+const sprite = (name) => 'images/' + name + '.png';
+const missing = 'images/missing.png';
@@ -0,0 +1,7 @@
+export function capitalize(s) {
+    return s.charAt(0).toUpperCase() + s.slice(1);
+}
+
+export function shout(s) {
+    return s + "!";
+}
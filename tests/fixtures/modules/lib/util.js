@@ -0,0 +1,5 @@
+import { capitalize } from "./format.js";
+
+export function greet(name) {
+    return "hello " + capitalize(name);
+}
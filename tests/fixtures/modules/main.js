@@ -0,0 +1,4 @@
+import { greet } from "./lib/util.js";
+import { shout } from './lib/format.js';
+
+document.getElementById("out").textContent = shout(greet("modules"));
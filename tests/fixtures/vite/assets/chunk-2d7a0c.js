@@ -0,0 +1,3 @@
+export function mount(el, root) {
+  root.appendChild(el);
+}
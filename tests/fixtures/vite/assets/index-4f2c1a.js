@@ -0,0 +1,3 @@
+import { h } from "./vendor-9b1e3d.js";
+import { mount } from "./chunk-2d7a0c.js";
+mount(h("p", "hello"), document.getElementById("app"));
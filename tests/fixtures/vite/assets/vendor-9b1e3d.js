@@ -0,0 +1,5 @@
+export function h(tag, text) {
+  const el = document.createElement(tag);
+  el.textContent = text;
+  return el;
+}
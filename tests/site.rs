@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("site")
+}
+
+/// MAPPING moves the pages of the fixture site into `out`, renaming some.
+const MAPPING: &str =
+    "index.html=out/index.html,about.html=out/about-us.html,blog/post.html=out/2024/post.html";
+
+/// inline runs inliner over the page of the fixture site at `page`, against
+/// `base`, with `args`.
+fn inline(page: &str, base: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_inliner"))
+        .current_dir(fixture())
+        .args(args)
+        .args([page, base])
+        .output()
+        .expect("running inliner")
+}
+
+#[test]
+fn rewrite_links() {
+    let tests = vec![
+        (
+            "index.html",
+            ".",
+            vec![
+                r#"href="about-us.html""#,
+                r#"href="2024/post.html""#,
+                r##"href="#top""##,
+                r#"href="https://example.com/""#,
+                r#"href="mailto:me@example.com""#,
+            ],
+        ),
+        (
+            "about.html",
+            ".",
+            vec![
+                r#"href="index.html""#,
+                r#"action="2024/post.html#comments""#,
+            ],
+        ),
+        (
+            "blog/post.html",
+            "blog",
+            vec![r#"href="../index.html""#, r#"href="../about-us.html""#],
+        ),
+    ];
+    for (page, base, want) in tests {
+        let output = inline(page, base, &["--rewrite-links", MAPPING]);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}: {}", page, stderr);
+        for link in want {
+            assert!(stdout.contains(link), "{}: {} in {}", page, link, stdout);
+        }
+    }
+}
+
+#[test]
+fn rewrite_and_report_links() {
+    let output = inline(
+        "blog/post.html",
+        "blog",
+        &["--rewrite-links", MAPPING, "--report-links"],
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        "link: blog/post.html:7: <area> ../index.html\n\
+         link: blog/post.html:8: <a> ../about.html\n",
+        stderr,
+        "links are reported as they're written"
+    );
+}
+
+#[test]
+fn rewrite_links_unmapped_input() {
+    let output = inline(
+        "blog/post.html",
+        "blog",
+        &["--rewrite-links", "about.html=a.html"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains(r#"href="../a.html""#), "{}", stdout);
+    assert!(stdout.contains(r#"href="../index.html""#), "{}", stdout);
+}
+
+#[test]
+fn rewrite_links_errors() {
+    let tests = vec![
+        (
+            vec!["--rewrite-links", "about.html"],
+            "index.html",
+            "error: parsing link rewrite: \"about.html\" isn't \"input=output\"\n",
+        ),
+        (
+            vec!["--rewrite-links", MAPPING],
+            "-",
+            "error: --rewrite-links needs the input to be a file\n",
+        ),
+    ];
+    for (args, page, want) in tests {
+        let output = inline(page, ".", &args);
+        assert_eq!(Some(1), output.status.code(), "{}", want);
+        assert_eq!(want, String::from_utf8_lossy(&output.stderr));
+    }
+}
@@ -0,0 +1,36 @@
+//! Run with `wasm-pack test --node -- --features wasm`.
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use inliner::wasm::inline_with_resources;
+use js_sys::Function;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+fn resolver(body: &str) -> Function {
+    Function::new_with_args("path", body)
+}
+
+#[wasm_bindgen_test]
+fn resources_come_from_the_resolver() {
+    let resolver = resolver(
+        "switch (path) {
+            case 'app.css': return 'body{background:url(dot.png)}';
+            case 'dot.png': return new Uint8Array([100, 111, 116]);
+            default: return null;
+        }",
+    );
+    let got =
+        inline_with_resources(r#"<link rel="stylesheet" href="app.css"/>"#, resolver).unwrap();
+    assert_eq!(
+        "<style> body{background:url(data:image/png;base64,ZG90)}</style>\n",
+        got
+    );
+}
+
+#[wasm_bindgen_test]
+fn missing_resources_are_errors() {
+    let got = inline_with_resources(r#"<img src="missing.png"/>"#, resolver("return null;"));
+    assert_eq!(
+        Some("missing.png: not found".to_string()),
+        got.unwrap_err().as_string()
+    );
+}